@@ -0,0 +1,189 @@
+//! Named directories that roots can be stored relative to.
+//!
+//! Anchors are kept per device in the config directory, rather than in the database, so the same
+//! database can be shared between machines where e.g. the home directory is at a different path.
+
+use {
+    crate::{error::bail, fs_util::ensure_dir_exists},
+    std::path::{Path, PathBuf},
+};
+
+const ANCHORS_FILENAME: &str = "anchors";
+
+/// The anchors of this device, as `name=path` lines in the config directory
+#[derive(Default, Clone)]
+pub struct Anchors {
+    entries: Vec<(String, PathBuf)>,
+}
+
+impl Anchors {
+    pub(crate) fn load(config_dir: &Path) -> crate::Result<Self> {
+        let path = config_dir.join(ANCHORS_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut entries = Vec::new();
+        for line in std::fs::read_to_string(path)?.lines() {
+            if let Some((name, path)) = line.split_once('=') {
+                entries.push((name.to_owned(), PathBuf::from(path)));
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    pub(crate) fn save(&self, config_dir: &Path) -> crate::Result<()> {
+        ensure_dir_exists(config_dir)?;
+        let mut out = String::new();
+        for (name, path) in &self.entries {
+            let Some(path) = path.to_str() else {
+                bail!("Anchor paths must be valid UTF-8");
+            };
+            out.push_str(&format!("{name}={path}\n"));
+        }
+        std::fs::write(config_dir.join(ANCHORS_FILENAME), out)?;
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.entries
+            .iter()
+            .map(|(name, path)| (name.as_str(), path.as_path()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        self.iter().find(|(n, _)| *n == name).map(|(_, path)| path)
+    }
+
+    /// Adds an anchor, or changes the path of an existing one
+    pub fn set(&mut self, name: &str, path: PathBuf) -> crate::Result<()> {
+        if name.is_empty() || name.contains([':', '=', '\n']) {
+            bail!("Anchor names can't be empty or contain ':', '=' or newlines");
+        }
+        if !path.is_absolute() {
+            bail!("Anchor paths must be absolute");
+        }
+        match self.entries.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = path,
+            None => self.entries.push((name.to_owned(), path)),
+        }
+        Ok(())
+    }
+
+    /// Removes an anchor, and returns whether it existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|(n, _)| n != name);
+        self.entries.len() != len
+    }
+
+    /// Returns the stored form of `path`, relative to the innermost anchor containing it
+    pub(crate) fn encode(&self, path: &Path) -> String {
+        let innermost = self
+            .entries
+            .iter()
+            .filter(|(_, anchor)| path.starts_with(anchor))
+            .max_by_key(|(_, anchor)| anchor.components().count());
+        match innermost {
+            Some((name, anchor)) => anchored(name, anchor, path),
+            None => paths_as_strings::encode_path(&path).into_owned(),
+        }
+    }
+
+    /// Returns every stored form `path` could have: absolute, or relative to any anchor
+    pub(crate) fn spellings(&self, path: &Path) -> Vec<String> {
+        let mut spellings = vec![paths_as_strings::encode_path(&path).into_owned()];
+        for (name, anchor) in &self.entries {
+            if path.starts_with(anchor) {
+                spellings.push(anchored(name, anchor, path));
+            }
+        }
+        spellings
+    }
+
+    /// Resolves a stored root.
+    ///
+    /// Roots relative to anchors that don't exist on this device are returned as they are stored.
+    pub(crate) fn decode(&self, stored: &str) -> crate::Result<PathBuf> {
+        if let Some((name, rel)) = stored.strip_prefix('@').and_then(|s| s.split_once(':')) {
+            if let Some(anchor) = self.get(name) {
+                if rel.is_empty() {
+                    return Ok(anchor.to_owned());
+                }
+                return Ok(anchor.join(crate::fs_util::decode_path(rel)?));
+            }
+            return Ok(PathBuf::from(stored));
+        }
+        crate::fs_util::decode_path(stored)
+    }
+}
+
+fn anchored(name: &str, anchor: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(anchor).unwrap_or(path);
+    format!("@{name}:{}", paths_as_strings::encode_path(&rel))
+}
+
+/// Returns the anchor name of a stored root, if it's stored relative to one
+pub(crate) fn anchor_name(stored: &str) -> Option<&str> {
+    stored
+        .strip_prefix('@')
+        .and_then(|s| s.split_once(':'))
+        .map(|(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anchors() -> Anchors {
+        let mut anchors = Anchors::default();
+        anchors.set("home", "/home/me".into()).unwrap();
+        anchors.set("proj", "/home/me/proj".into()).unwrap();
+        anchors
+    }
+
+    #[test]
+    fn round_trip() {
+        let anchors = anchors();
+        for path in [
+            "/home/me/proj/a",
+            "/home/me/x/y",
+            "/home/me",
+            "/home/me/proj",
+            "/elsewhere/z",
+            "/home/me/with\ttab",
+        ] {
+            let path = Path::new(path);
+            assert_eq!(anchors.decode(&anchors.encode(path)).unwrap(), path);
+        }
+    }
+
+    #[test]
+    fn innermost_anchor_is_used() {
+        let anchors = anchors();
+        assert_eq!(anchors.encode(Path::new("/home/me/proj/a")), "@proj:a");
+        assert_eq!(anchors.encode(Path::new("/home/me/x")), "@home:x");
+        // Only whole components count
+        assert_eq!(
+            anchors.encode(Path::new("/home/me/projects")),
+            "@home:projects"
+        );
+        assert_eq!(anchors.encode(Path::new("/home/meh")), "/home/meh");
+    }
+
+    #[test]
+    fn unknown_anchors_stay_as_stored() {
+        let anchors = anchors();
+        assert_eq!(anchors.decode("@gone:a/b").unwrap(), Path::new("@gone:a/b"));
+        assert_eq!(anchor_name("@gone:a/b"), Some("gone"));
+        assert_eq!(anchor_name("/home/me"), None);
+    }
+
+    #[test]
+    fn spellings_include_every_anchor() {
+        let anchors = anchors();
+        let mut spellings = anchors.spellings(Path::new("/home/me/proj/a"));
+        spellings.sort();
+        assert_eq!(spellings, ["/home/me/proj/a", "@home:proj/a", "@proj:a"]);
+        assert_eq!(anchors.spellings(Path::new("/tmp")), ["/tmp"]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Reading the commands in the history file of the user's shell, for `okeep add-from-history`
+
+use {
+    anyhow::Context,
+    std::path::{Path, PathBuf},
+};
+
+/// Parses the contents of a history file into commands
+type Parser = fn(&[u8]) -> Vec<String>;
+
+/// Returns the commands in the history of the user's shell, oldest first, along with the name of
+/// the shell.
+///
+/// The shell is detected from `$SHELL`. Shells only write their history file from time to time,
+/// so the last few commands may be missing. The shell integration passes the last command
+/// directly instead.
+pub(crate) fn recent_commands() -> anyhow::Result<(Vec<String>, &'static str)> {
+    let home = PathBuf::from(std::env::var_os("HOME").context("$HOME is not set")?);
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    let histfile = std::env::var_os("HISTFILE").map(PathBuf::from);
+    let (shell, path, parse): (_, PathBuf, Parser) =
+        match Path::new(&shell).file_name().and_then(|name| name.to_str()) {
+            Some("zsh") => (
+                "zsh",
+                histfile.unwrap_or_else(|| home.join(".zsh_history")),
+                parse_zsh,
+            ),
+            Some("fish") => {
+                let data_dir = std::env::var_os("XDG_DATA_HOME")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| home.join(".local/share"));
+                ("fish", data_dir.join("fish/fish_history"), parse_fish)
+            }
+            _ => (
+                "bash",
+                histfile.unwrap_or_else(|| home.join(".bash_history")),
+                parse_bash,
+            ),
+        };
+    let data = std::fs::read(&path)
+        .with_context(|| format!("Failed to read shell history from {}", path.display()))?;
+    Ok((parse(&data), shell))
+}
+
+fn parse_bash(data: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        // Timestamps, with HISTTIMEFORMAT set
+        .filter(|line| {
+            !line
+                .strip_prefix('#')
+                .is_some_and(|rest| !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Parses zsh history, which may be in the extended format (`: <start>:<duration>;<command>`)
+fn parse_zsh(data: &[u8]) -> Vec<String> {
+    // zsh escapes some bytes with a meta byte before them, and xors them with 32
+    const META: u8 = 0x83;
+    let mut unmeta = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            META => unmeta.extend(bytes.next().map(|b| b ^ 32)),
+            _ => unmeta.push(b),
+        }
+    }
+    let text = String::from_utf8_lossy(&unmeta);
+    let mut commands: Vec<String> = Vec::new();
+    let mut continued = false;
+    for line in text.lines() {
+        let (line, continues) = match line.strip_suffix('\\') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        match commands.last_mut() {
+            Some(command) if continued => {
+                command.push('\n');
+                command.push_str(line);
+            }
+            _ => {
+                let command = match line.strip_prefix(": ").and_then(|l| l.split_once(';')) {
+                    Some((_, command)) => command,
+                    None => line,
+                };
+                commands.push(command.to_owned());
+            }
+        }
+        continued = continues;
+    }
+    commands
+}
+
+/// Parses fish history, which is YAML-like with `- cmd: <command>` entries
+fn parse_fish(data: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(|command| {
+            let mut unescaped = String::with_capacity(command.len());
+            let mut chars = command.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some('n')) => {
+                        unescaped.push('\n');
+                        chars.next();
+                    }
+                    ('\\', Some('\\')) => {
+                        unescaped.push('\\');
+                        chars.next();
+                    }
+                    _ => unescaped.push(c),
+                }
+            }
+            unescaped
+        })
+        .collect()
+}
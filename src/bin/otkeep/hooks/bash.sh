@@ -0,0 +1,92 @@
+# OtKeep shell integration for bash. Load with: eval "$(okeep hook bash)"
+
+_otkeep_hook() {
+    [ "$PWD" = "${_OTKEEP_LAST_PWD-}" ] && return
+    _OTKEEP_LAST_PWD=$PWD
+    local out root
+    out=$(okeep hook-summary 2>/dev/null)
+    root=${out%%$'\n'*}
+    if [ -n "$root" ] && [ "$root" != "${_OTKEEP_LAST_ROOT-}" ]; then
+        printf '%s\n' "${out#*$'\n'}"
+    fi
+    _OTKEEP_LAST_ROOT=$root
+}
+
+if [[ ";${PROMPT_COMMAND:-};" != *";_otkeep_hook;"* ]]; then
+    PROMPT_COMMAND="_otkeep_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+
+# Adds the previous command as a script: okeep-save NAME
+okeep-save() {
+    okeep add-from-history --command="$(fc -ln -2 -2)" "$@"
+}
+
+# Completes the script names for orun and otrun, and the arguments the scripts declare
+_otkeep_complete() {
+    # Split the line ourselves, as bash would split --flag=value into three words
+    local line=${COMP_LINE:0:COMP_POINT} words i
+    read -ra words <<< "$line"
+    [[ -z $line || $line == *[[:space:]] ]] && words+=("")
+    local cur=${words[${#words[@]} - 1]}
+    local IFS=$'\n'
+    # The first word that isn't an option is the script name, the rest are its arguments
+    for ((i = 1; i < ${#words[@]} - 1; i++)); do
+        if [[ ${words[i]} != -* ]]; then
+            COMPREPLY=($(orun --complete-args "${words[i]}" -- "${words[@]:i+1}" 2>/dev/null))
+            # Only the part after the = gets replaced
+            [[ $cur == -*=* ]] && COMPREPLY=("${COMPREPLY[@]#"${cur%%=*}="}")
+            return 0
+        fi
+    done
+    [[ $cur == -* ]] && return 0
+    COMPREPLY=($(orun --complete "$cur" 2>/dev/null))
+}
+complete -o default -F _otkeep_complete orun otrun
+
+# Picks a script and inserts `orun <script>` on the command line. Bound to Alt+O, or to the key in
+# $OTKEEP_PICK_KEY.
+_otkeep_pick_widget() {
+    local name insert
+    name=$(okeep pick </dev/tty)
+    [[ -n $name ]] || return
+    insert="orun $(printf '%q' "$name") "
+    READLINE_LINE=${READLINE_LINE:0:READLINE_POINT}$insert${READLINE_LINE:READLINE_POINT}
+    READLINE_POINT=$((READLINE_POINT + ${#insert}))
+}
+if [[ $- == *i* ]]; then
+    bind -x "\"${OTKEEP_PICK_KEY:-\\eo}\": _otkeep_pick_widget"
+fi
+
+# Offers to run `orun <name>` when a command isn't found, but the current tree has a script of
+# that name. $OTKEEP_NOT_FOUND picks what happens: `ask` first (the default), `run` without asking,
+# or `off`. A handler that was set up before is used for the other commands.
+_otkeep_should_run() {
+    [[ ${OTKEEP_NOT_FOUND:-ask} != off ]] || return 1
+    local IFS=$'\n' name answer found=
+    for name in $(orun --complete "$1" 2>/dev/null); do
+        [[ $name == "$1" ]] && found=1
+    done
+    [[ -n $found ]] || return 1
+    [[ ${OTKEEP_NOT_FOUND:-ask} == run ]] && return 0
+    # Nobody to ask without a terminal
+    { : </dev/tty; } 2>/dev/null || return 1
+    printf "'%s' is a script of this tree. Run orun %s? [Y/n] " "$1" "$1" >&2
+    { read -r answer </dev/tty; } 2>/dev/null || return 1
+    [[ -z $answer || $answer == [yY]* ]]
+}
+if declare -F command_not_found_handle >/dev/null &&
+    [[ $(declare -f command_not_found_handle) != *_otkeep_should_run* ]]; then
+    eval "_otkeep_prev_not_found () $(declare -f command_not_found_handle | tail -n +2)"
+fi
+command_not_found_handle() {
+    if _otkeep_should_run "$1"; then
+        orun "$@"
+        return
+    fi
+    if declare -F _otkeep_prev_not_found >/dev/null; then
+        _otkeep_prev_not_found "$@"
+        return
+    fi
+    printf 'bash: %s: command not found\n' "$1" >&2
+    return 127
+}
@@ -0,0 +1,84 @@
+# OtKeep shell integration for fish. Load with: okeep hook fish | source
+
+function _otkeep_hook --on-variable PWD
+    set -l out (okeep hook-summary 2>/dev/null)
+    set -l root $out[1]
+    if test -n "$root"; and test "$root" != "$_otkeep_last_root"
+        printf '%s\n' $out[2]
+    end
+    set -g _otkeep_last_root $root
+end
+
+# Adds the previous command as a script: okeep-save NAME
+function okeep-save
+    okeep add-from-history --command="$history[1]" --shell=fish $argv
+end
+
+# Completes the script names for orun and otrun, and the arguments the scripts declare
+function _otkeep_needs_script
+    # Only the first word that isn't an option is a script name
+    for token in (commandline -opc)[2..]
+        string match -q -- '-*' $token; or return 1
+    end
+    not string match -q -- '-*' (commandline -ct)
+end
+function _otkeep_script_args
+    set -l tokens (commandline -opc)
+    for i in (seq 2 (count $tokens))
+        if not string match -q -- '-*' $tokens[$i]
+            set -l name $tokens[$i]
+            set -e tokens[1..$i]
+            orun --complete-args $name -- $tokens (commandline -ct) 2>/dev/null
+            return
+        end
+    end
+end
+for cmd in orun otrun
+    complete -c $cmd -f -n _otkeep_needs_script -a '(orun --complete (commandline -ct) 2>/dev/null)'
+    complete -c $cmd -n 'not _otkeep_needs_script' -a '(_otkeep_script_args)'
+end
+
+# Picks a script and inserts `orun <script>` on the command line. Bound to Alt+O, or to the key in
+# $OTKEEP_PICK_KEY.
+function _otkeep_pick_widget
+    set -l name (okeep pick </dev/tty)
+    if test -n "$name"
+        commandline -i "orun "(string escape -- $name)" "
+    end
+    commandline -f repaint
+end
+if set -q OTKEEP_PICK_KEY
+    bind $OTKEEP_PICK_KEY _otkeep_pick_widget
+else
+    bind \eo _otkeep_pick_widget
+end
+
+# Offers to run `orun <name>` when a command isn't found, but the current tree has a script of
+# that name. $OTKEEP_NOT_FOUND picks what happens: `ask` first (the default), `run` without asking,
+# or `off`. The handler that was set up before is used for the other commands.
+function _otkeep_should_run
+    test "$OTKEEP_NOT_FOUND" != off; or return 1
+    contains -- $argv[1] (orun --complete $argv[1] 2>/dev/null); or return 1
+    test "$OTKEEP_NOT_FOUND" = run; and return 0
+    read -P "'$argv[1]' is a script of this tree. Run orun $argv[1]? [Y/n] " answer </dev/tty 2>/dev/null
+    or return 1
+    test -z "$answer"; or string match -qi 'y*' -- $answer
+end
+if functions -q fish_command_not_found
+    and not functions fish_command_not_found | string match -q '*_otkeep_should_run*'
+    functions -e _otkeep_prev_not_found
+    functions -c fish_command_not_found _otkeep_prev_not_found
+end
+function fish_command_not_found
+    if _otkeep_should_run $argv[1]
+        orun $argv
+        return
+    end
+    if functions -q _otkeep_prev_not_found
+        _otkeep_prev_not_found $argv
+    else
+        __fish_default_command_not_found_handler $argv[1]
+    end
+end
+
+_otkeep_hook
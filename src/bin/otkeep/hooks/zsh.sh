@@ -0,0 +1,86 @@
+# OtKeep shell integration for zsh. Load with: eval "$(okeep hook zsh)"
+
+_otkeep_hook() {
+    local out root
+    out=$(okeep hook-summary 2>/dev/null)
+    root=${out%%$'\n'*}
+    if [[ -n "$root" && "$root" != "${_OTKEEP_LAST_ROOT-}" ]]; then
+        printf '%s\n' "${out#*$'\n'}"
+    fi
+    _OTKEEP_LAST_ROOT=$root
+}
+
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _otkeep_hook
+_otkeep_hook
+
+# Adds the previous command as a script: okeep-save NAME
+okeep-save() {
+    okeep add-from-history --command="$(fc -ln -2 -2)" "$@"
+}
+
+# Completes the script names for orun and otrun, and the arguments the scripts declare
+_otkeep_complete() {
+    local i
+    local -a completions
+    # The first word that isn't an option is the script name, the rest are its arguments
+    for ((i = 2; i < CURRENT; i++)); do
+        if [[ ${words[i]} != -* ]]; then
+            completions=(${(f)"$(orun --complete-args "${words[i]}" -- "${(@)words[i+1,CURRENT]}" 2>/dev/null)"})
+            if (( ${#completions} )); then
+                compadd -Q -a completions
+            else
+                _files
+            fi
+            return
+        fi
+    done
+    [[ ${words[CURRENT]} == -* ]] && return 1
+    completions=(${(f)"$(orun --complete "${words[CURRENT]}" 2>/dev/null)"})
+    compadd -a completions
+}
+(( $+functions[compdef] )) && compdef _otkeep_complete orun otrun
+
+# Picks a script and inserts `orun <script>` on the command line. Bound to Alt+O, or to the key in
+# $OTKEEP_PICK_KEY.
+_otkeep_pick_widget() {
+    local name
+    name=$(okeep pick </dev/tty)
+    [[ -n $name ]] && LBUFFER+="orun ${(q)name} "
+    zle reset-prompt
+}
+zle -N _otkeep_pick_widget
+bindkey "${OTKEEP_PICK_KEY:-^[o}" _otkeep_pick_widget
+
+# Offers to run `orun <name>` when a command isn't found, but the current tree has a script of
+# that name. $OTKEEP_NOT_FOUND picks what happens: `ask` first (the default), `run` without asking,
+# or `off`. A handler that was set up before is used for the other commands.
+_otkeep_should_run() {
+    [[ ${OTKEEP_NOT_FOUND:-ask} != off ]] || return 1
+    local answer
+    local -a names
+    names=(${(f)"$(orun --complete "$1" 2>/dev/null)"})
+    (( ${names[(Ie)$1]} )) || return 1
+    [[ ${OTKEEP_NOT_FOUND:-ask} == run ]] && return 0
+    # Nobody to ask without a terminal
+    { : </dev/tty; } 2>/dev/null || return 1
+    printf "'%s' is a script of this tree. Run orun %s? [Y/n] " "$1" "$1" >&2
+    { read -r answer </dev/tty; } 2>/dev/null || return 1
+    [[ -z $answer || $answer == [yY]* ]]
+}
+if (( $+functions[command_not_found_handler] )) &&
+    [[ $functions[command_not_found_handler] != *_otkeep_should_run* ]]; then
+    functions[_otkeep_prev_not_found]=$functions[command_not_found_handler]
+fi
+command_not_found_handler() {
+    if _otkeep_should_run "$1"; then
+        orun "$@"
+        return
+    fi
+    if (( $+functions[_otkeep_prev_not_found] )); then
+        _otkeep_prev_not_found "$@"
+        return
+    fi
+    print -u2 "zsh: command not found: $1"
+    return 127
+}
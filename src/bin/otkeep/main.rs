@@ -0,0 +1,206 @@
+#![feature(never_type)]
+
+//! Multicall binary for all the OtKeep tools.
+//!
+//! It acts as `okeep`, `orun` or `otrun` depending on the name it was invoked as,
+//! similarly to busybox.
+
+use {
+    anyhow::{bail, Context},
+    otkeep::database::Database,
+    std::{
+        ffi::{OsStr, OsString},
+        io::IsTerminal,
+        path::{Path, PathBuf},
+        time::Duration,
+    },
+};
+
+mod history;
+mod okeep;
+mod orun;
+mod table;
+mod tree_templates;
+
+/// The names this binary can be invoked as. `cargo-orun` makes `cargo orun <script>` work, for
+/// the aliases of `okeep export cargo-aliases`.
+const APPLETS: [&str; 4] = ["okeep", "orun", "otrun", "cargo-orun"];
+
+fn main() {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let invoked_as = args
+        .first()
+        .map(Path::new)
+        .and_then(Path::file_name)
+        .unwrap_or_default();
+    let result = if is_applet(invoked_as) {
+        run_applet(args)
+    } else {
+        match args.get(1) {
+            // `otkeep okeep ...` style invocation
+            Some(arg) if is_applet(arg) => run_applet(args.into_iter().skip(1).collect()),
+            Some(arg) if arg == "--install-links" => install_links(&args[2..]),
+            _ => {
+                usage();
+                std::process::exit(1);
+            }
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(1);
+    }
+}
+
+fn is_applet(name: &OsStr) -> bool {
+    APPLETS.iter().any(|applet| name == *applet)
+}
+
+/// Runs the applet named by the first argument
+fn run_applet(args: Vec<OsString>) -> anyhow::Result<()> {
+    let name = Path::new(&args[0])
+        .file_name()
+        .unwrap_or_default()
+        .to_owned();
+    let args = args.into_iter();
+    if name == "okeep" {
+        okeep::main(args)
+    } else if name == "cargo-orun" {
+        // Cargo passes the name of the subcommand first, as in `cargo-orun orun <script>`
+        let mut args = args.skip(1).peekable();
+        args.next_if(|arg| arg == "orun");
+        match orun::main(std::iter::once("orun".into()).chain(args), false) {
+            Err(e) => Err(e),
+        }
+    } else {
+        match orun::main(args, name == "otrun") {
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Parses a `KEY=VAL` argument
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, val)) if !key.is_empty() => Ok((key.to_owned(), val.to_owned())),
+        _ => Err("expected KEY=VAL".into()),
+    }
+}
+
+/// Formats a duration in a human readable way, e.g. `1.25s` or `3m 20s`
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        ..60 => format!("{:.2}s", d.as_secs_f64()),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Finds the root the current directory belongs to. If the tree was found by its marker after it
+/// moved, its recorded root is updated, unless the database is read-only.
+fn find_root(db: &Database) -> anyhow::Result<Option<(i64, PathBuf)>> {
+    let Some(root) = otkeep::locate_root(db)? else {
+        return Ok(None);
+    };
+    if let Some(old_root) = &root.moved_from {
+        if !db.is_read_only() {
+            db.rename_tree(old_root, &root.path)?;
+            eprintln!(
+                "Tree moved from {} to {}, updated its root",
+                old_root.display(),
+                root.path.display()
+            );
+        }
+    }
+    Ok(Some((root.id, root.path)))
+}
+
+/// Shows the scripts among `names` and the scripts they need that haven't been trusted yet, and asks
+/// whether to trust them.
+///
+/// Does nothing without a terminal, so running them fails instead.
+fn ask_trust(db: &mut Database, tree_id: i64, names: &[String]) -> anyhow::Result<()> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    let mut untrusted = Vec::new();
+    for name in names {
+        // Missing scripts are reported when they're run
+        if !db.script_exists(tree_id, name)? {
+            continue;
+        }
+        for script in db.run_order(tree_id, name)? {
+            if !db.script_trusted(tree_id, &script)? && !untrusted.contains(&script) {
+                untrusted.push(script);
+            }
+        }
+    }
+    for name in untrusted {
+        let origin = match db.script_origin(tree_id, &name)? {
+            Some(origin) => format!(" (from {origin})"),
+            None => String::new(),
+        };
+        let body = db.get_script_by_name(tree_id, &name)?;
+        // Control characters could mess with the terminal, or hide parts of the script
+        let body = String::from_utf8_lossy(&body)
+            .replace(|c: char| c.is_control() && c != '\n' && c != '\t', "?");
+        eprintln!("{name}{origin} came from elsewhere, and hasn't been run here before:\n");
+        eprintln!("{body}");
+        eprintln!("Trust and run {name}? (y/n)");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if answer.trim() != "y" {
+            bail!("Not running untrusted script {name}");
+        }
+        if db.is_read_only() {
+            eprintln!("The database is read-only, so you'll be asked again next time");
+            db.trust_script_for_now(tree_id, &name);
+        } else {
+            db.set_script_trusted(tree_id, &name, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Links all the applet names to this executable in the given directory
+fn install_links(args: &[OsString]) -> anyhow::Result<()> {
+    let mut symlink = false;
+    let mut dir = None;
+    for arg in args {
+        if arg == "--symlink" {
+            symlink = true;
+        } else {
+            dir = Some(Path::new(arg));
+        }
+    }
+    let Some(dir) = dir else {
+        usage();
+        std::process::exit(1);
+    };
+    let exe = std::env::current_exe().context("Failed to determine own executable path")?;
+    for applet in APPLETS {
+        let link = dir.join(applet);
+        if link.symlink_metadata().is_ok() {
+            eprintln!("{} already exists, skipping", link.display());
+            continue;
+        }
+        if symlink {
+            std::os::unix::fs::symlink(&exe, &link)
+        } else {
+            std::fs::hard_link(&exe, &link)
+        }
+        .with_context(|| format!("Failed to link {}", link.display()))?;
+        eprintln!("{} -> {}", link.display(), exe.display());
+    }
+    Ok(())
+}
+
+fn usage() {
+    eprintln!("otkeep is a multicall binary. It acts as okeep, orun or otrun, depending on the");
+    eprintln!("name it is invoked as. As cargo-orun, it runs scripts for cargo aliases.\n");
+    eprintln!("Usage:");
+    eprintln!("    otkeep <okeep|orun|otrun> [args...]");
+    eprintln!("    otkeep --install-links [--symlink] <dir>");
+    eprintln!("\nInstall links with: otkeep --install-links ~/.cargo/bin");
+}
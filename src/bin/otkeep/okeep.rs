@@ -0,0 +1,5123 @@
+use {
+    crate::{parse_env_var, table::Format},
+    anyhow::{bail, Context},
+    clap::{builder::PossibleValuesParser, Parser, Subcommand, ValueEnum},
+    otkeep::{
+        database::{ItemSort, ListOpts, PurgeOpts},
+        signing::Policy,
+        AppContext,
+    },
+    std::{ffi::OsString, path::PathBuf},
+};
+
+#[derive(Parser)]
+#[clap(name = "okeep", about, version)]
+struct Args {
+    /// Open the database read-only, e.g. when it's shared by other machines.
+    ///
+    /// This also happens automatically when the database isn't writable. Commands that would
+    /// change it are rejected.
+    #[clap(long, global = true)]
+    read_only: bool,
+    #[clap(subcommand)]
+    subcommand: Option<Sub>,
+}
+
+/// Out of tree keeper
+#[derive(Subcommand)]
+enum Sub {
+    /// Adds a script for the current tree
+    Add {
+        /// The name the script will be referred to as
+        name: String,
+        /// A path to a script or an inline script
+        ///
+        /// If `-`, the script is read from standard input.
+        /// If not provided, $EDITOR will open to edit a new script
+        script: Option<String>,
+        /// Add an inline script instead of loading from a file
+        #[clap(short = 'i', long = "inline")]
+        inline: bool,
+        /// Download the script from the URL given as the script argument
+        #[clap(long = "from-url", conflicts_with = "inline")]
+        from_url: bool,
+        /// Size limit for downloaded scripts, in bytes
+        #[clap(long, default_value_t = cmd::DEFAULT_MAX_DOWNLOAD_SIZE)]
+        max_size: u64,
+        /// Start from a template (see `okeep template`), with its placeholders filled in
+        #[clap(long, conflicts_with_all = ["script", "from_url"])]
+        template: Option<String>,
+        /// Overwrite the script if it already exists
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Establish the current directory as a root first if there is no root yet
+        #[clap(long)]
+        establish: bool,
+        /// Add a shebang for this interpreter to a script without one, like `python3`
+        ///
+        /// `--shell fish` does the same, for inline snippets written for another shell than sh.
+        #[clap(
+            long,
+            visible_alias = "shell",
+            value_name = "PROGRAM",
+            conflicts_with = "from_url"
+        )]
+        interpreter: Option<String>,
+        /// Don't check the script for problems before storing it
+        #[clap(long)]
+        no_lint: bool,
+        /// Store the script encrypted with gpg, for your own key
+        ///
+        /// It's decrypted whenever it's shown or run, which gpg-agent may ask a passphrase for.
+        #[clap(long)]
+        encrypt: bool,
+    },
+    /// Add multiple scripts from files, named after the file names without extension
+    ///
+    /// Files without a shebang get one based on their extension, if it's a known one.
+    AddMany {
+        /// Paths to the scripts
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+        /// Overwrite scripts that already exist instead of skipping them
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+    /// Add the last command from your shell history as a script
+    ///
+    /// The history file is only written from time to time by most shells. The `okeep-save NAME`
+    /// function of the shell integration (`okeep hook`) passes the last command directly instead.
+    AddFromHistory {
+        /// The name the script will be referred to as
+        name: String,
+        /// Pick the command from a list of recent ones
+        #[clap(short = 'i', long, conflicts_with = "command")]
+        interactive: bool,
+        /// Use this command instead of reading the history file
+        #[clap(long, hide = true)]
+        command: Option<String>,
+        /// The shell the command is run with. Defaults to fish for commands from the fish history,
+        /// and sh otherwise.
+        #[clap(long, value_name = "PROGRAM")]
+        shell: Option<String>,
+        /// Overwrite the script if it already exists
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Don't check the script for problems before storing it
+        #[clap(long)]
+        no_lint: bool,
+    },
+    /// Create a new script step by step
+    ///
+    /// Asks for the name, description, interpreter and tags of the script, then opens $EDITOR to
+    /// write it.
+    New {
+        /// The name the script will be referred to as. Asked for if not given.
+        name: Option<String>,
+        /// Don't check the script for problems before storing it
+        #[clap(long)]
+        no_lint: bool,
+    },
+    /// Modify the commands for the current tree
+    Mod {
+        /// Name of the script
+        name: String,
+        /// Add optional description for the command
+        desc: Option<String>,
+        /// Scripts that have to run successfully before this one, separated by commas
+        ///
+        /// `orun` runs them first, in order. Pass an empty string to remove the dependencies.
+        #[clap(long, value_delimiter = ',', num_args = 1)]
+        needs: Option<Vec<String>>,
+        /// Whether the script always runs with a minimal environment, like with `orun --clean-env`
+        #[clap(long, value_name = "BOOL")]
+        clean_env: Option<bool>,
+        /// The sandbox the script always runs in, like with `orun --sandbox`, or `none`
+        #[clap(long, value_name = "MODE", value_parser = ["none", "read-only", "hidden"])]
+        sandbox: Option<String>,
+        /// Image of a container (run with podman or docker) the script always runs in.
+        /// An empty image makes the script run on the host again.
+        #[clap(long, value_name = "IMAGE")]
+        container: Option<String>,
+        /// Tags of the script, separated by commas. Pass an empty string to remove them.
+        #[clap(long, value_delimiter = ',', num_args = 1)]
+        tags: Option<Vec<String>>,
+        /// A message shown when the script fails under `orun --check`, like how to fix common
+        /// problems. Pass an empty string to remove it.
+        #[clap(long, value_name = "MESSAGE")]
+        failure_hint: Option<String>,
+        /// Set an environment variable for every run of the script, e.g. `--env RUST_LOG=debug`.
+        /// Can be given multiple times.
+        #[clap(long, value_name = "KEY=VAL", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Stop setting an environment variable added with `--env`. Can be given multiple times.
+        #[clap(long, value_name = "KEY")]
+        unset_env: Vec<String>,
+        /// Protect the script against being updated, renamed, edited or removed without `--force`
+        #[clap(long)]
+        lock: bool,
+        /// Remove the protection added with `--lock`
+        #[clap(long, conflicts_with = "lock")]
+        unlock: bool,
+    },
+    /// Sign a script with GPG, so it can be verified before running
+    ///
+    /// The signature is stored along with the script. Updating the script removes it.
+    Sign {
+        /// Name of the script
+        name: String,
+        /// The key to sign with, instead of the default key of gpg
+        #[clap(long)]
+        key: Option<String>,
+        /// Remove the signature instead
+        #[clap(long, conflicts_with = "key")]
+        remove: bool,
+    },
+    /// Trust scripts that came from other trees, databases or URLs, so they run without asking
+    ///
+    /// Such scripts are shown before they run for the first time, and only run after confirming.
+    Trust {
+        /// Names of the scripts
+        #[clap(required = true)]
+        names: Vec<String>,
+    },
+    /// Show or set what happens when running a script without a valid signature on this device
+    ///
+    /// `ignore` doesn't check signatures, `warn` prints a warning, and `refuse` doesn't run the
+    /// script.
+    ///
+    /// Signatures are valid if gpg fully trusts the key that made them, or if its fingerprint is
+    /// in the `allowed-signers` file in the config directory.
+    SignaturePolicy {
+        #[clap(value_parser = PossibleValuesParser::new(Policy::NAMES))]
+        policy: Option<String>,
+    },
+    /// Remove scripts
+    ///
+    /// Asks for confirmation when removing more than one script, or when using glob patterns.
+    #[clap(alias = "rm")]
+    Remove {
+        /// Names of the scripts, or glob patterns like 'tmp-*'
+        #[clap(required = true)]
+        names: Vec<String>,
+        /// Don't ask for confirmation
+        #[clap(short = 'y', long)]
+        yes: bool,
+        /// Also remove locked scripts
+        #[clap(long)]
+        force: bool,
+    },
+    /// Put scripts away without removing them
+    ///
+    /// Archived scripts aren't listed and can't be run, until they're unarchived.
+    /// Without names, lists the archived scripts.
+    Archive {
+        /// Names of the scripts
+        names: Vec<String>,
+    },
+    /// Bring back archived scripts
+    Unarchive {
+        /// Names of the scripts
+        #[clap(required = true)]
+        names: Vec<String>,
+    },
+    /// Establish the current directory (or another directory) as a root
+    Establish {
+        /// The directory to establish. Defaults to the current directory.
+        path: Option<PathBuf>,
+        /// A short description of the tree, shown by list-trees
+        #[clap(long)]
+        desc: Option<String>,
+        /// Also write a `.otkeep` marker file, so the tree is found even after it's moved
+        #[clap(long)]
+        marker: bool,
+        /// Start the tree with a set of scripts
+        ///
+        /// Either the name of a bundled template (rust, go, node or python), or the root of
+        /// another tree to copy the scripts of.
+        #[clap(long)]
+        template: Option<String>,
+    },
+    /// Set up the current directory as a tree in one step
+    ///
+    /// Establishes it as a root unless it's in a tree already, offers the scripts of
+    /// `okeep suggest` and the files of `okeep suggest-save`, and adds the shell hook to the config
+    /// of your shell.
+    Init {
+        /// A short description of the tree, shown by list-trees
+        #[clap(long)]
+        desc: Option<String>,
+        /// Do all of it without asking
+        #[clap(short = 'y', long)]
+        yes: bool,
+        /// The shell to add the hook for. Defaults to the one in $SHELL.
+        #[clap(long, conflicts_with = "no_hook")]
+        shell: Option<ShellKind>,
+        /// Don't add the shell hook
+        #[clap(long)]
+        no_hook: bool,
+    },
+    /// Unestablish the current directory (or another directory) as a root
+    ///
+    /// When given a path, asks for confirmation first. The directory doesn't need to exist.
+    Unestablish {
+        /// The root to unestablish. Defaults to the current directory.
+        path: Option<PathBuf>,
+        /// Don't ask for confirmation
+        #[clap(short = 'y', long, requires = "path")]
+        yes: bool,
+    },
+    /// Reestablish (move) another root to the current directory
+    Reestablish {
+        /// The root to move
+        #[clap(required_unless_present = "detect")]
+        old_root: Option<PathBuf>,
+        /// Look for a root with a missing directory that matches the current directory
+        ///
+        /// Candidates are ranked by matching the `.otkeep` marker file, having the same directory
+        /// name, and by how many of their saved files exist in the current directory.
+        #[clap(long, conflicts_with = "old_root")]
+        detect: bool,
+    },
+    /// List all the trees kept in the database
+    ListTrees {
+        /// What to sort the trees by
+        #[clap(long, value_enum, default_value = "path")]
+        sort: TreeSort,
+        /// How to print the listing. Times are in seconds since the Unix epoch, and sizes in bytes.
+        #[clap(long, value_enum, default_value = "text")]
+        format: Format,
+        /// Only list the trees with this tag
+        #[clap(long)]
+        tag: Option<String>,
+    },
+    /// Run a command in the root of every tree, or of the trees with a tag
+    ///
+    /// Trees whose root doesn't exist on this machine are skipped. Exits with an error if the
+    /// command failed in any of the trees.
+    Foreach {
+        /// Only run in the trees with this tag
+        #[clap(long)]
+        tag: Option<String>,
+        /// Keep going after the command fails in a tree
+        #[clap(short = 'k', long)]
+        keep_going: bool,
+        /// The command to run, e.g. `okeep foreach --tag rust -- orun build`
+        #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<OsString>,
+    },
+    /// Check out a copy of a script as a file
+    Checkout {
+        /// Name of the script
+        name: String,
+    },
+    /// Concatenate a script to standard out
+    ///
+    /// Binary scripts aren't written to a terminal, unless as a hex dump with `--binary`.
+    Cat {
+        /// Name of the script
+        name: String,
+        /// Write a hex dump of the script
+        #[clap(long)]
+        binary: bool,
+    },
+    /// Update a script with new contents
+    Update {
+        /// The of the script to update
+        name: String,
+        /// A path to a source script or an inline script
+        ///
+        /// If `-`, the script is read from standard input.
+        script: String,
+        /// Add an inline script instead of loading from a file
+        #[clap(short = 'i', long = "inline")]
+        inline: bool,
+        /// Change the script even if it's locked
+        #[clap(long)]
+        force: bool,
+        /// Add a shebang for this interpreter to a script without one, like `python3`
+        #[clap(long, visible_alias = "shell", value_name = "PROGRAM")]
+        interpreter: Option<String>,
+        /// Don't check the script for problems before storing it
+        #[clap(long)]
+        no_lint: bool,
+        /// Store the script encrypted with gpg. Scripts that already are stay encrypted.
+        #[clap(long)]
+        encrypt: bool,
+    },
+    /// Rename a script
+    Rename {
+        /// The current name of the script
+        current: String,
+        /// The new name of the script
+        new: String,
+        /// Change the script even if it's locked
+        #[clap(long)]
+        force: bool,
+    },
+    /// Duplicate a script under a new name, along with its description and other metadata
+    Copy {
+        /// Name of the script to copy
+        name: String,
+        /// Name of the copy
+        new: String,
+    },
+    /// Save a file from the working tree
+    Save {
+        /// Path to the file
+        path: String,
+        /// Save the file even if git tracks it, or it goes over the size limits of the tree
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Store the file encrypted with gpg, for your own key. Files that already are stay
+        /// encrypted.
+        ///
+        /// It's decrypted whenever it's restored, which gpg-agent may ask a passphrase for.
+        #[clap(long)]
+        encrypt: bool,
+    },
+    /// Restore a saved file to the working tree
+    Restore {
+        /// Path to the file
+        path: Option<String>,
+        /// Restore all files of this bundle instead, see `okeep bundle`
+        #[clap(long, value_name = "NAME", conflicts_with = "path")]
+        bundle: Option<String>,
+        /// Don't ask before replacing a changed file that git tracks
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Restore this version of the file, see `okeep file-versions`
+        #[clap(long, value_name = "N", requires = "path")]
+        version: Option<i64>,
+    },
+    /// List the versions of a saved file, which `okeep restore <path> --version <N>` restores
+    ///
+    /// The former contents of a file are kept when it's saved again. Set how many versions are
+    /// kept with `okeep tree keep-versions`.
+    FileVersions {
+        /// Path of the saved file
+        path: String,
+    },
+    /// Offer to save the files under the current directory that git ignores, and that look like
+    /// local configuration, like `.env` files
+    ///
+    /// Ignored directories, binary files, logs and large files are skipped.
+    SuggestSave {
+        /// Also offer the other small text files that are ignored
+        #[clap(long)]
+        all: bool,
+        /// Save all of them without asking
+        #[clap(short = 'y', long)]
+        yes: bool,
+        /// Only list them
+        #[clap(long, conflicts_with = "yes")]
+        dry_run: bool,
+    },
+    /// Offer starter scripts like build, test, fmt and clean that fit the project in the tree root
+    ///
+    /// Looks at the Makefile, Cargo.toml, package.json and flake.nix of the root. Scripts that
+    /// already exist aren't offered.
+    Suggest {
+        /// Add all of them without asking
+        #[clap(short = 'y', long)]
+        yes: bool,
+        /// Only list them
+        #[clap(long, conflicts_with = "yes")]
+        dry_run: bool,
+    },
+    /// Clone a single script from a path
+    Cp {
+        /// Path to the tree
+        tree: PathBuf,
+        /// Name of the script
+        name: String,
+    },
+    /// Clone all scripts from another tree
+    Clone {
+        /// Path to the tree
+        tree: PathBuf,
+        /// Only clone scripts with names matching this glob pattern, e.g. 'db/*'
+        #[clap(long)]
+        only: Option<String>,
+        /// Don't clone scripts with names matching this glob pattern
+        #[clap(long)]
+        except: Option<String>,
+        /// Keep scripts that already exist in the current tree
+        #[clap(long, group = "conflict")]
+        skip_existing: bool,
+        /// Overwrite scripts that already exist in the current tree
+        #[clap(long, group = "conflict")]
+        overwrite: bool,
+        /// Ask what to do with each script that already exists in the current tree
+        #[clap(short = 'i', long, group = "conflict")]
+        interactive: bool,
+        /// Also clone the saved files
+        #[clap(long)]
+        files: bool,
+        /// Also overwrite scripts that are locked in the current tree
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+    /// Compare the scripts of two trees
+    ///
+    /// Lists the scripts that only exist in one of the trees, and the ones whose contents differ.
+    DiffTrees {
+        /// Path to the first tree
+        a: PathBuf,
+        /// Path to the second tree
+        b: PathBuf,
+        /// Also show the differences in the contents of the scripts
+        #[clap(short = 'p', long)]
+        patch: bool,
+    },
+    /// List the scripts of the current tree, or its saved files with --files
+    List {
+        /// List the saved files instead of the scripts
+        #[clap(long)]
+        files: bool,
+        /// What to sort by. Sizes and times are sorted largest and most recent first.
+        #[clap(
+            long,
+            default_value = "name",
+            value_parser = PossibleValuesParser::new(ItemSort::NAMES)
+        )]
+        sort: String,
+        /// Only list the items with names matching this glob pattern, e.g. 'db/*'
+        #[clap(long, value_name = "GLOB")]
+        filter: Option<String>,
+        /// List at most this many items
+        #[clap(long, value_name = "N")]
+        limit: Option<u64>,
+        /// How to print the listing. Times are in seconds since the Unix epoch, and sizes in bytes.
+        #[clap(long, value_enum, default_value = "text")]
+        format: Format,
+    },
+    /// List scripts from a tree
+    ListScripts {
+        /// Path to the tree
+        tree: PathBuf,
+    },
+    /// Edit a script. Uses editor from $EDITOR env var.
+    Edit {
+        /// Name of the script
+        name: String,
+        /// Change the script even if it's locked
+        #[clap(long)]
+        force: bool,
+        /// Don't check the script for problems before storing it
+        #[clap(long)]
+        no_lint: bool,
+    },
+    /// Interactively remove unused things
+    #[clap(subcommand)]
+    Prune(PruneSubCmd),
+    /// Delete the old run history, captured output and file versions of the current tree, then
+    /// shrink the database file
+    ///
+    /// Without options, everything is deleted.
+    PurgeHistory {
+        /// Only delete what's older than this, like `90d`, `12w` or `36h`
+        #[clap(long, value_parser = parse_age, value_name = "AGE")]
+        older_than: Option<i64>,
+        /// Only delete the runs of this script
+        #[clap(long, value_name = "NAME")]
+        script: Option<String>,
+        /// Only delete the former versions of this saved file
+        #[clap(long, value_name = "PATH")]
+        file: Option<String>,
+        /// Keep the runs, and only delete the output captured with `orun --log`
+        #[clap(long, conflicts_with = "file")]
+        logs_only: bool,
+        /// Don't ask for confirmation
+        #[clap(short = 'y', long)]
+        yes: bool,
+    },
+    /// Delete the blobs removed by `okeep prune blobs` for good, and shrink the database file
+    ///
+    /// Pruning only empties blobs, which leaves the database file the same size.
+    Vacuum,
+    /// Run a script, like orun
+    Run {
+        /// Name of the script
+        name: String,
+        /// Arguments passed to the script. Use `--` to pass arguments starting with `-`.
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Show the output of runs of a script that were logged with `orun --log`
+    ///
+    /// Lists the runs of the script, or prints the output of one of them.
+    Logs {
+        /// Name of the script
+        name: String,
+        /// Number of the run to print the output of, as listed
+        run: Option<i64>,
+        /// How to print the list of runs. Times are in seconds since the Unix epoch, and sizes in
+        /// bytes.
+        #[clap(long, value_enum, default_value = "text", conflicts_with = "run")]
+        format: Format,
+    },
+    /// Print the output and exit code of the most recent run of a script
+    ///
+    /// The output is only available for runs logged with `orun --log`.
+    Last {
+        /// Name of the script. If not given, the most recent run of any script is shown.
+        name: Option<String>,
+    },
+    /// Show how long the runs of a script took over time
+    ///
+    /// Runs are recorded when running scripts with otrun or `orun --log`.
+    /// Without a name, shows how much space the items of the current tree take, and which ones
+    /// are over its size limits.
+    Stats {
+        /// Name of the script
+        name: Option<String>,
+    },
+    /// Run several scripts one after another, and print a summary of how they went
+    ///
+    /// Stops at the first script that fails, unless `--keep-going` is given.
+    RunSeq {
+        /// Names of the scripts, in the order they are run
+        #[clap(required = true)]
+        names: Vec<String>,
+        /// Keep running the remaining scripts after one fails
+        #[clap(short = 'k', long)]
+        keep_going: bool,
+    },
+    /// Re-fetch scripts from the URL or path they were added from, and update them
+    ///
+    /// Shows the changes and asks for confirmation before updating each script.
+    Refresh {
+        /// Name of the script
+        #[clap(required_unless_present = "all")]
+        name: Option<String>,
+        /// Refresh all scripts that have a recorded origin
+        #[clap(long, conflicts_with = "name")]
+        all: bool,
+        /// Refresh locked scripts too
+        #[clap(long)]
+        force: bool,
+    },
+    /// Spawn $SHELL with the scripts of the current tree available as commands
+    Shell,
+    /// Manage directories of wrapper executables for the scripts of the current tree
+    #[clap(subcommand)]
+    Shims(ShimsSubCmd),
+    /// Print shell code that shows the available scripts when entering a tree
+    ///
+    /// It also completes script names, and offers to run a script of the current tree when a
+    /// command of the same name isn't found, which `OTKEEP_NOT_FOUND=run` or `off` changes.
+    ///
+    /// Add `eval "$(okeep hook bash)"` to your .bashrc, `eval "$(okeep hook zsh)"` to your .zshrc,
+    /// or `okeep hook fish | source` to your config.fish.
+    Hook {
+        /// The shell to print the code for
+        shell: ShellKind,
+    },
+    /// Choose a script of the current tree, and print its name
+    ///
+    /// Uses fzf if it's installed, and a numbered list otherwise. Used by the key binding of the
+    /// shell integration, which inserts `orun <script>` on the command line.
+    Pick,
+    /// Print a short summary of the current tree for the shell hook
+    #[clap(hide = true)]
+    HookSummary,
+    /// Print a short indicator of the current tree, for use in shell prompts
+    ///
+    /// Prints the name of the tree and the number of scripts, or nothing outside of trees.
+    Prompt,
+    /// Export the scripts of the current tree in other formats
+    #[clap(subcommand)]
+    Export(ExportSubCmd),
+    /// Manage templates that new scripts can start from, with `okeep add --template`
+    ///
+    /// Templates are shared by all trees. `{{name}}` in a template is replaced with the name of
+    /// the new script, and `{{tree_root}}` with the root of the tree.
+    #[clap(subcommand)]
+    Template(TemplateSubCmd),
+    /// Manage the current tree
+    #[clap(subcommand)]
+    Tree(TreeSubCmd),
+    /// Manage named sets of saved files, which `okeep restore --bundle` restores together
+    #[clap(subcommand)]
+    Bundle(BundleSubCmd),
+    /// Show information about the current tree
+    Info,
+    /// Show the details of a script, like its description, tags and when it was changed
+    Show {
+        /// Name of the script, or path of the saved file with --file
+        name: String,
+        /// Show a saved file instead of a script
+        #[clap(long)]
+        file: bool,
+    },
+    /// Check that the scripts and saved files of the current tree weren't changed unexpectedly
+    ///
+    /// Compares their contents with the SHA-256 hashes recorded when they were stored, to detect
+    /// corruption or edits made to the database by other programs.
+    Verify {
+        /// Check the items of all trees
+        #[clap(long)]
+        all: bool,
+    },
+    /// Print the root of the tree a path belongs to
+    Owner {
+        /// The path to look up
+        path: PathBuf,
+        /// Also print the roots of the trees that contain the owning tree, innermost first
+        #[clap(long)]
+        all: bool,
+    },
+    /// Manage the database
+    #[clap(subcommand)]
+    Db(DbSubCmd),
+    /// Manage the anchors of this device
+    ///
+    /// Roots inside of an anchor are stored relative to it, so the database can be shared between
+    /// machines where the anchor directory has a different path, like the home directory.
+    #[clap(subcommand)]
+    Anchor(AnchorSubCmd),
+    /// Run scripts of the current tree periodically, with systemd user timers or cron
+    ///
+    /// Scheduled runs use `otrun --log`, so their output can be viewed with `okeep logs`.
+    #[clap(subcommand)]
+    Schedule(ScheduleSubCmd),
+}
+
+impl Sub {
+    /// Whether the command changes the database, so it can't be used when it's read-only
+    fn writes_db(&self) -> bool {
+        if let Sub::Archive { names } = self {
+            // Lists the archived scripts
+            return !names.is_empty();
+        }
+        !matches!(
+            self,
+            Sub::SignaturePolicy { .. }
+                | Sub::ListTrees { .. }
+                | Sub::Foreach { .. }
+                | Sub::Checkout { .. }
+                | Sub::Cat { .. }
+                | Sub::Restore { .. }
+                | Sub::FileVersions { .. }
+                | Sub::SuggestSave { dry_run: true, .. }
+                | Sub::Suggest { dry_run: true, .. }
+                | Sub::DiffTrees { .. }
+                | Sub::List { .. }
+                | Sub::ListScripts { .. }
+                | Sub::Run { .. }
+                | Sub::Logs { .. }
+                | Sub::Last { .. }
+                | Sub::Stats { .. }
+                | Sub::RunSeq { .. }
+                | Sub::Shell
+                | Sub::Shims(ShimsSubCmd::List)
+                | Sub::Prune(PruneSubCmd::Scripts { dry_run: true, .. })
+                | Sub::Hook { .. }
+                | Sub::HookSummary
+                | Sub::Pick
+                | Sub::Prompt
+                | Sub::Export(_)
+                | Sub::Template(TemplateSubCmd::List | TemplateSubCmd::Show { .. })
+                | Sub::Bundle(BundleSubCmd::List)
+                | Sub::Tree(
+                    TreeSubCmd::Marker
+                        | TreeSubCmd::Desc {
+                            desc: None,
+                            clear: false
+                        }
+                        | TreeSubCmd::DevShell { kind: None }
+                        | TreeSubCmd::SizeLimits {
+                            item: None,
+                            total: None
+                        }
+                        | TreeSubCmd::KeepVersions {
+                            count: None,
+                            default: false
+                        }
+                        | TreeSubCmd::Tag(TreeTagSubCmd::List)
+                )
+                | Sub::Info
+                | Sub::Show { .. }
+                | Sub::Verify { .. }
+                | Sub::Owner { .. }
+                | Sub::Db(DbSubCmd::ExportTree { .. })
+                | Sub::Anchor(AnchorSubCmd::List)
+                | Sub::Schedule(_)
+        )
+    }
+}
+
+#[derive(Subcommand)]
+enum ScheduleSubCmd {
+    /// Schedule a script to run periodically
+    Add {
+        /// Name of the script
+        name: String,
+        /// When to run the script, as a systemd calendar expression like 'daily' or 'Mon 09:00'
+        #[clap(long, required_unless_present = "cron")]
+        calendar: Option<String>,
+        /// Use a cron job instead of a systemd timer, running on this cron schedule, e.g. '0 3 * * *'
+        #[clap(long, conflicts_with = "calendar")]
+        cron: Option<String>,
+    },
+    /// List the scheduled scripts of the current tree
+    List,
+    /// Stop running a script periodically
+    Remove {
+        /// Name of the script
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbSubCmd {
+    /// Merge the trees, scripts and saved files of another database file into this one
+    ///
+    /// Trees are matched by their UUIDs, or their root paths. Items that exist in both databases
+    /// with the same contents are left alone.
+    Import {
+        /// Path to the other otkeep.sqlite3
+        path: PathBuf,
+        /// Only show what would be imported
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+        /// Keep items that already exist with different contents
+        #[clap(long, group = "conflict")]
+        skip_existing: bool,
+        /// Overwrite items that already exist with different contents
+        #[clap(long, group = "conflict")]
+        overwrite: bool,
+        /// Ask what to do with each item that already exists with different contents
+        #[clap(short = 'i', long, group = "conflict")]
+        interactive: bool,
+        /// Also overwrite scripts that are locked in this database
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+    /// Write the current tree into a new database file, e.g. to share its scripts with others
+    ExportTree {
+        /// Path of the database file to create
+        dest: PathBuf,
+        /// Overwrite the destination if it exists
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Export all the trees with this tag instead of the current tree
+        #[clap(long)]
+        tag: Option<String>,
+    },
+    /// Encrypt the database with a passphrase, or change the passphrase if it's already encrypted
+    ///
+    /// Needs OtKeep to be built with the `sqlcipher` feature. The passphrase is asked for whenever
+    /// the database is opened, unless it's stored in the keyring, or set in OTKEEP_PASSPHRASE.
+    Encrypt {
+        /// Store the passphrase in the keyring, with secret-tool
+        #[clap(long)]
+        keyring: bool,
+    },
+    /// Decrypt the database, so it no longer needs a passphrase
+    Decrypt,
+}
+
+#[derive(Subcommand)]
+enum AnchorSubCmd {
+    /// Add an anchor, or change the path of an existing one
+    ///
+    /// Existing roots inside of the anchor will be stored relative to it.
+    Add {
+        /// Name of the anchor. Use the same name on every device.
+        name: String,
+        /// The directory this anchor points to on this device
+        path: PathBuf,
+    },
+    /// Remove an anchor
+    Remove {
+        /// Name of the anchor
+        name: String,
+    },
+    /// List the anchors of this device
+    List,
+}
+
+#[derive(Subcommand)]
+enum TreeSubCmd {
+    /// Write a `.otkeep` marker file into the root, so the tree is found even after it's moved
+    ///
+    /// The marker contains the UUID of the tree. Add it to your global gitignore to keep it out of
+    /// commits.
+    Marker,
+    /// Show or set the description of the current tree
+    Desc {
+        /// The new description
+        desc: Option<String>,
+        /// Remove the description
+        #[clap(long, conflicts_with = "desc")]
+        clear: bool,
+    },
+    /// Show or set the nix development environment the scripts of the current tree run in
+    ///
+    /// With `develop`, scripts run through `nix develop <root> -c`, and with `shell` through
+    /// `nix-shell <root> --run`. `none` makes them run directly again.
+    DevShell {
+        #[clap(value_parser = ["develop", "shell", "none"])]
+        kind: Option<String>,
+    },
+    /// Show or set how large the scripts and saved files of the current tree may get
+    ///
+    /// Sizes are in bytes, or with a unit like `512K`, `10M` or `1G`. `none` turns a limit off.
+    /// Saving a file over a limit needs `--force`, and adding a script over one prints a warning.
+    SizeLimits {
+        /// Largest size of a single script or saved file
+        #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+        item: Option<u64>,
+        /// Largest total size of the scripts, saved files and file versions
+        #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+        total: Option<u64>,
+    },
+    /// Show or set how many former versions of each saved file the current tree keeps
+    KeepVersions {
+        /// The number of versions. Lowering it drops the oldest versions.
+        count: Option<u32>,
+        /// Go back to the default
+        #[clap(long, conflicts_with = "count")]
+        default: bool,
+    },
+    /// Manage the tags of the current tree, which `list-trees`, `foreach` and `db export-tree`
+    /// can select trees by
+    #[clap(subcommand)]
+    Tag(TreeTagSubCmd),
+}
+
+#[derive(Subcommand)]
+enum TreeTagSubCmd {
+    /// Tag the current tree
+    Add {
+        /// The tags, separated by spaces or commas
+        #[clap(required = true, value_delimiter = ',')]
+        tags: Vec<String>,
+    },
+    /// Remove tags from the current tree
+    Remove {
+        #[clap(required = true, value_delimiter = ',')]
+        tags: Vec<String>,
+    },
+    /// List the tags of the current tree
+    List,
+}
+
+#[derive(Subcommand)]
+enum ExportSubCmd {
+    /// Print shell aliases that invoke orun for each script
+    Aliases {
+        /// The shell dialect to print the aliases in
+        #[clap(long, default_value = "bash")]
+        shell: ShellKind,
+        /// Prefix to prepend to the alias names, e.g. `o-`
+        #[clap(long)]
+        prefix: Option<String>,
+    },
+    /// Write a shell script containing all scripts of the tree, for machines without OtKeep
+    ///
+    /// `./bundle.sh NAME ARGS...` runs a script, and the bundle lists the scripts when run
+    /// without arguments. Sandboxes, containers and dev shells aren't applied by the bundle.
+    Bundle {
+        /// Path of the bundle to write
+        path: PathBuf,
+        /// Overwrite the file if it exists
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+    /// Print a `.pre-commit-config.yaml` with a local hook running `orun <script>` for each script
+    /// with the tag
+    ///
+    /// The hooks don't get the names of the changed files, and run on every commit.
+    PreCommit {
+        /// The tag of the scripts to make hooks of
+        #[clap(long, default_value = "hook")]
+        tag: String,
+    },
+    /// Add an alias running `orun <script>` for each script to the `.cargo/config.toml` of the root
+    ///
+    /// `cargo <script>` then runs the script. The aliases go through the `cargo-orun` link that
+    /// `otkeep --install-links` makes, which has to be in PATH. Scripts named like built-in cargo
+    /// commands are skipped.
+    CargoAliases {
+        /// Prefix to prepend to the alias names, e.g. `o-`
+        #[clap(long)]
+        prefix: Option<String>,
+        /// Replace aliases of the same names that run something else
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TreeSort {
+    /// Root path
+    Path,
+    /// Name of the root directory
+    Name,
+    /// Number of scripts, most first
+    Scripts,
+    /// Number of saved files, most first
+    Files,
+    /// Total size of the scripts and files, largest first
+    Size,
+    /// Last time a script was run, most recent first
+    Used,
+}
+
+#[derive(Subcommand)]
+enum TemplateSubCmd {
+    /// Add a template
+    Add {
+        /// Name of the template
+        name: String,
+        /// A path to the template or an inline template
+        ///
+        /// If `-`, the template is read from standard input.
+        /// If not provided, $EDITOR will open to edit a new template
+        body: Option<String>,
+        /// Add an inline template instead of loading from a file
+        #[clap(short = 'i', long = "inline")]
+        inline: bool,
+        /// Overwrite the template if it already exists
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+    /// List the templates
+    List,
+    /// Print a template
+    Show {
+        /// Name of the template
+        name: String,
+    },
+    /// Remove a template
+    Remove {
+        /// Name of the template
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum BundleSubCmd {
+    /// Create a bundle of saved files
+    ///
+    /// Files that aren't saved yet are saved from the working tree.
+    Create {
+        /// Name of the bundle
+        name: String,
+        /// Paths of the files
+        #[clap(required = true)]
+        files: Vec<String>,
+        /// Replace the bundle if it already exists, and save files even if git tracks them
+        #[clap(short = 'f', long)]
+        force: bool,
+    },
+    /// List the bundles of the current tree
+    List,
+    /// Remove a bundle. Its files stay saved.
+    Remove {
+        /// Name of the bundle
+        name: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Subcommand)]
+enum PruneSubCmd {
+    /// Interactively remove old trees that don't exist on the filesystem
+    Trees,
+    /// Interactively remove old blobs that aren't referenced by any trees
+    Blobs,
+    /// Interactively archive or remove scripts of the current tree that haven't been run lately
+    Scripts {
+        /// How long a script has to be unused, like `90d`, `12w` or `36h`
+        #[clap(long, value_parser = parse_age)]
+        unused_for: i64,
+        /// Only list the scripts, without changing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+/// Parses a size like `512K`, `10M` or `1G` into bytes. `none` is 0.
+fn parse_size(s: &str) -> Result<u64, String> {
+    if s == "none" {
+        return Ok(0);
+    }
+    let unit_pos = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(unit_pos);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("'{s}' doesn't start with a number"))?;
+    let unit_bytes = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown unit '{unit}'. Use one of K, M or G")),
+    };
+    Ok(amount * unit_bytes)
+}
+
+/// Parses an amount of time like `180d` into seconds
+fn parse_age(s: &str) -> Result<i64, String> {
+    let unit_pos = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(unit_pos);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("'{s}' doesn't start with a number"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit '{unit}'. Use one of s, m, h, d or w")),
+    };
+    Ok(amount * unit_secs)
+}
+
+#[derive(Subcommand)]
+enum ShimsSubCmd {
+    /// Write shims for all scripts into a directory, and keep them up to date
+    Install {
+        /// The directory to write the shims into
+        dir: PathBuf,
+    },
+    /// Remove the shims from a directory, and stop keeping them up to date
+    Uninstall {
+        /// The directory containing the shims
+        dir: PathBuf,
+    },
+    /// List the shim directories of the current tree
+    List,
+}
+
+pub fn main(args: impl Iterator<Item = OsString>) -> anyhow::Result<()> {
+    let args = Args::parse_from(args);
+    // Runs on every prompt, so it avoids even opening the database if it can
+    if let Some(Sub::Prompt) = args.subcommand {
+        return cmd::prompt();
+    }
+    // Runs on every cd, so it never asks for the passphrase
+    if let Some(Sub::HookSummary) = args.subcommand {
+        return cmd::hook_summary();
+    }
+    let mut db = otkeep::load_db(args.read_only)?;
+    if let Some(sub) = &args.subcommand {
+        if db.is_read_only() && sub.writes_db() {
+            bail!("The database is opened read-only, so this command can't be used");
+        }
+    }
+    let opt_root = crate::find_root(&db)?;
+    let Some(subcommand) = args.subcommand else {
+        match opt_root {
+            Some(root) => {
+                let ctx = &AppContext {
+                    db,
+                    root_id: root.0,
+                };
+                otkeep::list_scripts(ctx)?;
+                println!();
+                otkeep::list_files(ctx)?;
+                help_msg();
+                return Ok(());
+            }
+            None => {
+                eprintln!("The following trees are available:");
+                cmd::list_trees(&db, TreeSort::Path, Format::Text, None)?;
+                help_msg();
+                return Ok(());
+            }
+        }
+    };
+
+    match subcommand {
+        Sub::ListTrees {
+            sort,
+            format,
+            ref tag,
+        } => {
+            cmd::list_trees(&db, sort, format, tag.as_deref())?;
+            return Ok(());
+        }
+        Sub::Foreach {
+            ref tag,
+            keep_going,
+            ref command,
+        } => {
+            cmd::foreach(&db, tag.as_deref(), keep_going, command)?;
+            return Ok(());
+        }
+        Sub::Db(DbSubCmd::ExportTree {
+            ref dest,
+            force,
+            tag: Some(ref tag),
+        }) => {
+            cmd::db_export_tagged(&db, tag, dest, force).context("Export failed")?;
+            return Ok(());
+        }
+        Sub::Establish {
+            ref path,
+            ref desc,
+            marker,
+            ref template,
+        } => {
+            let template = template
+                .as_deref()
+                .map(|template| cmd::resolve_tree_template(&db, template))
+                .transpose()?;
+            let path = cmd::establish(&db, path.as_deref(), desc.as_deref())
+                .context("Failed to establish OtKeep root")?;
+            let id = db.query_tree(&path)?.context("Missing tree")?;
+            if marker {
+                otkeep::marker::write(&path, &db.tree_uuid(id)?)
+                    .context("Failed to write marker file")?;
+            }
+            eprintln!("Established {}", path.display());
+            if let Some(template) = template {
+                cmd::apply_tree_template(&mut db, id, template)
+                    .context("Failed to add the scripts of the template")?;
+            }
+            return Ok(());
+        }
+        Sub::Init {
+            ref desc,
+            yes,
+            shell,
+            no_hook,
+        } => {
+            let (root_id, root_path) = match opt_root {
+                Some(root) => {
+                    eprintln!("{} is a tree already", root.1.display());
+                    root
+                }
+                None => {
+                    let path = cmd::establish(&db, None, desc.as_deref())
+                        .context("Failed to establish OtKeep root")?;
+                    eprintln!("Established {}", path.display());
+                    (db.query_tree(&path)?.context("Missing tree")?, path)
+                }
+            };
+            let mut app = AppContext { db, root_id };
+            cmd::suggest(&mut app, &root_path, yes, false).context("Failed to suggest scripts")?;
+            if let Err(e) = cmd::suggest_save(&mut app, false, yes, false) {
+                eprintln!("Skipped looking for files to save: {e:#}");
+            }
+            if !no_hook {
+                cmd::install_hook(shell, yes).context("Failed to add the shell hook")?;
+            }
+            return Ok(());
+        }
+        Sub::Unestablish {
+            path: Some(ref path),
+            yes,
+        } => {
+            cmd::unestablish_path(&mut db, path, yes)
+                .context("Failed to unestablish OtKeep root")?;
+            return Ok(());
+        }
+        Sub::Hook { shell } => {
+            print!("{}", cmd::hook_script(shell));
+            return Ok(());
+        }
+        Sub::DiffTrees {
+            ref a,
+            ref b,
+            patch,
+        } => {
+            cmd::diff_trees(&db, a, b, patch).context("Diff failed")?;
+            return Ok(());
+        }
+        Sub::Db(DbSubCmd::Encrypt { keyring }) => {
+            cmd::db_encrypt(db, keyring).context("Encryption failed")?;
+            return Ok(());
+        }
+        Sub::Db(DbSubCmd::Decrypt) => {
+            cmd::db_decrypt(db).context("Decryption failed")?;
+            return Ok(());
+        }
+        Sub::Db(DbSubCmd::Import {
+            ref path,
+            dry_run,
+            skip_existing,
+            overwrite,
+            interactive,
+            force,
+        }) => {
+            let policy = cmd::ConflictPolicy::from_flags(skip_existing, overwrite, interactive);
+            cmd::db_import(&mut db, path, dry_run, force, policy).context("Import failed")?;
+            return Ok(());
+        }
+        Sub::Owner { ref path, all } => {
+            if !cmd::owner(&db, path, all)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Sub::Anchor(ref anchor_cmd) => {
+            cmd::anchor(&mut db, anchor_cmd)?;
+            return Ok(());
+        }
+        Sub::Verify { all: true } => {
+            cmd::verify(&db, None)?;
+            return Ok(());
+        }
+        Sub::Vacuum => {
+            cmd::vacuum(&db).context("Vacuum failed")?;
+            return Ok(());
+        }
+        Sub::Template(ref template_cmd) => {
+            cmd::template(&db, template_cmd)?;
+            return Ok(());
+        }
+        Sub::SignaturePolicy { ref policy } => {
+            cmd::signature_policy(&db, policy.as_deref())?;
+            return Ok(());
+        }
+        Sub::Reestablish { detect: true, .. } => {
+            cmd::reestablish_detect(&db).context("Failed to reestablish OtKeep root")?;
+            return Ok(());
+        }
+        Sub::Reestablish {
+            old_root: Some(ref old_root),
+            ..
+        } => {
+            cmd::reestablish(&db, old_root).context("Failed to reestablish OtKeep root")?;
+            eprintln!(
+                "Reestablished {} as {}",
+                old_root.display(),
+                std::env::current_dir()?.display()
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let (root_id, root_path) = match opt_root {
+        Some(root) => root,
+        None if matches!(subcommand, Sub::Add { establish, .. }
+            if establish || cmd::offer_establish()?) =>
+        {
+            let path =
+                cmd::establish(&db, None, None).context("Failed to establish OtKeep root")?;
+            eprintln!("Established {}", path.display());
+            (db.query_tree(&path)?.context("Missing tree")?, path)
+        }
+        None => {
+            otkeep::print_established_trees(&db)?;
+            bail!("No OtKeep tree root was found. To establish one, use okeep establish");
+        }
+    };
+
+    let mut app = AppContext { db, root_id };
+    let changes_script_set = matches!(
+        subcommand,
+        Sub::Add { .. }
+            | Sub::AddMany { .. }
+            | Sub::AddFromHistory { .. }
+            | Sub::New { .. }
+            | Sub::Remove { .. }
+            | Sub::Rename { .. }
+            | Sub::Copy { .. }
+            | Sub::Cp { .. }
+            | Sub::Clone { .. }
+            | Sub::Archive { .. }
+            | Sub::Unarchive { .. }
+            | Sub::Prune(PruneSubCmd::Scripts { dry_run: false, .. })
+    );
+    match subcommand {
+        Sub::Add {
+            name,
+            script,
+            inline,
+            from_url,
+            max_size,
+            template,
+            force,
+            establish: _,
+            interpreter,
+            no_lint,
+            encrypt,
+        } => {
+            let result = if from_url {
+                let url = script.context("A URL is required with --from-url")?;
+                cmd::add_from_url(&mut app, &name, &url, max_size, force, encrypt)
+            } else if let Some(template) = template {
+                cmd::add_from_template(&mut app, &name, &template, force, !no_lint, encrypt)
+            } else {
+                let opts = cmd::AddOpts {
+                    inline,
+                    interpreter: interpreter.as_deref(),
+                    lint: !no_lint,
+                    encrypt,
+                };
+                cmd::add(&mut app, &name, script.as_deref(), force, opts)
+            };
+            result.context("Failed to add script")?
+        }
+        Sub::AddFromHistory {
+            name,
+            interactive,
+            command,
+            shell,
+            force,
+            no_lint,
+        } => cmd::add_from_history(
+            &mut app,
+            &name,
+            cmd::HistoryCommand {
+                command,
+                interactive,
+                shell,
+            },
+            force,
+            !no_lint,
+        )
+        .context("Failed to add script")?,
+        Sub::New { name, no_lint } => {
+            cmd::new(&mut app, name, !no_lint).context("Failed to add script")?
+        }
+        Sub::AddMany { paths, force } => {
+            cmd::add_many(&mut app, &paths, force).context("Failed to add scripts")?
+        }
+        Sub::Mod {
+            name,
+            desc,
+            needs,
+            clean_env,
+            sandbox,
+            container,
+            tags,
+            failure_hint,
+            env,
+            unset_env,
+            lock,
+            unlock,
+        } => cmd::mod_(
+            &mut app,
+            &name,
+            cmd::ModOpts {
+                desc: desc.as_deref(),
+                needs: needs.as_deref(),
+                clean_env,
+                sandbox: sandbox.as_deref(),
+                container: container.as_deref(),
+                tags: tags.as_deref(),
+                failure_hint: failure_hint.as_deref(),
+                env: &env,
+                unset_env: &unset_env,
+                locked: (lock || unlock).then_some(lock),
+            },
+        )
+        .context("Mod failed")?,
+        Sub::Trust { names } => {
+            for name in &names {
+                app.db.set_script_trusted(root_id, name, true)?;
+                eprintln!("Trusted {name}");
+            }
+        }
+        Sub::Archive { names } if names.is_empty() => {
+            let scripts = app.db.archived_scripts_for_tree(root_id)?;
+            if scripts.is_empty() {
+                eprintln!("No scripts have been archived.");
+            }
+            for script in scripts {
+                eprintln!("{}", script.name);
+            }
+        }
+        Sub::Archive { names } => {
+            for name in &names {
+                app.db.set_script_archived(root_id, name, true)?;
+                eprintln!("Archived {name}");
+            }
+        }
+        Sub::Unarchive { names } => {
+            for name in &names {
+                app.db.set_script_archived(root_id, name, false)?;
+                eprintln!("Unarchived {name}");
+            }
+        }
+        Sub::Sign { name, key, remove } => {
+            cmd::sign(&app, &name, key.as_deref(), remove).context("Signing failed")?
+        }
+        Sub::Remove { names, yes, force } => {
+            cmd::remove(&mut app, &names, yes, force).context("Failed to remove script")?
+        }
+        Sub::Establish { .. } | Sub::Reestablish { .. } | Sub::Init { .. } => unreachable!(),
+        Sub::Unestablish { path: Some(_), .. } => unreachable!(),
+        Sub::Unestablish { path: None, .. } => {
+            if std::env::current_dir()? != root_path {
+                eprintln!("The current directory is not the root.");
+                eprintln!("Go to {}", root_path.display());
+                eprintln!("Then run this command again if you really want to unestablish");
+                return Ok(());
+            }
+            cmd::unestablish(&mut app.db, app.root_id)
+                .context("Failed to unestablish current directory")?;
+            eprintln!("Unestablished {}", root_path.display());
+        }
+        Sub::ListTrees { .. }
+        | Sub::Foreach { .. }
+        | Sub::Hook { .. }
+        | Sub::HookSummary
+        | Sub::Prompt
+        | Sub::DiffTrees { .. }
+        | Sub::Anchor(_)
+        | Sub::Owner { .. }
+        | Sub::SignaturePolicy { .. }
+        | Sub::Verify { all: true }
+        | Sub::Vacuum
+        | Sub::Template(_)
+        | Sub::Db(
+            DbSubCmd::Import { .. }
+            | DbSubCmd::Encrypt { .. }
+            | DbSubCmd::Decrypt
+            | DbSubCmd::ExportTree { tag: Some(_), .. },
+        ) => {
+            unreachable!()
+        }
+        Sub::Db(DbSubCmd::ExportTree {
+            dest,
+            force,
+            tag: None,
+        }) => {
+            let roots = app
+                .db
+                .get_tree_roots()?
+                .into_iter()
+                .filter(|root| root.id == root_id)
+                .collect();
+            cmd::db_export_trees(&app.db, roots, &dest, force).context("Export failed")?;
+            eprintln!("Exported {} to {}", root_path.display(), dest.display());
+        }
+        Sub::Checkout { name } => cmd::checkout(&mut app, &name).context("Checkout failed")?,
+        Sub::Cat { name, binary } => cmd::cat(&app, &name, binary).context("Cat failed")?,
+        Sub::Update {
+            name,
+            script,
+            inline,
+            force,
+            interpreter,
+            no_lint,
+            encrypt,
+        } => {
+            cmd::check_unlocked(&app, &name, force).context("Update failed")?;
+            let opts = cmd::AddOpts {
+                inline,
+                interpreter: interpreter.as_deref(),
+                lint: !no_lint,
+                encrypt,
+            };
+            cmd::update(&mut app, &name, &script, opts).context("Update failed")?
+        }
+        Sub::Rename {
+            current,
+            new,
+            force,
+        } => {
+            cmd::check_unlocked(&app, &current, force).context("Failed to rename script")?;
+            cmd::rename(&mut app, &current, &new).context("Failed to rename script")?
+        }
+        Sub::Copy { name, new } => app
+            .db
+            .copy_script(root_id, &name, &new)
+            .context("Failed to copy script")?,
+        Sub::Save {
+            path,
+            force,
+            encrypt,
+        } => {
+            if !cmd::save(&mut app, &path, force, encrypt).context("File save failed")? {
+                eprintln!("{path} is unchanged");
+            }
+        }
+        Sub::Restore {
+            path,
+            bundle,
+            force,
+            version,
+        } => match bundle {
+            Some(bundle) => {
+                cmd::restore_bundle(&mut app, &bundle, force).context("Bundle restore failed")?
+            }
+            None => cmd::restore(&mut app, path.as_deref(), version, force)
+                .context("File restore failed")?,
+        },
+        Sub::FileVersions { path } => cmd::file_versions(&app, &path)?,
+        Sub::Bundle(bundle_cmd) => cmd::bundle(&mut app, &bundle_cmd)?,
+        Sub::SuggestSave { all, yes, dry_run } => {
+            cmd::suggest_save(&mut app, all, yes, dry_run).context("Failed to suggest files")?
+        }
+        Sub::Suggest { yes, dry_run } => {
+            cmd::suggest(&mut app, &root_path, yes, dry_run).context("Failed to suggest scripts")?
+        }
+        Sub::Clone {
+            tree,
+            only,
+            except,
+            skip_existing,
+            overwrite,
+            interactive,
+            files,
+            force,
+        } => {
+            let policy = cmd::ConflictPolicy::from_flags(skip_existing, overwrite, interactive);
+            let opts = otkeep::database::CloneOpts {
+                only: only.as_deref(),
+                except: except.as_deref(),
+                with_files: files,
+                force,
+            };
+            cmd::clone(&mut app, &tree, opts, policy).context("Clone failed")?
+        }
+        Sub::ListScripts { tree } => {
+            match otkeep::find_root_for_path(&app.db, &tree)? {
+                Some((root_id, _)) => otkeep::list_scripts_for_tree(&app, root_id)?,
+                None => {
+                    eprintln!("No root found at the given location ({})", tree.display());
+                }
+            };
+        }
+        Sub::List {
+            files,
+            sort,
+            filter,
+            limit,
+            format,
+        } => {
+            let opts = ListOpts {
+                sort: sort.parse()?,
+                filter: filter.as_deref(),
+                limit,
+            };
+            cmd::list(&app, files, opts, format)?
+        }
+        Sub::Cp { tree, name } => match otkeep::find_root_for_path(&app.db, &tree)? {
+            Some((other_tree_id, _)) => {
+                app.db
+                    .copy_script_to(other_tree_id, &name, root_id, &name)?;
+                app.db.set_script_trusted(root_id, &name, false)?;
+            }
+            None => {
+                eprintln!("No root found at the given location ({})", tree.display());
+            }
+        },
+        Sub::Edit {
+            name,
+            force,
+            no_lint,
+        } => {
+            cmd::check_unlocked(&app, &name, force)?;
+            let Some(editor) = std::env::var_os("EDITOR") else {
+                eprintln!("$EDITOR env var needs to be set to edit");
+                return Ok(());
+            };
+            let blob = app.db.get_script_by_name(root_id, &name)?;
+            let dir = temp_dir::TempDir::new()?;
+            let filepath = dir.path().join("okeep-script.txt");
+            std::fs::write(&filepath, blob)?;
+            let blob = loop {
+                std::process::Command::new(&editor)
+                    .arg(&filepath)
+                    .status()?;
+                let blob = std::fs::read(&filepath)?;
+                if no_lint || cmd::check_script(&blob)? {
+                    break blob;
+                }
+                if !cmd::confirm("Edit the script again?")? {
+                    eprintln!("Discarded the changes to '{name}'");
+                    return Ok(());
+                }
+            };
+            app.db.update_script(root_id, &name, blob)?;
+        }
+        Sub::Run { name, args } => match cmd::run(&mut app, &name, &args) {
+            Err(e) => return Err(e),
+        },
+        Sub::Logs { name, run, format } => cmd::logs(&app, &name, run, format)?,
+        Sub::Last { name } => cmd::last(&app, name.as_deref())?,
+        Sub::Stats { name } => match name {
+            Some(name) => cmd::stats(&app, &name)?,
+            None => cmd::size_stats(&app)?,
+        },
+        Sub::RunSeq { names, keep_going } => {
+            let code = cmd::run_seq(&mut app, &names, keep_going)?;
+            std::process::exit(code);
+        }
+        Sub::Refresh {
+            name,
+            all: _,
+            force,
+        } => cmd::refresh(&mut app, name.as_deref(), force).context("Refresh failed")?,
+        Sub::Shell => cmd::shell(&app, &root_path).context("Failed to spawn shell")?,
+        Sub::Shims(ShimsSubCmd::Install { dir }) => {
+            cmd::shims_install(&app, &dir).context("Failed to install shims")?
+        }
+        Sub::Shims(ShimsSubCmd::Uninstall { dir }) => {
+            cmd::shims_uninstall(&app, &dir).context("Failed to uninstall shims")?
+        }
+        Sub::Shims(ShimsSubCmd::List) => {
+            for dir in app.db.shim_dirs_for_tree(root_id)? {
+                eprintln!("{}", dir.display());
+            }
+        }
+        Sub::Schedule(schedule_cmd) => cmd::schedule(&app, &root_path, &schedule_cmd)?,
+        Sub::Info => cmd::info(&app)?,
+        Sub::Show { name, file } => cmd::show(&app, &name, file)?,
+        Sub::Pick => {
+            if let Some(name) = cmd::pick(&app)? {
+                println!("{name}");
+            }
+        }
+        Sub::Verify { all: false } => cmd::verify(&app.db, Some(root_id))?,
+        Sub::Tree(TreeSubCmd::Marker) => {
+            otkeep::marker::write(&root_path, &app.db.tree_uuid(root_id)?)
+                .context("Failed to write marker file")?;
+            eprintln!(
+                "Wrote {}",
+                root_path.join(otkeep::marker::MARKER_FILENAME).display()
+            );
+        }
+        Sub::Tree(TreeSubCmd::Desc { desc, clear }) => {
+            cmd::tree_desc(&app, desc.as_deref(), clear)?
+        }
+        Sub::Tree(TreeSubCmd::DevShell { kind }) => cmd::tree_dev_shell(&app, kind.as_deref())?,
+        Sub::Tree(TreeSubCmd::SizeLimits { item, total }) => {
+            cmd::tree_size_limits(&app, item, total)?
+        }
+        Sub::Tree(TreeSubCmd::KeepVersions { count, default }) => {
+            cmd::tree_keep_versions(&mut app, count, default)?
+        }
+        Sub::Tree(TreeSubCmd::Tag(tag_cmd)) => cmd::tree_tag(&app, tag_cmd)?,
+        Sub::Export(ExportSubCmd::Aliases { shell, prefix }) => {
+            cmd::export_aliases(&app, shell, prefix.as_deref().unwrap_or_default())?
+        }
+        Sub::Export(ExportSubCmd::Bundle { path, force }) => {
+            cmd::export_bundle(&app, &path, force).context("Failed to export bundle")?
+        }
+        Sub::Export(ExportSubCmd::PreCommit { tag }) => cmd::export_pre_commit(&app, &tag)?,
+        Sub::Export(ExportSubCmd::CargoAliases { prefix, force }) => cmd::export_cargo_aliases(
+            &app,
+            &root_path,
+            prefix.as_deref().unwrap_or_default(),
+            force,
+        )
+        .context("Failed to export cargo aliases")?,
+        Sub::Prune(PruneSubCmd::Trees) => {
+            let mut any_was_stray = false;
+            for root in app.db.get_tree_roots()? {
+                if !root.path.exists() {
+                    any_was_stray = true;
+                    eprintln!("`{}` has the following scripts: ", root.path.display());
+                    for script in app.db.scripts_for_tree(root.id)? {
+                        eprintln!("{}", script.name);
+                    }
+                    let files = app.db.files_for_tree(root.id)?;
+                    if !files.is_empty() {
+                        eprintln!("... and following files: ");
+                        for file in files {
+                            eprintln!("{}", file.name);
+                        }
+                    }
+                    if cmd::confirm("Remove?")? {
+                        otkeep::shims::remove_all_shims(&app.db, root.id)?;
+                        app.db.remove_tree(root.id)?;
+                    }
+                }
+            }
+            if !any_was_stray {
+                eprintln!("No stray roots were detected.");
+            }
+        }
+        Sub::Prune(PruneSubCmd::Blobs) => cmd::prune_blobs(&app)?,
+        Sub::Prune(PruneSubCmd::Scripts {
+            unused_for,
+            dry_run,
+        }) => cmd::prune_scripts(&mut app, unused_for, dry_run)?,
+        Sub::PurgeHistory {
+            older_than,
+            script,
+            file,
+            logs_only,
+            yes,
+        } => {
+            let opts = PurgeOpts {
+                older_than,
+                script: script.as_deref(),
+                file: file.as_deref(),
+                logs_only,
+                dry_run: false,
+            };
+            cmd::purge_history(&mut app, opts, yes).context("Failed to purge history")?
+        }
+    }
+    if changes_script_set {
+        otkeep::shims::refresh_shims(&app.db, root_id).context("Failed to refresh shims")?;
+    }
+    Ok(())
+}
+
+fn help_msg() {
+    eprintln!("\nType okeep --help for help.");
+}
+
+mod cmd {
+    use {
+        super::{
+            AnchorSubCmd, BundleSubCmd, ScheduleSubCmd, ShellKind, TemplateSubCmd, TreeSort,
+            TreeTagSubCmd,
+        },
+        crate::{
+            format_duration,
+            table::{json_string, Format, Table},
+            tree_templates,
+        },
+        anyhow::{bail, Context},
+        otkeep::{
+            database::{
+                content_hash_reader, CloneOpts, ConflictResolution, Database, ItemKind, ListOpts,
+                PurgeOpts, PurgeReport, TreeRootInfo,
+            },
+            format_size,
+            progress::Progress,
+            sandbox::Sandbox,
+            schedule,
+            shims::shell_quote,
+            signing::{self, Policy},
+            AppContext,
+        },
+        owo_colors::{OwoColorize, Style},
+        std::{
+            collections::{BTreeSet, HashSet},
+            ffi::OsString,
+            fs::File,
+            io::{BufReader, IsTerminal, Read, Write},
+            os::unix::fs::PermissionsExt,
+            path::{Path, PathBuf},
+            process::ChildStdin,
+            time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+        },
+    };
+
+    /// How much of a blob `prune blobs` reads to preview it
+    const PREVIEW_BYTES: u64 = 16 * 1024;
+
+    /// How `add` and `update` store a script
+    pub(crate) struct AddOpts<'a> {
+        pub inline: bool,
+        /// Interpreter to add a shebang for
+        pub interpreter: Option<&'a str>,
+        /// Whether to check the script for problems first
+        pub lint: bool,
+        /// Whether to store the script encrypted with gpg
+        pub encrypt: bool,
+    }
+
+    pub(crate) fn add(
+        ctx: &mut AppContext,
+        name: &str,
+        script: Option<&str>,
+        force: bool,
+        opts: AddOpts,
+    ) -> anyhow::Result<()> {
+        check_add_conflict(ctx, name, force)?;
+        let mut inline = opts.inline;
+        let script_buf;
+        let script = match script {
+            Some(s) => s,
+            None => {
+                inline = true;
+                script_buf = edit_new()?;
+                &script_buf
+            }
+        };
+        let (mut script_body, origin) = read_script_body(script, inline)?;
+        if let Some(interpreter) = opts.interpreter {
+            add_shebang(&mut script_body, interpreter)?;
+        }
+        if opts.lint && !check_script(&script_body)? {
+            eprintln!("Not adding '{name}'");
+            return Ok(());
+        }
+        store_script(ctx, name, script_body, opts.encrypt)?;
+        if let Some(origin) = origin {
+            ctx.db.set_script_origin(ctx.root_id, name, &origin)?;
+        }
+        Ok(())
+    }
+    /// Opens `$EDITOR` on an empty file, and returns what was written into it
+    fn edit_new() -> anyhow::Result<String> {
+        let Some(editor) = std::env::var_os("EDITOR") else {
+            bail!("No $EDITOR set. Can't edit script");
+        };
+        let dir = temp_dir::TempDir::new()?;
+        let filepath = dir.child("script.txt");
+        std::process::Command::new(editor)
+            .arg(&filepath)
+            .status()
+            .context("Launching editor")?;
+        std::fs::read_to_string(filepath).context("Reading script file")
+    }
+
+    pub(crate) fn add_from_template(
+        ctx: &mut AppContext,
+        name: &str,
+        template: &str,
+        force: bool,
+        lint: bool,
+        encrypt: bool,
+    ) -> anyhow::Result<()> {
+        check_add_conflict(ctx, name, force)?;
+        let Some(body) = ctx.db.template(template)? else {
+            bail!("There is no template named '{template}'");
+        };
+        let root = ctx.db.query_tree_root(ctx.root_id)?;
+        let body = otkeep::expand_placeholders(
+            &body,
+            &[("name", name), ("tree_root", &root.to_string_lossy())],
+        );
+        if lint && !check_script(&body)? {
+            eprintln!("Not adding '{name}'");
+            return Ok(());
+        }
+        store_script(ctx, name, body, encrypt)
+    }
+
+    pub(crate) fn template(db: &Database, template_cmd: &TemplateSubCmd) -> anyhow::Result<()> {
+        match template_cmd {
+            TemplateSubCmd::Add {
+                name,
+                body,
+                inline,
+                force,
+            } => {
+                let body = match body {
+                    Some(body) => read_script_body(body, *inline)?.0,
+                    None => edit_new()?.into_bytes(),
+                };
+                db.add_template(name, &body, *force)?;
+                eprintln!("Added template '{name}'");
+            }
+            TemplateSubCmd::List => {
+                let names = db.template_names()?;
+                if names.is_empty() {
+                    eprintln!(
+                        "No templates have been added yet. To add one, use okeep template add."
+                    );
+                }
+                for name in names {
+                    println!("{name}");
+                }
+            }
+            TemplateSubCmd::Show { name } => match db.template(name)? {
+                Some(body) => std::io::stdout().write_all(&body)?,
+                None => bail!("There is no template named '{name}'"),
+            },
+            TemplateSubCmd::Remove { name } => {
+                if !db.remove_template(name)? {
+                    bail!("There is no template named '{name}'");
+                }
+                eprintln!("Removed template '{name}'");
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_from_url(
+        ctx: &mut AppContext,
+        name: &str,
+        url: &str,
+        max_size: u64,
+        force: bool,
+        encrypt: bool,
+    ) -> anyhow::Result<()> {
+        check_add_conflict(ctx, name, force)?;
+        let body = download(url, max_size)?;
+        eprintln!("Downloaded {} bytes from {url}:\n", body.len());
+        print_preview(&body, false);
+        if !confirm("\nAdd this script?")? {
+            eprintln!("Not adding '{name}'");
+            return Ok(());
+        }
+        store_script(ctx, name, body, encrypt)?;
+        ctx.db.set_script_origin(ctx.root_id, name, url)?;
+        // Only a preview was shown, so the whole script is shown before running it
+        ctx.db.set_script_trusted(ctx.root_id, name, false)?;
+        Ok(())
+    }
+
+    /// Where `okeep add-from-history` takes the command from, and what runs it
+    pub(crate) struct HistoryCommand {
+        /// Passed by the shell integration, instead of reading the history file
+        pub command: Option<String>,
+        pub interactive: bool,
+        pub shell: Option<String>,
+    }
+
+    pub(crate) fn add_from_history(
+        ctx: &mut AppContext,
+        name: &str,
+        source: HistoryCommand,
+        force: bool,
+        lint: bool,
+    ) -> anyhow::Result<()> {
+        const PICK_COUNT: usize = 20;
+        let HistoryCommand {
+            command,
+            interactive,
+            mut shell,
+        } = source;
+        let command = match command {
+            Some(command) => command,
+            None => {
+                let (mut commands, history_shell) = crate::history::recent_commands()?;
+                // Bash and zsh commands mostly work with sh, but fish syntax doesn't
+                if history_shell == "fish" {
+                    shell.get_or_insert_with(|| history_shell.to_owned());
+                }
+                // Leave out the invocations of this command
+                commands.retain(|c| !c.contains("add-from-history") && !c.contains("okeep-save"));
+                commands.dedup();
+                if interactive {
+                    let recent: Vec<_> = commands.iter().rev().take(PICK_COUNT).collect();
+                    for (i, command) in recent.iter().enumerate().rev() {
+                        eprintln!("{:>2}: {command}", i + 1);
+                    }
+                    eprintln!("Which command? (1-{})", recent.len());
+                    let mut ans_line = String::new();
+                    std::io::stdin().read_line(&mut ans_line)?;
+                    let n: usize = ans_line.trim().parse().context("Not a number")?;
+                    match n.checked_sub(1).and_then(|i| recent.get(i)) {
+                        Some(command) => command.to_string(),
+                        None => bail!("There is no command {n}"),
+                    }
+                } else {
+                    commands.pop().unwrap_or_default()
+                }
+            }
+        };
+        let command = command.trim();
+        if command.is_empty() {
+            bail!("No command was found in the shell history");
+        }
+        eprintln!("Adding '{name}': {command}");
+        let opts = AddOpts {
+            inline: true,
+            interpreter: shell.as_deref(),
+            lint,
+            encrypt: false,
+        };
+        add(ctx, name, Some(command), force, opts)
+    }
+
+    pub(crate) fn add_many(
+        ctx: &mut AppContext,
+        paths: &[PathBuf],
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let (mut added, mut updated, mut skipped) = (0, 0, 0);
+        let mut batch = ctx.db.batch()?;
+        for path in paths {
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                eprintln!("Skipping {}: no valid file name", path.display());
+                skipped += 1;
+                continue;
+            };
+            let exists = batch.script_exists(ctx.root_id, name)?;
+            if exists && !force {
+                eprintln!("Skipping {}: '{name}' already exists", path.display());
+                skipped += 1;
+                continue;
+            }
+            // Nothing is added if any of the files can't be read
+            let read = || -> std::io::Result<_> {
+                let absolute_path = std::fs::canonicalize(path)?;
+                Ok((std::fs::read(&absolute_path)?, absolute_path))
+            };
+            let (mut body, absolute_path) =
+                read().with_context(|| format!("Failed to read {}", path.display()))?;
+            if !body.starts_with(b"#!") {
+                match path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .and_then(interpreter_for_extension)
+                {
+                    Some(interpreter) => {
+                        body.splice(0..0, format!("#!{interpreter}\n").into_bytes());
+                    }
+                    None => eprintln!(
+                        "Note: {} has no shebang, it will be run with /bin/sh",
+                        path.display()
+                    ),
+                }
+            }
+            if exists {
+                batch.update_script(ctx.root_id, name, body)?;
+            } else {
+                batch.add_script(ctx.root_id, name, body)?;
+            }
+            let origin = paths_as_strings::encode_path(&absolute_path);
+            batch.set_script_origin(ctx.root_id, name, &origin)?;
+            if exists {
+                eprintln!("Updated '{name}' from {}", path.display());
+                updated += 1;
+            } else {
+                eprintln!("Added '{name}' from {}", path.display());
+                added += 1;
+            }
+        }
+        batch.commit()?;
+        eprintln!("\n{added} added, {updated} updated, {skipped} skipped");
+        Ok(())
+    }
+
+    /// Guesses the interpreter for a script file from its extension
+    fn interpreter_for_extension(ext: &str) -> Option<&'static str> {
+        Some(match ext {
+            "sh" => "/bin/sh",
+            "bash" => "/usr/bin/env bash",
+            "zsh" => "/usr/bin/env zsh",
+            "fish" => "/usr/bin/env fish",
+            "py" => "/usr/bin/env python3",
+            "rb" => "/usr/bin/env ruby",
+            "pl" => "/usr/bin/env perl",
+            "js" => "/usr/bin/env node",
+            "lua" => "/usr/bin/env lua",
+            _ => return None,
+        })
+    }
+
+    /// Fails if a script named `name` already exists, unless overwriting is forced
+    fn check_add_conflict(ctx: &AppContext, name: &str, force: bool) -> anyhow::Result<()> {
+        if !force && ctx.db.script_exists(ctx.root_id, name)? {
+            bail!(
+                "'{name}' already exists. Use okeep update, or okeep add --force to overwrite it"
+            );
+        }
+        Ok(())
+    }
+
+    /// Adds a script, or replaces the body of an existing one. With `encrypt`, the body is
+    /// stored encrypted with gpg.
+    fn store_script(
+        ctx: &mut AppContext,
+        name: &str,
+        body: Vec<u8>,
+        encrypt: bool,
+    ) -> anyhow::Result<()> {
+        if let Some(problem) =
+            ctx.db
+                .size_limit_problem(ItemKind::Script, ctx.root_id, name, body.len() as u64)?
+        {
+            eprintln!("Warning: {problem}");
+        }
+        let exists = ctx.db.script_exists(ctx.root_id, name)?;
+        match (exists, encrypt) {
+            (true, false) => ctx.db.update_script(ctx.root_id, name, body)?,
+            (true, true) => ctx.db.update_script_encrypted(ctx.root_id, name, body)?,
+            (false, false) => ctx.db.add_script(ctx.root_id, name, body)?,
+            (false, true) => ctx.db.add_script_encrypted(ctx.root_id, name, body)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 1024 * 1024;
+
+    fn download(url: &str, max_size: u64) -> anyhow::Result<Vec<u8>> {
+        let response = ureq::get(url).call()?;
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(max_size + 1)
+            .read_to_end(&mut body)?;
+        if body.len() as u64 > max_size {
+            bail!("The download exceeds the size limit of {max_size} bytes");
+        }
+        Ok(body)
+    }
+
+    /// Interactively removes the blobs that no script or file uses anymore
+    pub(crate) fn prune_blobs(app: &AppContext) -> anyhow::Result<()> {
+        let mut any_was_stray_and_nonnull = false;
+        let tree_blob_refs = app.db.tree_script_blob_ids()?;
+        for rowid in app.db.blob_ids()? {
+            if tree_blob_refs.contains(&rowid) || app.db.blob_is_null(rowid)? {
+                continue;
+            }
+            any_was_stray_and_nonnull = true;
+            // Only the start is needed for the preview, and encrypted blobs aren't decrypted
+            // unless asked to view them
+            let encrypted = app.db.blob_encrypted(rowid)?;
+            let mut blob = app.db.open_stored_blob(rowid)?;
+            let len = blob.len();
+            let mut start = Vec::new();
+            if !encrypted {
+                (&mut blob).take(PREVIEW_BYTES).read_to_end(&mut start)?;
+            }
+            let kind = if encrypted {
+                Some("encrypted")
+            } else {
+                otkeep::binary_kind(&start)
+            };
+            let size = format_size(len);
+            match app.db.blob_former_owner(rowid)? {
+                Some((root, name)) => eprintln!(
+                    "Unreferenced blob, {size}, last used as '{name}' in {}:",
+                    root.display()
+                ),
+                None => eprintln!("Unreferenced blob, {size}:"),
+            }
+            match kind {
+                Some(kind) => eprintln!("({kind})"),
+                None => print_preview(&start, len > start.len() as u64),
+            }
+            loop {
+                eprintln!("Remove? (y/n, or v to view it in full)");
+                let mut ans_line = String::new();
+                std::io::stdin().read_line(&mut ans_line)?;
+                match ans_line.trim() {
+                    "y" => {
+                        app.db.nullify_blob(rowid)?;
+                        break;
+                    }
+                    "v" => {
+                        let mut blob = app.db.open_blob(rowid)?;
+                        let mut start = Vec::new();
+                        (&mut blob).take(PREVIEW_BYTES).read_to_end(&mut start)?;
+                        let binary = otkeep::binary_kind(&start).is_some();
+                        let mut data = (&start[..]).chain(blob);
+                        if binary {
+                            page(|out| hex_dump(data, out))?;
+                        } else {
+                            page(|out| {
+                                std::io::copy(&mut data, out)?;
+                                Ok(())
+                            })?;
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        }
+        if !any_was_stray_and_nonnull {
+            eprintln!("No stray blobs were detected.");
+        }
+        Ok(())
+    }
+
+    /// Offers to archive or remove the scripts that weren't used in the last `unused_for` seconds
+    pub(crate) fn prune_scripts(
+        app: &mut AppContext,
+        unused_for: i64,
+        dry_run: bool,
+    ) -> anyhow::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let stale = app.db.scripts_unused_since(app.root_id, now - unused_for)?;
+        if stale.is_empty() {
+            eprintln!("No stale scripts were detected.");
+            return Ok(());
+        }
+        let (mut archived, mut removed) = (0, 0);
+        for (name, last_used) in stale {
+            match last_used {
+                Some(last_used) => {
+                    eprintln!("'{name}' was last used {}", format_age(now - last_used))
+                }
+                None => eprintln!("'{name}' was never used"),
+            }
+            if dry_run {
+                continue;
+            }
+            eprintln!("Archive, remove or keep it? (a/r/k)");
+            let mut ans_line = String::new();
+            std::io::stdin().read_line(&mut ans_line)?;
+            match ans_line.trim() {
+                "a" => {
+                    app.db.set_script_archived(app.root_id, &name, true)?;
+                    archived += 1;
+                }
+                "r" if app.db.script_locked(app.root_id, &name)? => {
+                    eprintln!("Not removing '{name}', because it's locked");
+                }
+                "r" => {
+                    app.db.remove_script(app.root_id, &name)?;
+                    removed += 1;
+                }
+                _ => {}
+            }
+        }
+        if !dry_run {
+            eprintln!("\n{archived} archived, {removed} removed");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn purge_history(
+        app: &mut AppContext,
+        opts: PurgeOpts,
+        yes: bool,
+    ) -> anyhow::Result<()> {
+        let describe = |report: &PurgeReport| {
+            let mut parts = Vec::new();
+            if report.runs > 0 {
+                let runs = plural(report.runs as u64, "run", "runs");
+                parts.push(format!("{} {runs}", report.runs));
+            }
+            if report.logs > 0 {
+                let logs = plural(report.logs as u64, "captured output", "captured outputs");
+                parts.push(format!("{} {logs}", report.logs));
+            }
+            if report.file_versions > 0 {
+                let versions = plural(report.file_versions as u64, "file version", "file versions");
+                parts.push(format!("{} {versions}", report.file_versions));
+            }
+            parts.join(", ")
+        };
+        let dry_run = PurgeOpts {
+            dry_run: true,
+            ..opts
+        };
+        let found = describe(&app.db.purge_history(app.root_id, dry_run)?);
+        if found.is_empty() {
+            eprintln!("There's no history to delete");
+            return Ok(());
+        }
+        if !yes && !confirm(&format!("Delete {found}?"))? {
+            return Ok(());
+        }
+        let report = app.db.purge_history(app.root_id, opts)?;
+        eprintln!("Deleted {}", describe(&report));
+        vacuum(&app.db)
+    }
+
+    pub(crate) fn vacuum(db: &Database) -> anyhow::Result<()> {
+        let path = otkeep::db_file_path()?;
+        let size_before = std::fs::metadata(&path)?.len();
+        let deleted = db.vacuum(&mut progress_bar)?;
+        let size_after = std::fs::metadata(&path)?.len();
+        eprintln!(
+            "Deleted {deleted} pruned {}, the database shrank from {} to {}",
+            plural(deleted as u64, "blob", "blobs"),
+            format_size(size_before),
+            format_size(size_after)
+        );
+        Ok(())
+    }
+
+    /// Lets the user choose one of the scripts of the current tree with fzf, or from a numbered
+    /// list if fzf isn't installed. Returns `None` if nothing was chosen.
+    pub(crate) fn pick(app: &AppContext) -> anyhow::Result<Option<String>> {
+        let scripts = otkeep::script_list(app)?;
+        if scripts.is_empty() {
+            bail!("No scripts have been added yet. To add one, use okeep add.");
+        }
+        let lines: Vec<_> = scripts
+            .iter()
+            .map(|script| format!("{}\t{}", script.name, script.description))
+            .collect();
+        let fzf = std::process::Command::new("fzf")
+            .args([
+                "--delimiter=\t",
+                "--prompt=orun> ",
+                "--height=40%",
+                "--reverse",
+            ])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn();
+        match fzf {
+            Ok(mut child) => {
+                let mut stdin = child.stdin.take().expect("stdin is piped");
+                // fzf may quit before reading everything
+                let _ = stdin.write_all(lines.join("\n").as_bytes());
+                drop(stdin);
+                let output = child.wait_with_output()?;
+                let chosen = String::from_utf8_lossy(&output.stdout);
+                Ok(chosen
+                    .split('\t')
+                    .next()
+                    .map(str::trim_end)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_owned))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                for (i, script) in scripts.iter().enumerate() {
+                    let sep = if script.description.is_empty() {
+                        ""
+                    } else {
+                        " - "
+                    };
+                    eprintln!("{:>3}: {}{sep}{}", i + 1, script.name, script.description);
+                }
+                let answer = ask(&format!("Which script? (1-{})", scripts.len()))?;
+                if answer.is_empty() {
+                    return Ok(None);
+                }
+                let n: usize = answer.parse().context("Not a number")?;
+                match n.checked_sub(1).and_then(|i| scripts.get(i)) {
+                    Some(script) => Ok(Some(script.name.clone())),
+                    None => bail!("There is no script {n}"),
+                }
+            }
+            Err(e) => Err(e).context("Failed to run fzf"),
+        }
+    }
+
+    /// Shows what `write` writes in `$PAGER`, or `less` if it's not set
+    fn page(write: impl FnOnce(&mut ChildStdin) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let pager = std::env::var_os("PAGER").unwrap_or_else(|| "less".into());
+        let mut child = std::process::Command::new(&pager)
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run pager {}", pager.to_string_lossy()))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // The pager may quit before reading everything
+        let _ = write(&mut stdin);
+        drop(stdin);
+        child.wait()?;
+        Ok(())
+    }
+
+    /// Prints the first few lines of a script
+    ///
+    /// Overly long lines are cut short. `truncated` tells that `body` is just the start of the
+    /// contents.
+    fn print_preview(body: &[u8], truncated: bool) {
+        const PREVIEW_LINES: usize = 20;
+        const MAX_LINE_CHARS: usize = 200;
+        let text = String::from_utf8_lossy(body);
+        for line in text.lines().take(PREVIEW_LINES) {
+            match line.char_indices().nth(MAX_LINE_CHARS) {
+                Some((end, _)) => eprintln!("{}...", &line[..end]),
+                None => eprintln!("{line}"),
+            }
+        }
+        let total = text.lines().count();
+        if truncated {
+            eprintln!("... (more lines)");
+        } else if total > PREVIEW_LINES {
+            eprintln!("... ({} more lines)", total - PREVIEW_LINES);
+        }
+    }
+
+    /// Shows the progress of a long operation on stderr, if it's a terminal
+    fn progress_bar(progress: Progress) {
+        const WIDTH: usize = 30;
+        if !std::io::stderr().is_terminal() {
+            return;
+        }
+        let filled = WIDTH * progress.done / progress.total.max(1);
+        eprint!(
+            "\r\x1b[2K{} [{}{}] {}/{} {}",
+            progress.action,
+            "#".repeat(filled),
+            " ".repeat(WIDTH - filled),
+            progress.done,
+            progress.total,
+            progress.item
+        );
+        if progress.done == progress.total {
+            eprint!("\r\x1b[2K");
+        }
+    }
+
+    /// Asks a question, and returns the trimmed answer
+    fn ask(question: &str) -> anyhow::Result<String> {
+        eprint!("{question} ");
+        let mut ans_line = String::new();
+        if std::io::stdin().read_line(&mut ans_line)? == 0 {
+            bail!("Cancelled");
+        }
+        Ok(ans_line.trim().to_owned())
+    }
+
+    /// Asks a yes/no question, and returns whether the answer was yes
+    pub(crate) fn confirm(question: &str) -> anyhow::Result<bool> {
+        eprintln!("{question} (y/n)");
+        let mut ans_line = String::new();
+        std::io::stdin().read_line(&mut ans_line)?;
+        Ok(ans_line.trim() == "y")
+    }
+
+    /// Checks the shebang and syntax of `body`, and runs the configured linter on it.
+    ///
+    /// Asks whether to store the script if there are problems. Without a terminal to ask on,
+    /// problems are an error.
+    pub(crate) fn check_script(body: &[u8]) -> anyhow::Result<bool> {
+        let mut problems = Vec::from_iter(otkeep::lint::check_shebang(body));
+        if !body.starts_with(b"#!") {
+            const NOTE: &str =
+                "(Scripts without a shebang are run with /bin/sh. Use --interpreter to set one)";
+            problems.extend(otkeep::lint::check_syntax(body)?.map(|errors| errors + NOTE));
+        } else if problems.is_empty() {
+            problems.extend(otkeep::lint::check_syntax(body)?);
+        }
+        problems.extend(otkeep::load_linters()?.lint(body)?);
+        if problems.is_empty() {
+            return Ok(true);
+        }
+        eprintln!("Found problems with the script:\n");
+        for problem in problems {
+            eprintln!("{}", problem.trim_end());
+        }
+        eprintln!();
+        if !std::io::stdin().is_terminal() {
+            bail!("The script has problems. Use --no-lint to store it anyway");
+        }
+        confirm("Store the script anyway?")
+    }
+
+    /// Puts a shebang for `interpreter` at the start of `body`.
+    ///
+    /// Interpreters that aren't absolute paths are looked up through `env`.
+    fn add_shebang(body: &mut Vec<u8>, interpreter: &str) -> anyhow::Result<()> {
+        if body.starts_with(b"#!") {
+            bail!("The script already has a shebang");
+        }
+        body.splice(0..0, shebang(interpreter).into_bytes());
+        Ok(())
+    }
+
+    /// A shebang line running `interpreter`, which is looked up in PATH unless it's absolute
+    fn shebang(interpreter: &str) -> String {
+        if interpreter.starts_with('/') {
+            format!("#!{interpreter}\n")
+        } else {
+            format!("#!/usr/bin/env {interpreter}\n")
+        }
+    }
+
+    /// Trims tags and drops the empty ones
+    fn parse_tags<'a>(tags: impl Iterator<Item = &'a str>) -> Vec<String> {
+        tags.map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Asks the questions of `okeep new`, lets the user write the script, and stores it
+    pub(crate) fn new(
+        ctx: &mut AppContext,
+        name: Option<String>,
+        lint: bool,
+    ) -> anyhow::Result<()> {
+        if !std::io::stdin().is_terminal() {
+            bail!("okeep new asks questions, but standard input is not a terminal. Use okeep add");
+        }
+        let Some(editor) = std::env::var_os("EDITOR") else {
+            bail!("No $EDITOR set. Can't edit script");
+        };
+        let name = match name {
+            Some(name) => name,
+            None => loop {
+                let name = ask("Name:")?;
+                if name.is_empty() {
+                    continue;
+                }
+                if ctx.db.script_exists(ctx.root_id, &name)? {
+                    eprintln!("'{name}' already exists");
+                    continue;
+                }
+                break name;
+            },
+        };
+        check_add_conflict(ctx, &name, false)?;
+        let desc = ask("Description (optional):")?;
+        let interpreter = ask("Interpreter [sh]:")?;
+        let tags = parse_tags(ask("Tags, separated by commas (optional):")?.split(','));
+        let interpreter = if interpreter.is_empty() {
+            "/bin/sh"
+        } else {
+            &interpreter
+        };
+        let dir = temp_dir::TempDir::new()?;
+        let filepath = dir.child("script.txt");
+        std::fs::write(&filepath, format!("{}\n", shebang(interpreter)))?;
+        let body = loop {
+            std::process::Command::new(&editor)
+                .arg(&filepath)
+                .status()
+                .context("Launching editor")?;
+            let body = std::fs::read(&filepath).context("Reading script file")?;
+            if !lint || check_script(&body)? {
+                break body;
+            }
+            if !confirm("Edit the script again?")? {
+                eprintln!("Not adding '{name}'");
+                return Ok(());
+            }
+        };
+        ctx.db.add_script(ctx.root_id, &name, body)?;
+        if !desc.is_empty() {
+            ctx.db.add_script_description(ctx.root_id, &name, &desc)?;
+        }
+        if !tags.is_empty() {
+            ctx.db.set_script_tags(ctx.root_id, &name, &tags)?;
+        }
+        eprintln!("Added '{name}'. Run it with orun {name}");
+        Ok(())
+    }
+
+    /// Asks whether to establish the current directory as a root, if stdin is a terminal
+    pub(crate) fn offer_establish() -> anyhow::Result<bool> {
+        if !std::io::stdin().is_terminal() {
+            return Ok(false);
+        }
+        confirm(&format!(
+            "No OtKeep tree root was found. Establish {} as a root?",
+            std::env::current_dir()?.display()
+        ))
+    }
+
+    /// Reads a script body from an inline script, a path, or standard input if the path is `-`
+    ///
+    /// Also returns the origin to record for the script, if it was read from a path.
+    fn read_script_body(script: &str, inline: bool) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+        if inline {
+            return Ok((script.as_bytes().to_vec(), None));
+        }
+        if script == "-" {
+            let mut body = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut body)
+                .context("Reading script from stdin")?;
+            return Ok((body, None));
+        }
+        let curr_dir = std::env::current_dir()?;
+        let absolute_path = std::fs::canonicalize(curr_dir.join(script))?;
+        let body = std::fs::read(&absolute_path)?;
+        let origin = paths_as_strings::encode_path(&absolute_path).into_owned();
+        Ok((body, Some(origin)))
+    }
+
+    fn is_url(origin: &str) -> bool {
+        origin.starts_with("http://") || origin.starts_with("https://")
+    }
+
+    /// Fetches the current contents of a script origin
+    fn fetch_origin(origin: &str) -> anyhow::Result<Vec<u8>> {
+        if is_url(origin) {
+            download(origin, DEFAULT_MAX_DOWNLOAD_SIZE)
+        } else {
+            Ok(std::fs::read(paths_as_strings::decode_path(origin)?)?)
+        }
+    }
+
+    pub(crate) fn refresh(
+        app: &mut AppContext,
+        name: Option<&str>,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let scripts = match name {
+            Some(name) => match app.db.script_origin(app.root_id, name)? {
+                Some(origin) => {
+                    check_unlocked(app, name, force)?;
+                    vec![(name.to_owned(), origin)]
+                }
+                None => bail!("'{name}' has no recorded origin to refresh from"),
+            },
+            None => app.db.scripts_with_origin(app.root_id)?,
+        };
+        if scripts.is_empty() {
+            eprintln!("No scripts have a recorded origin.");
+        }
+        for (name, origin) in scripts {
+            if !force && app.db.script_locked(app.root_id, &name)? {
+                eprintln!("Skipping '{name}', it's locked. Use --force to refresh it anyway");
+                continue;
+            }
+            let display_origin = if is_url(&origin) {
+                origin.clone()
+            } else {
+                paths_as_strings::decode_path(&origin)?
+                    .display()
+                    .to_string()
+            };
+            let new = match fetch_origin(&origin) {
+                Ok(new) => new,
+                Err(e) => {
+                    eprintln!("Failed to fetch '{name}' from {display_origin}: {e}");
+                    continue;
+                }
+            };
+            let old = app.db.get_script_by_name(app.root_id, &name)?;
+            if old == new {
+                eprintln!("'{name}' is up to date");
+                continue;
+            }
+            eprintln!("'{name}' changed at {display_origin}:");
+            print_diff(&old, &new, &name, &display_origin);
+            if confirm("Apply?")? {
+                app.db.update_script(app.root_id, &name, new)?;
+                eprintln!("Updated '{name}'");
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints a colored unified diff between two blobs
+    fn print_diff(old: &[u8], new: &[u8], old_label: &str, new_label: &str) {
+        let (Ok(old), Ok(new)) = (std::str::from_utf8(old), std::str::from_utf8(new)) else {
+            eprintln!("Binary contents differ");
+            return;
+        };
+        let diff = similar::TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(old_label, new_label)
+            .to_string();
+        for line in diff.lines() {
+            if line.starts_with('+') {
+                eprintln!("{}", line.green());
+            } else if line.starts_with('-') {
+                eprintln!("{}", line.red());
+            } else {
+                eprintln!("{line}");
+            }
+        }
+    }
+    pub(crate) fn diff_trees(db: &Database, a: &Path, b: &Path, patch: bool) -> anyhow::Result<()> {
+        let tree_id = |path: &Path| -> anyhow::Result<i64> {
+            match otkeep::find_root_for_path(db, path)? {
+                Some((id, _)) => Ok(id),
+                None => bail!("No root found at the given location ({})", path.display()),
+            }
+        };
+        let (a_id, b_id) = (tree_id(a)?, tree_id(b)?);
+        let names = |id| -> anyhow::Result<BTreeSet<String>> {
+            Ok(db
+                .scripts_for_tree(id)?
+                .into_iter()
+                .map(|s| s.name)
+                .collect())
+        };
+        let (a_names, b_names) = (names(a_id)?, names(b_id)?);
+        let mut differing = Vec::new();
+        for name in a_names.intersection(&b_names) {
+            let a_body = db.get_script_by_name(a_id, name)?;
+            let b_body = db.get_script_by_name(b_id, name)?;
+            if a_body != b_body {
+                differing.push((name, a_body, b_body));
+            }
+        }
+        let (a_label, b_label) = (a.display().to_string(), b.display().to_string());
+        let mut identical = true;
+        for (label, names) in [
+            (&a_label, a_names.difference(&b_names).collect::<Vec<_>>()),
+            (&b_label, b_names.difference(&a_names).collect()),
+        ] {
+            if !names.is_empty() {
+                identical = false;
+                eprintln!("Only in {label}:");
+                for name in names {
+                    eprintln!("    {name}");
+                }
+            }
+        }
+        if !differing.is_empty() {
+            identical = false;
+            eprintln!("Differing:");
+            for (name, _, _) in &differing {
+                eprintln!("    {name}");
+            }
+        }
+        if patch {
+            for (name, a_body, b_body) in &differing {
+                eprintln!();
+                print_diff(
+                    a_body,
+                    b_body,
+                    &format!("{a_label}: {name}"),
+                    &format!("{b_label}: {name}"),
+                );
+            }
+        }
+        if identical {
+            eprintln!("The trees have the same scripts.");
+        }
+        Ok(())
+    }
+
+    /// Establishes `path` (or the current directory) as a root, and returns its canonical path
+    pub fn establish(
+        db: &Database,
+        path: Option<&Path>,
+        desc: Option<&str>,
+    ) -> anyhow::Result<PathBuf> {
+        let path = match path {
+            Some(path) => path
+                .canonicalize()
+                .with_context(|| format!("Can't access {}", path.display()))?,
+            None => std::env::current_dir()?,
+        };
+        if !path.is_dir() {
+            bail!("{} is not a directory", path.display());
+        }
+        match db.query_tree(&path)? {
+            None => {
+                let id = db.add_new_tree(&path)?;
+                if desc.is_some() {
+                    db.set_tree_desc(id, desc)?;
+                }
+            }
+            Some(_) => bail!("There is already a OtKeep tree root here."),
+        }
+        Ok(path)
+    }
+    /// Where the scripts of `okeep establish --template` come from
+    pub(crate) enum TreeTemplateSource {
+        Bundled(&'static tree_templates::TreeTemplate),
+        Tree(i64),
+    }
+
+    /// Looks up `template` as a bundled template, then as the root of a tree
+    pub(crate) fn resolve_tree_template(
+        db: &Database,
+        template: &str,
+    ) -> anyhow::Result<TreeTemplateSource> {
+        if let Some(bundled) = tree_templates::find(template) {
+            return Ok(TreeTemplateSource::Bundled(bundled));
+        }
+        if let Ok(path) = Path::new(template).canonicalize() {
+            if let Some(id) = db.query_tree(&path)? {
+                return Ok(TreeTemplateSource::Tree(id));
+            }
+        }
+        bail!(
+            "'{template}' is neither a bundled template ({}) nor the root of a tree",
+            tree_templates::names()
+        );
+    }
+
+    /// Adds the scripts of `template` to the (new) tree `tree_id`
+    pub(crate) fn apply_tree_template(
+        db: &mut Database,
+        tree_id: i64,
+        template: TreeTemplateSource,
+    ) -> anyhow::Result<()> {
+        let names = match template {
+            TreeTemplateSource::Bundled(template) => {
+                for script in template.scripts {
+                    db.add_script(tree_id, script.name, script.body.into())?;
+                    db.add_script_description(tree_id, script.name, script.desc)?;
+                }
+                template.scripts.iter().map(|s| s.name.to_owned()).collect()
+            }
+            TreeTemplateSource::Tree(src) => {
+                let report = db.clone_tree(
+                    src,
+                    tree_id,
+                    CloneOpts::default(),
+                    |_, _| Ok(ConflictResolution::Skip),
+                    &mut (),
+                )?;
+                report.scripts.cloned
+            }
+        };
+        if names.is_empty() {
+            eprintln!("The template has no scripts.");
+        } else {
+            eprintln!("Added scripts: {}", names.join(", "));
+        }
+        Ok(())
+    }
+    pub fn unestablish(db: &mut Database, tree_id: i64) -> anyhow::Result<()> {
+        otkeep::shims::remove_all_shims(db, tree_id)?;
+        Ok(db.remove_tree(tree_id)?)
+    }
+    /// Unestablishes the root at `path`, after asking for confirmation unless `yes` is set.
+    ///
+    /// Roots whose directory no longer exists are looked up by their absolute path.
+    pub(crate) fn unestablish_path(
+        db: &mut Database,
+        path: &Path,
+        yes: bool,
+    ) -> anyhow::Result<()> {
+        let exists = path.exists();
+        let path = if exists {
+            path.canonicalize()?
+        } else {
+            std::path::absolute(path)?
+        };
+        let Some(tree_id) = db.query_tree(&path)? else {
+            bail!("{} is not a root", path.display());
+        };
+        if !yes {
+            let script_count = db.scripts_for_tree(tree_id)?.len();
+            let file_count = db.files_for_tree(tree_id)?.len();
+            let missing = if exists { "" } else { " (missing)" };
+            let question = format!(
+                "Unestablish {}{missing}? Its {script_count} scripts and {file_count} saved files \
+                 will be removed.",
+                path.display()
+            );
+            if !confirm(&question)? {
+                eprintln!("Not unestablishing {}", path.display());
+                return Ok(());
+            }
+        }
+        unestablish(db, tree_id)?;
+        eprintln!("Unestablished {}", path.display());
+        Ok(())
+    }
+    pub fn reestablish(db: &Database, old_root: &Path) -> anyhow::Result<()> {
+        let current_dir = std::env::current_dir()?;
+        match db.query_tree(&current_dir)? {
+            None => {
+                db.rename_tree(old_root, &current_dir)?;
+            }
+            Some(_) => bail!("There is already a OtKeep tree root here."),
+        }
+        Ok(())
+    }
+    /// Finds the stray root that best matches the current directory, and offers to move it here
+    pub(crate) fn reestablish_detect(db: &Database) -> anyhow::Result<()> {
+        let current_dir = std::env::current_dir()?;
+        if db.query_tree(&current_dir)?.is_some() {
+            bail!("There is already a OtKeep tree root here.");
+        }
+        let marker_uuid = otkeep::marker::read(&current_dir);
+        let mut candidates = Vec::new();
+        for root in db.get_tree_roots()? {
+            if root.path.exists() {
+                continue;
+            }
+            let mut score = 0;
+            if marker_uuid.as_ref() == Some(&root.uuid) {
+                score += 100;
+            }
+            if root.path.file_name() == current_dir.file_name() {
+                score += 2;
+            }
+            for file in db.files_for_tree(root.id)? {
+                if current_dir.join(&file.name).exists() {
+                    score += 1;
+                }
+            }
+            if score > 0 {
+                candidates.push((score, root.path));
+            }
+        }
+        candidates.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let Some((_, best)) = candidates.first() else {
+            eprintln!("No matching roots found");
+            return Ok(());
+        };
+        for (_, other) in &candidates[1..] {
+            eprintln!("Other candidate: {}", other.display());
+        }
+        if !confirm(&format!("Move {} here?", best.display()))? {
+            return Ok(());
+        }
+        db.rename_tree(best, &current_dir)?;
+        eprintln!(
+            "Reestablished {} as {}",
+            best.display(),
+            current_dir.display()
+        );
+        Ok(())
+    }
+    /// The changes `okeep mod` makes to a script. `None` leaves a setting alone.
+    pub(crate) struct ModOpts<'a> {
+        pub desc: Option<&'a str>,
+        pub needs: Option<&'a [String]>,
+        pub clean_env: Option<bool>,
+        pub sandbox: Option<&'a str>,
+        pub container: Option<&'a str>,
+        pub tags: Option<&'a [String]>,
+        pub failure_hint: Option<&'a str>,
+        /// Environment variables to set for the script
+        pub env: &'a [(String, String)],
+        /// Environment variables to stop setting for the script
+        pub unset_env: &'a [String],
+        pub locked: Option<bool>,
+    }
+
+    pub fn mod_(ctx: &mut AppContext, name: &str, opts: ModOpts) -> anyhow::Result<()> {
+        let ModOpts {
+            desc,
+            needs,
+            clean_env,
+            sandbox,
+            container,
+            tags,
+            failure_hint,
+            env,
+            unset_env,
+            locked,
+        } = opts;
+        let mut modded = false;
+
+        if let Some(description) = desc {
+            ctx.db
+                .add_script_description(ctx.root_id, name, description)?;
+            eprintln!("{} => {}", name, description);
+            modded = true;
+        }
+        if let Some(needs) = needs {
+            let needs: Vec<String> = needs.iter().filter(|s| !s.is_empty()).cloned().collect();
+            ctx.db.set_script_needs(ctx.root_id, name, &needs)?;
+            if needs.is_empty() {
+                eprintln!("{name} no longer needs other scripts");
+            } else {
+                eprintln!("{name} needs {}", needs.join(", "));
+            }
+            modded = true;
+        }
+        if let Some(clean) = clean_env {
+            ctx.db.set_script_clean_env(ctx.root_id, name, clean)?;
+            if clean {
+                eprintln!("{name} now runs with a clean environment");
+            } else {
+                eprintln!("{name} now runs with the full environment");
+            }
+            modded = true;
+        }
+        if let Some(sandbox) = sandbox {
+            let sandbox = match sandbox {
+                "none" => None,
+                sandbox => Some(sandbox.parse::<Sandbox>()?),
+            };
+            ctx.db.set_script_sandbox(ctx.root_id, name, sandbox)?;
+            match sandbox {
+                Some(sandbox) => eprintln!("{name} now runs in the {} sandbox", sandbox.name()),
+                None => eprintln!("{name} no longer runs in a sandbox"),
+            }
+            modded = true;
+        }
+        if let Some(image) = container {
+            let image = Some(image).filter(|image| !image.is_empty());
+            ctx.db.set_script_container(ctx.root_id, name, image)?;
+            match image {
+                Some(image) => eprintln!("{name} now runs in a container from {image}"),
+                None => eprintln!("{name} no longer runs in a container"),
+            }
+            modded = true;
+        }
+        if let Some(tags) = tags {
+            let tags = parse_tags(tags.iter().map(String::as_str));
+            ctx.db.set_script_tags(ctx.root_id, name, &tags)?;
+            if tags.is_empty() {
+                eprintln!("{name} no longer has tags");
+            } else {
+                eprintln!("{name} is tagged {}", tags.join(", "));
+            }
+            modded = true;
+        }
+        if let Some(hint) = failure_hint {
+            let hint = Some(hint).filter(|hint| !hint.is_empty());
+            ctx.db.set_script_failure_hint(ctx.root_id, name, hint)?;
+            match hint {
+                Some(hint) => eprintln!("When {name} fails under orun --check: {hint}"),
+                None => eprintln!("{name} no longer has a failure hint"),
+            }
+            modded = true;
+        }
+        if !env.is_empty() || !unset_env.is_empty() {
+            let mut vars = ctx.db.script_env(ctx.root_id, name)?;
+            for key in unset_env {
+                if !vars.iter().any(|(k, _)| k == key) {
+                    bail!("{name} doesn't set {key}");
+                }
+                vars.retain(|(k, _)| k != key);
+            }
+            for (key, val) in env {
+                match vars.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, old)) => old.clone_from(val),
+                    None => vars.push((key.clone(), val.clone())),
+                }
+            }
+            ctx.db.set_script_env(ctx.root_id, name, &vars)?;
+            for key in unset_env {
+                eprintln!("{name} no longer sets {key}");
+            }
+            for (key, val) in env {
+                eprintln!("{name} now runs with {key}={val}");
+            }
+            modded = true;
+        }
+        if let Some(locked) = locked {
+            ctx.db.set_script_locked(ctx.root_id, name, locked)?;
+            if locked {
+                eprintln!("{name} is now locked");
+            } else {
+                eprintln!("{name} is no longer locked");
+            }
+            modded = true;
+        }
+        if !modded {
+            eprintln!("No modification option given, did nothing.");
+        }
+        Ok(())
+    }
+
+    /// Fails if the script is locked, unless `force` is true
+    pub(crate) fn check_unlocked(app: &AppContext, name: &str, force: bool) -> anyhow::Result<()> {
+        if !force && app.db.script_locked(app.root_id, name)? {
+            return Err(otkeep::Error::ScriptLocked(name.to_owned()).into());
+        }
+        Ok(())
+    }
+
+    pub fn remove(
+        ctx: &mut AppContext,
+        names: &[String],
+        yes: bool,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let is_glob = |name: &str| name.contains(['*', '?', '[']);
+        if let [name] = names {
+            if !is_glob(name) {
+                check_unlocked(ctx, name, force)?;
+                if ctx.db.remove_script(ctx.root_id, name)? {
+                    eprintln!("Removed script '{}'", name);
+                } else {
+                    eprintln!("Didn't remove anything. '{}' probably doesn't exist.", name);
+                }
+                return Ok(());
+            }
+        }
+        let mut to_remove = Vec::new();
+        for name in names {
+            let matches = if is_glob(name) {
+                ctx.db.script_names_matching(ctx.root_id, name)?
+            } else if ctx.db.script_exists(ctx.root_id, name)? {
+                vec![name.clone()]
+            } else {
+                Vec::new()
+            };
+            if matches.is_empty() {
+                eprintln!("Nothing matches '{name}'");
+            }
+            for name in matches {
+                if !force && ctx.db.script_locked(ctx.root_id, &name)? {
+                    eprintln!("Skipping '{name}', because it's locked");
+                    continue;
+                }
+                if !to_remove.contains(&name) {
+                    to_remove.push(name);
+                }
+            }
+        }
+        if to_remove.is_empty() {
+            eprintln!("Didn't remove anything.");
+            return Ok(());
+        }
+        eprintln!("The following scripts will be removed:");
+        for name in &to_remove {
+            eprintln!("{name}");
+        }
+        if !yes && !confirm("Remove?")? {
+            return Ok(());
+        }
+        let removed = ctx.db.remove_scripts(ctx.root_id, &to_remove)?;
+        eprintln!("Removed {removed} scripts");
+        Ok(())
+    }
+
+    pub(crate) fn list_trees(
+        db: &Database,
+        sort: TreeSort,
+        format: Format,
+        tag: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let mut trees = db.tree_stats()?;
+        if let Some(tag) = tag {
+            trees.retain(|tree| tree.root.tags.iter().any(|t| t == tag));
+        }
+        match sort {
+            TreeSort::Path => trees.sort_by(|a, b| a.root.path.cmp(&b.root.path)),
+            TreeSort::Name => {
+                trees.sort_by(|a, b| a.root.path.file_name().cmp(&b.root.path.file_name()))
+            }
+            TreeSort::Scripts => trees.sort_by_key(|t| std::cmp::Reverse(t.script_count)),
+            TreeSort::Files => trees.sort_by_key(|t| std::cmp::Reverse(t.file_count)),
+            TreeSort::Size => trees.sort_by_key(|t| std::cmp::Reverse(t.size)),
+            TreeSort::Used => trees.sort_by_key(|t| std::cmp::Reverse(t.last_used)),
+        }
+        if format != Format::Text {
+            let mut table = Table::new(&[
+                "root",
+                "description",
+                "uuid",
+                "scripts",
+                "files",
+                "size",
+                "last_used",
+                "tags",
+            ]);
+            for tree in trees {
+                table.push(vec![
+                    tree.root.path.to_string_lossy().as_ref().into(),
+                    tree.root.desc.into(),
+                    tree.root.uuid.into(),
+                    tree.script_count.into(),
+                    tree.file_count.into(),
+                    tree.size.into(),
+                    tree.last_used.into(),
+                    tree.root.tags.join(",").into(),
+                ]);
+            }
+            return table.print(format);
+        }
+        if let (Some(tag), true) = (tag, trees.is_empty()) {
+            eprintln!("No trees are tagged {tag}. Tag one with `okeep tree tag add {tag}`.");
+            return Ok(());
+        }
+        if trees.is_empty() {
+            eprintln!("Looks like no trees have been added yet.");
+            eprintln!("Find a tree you'd like to add and type `okeep establish`.");
+            return Ok(());
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        for tree in trees {
+            let mut style = Style::new();
+            if !tree.root.path.exists() {
+                style = style.bright_black();
+            }
+            match &tree.root.desc {
+                Some(desc) => eprintln!("{} - {desc}", tree.root.path.display().style(style)),
+                None => eprintln!("{}", tree.root.path.display().style(style)),
+            }
+            let last_used = match tree.last_used {
+                Some(time) => format_age(now - time),
+                None => "never".into(),
+            };
+            let mut details = format!(
+                "{} {}, {} {}, {}, last used {last_used}",
+                tree.script_count,
+                plural(tree.script_count, "script", "scripts"),
+                tree.file_count,
+                plural(tree.file_count, "file", "files"),
+                format_size(tree.size),
+            );
+            if !tree.root.tags.is_empty() {
+                details += &format!(", tagged {}", tree.root.tags.join(", "));
+            }
+            eprintln!("    {}", details.bright_black());
+        }
+        Ok(())
+    }
+
+    /// Runs `command` in the root of each tree, or of each tree tagged `tag`
+    pub(crate) fn foreach(
+        db: &Database,
+        tag: Option<&str>,
+        keep_going: bool,
+        command: &[OsString],
+    ) -> anyhow::Result<()> {
+        let mut roots = match tag {
+            Some(tag) => tagged_trees(db, tag)?,
+            None => db.get_tree_roots()?,
+        };
+        roots.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut failed = Vec::new();
+        let mut ran = 0u64;
+        for root in roots {
+            if !root.path.is_dir() {
+                eprintln!(
+                    "{}",
+                    format!("Skipping {}, which doesn't exist", root.path.display()).bright_black()
+                );
+                continue;
+            }
+            eprintln!("{}", format!("==> {}", root.path.display()).bold());
+            ran += 1;
+            let status = std::process::Command::new(&command[0])
+                .args(&command[1..])
+                .current_dir(&root.path)
+                .status()
+                .with_context(|| format!("Failed to run {}", command[0].to_string_lossy()))?;
+            if !status.success() {
+                eprintln!(
+                    "{}",
+                    format!("Failed in {} ({status})", root.path.display()).red()
+                );
+                failed.push(root.path);
+                if !keep_going {
+                    break;
+                }
+            }
+        }
+        if !failed.is_empty() {
+            bail!(
+                "The command failed in {} of {ran} {}:\n{}",
+                failed.len(),
+                plural(ran, "tree", "trees"),
+                failed
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints the root(s) owning `path`, and returns whether there were any
+    pub(crate) fn owner(db: &Database, path: &Path, all: bool) -> anyhow::Result<bool> {
+        if !all {
+            return Ok(match otkeep::find_root_for_path(db, path)? {
+                Some((_, root)) => {
+                    println!("{}", root.display());
+                    true
+                }
+                None => {
+                    eprintln!("{} doesn't belong to any tree", path.display());
+                    false
+                }
+            });
+        }
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Can't access {}", path.display()))?;
+        let mut any = false;
+        for dir in path.ancestors() {
+            if db.query_tree(dir)?.is_some() {
+                println!("{}", dir.display());
+                any = true;
+            }
+        }
+        if !any {
+            eprintln!("{} doesn't belong to any tree", path.display());
+        }
+        Ok(any)
+    }
+
+    pub(crate) fn info(app: &AppContext) -> anyhow::Result<()> {
+        let tree = app
+            .db
+            .tree_stats()?
+            .into_iter()
+            .find(|tree| tree.root.id == app.root_id)
+            .context("Missing tree")?;
+        println!("Root: {}", tree.root.path.display());
+        if let Some(desc) = &tree.root.desc {
+            println!("Description: {desc}");
+        }
+        println!("UUID: {}", tree.root.uuid);
+        if !tree.root.tags.is_empty() {
+            println!("Tags: {}", tree.root.tags.join(", "));
+        }
+        if let Some(dev_shell) = app.db.tree_dev_shell(app.root_id)? {
+            println!("Dev shell: nix {}", dev_shell.name());
+        }
+        println!("Scripts: {}", tree.script_count);
+        println!("Saved files: {}", tree.file_count);
+        println!("Size: {}", format_size(tree.size));
+        let last_used = match tree.last_used {
+            Some(time) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+                format_age(now - time)
+            }
+            None => "never".into(),
+        };
+        println!("Last used: {last_used}");
+        Ok(())
+    }
+
+    pub(crate) fn list(
+        app: &AppContext,
+        files: bool,
+        opts: ListOpts,
+        format: Format,
+    ) -> anyhow::Result<()> {
+        let kind = if files {
+            ItemKind::File
+        } else {
+            ItemKind::Script
+        };
+        let items = app.db.list_items(kind, app.root_id, opts)?;
+        if format != Format::Text {
+            let mut table = Table::new(&["name", "description", "size"]);
+            for item in items {
+                table.push(vec![
+                    item.name.into(),
+                    item.description.into(),
+                    item.size.into(),
+                ]);
+            }
+            return table.print(format);
+        }
+        for item in items {
+            let sep = if item.description.is_empty() {
+                ""
+            } else {
+                " - "
+            };
+            println!(
+                "{} ({}){sep}{}",
+                item.name,
+                format_size(item.size),
+                item.description
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn show(app: &AppContext, name: &str, file: bool) -> anyhow::Result<()> {
+        let (db, root_id) = (&app.db, app.root_id);
+        let kind = if file {
+            ItemKind::File
+        } else {
+            ItemKind::Script
+        };
+        let details = db.item_details(kind, root_id, name)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let age = |time: Option<i64>| match time {
+            Some(time) => format_age(now - time),
+            None => "unknown".into(),
+        };
+        println!("Name: {name}");
+        if let Some(desc) = &details.desc {
+            println!("Description: {desc}");
+        }
+        if !file {
+            let tags = db.script_tags(root_id, name)?;
+            if !tags.is_empty() {
+                println!("Tags: {}", tags.join(", "));
+            }
+            let needs = db.script_needs(root_id, name)?;
+            if !needs.is_empty() {
+                println!("Needs: {}", needs.join(", "));
+            }
+            if let Some(origin) = db.script_origin(root_id, name)? {
+                println!("Origin: {origin}");
+            }
+            if let Some(sandbox) = db.script_sandbox(root_id, name)? {
+                println!("Sandbox: {}", sandbox.name());
+            }
+            if let Some(image) = db.script_container(root_id, name)? {
+                println!("Container: {image}");
+            }
+            if let Some(hint) = db.script_failure_hint(root_id, name)? {
+                println!("Failure hint: {hint}");
+            }
+            for (key, val) in db.script_env(root_id, name)? {
+                println!("Env: {key}={val}");
+            }
+            let flags = [
+                (db.script_clean_env(root_id, name)?, "clean env"),
+                (db.script_locked(root_id, name)?, "locked"),
+                (db.script_archived(root_id, name)?, "archived"),
+                (!db.script_trusted(root_id, name)?, "untrusted"),
+            ];
+            let flags: Vec<_> = flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| *flag)
+                .collect();
+            if !flags.is_empty() {
+                println!("Flags: {}", flags.join(", "));
+            }
+        }
+        println!("Created: {}", age(details.created_at));
+        println!("Modified: {}", age(details.updated_at));
+        if details.encrypted {
+            println!("Encrypted: yes");
+        }
+        if !file {
+            let last_run = db.last_run(root_id, Some(name))?;
+            let last_run = match last_run {
+                Some(run) => format!(
+                    "{} (exit code {})",
+                    format_age(now - run.started),
+                    run.exit_code
+                ),
+                None => "never".into(),
+            };
+            println!("Last run: {last_run}");
+        }
+        Ok(())
+    }
+
+    fn plural<'a>(count: u64, singular: &'a str, plural: &'a str) -> &'a str {
+        if count == 1 {
+            singular
+        } else {
+            plural
+        }
+    }
+
+    /// Formats an amount of seconds in the past in a human readable way, e.g. `3 days ago`
+    fn format_age(secs: i64) -> String {
+        let (amount, unit) = match secs {
+            ..60 => return "just now".into(),
+            60..3600 => (secs / 60, "minute"),
+            3600..86400 => (secs / 3600, "hour"),
+            _ => (secs / 86400, "day"),
+        };
+        let s = if amount == 1 { "" } else { "s" };
+        format!("{amount} {unit}{s} ago")
+    }
+
+    pub(crate) fn anchor(db: &mut Database, anchor_cmd: &AnchorSubCmd) -> anyhow::Result<()> {
+        let mut anchors = db.anchors().clone();
+        match anchor_cmd {
+            AnchorSubCmd::Add { name, path } => {
+                let path = path
+                    .canonicalize()
+                    .with_context(|| format!("Can't access {}", path.display()))?;
+                anchors.set(name, path)?;
+                otkeep::save_anchors(&anchors)?;
+                db.set_anchors(anchors);
+                let changed = db.anchor_roots()?;
+                if changed > 0 {
+                    eprintln!("{changed} roots are now stored relative to '{name}'");
+                }
+            }
+            AnchorSubCmd::Remove { name } => {
+                if !anchors.remove(name) {
+                    bail!("No such anchor: {name}");
+                }
+                otkeep::save_anchors(&anchors)?;
+                let using = db.roots_using_anchor(name)?;
+                if using > 0 {
+                    eprintln!(
+                        "{using} roots are stored relative to '{name}', \
+                         and won't be found until it's added again"
+                    );
+                }
+            }
+            AnchorSubCmd::List => {
+                for (name, path) in anchors.iter() {
+                    println!("{name}={}", path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn tree_desc(
+        app: &AppContext,
+        desc: Option<&str>,
+        clear: bool,
+    ) -> anyhow::Result<()> {
+        if desc.is_some() || clear {
+            return Ok(app.db.set_tree_desc(app.root_id, desc)?);
+        }
+        match app.db.tree_desc(app.root_id)? {
+            Some(desc) => println!("{desc}"),
+            None => {
+                eprintln!("This tree has no description. Set one with `okeep tree desc <DESC>`.")
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn tree_tag(app: &AppContext, tag_cmd: TreeTagSubCmd) -> anyhow::Result<()> {
+        let mut tags = app.db.tree_tags(app.root_id)?;
+        match tag_cmd {
+            TreeTagSubCmd::Add { tags: new } => {
+                for tag in parse_tags(new.iter().map(String::as_str)) {
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+            }
+            TreeTagSubCmd::Remove { tags: old } => {
+                let old = parse_tags(old.iter().map(String::as_str));
+                tags.retain(|tag| !old.contains(tag));
+            }
+            TreeTagSubCmd::List => {
+                if tags.is_empty() {
+                    eprintln!(
+                        "This tree has no tags. Add some with `okeep tree tag add <TAGS>...`."
+                    );
+                }
+                for tag in tags {
+                    println!("{tag}");
+                }
+                return Ok(());
+            }
+        }
+        app.db.set_tree_tags(app.root_id, &tags)?;
+        if tags.is_empty() {
+            eprintln!("This tree no longer has tags");
+        } else {
+            eprintln!("This tree is tagged {}", tags.join(", "));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn tree_size_limits(
+        app: &AppContext,
+        item: Option<u64>,
+        total: Option<u64>,
+    ) -> anyhow::Result<()> {
+        if let Some(item) = item {
+            app.db.set_tree_max_item_size(app.root_id, Some(item))?;
+        }
+        if let Some(total) = total {
+            app.db
+                .set_tree_max_size(app.root_id, Some(total).filter(|total| *total > 0))?;
+        }
+        let limits = app.db.tree_size_limits(app.root_id)?;
+        let describe = |limit: Option<u64>| limit.map_or("none".into(), format_size);
+        println!("Item: {}", describe(limits.item));
+        println!("Total: {}", describe(limits.total));
+        Ok(())
+    }
+
+    pub(crate) fn tree_keep_versions(
+        app: &mut AppContext,
+        count: Option<u32>,
+        default: bool,
+    ) -> anyhow::Result<()> {
+        if count.is_some() || default {
+            app.db.set_tree_file_versions(app.root_id, count)?;
+        } else {
+            println!("{}", app.db.tree_file_versions(app.root_id)?);
+            return Ok(());
+        }
+        match app.db.tree_file_versions(app.root_id)? {
+            0 => eprintln!("Saved files of this tree no longer keep their former versions"),
+            n => eprintln!("Saved files of this tree keep their last {n} former versions"),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn tree_dev_shell(app: &AppContext, kind: Option<&str>) -> anyhow::Result<()> {
+        match kind {
+            Some("none") => {
+                app.db.set_tree_dev_shell(app.root_id, None)?;
+                eprintln!("Scripts of this tree now run directly");
+            }
+            Some(kind) => {
+                app.db.set_tree_dev_shell(app.root_id, Some(kind.parse()?))?;
+                eprintln!("Scripts of this tree now run in its nix {kind} environment");
+            }
+            None => match app.db.tree_dev_shell(app.root_id)? {
+                Some(dev_shell) => println!("{}", dev_shell.name()),
+                None => eprintln!(
+                    "Scripts of this tree run directly. Set a dev shell with `okeep tree dev-shell <KIND>`."
+                ),
+            },
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sign(
+        app: &AppContext,
+        name: &str,
+        key: Option<&str>,
+        remove: bool,
+    ) -> anyhow::Result<()> {
+        if remove {
+            app.db.set_script_signature(app.root_id, name, None)?;
+            eprintln!("Removed the signature of {name}");
+            return Ok(());
+        }
+        let body = app.db.get_script_by_name(app.root_id, name)?;
+        let signature = signing::sign(&body, key)?;
+        let signer = signing::verify(&body, &signature, app.db.allowed_signers())?;
+        app.db
+            .set_script_signature(app.root_id, name, Some(&signature))?;
+        eprintln!("Signed {name} as {signer}");
+        Ok(())
+    }
+
+    pub(crate) fn verify(db: &Database, tree_id: Option<i64>) -> anyhow::Result<()> {
+        let (checked, mismatches) = db.verify_hashes(tree_id)?;
+        for m in &mismatches {
+            let location = match tree_id {
+                Some(_) => String::new(),
+                None => format!(" in {}", m.root.display()),
+            };
+            match &m.expected {
+                Some(expected) => eprintln!(
+                    "{} {} {}{location} changed: expected {expected}, found {}",
+                    "MISMATCH".red().bold(),
+                    m.kind,
+                    m.name.bold(),
+                    m.actual
+                ),
+                None => eprintln!(
+                    "{} {} {}{location} has no recorded hash",
+                    "UNKNOWN".yellow().bold(),
+                    m.kind,
+                    m.name.bold()
+                ),
+            }
+        }
+        let changed = mismatches.iter().filter(|m| m.expected.is_some()).count();
+        eprintln!(
+            "Checked {checked} {}, {changed} changed",
+            plural(checked as u64, "item", "items")
+        );
+        if changed > 0 {
+            std::process::exit(1);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn signature_policy(db: &Database, policy: Option<&str>) -> anyhow::Result<()> {
+        match policy {
+            Some(policy) => {
+                let policy: Policy = policy.parse()?;
+                otkeep::save_signature_policy(policy)?;
+                eprintln!("Signature policy set to {}", policy.name());
+            }
+            None => println!("{}", db.signature_policy().name()),
+        }
+        Ok(())
+    }
+
+    pub fn checkout(ctx: &mut AppContext, name: &str) -> anyhow::Result<()> {
+        otkeep::checkout(name, ctx)?;
+        Ok(())
+    }
+
+    pub fn cat(ctx: &AppContext, name: &str, binary: bool) -> anyhow::Result<()> {
+        /// How much of the body is looked at to tell whether it's text
+        const SNIFF_LEN: u64 = 8 * 1024;
+        let mut body = ctx.db.open_script(ctx.root_id, name)?;
+        let mut stdout = std::io::stdout().lock();
+        if binary {
+            return hex_dump(body, &mut stdout);
+        }
+        let mut start = Vec::new();
+        (&mut body).take(SNIFF_LEN).read_to_end(&mut start)?;
+        if stdout.is_terminal() {
+            if let Some(kind) = otkeep::binary_kind(&start) {
+                bail!(
+                    "'{name}' isn't text ({kind}, {}). Use --binary for a hex dump, \
+                     or redirect the output to a file",
+                    format_size(body.len())
+                );
+            }
+        }
+        stdout.write_all(&start)?;
+        std::io::copy(&mut body, &mut stdout)?;
+        Ok(())
+    }
+
+    /// Writes what `data` reads as hex, 16 bytes per line, with the printable characters next to
+    /// them
+    fn hex_dump(data: impl Read, out: &mut impl Write) -> anyhow::Result<()> {
+        let mut data = BufReader::new(data);
+        let mut chunk = Vec::with_capacity(16);
+        for i in 0u64.. {
+            chunk.clear();
+            (&mut data).take(16).read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            write!(out, "{:08x} ", i * 16)?;
+            for j in 0..16 {
+                match chunk.get(j) {
+                    Some(byte) => write!(out, " {byte:02x}")?,
+                    None => write!(out, "   ")?,
+                }
+            }
+            let text: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(out, "  |{text}|")?;
+        }
+        Ok(())
+    }
+
+    pub fn update(
+        ctx: &mut AppContext,
+        name: &str,
+        script: &str,
+        opts: AddOpts,
+    ) -> anyhow::Result<()> {
+        let (mut script_body, origin) = read_script_body(script, opts.inline)?;
+        if let Some(interpreter) = opts.interpreter {
+            add_shebang(&mut script_body, interpreter)?;
+        }
+        if opts.lint && !check_script(&script_body)? {
+            eprintln!("Not updating '{name}'");
+            return Ok(());
+        }
+        if opts.encrypt {
+            ctx.db
+                .update_script_encrypted(ctx.root_id, name, script_body)?;
+        } else {
+            ctx.db.update_script(ctx.root_id, name, script_body)?;
+        }
+        if let Some(origin) = origin {
+            ctx.db.set_script_origin(ctx.root_id, name, &origin)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rename(ctx: &mut AppContext, current: &str, new: &str) -> anyhow::Result<()> {
+        otkeep::rename_script(current, new, ctx)?;
+        Ok(())
+    }
+
+    /// Saves the file at `path`, and returns false if it was already saved with the same
+    /// contents. Files tracked by git are only saved with `force`.
+    pub(crate) fn save(
+        app: &mut AppContext,
+        path: &str,
+        force: bool,
+        encrypt: bool,
+    ) -> anyhow::Result<bool> {
+        if !force && otkeep::git::is_tracked(path)? {
+            bail!(
+                "{path} is tracked by git, so it doesn't need to be kept out of tree. \
+                 Use --force to save it anyway"
+            );
+        }
+        let size = std::fs::metadata(path)?.len();
+        if let Some(problem) = app
+            .db
+            .size_limit_problem(ItemKind::File, app.root_id, path, size)?
+        {
+            if !force {
+                bail!(
+                    "{problem}. Use --force to save it anyway, \
+                     or change the limits with okeep tree size-limits"
+                );
+            }
+            eprintln!("Warning: {problem}");
+        }
+        Ok(otkeep::add_file(app, path, File::open(path)?, encrypt)?)
+    }
+
+    pub(crate) fn restore(
+        app: &mut AppContext,
+        path: Option<&str>,
+        version: Option<i64>,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                otkeep::list_files(app)?;
+                return Ok(());
+            }
+        };
+        let blob_id = app.db.file_blob_id(app.root_id, path, version)?;
+        if !force && !confirm_restore_tracked(app, path, blob_id)? {
+            return Ok(());
+        }
+        write_blob_to(app, blob_id, path)
+    }
+
+    /// Writes the contents of a blob to the file at `path`, streaming them out
+    fn write_blob_to(app: &AppContext, blob_id: i64, path: &str) -> anyhow::Result<()> {
+        let mut blob = app.db.open_blob(blob_id)?;
+        std::io::copy(&mut blob, &mut File::create(path)?)?;
+        Ok(())
+    }
+
+    /// Asks whether to restore `path` if git tracks it, and the checked out version differs from
+    /// the contents of the blob. Returns whether to go on.
+    fn confirm_restore_tracked(app: &AppContext, path: &str, blob_id: i64) -> anyhow::Result<bool> {
+        if !otkeep::git::is_tracked(path)? {
+            return Ok(true);
+        }
+        // The stored hash of an encrypted blob is of the encrypted contents
+        let stored = if app.db.blob_encrypted(blob_id)? {
+            Some(content_hash_reader(app.db.open_blob(blob_id)?)?)
+        } else {
+            app.db.blob_hash(blob_id)?
+        };
+        let differs = File::open(path)
+            .and_then(content_hash_reader)
+            .is_ok_and(|current| Some(current) != stored);
+        if differs {
+            return confirm(&format!(
+                "{path} is tracked by git, and restoring it replaces the checked out version. \
+                 Restore it anyway?"
+            ));
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn suggest_save(
+        app: &mut AppContext,
+        all: bool,
+        yes: bool,
+        dry_run: bool,
+    ) -> anyhow::Result<()> {
+        let candidates = otkeep::save_suggestions::candidates(&app.db, app.root_id, all)?;
+        if candidates.is_empty() {
+            eprintln!("Found no ignored files that look worth saving");
+            return Ok(());
+        }
+        let mut saved = 0;
+        for candidate in candidates {
+            let what = format!(
+                "{} ({}, {})",
+                candidate.path,
+                candidate.reason,
+                format_size(candidate.size)
+            );
+            if dry_run {
+                println!("{what}");
+                continue;
+            }
+            if yes || confirm(&format!("Save {what}?"))? {
+                save(app, &candidate.path, false, false)?;
+                eprintln!("Saved {}", candidate.path);
+                saved += 1;
+            }
+        }
+        if !dry_run {
+            eprintln!("Saved {saved} files. Restore them with okeep restore <path>");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn suggest(
+        app: &mut AppContext,
+        root: &Path,
+        yes: bool,
+        dry_run: bool,
+    ) -> anyhow::Result<()> {
+        let suggestions = otkeep::script_suggestions::suggestions(&app.db, app.root_id, root)?;
+        if suggestions.is_empty() {
+            eprintln!(
+                "Found nothing to suggest. Scripts are suggested for a Makefile, Cargo.toml, \
+                 package.json or flake.nix in the root, unless they exist already"
+            );
+            return Ok(());
+        }
+        let mut added = Vec::new();
+        for suggestion in suggestions {
+            let command = suggestion
+                .body
+                .lines()
+                .skip(1)
+                .collect::<Vec<_>>()
+                .join("; ");
+            let what = format!(
+                "{}: {} ({})",
+                suggestion.name, suggestion.desc, suggestion.source
+            );
+            if dry_run {
+                println!("{what}");
+                println!("    {}", command.bright_black());
+                continue;
+            }
+            eprintln!("{what}");
+            eprintln!("    {}", command.bright_black());
+            if yes || confirm(&format!("Add {}?", suggestion.name))? {
+                app.db
+                    .add_script(app.root_id, suggestion.name, suggestion.body.into())?;
+                app.db
+                    .add_script_description(app.root_id, suggestion.name, &suggestion.desc)?;
+                added.push(suggestion.name);
+            }
+        }
+        if !dry_run {
+            match added.as_slice() {
+                [] => eprintln!("Didn't add any scripts"),
+                names => eprintln!("Added scripts: {}", names.join(", ")),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn file_versions(app: &AppContext, path: &str) -> anyhow::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        for version in app.db.file_versions(app.root_id, path)? {
+            let saved = version
+                .saved_at
+                .map_or("unknown".into(), |t| format_age(now - t));
+            let current = if version.current { "  (current)" } else { "" };
+            println!(
+                "{:>4}  {saved:<16}  {}{current}",
+                version.version,
+                format_size(version.size)
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn restore_bundle(
+        app: &mut AppContext,
+        name: &str,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let Some(files) = app.db.file_bundle(app.root_id, name)? else {
+            bail!("There is no bundle named '{name}'");
+        };
+        for file in &files {
+            let blob_id = app
+                .db
+                .file_blob_id(app.root_id, file, None)
+                .with_context(|| format!("Failed to get {file}"))?;
+            if !force && !confirm_restore_tracked(app, file, blob_id)? {
+                eprintln!("Skipped {file}");
+                continue;
+            }
+            write_blob_to(app, blob_id, file).with_context(|| format!("Failed to write {file}"))?;
+            eprintln!("Restored {file}");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn bundle(app: &mut AppContext, bundle_cmd: &BundleSubCmd) -> anyhow::Result<()> {
+        match bundle_cmd {
+            BundleSubCmd::Create { name, files, force } => {
+                for file in files {
+                    if app.db.file_blob_id(app.root_id, file, None).is_err() {
+                        save(app, file, *force, false)
+                            .with_context(|| format!("Failed to save {file}"))?;
+                        eprintln!("Saved {file}");
+                    }
+                }
+                app.db.add_file_bundle(app.root_id, name, files, *force)?;
+                eprintln!("Created bundle '{name}' with {} files", files.len());
+            }
+            BundleSubCmd::List => {
+                let bundles = app.db.file_bundles(app.root_id)?;
+                if bundles.is_empty() {
+                    eprintln!(
+                        "No bundles have been created yet. To create one, use okeep bundle create."
+                    );
+                }
+                for (name, files) in bundles {
+                    println!("{name}: {}", files.join(", "));
+                }
+            }
+            BundleSubCmd::Remove { name } => {
+                if !app.db.remove_file_bundle(app.root_id, name)? {
+                    bail!("There is no bundle named '{name}'");
+                }
+                eprintln!("Removed bundle '{name}'");
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn run(app: &mut AppContext, name: &str, args: &[OsString]) -> anyhow::Result<!> {
+        if app.db.script_clean_env(app.root_id, name).unwrap_or(false) {
+            otkeep::clean_env(&[]);
+        }
+        crate::ask_trust(&mut app.db, app.root_id, &[name.to_owned()])?;
+        if let Some(status) = otkeep::run_needs(app, name).context("Failed to run dependencies")? {
+            std::process::exit(otkeep::exit_code(status));
+        }
+        app.db
+            .run_script(app.root_id, name, args.iter())
+            .context("Failed to run script")
+    }
+
+    pub(crate) fn logs(
+        app: &AppContext,
+        name: &str,
+        run: Option<i64>,
+        format: Format,
+    ) -> anyhow::Result<()> {
+        if let Some(run) = run {
+            match app.db.run_output(app.root_id, name, run)? {
+                Some(output) => std::io::stdout().write_all(&output)?,
+                None => {
+                    bail!("The output of run {run} wasn't captured. Use orun --log to capture it")
+                }
+            }
+            return Ok(());
+        }
+        let runs = app.db.runs(app.root_id, name)?;
+        if format != Format::Text {
+            let mut table = Table::new(&[
+                "run",
+                "name",
+                "started",
+                "duration_ms",
+                "exit_code",
+                "output_size",
+            ]);
+            for run in runs {
+                table.push(vec![
+                    run.id.into(),
+                    run.name.into(),
+                    run.started.into(),
+                    run.duration.map(|d| d.as_millis() as i64).into(),
+                    i64::from(run.exit_code).into(),
+                    run.output_size.into(),
+                ]);
+            }
+            return table.print(format);
+        }
+        if runs.is_empty() {
+            eprintln!("No runs of '{name}' have been recorded. Use orun --log to record one");
+            return Ok(());
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        for run in runs {
+            let output = match run.output_size {
+                Some(size) => format_size(size),
+                None => "not captured".into(),
+            };
+            eprintln!(
+                "{:>6}  {:16}  exit code {:<3}  {output}",
+                run.id,
+                format_age(now - run.started),
+                run.exit_code
+            );
+        }
+        eprintln!("\nTo print the output of a run, use okeep logs {name} <run>");
+        Ok(())
+    }
+
+    pub(crate) fn last(app: &AppContext, name: Option<&str>) -> anyhow::Result<()> {
+        let Some(run) = app.db.last_run(app.root_id, name)? else {
+            match name {
+                Some(name) => eprintln!("No runs of '{name}' have been recorded"),
+                None => eprintln!("No runs have been recorded for the current tree"),
+            }
+            eprintln!("Use orun --log to record the output of a run");
+            return Ok(());
+        };
+        match app.db.run_output(app.root_id, &run.name, run.id)? {
+            Some(output) => std::io::stdout().write_all(&output)?,
+            None => eprintln!("(The output wasn't captured. Use orun --log to capture it)"),
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        eprintln!(
+            "\n'{}' exited with code {}, {}",
+            run.name,
+            run.exit_code,
+            format_age(now - run.started)
+        );
+        Ok(())
+    }
+
+    pub(crate) fn stats(app: &AppContext, name: &str) -> anyhow::Result<()> {
+        /// How many of the most recent runs are listed
+        const RECENT: usize = 10;
+        const BAR_WIDTH: f64 = 30.;
+        let runs = app.db.runs(app.root_id, name)?;
+        if runs.is_empty() {
+            eprintln!("No runs of '{name}' have been recorded. Runs are recorded by otrun");
+            return Ok(());
+        }
+        let succeeded = runs.iter().filter(|run| run.exit_code == 0).count();
+        let count = runs.len() as u64;
+        eprintln!(
+            "{count} {} of '{name}', {succeeded} succeeded",
+            plural(count, "run", "runs")
+        );
+        let timed: Vec<_> = runs.iter().filter(|run| run.duration.is_some()).collect();
+        let durations: Vec<Duration> = timed.iter().filter_map(|run| run.duration).collect();
+        let (Some(min), Some(max)) = (durations.iter().min(), durations.iter().max()) else {
+            eprintln!("No durations have been recorded");
+            return Ok(());
+        };
+        eprintln!(
+            "Duration: min {}, mean {}, max {}",
+            format_duration(*min),
+            format_duration(mean(&durations)),
+            format_duration(*max)
+        );
+        eprintln!("\nRecent runs:");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        for run in &timed[timed.len().saturating_sub(RECENT)..] {
+            let duration = run.duration.unwrap_or_default();
+            let bar = "█".repeat(
+                (duration.as_secs_f64() / max.as_secs_f64().max(f64::EPSILON) * BAR_WIDTH).ceil()
+                    as usize,
+            );
+            let failed = match run.exit_code {
+                0 => String::new(),
+                code => format!(" (exit code {code})").red().to_string(),
+            };
+            eprintln!(
+                "  {:16}  {:>8}  {bar}{failed}",
+                format_age(now - run.started),
+                format_duration(duration)
+            );
+        }
+        if durations.len() >= 4 {
+            let split = durations.len() - (durations.len() / 2).min(5);
+            let (before, recent) = durations.split_at(split);
+            let (before_mean, recent_mean) = (mean(before), mean(recent));
+            let change = (recent_mean.as_secs_f64() / before_mean.as_secs_f64().max(f64::EPSILON)
+                - 1.)
+                * 100.;
+            let direction = if change < 0. { "faster" } else { "slower" };
+            eprintln!(
+                "\nThe last {} runs took {} on average, {:.0}% {direction} than the {} before them",
+                recent.len(),
+                format_duration(recent_mean),
+                change.abs(),
+                before.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints how much space the items of the current tree take, flagging the ones over its
+    /// size limits
+    pub(crate) fn size_stats(app: &AppContext) -> anyhow::Result<()> {
+        /// How many of the largest items are listed
+        const LARGEST: usize = 5;
+        let limits = app.db.tree_size_limits(app.root_id)?;
+        let total = app.db.tree_size(app.root_id)?;
+        let over_total = limits.total.is_some_and(|limit| total > limit);
+        let total_line = match limits.total {
+            Some(limit) => format!(
+                "The items of this tree take {} of their limit of {}",
+                format_size(total),
+                format_size(limit)
+            ),
+            None => format!("The items of this tree take {}", format_size(total)),
+        };
+        if over_total {
+            println!("{}", total_line.red());
+        } else {
+            println!("{total_line}");
+        }
+        let chunks = app.db.chunk_stats(app.root_id)?;
+        if chunks.files > 0 {
+            println!(
+                "{} large saved files and versions are stored as {} chunks, \
+                 which take {} instead of {}",
+                chunks.files,
+                chunks.chunks,
+                format_size(chunks.stored),
+                format_size(chunks.size)
+            );
+        }
+        let opts = ListOpts {
+            sort: otkeep::database::ItemSort::Size,
+            filter: None,
+            limit: None,
+        };
+        let mut items: Vec<_> = app
+            .db
+            .list_items(ItemKind::Script, app.root_id, opts)?
+            .into_iter()
+            .map(|item| (ItemKind::Script, item))
+            .chain(
+                app.db
+                    .list_items(ItemKind::File, app.root_id, opts)?
+                    .into_iter()
+                    .map(|item| (ItemKind::File, item)),
+            )
+            .collect();
+        items.sort_by_key(|(_, item)| std::cmp::Reverse(item.size));
+        let is_over = |size: u64| limits.item.is_some_and(|limit| size > limit);
+        let over: Vec<_> = items
+            .iter()
+            .filter(|(_, item)| is_over(item.size))
+            .collect();
+        if let Some(limit) = limits.item.filter(|_| !over.is_empty()) {
+            println!(
+                "\nOver the limit of {} for a single item:",
+                format_size(limit)
+            );
+            for (kind, item) in &over {
+                let line = format!("  {kind:6}  {:>10}  {}", format_size(item.size), item.name);
+                println!("{}", line.red());
+            }
+        }
+        if !items.is_empty() {
+            println!("\nLargest items:");
+        }
+        for (kind, item) in items.iter().take(LARGEST) {
+            println!("  {kind:6}  {:>10}  {}", format_size(item.size), item.name);
+        }
+        Ok(())
+    }
+
+    fn mean(durations: &[Duration]) -> Duration {
+        durations.iter().sum::<Duration>() / durations.len().max(1) as u32
+    }
+
+    /// Runs the scripts in order, and returns the exit code of the first one that failed
+    pub(crate) fn run_seq(
+        app: &mut AppContext,
+        names: &[String],
+        keep_going: bool,
+    ) -> anyhow::Result<i32> {
+        let existing: HashSet<String> = app
+            .db
+            .scripts_for_tree(app.root_id)?
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        let missing: Vec<&str> = names
+            .iter()
+            .filter(|name| !existing.contains(*name))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "No such script(s) for the current tree: {}",
+                missing.join(", ")
+            );
+        }
+        crate::ask_trust(&mut app.db, app.root_id, names)?;
+        let mut results = Vec::new();
+        let mut first_failure = None;
+        for name in names {
+            if first_failure.is_some() && !keep_going {
+                break;
+            }
+            eprintln!("{}", format!("==> {name}").bold());
+            let start = Instant::now();
+            let status = app
+                .db
+                .run_script_supervised(app.root_id, name, std::iter::empty::<&str>())
+                .with_context(|| format!("Failed to run {name}"))?;
+            let code = otkeep::exit_code(status);
+            if code != 0 && first_failure.is_none() {
+                first_failure = Some(code);
+            }
+            results.push((code, start.elapsed()));
+        }
+        let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+        eprintln!("\nSummary:");
+        for (i, name) in names.iter().enumerate() {
+            match results.get(i) {
+                Some((0, elapsed)) => eprintln!(
+                    "  {name:width$}  {}  {:.1}s",
+                    "ok    ".green(),
+                    elapsed.as_secs_f64()
+                ),
+                Some((code, elapsed)) => eprintln!(
+                    "  {name:width$}  {}  {:.1}s (exit code {code})",
+                    "failed".red(),
+                    elapsed.as_secs_f64()
+                ),
+                None => eprintln!("  {name:width$}  {}", "skipped".dimmed()),
+            }
+        }
+        Ok(first_failure.unwrap_or(0))
+    }
+
+    pub(crate) fn shell(app: &AppContext, root_path: &Path) -> anyhow::Result<()> {
+        let shell = std::env::var_os("SHELL").unwrap_or_else(|| "/bin/sh".into());
+        let dir = temp_dir::TempDir::new()?;
+        let exe = std::env::current_exe().context("Failed to determine own executable path")?;
+        let scripts = app.db.scripts_for_tree(app.root_id)?;
+        otkeep::shims::write_shims(
+            dir.path(),
+            &exe,
+            app.root_id,
+            &app.db.tree_uuid(app.root_id)?,
+            scripts.iter().map(|s| s.name.as_str()),
+        )?;
+        let mut paths = vec![dir.path().to_owned()];
+        if let Some(path) = std::env::var_os("PATH") {
+            paths.extend(std::env::split_paths(&path));
+        }
+        eprintln!(
+            "Entering OtKeep shell for {}. Exit the shell to leave.",
+            root_path.display()
+        );
+        std::process::Command::new(shell)
+            .env("PATH", std::env::join_paths(paths)?)
+            .env("OTKEEP_SHELL", root_path)
+            .status()?;
+        eprintln!("Left OtKeep shell for {}", root_path.display());
+        Ok(())
+    }
+
+    pub(crate) fn schedule(
+        app: &AppContext,
+        root_path: &Path,
+        schedule_cmd: &ScheduleSubCmd,
+    ) -> anyhow::Result<()> {
+        let uuid = app.db.tree_uuid(app.root_id)?;
+        match schedule_cmd {
+            ScheduleSubCmd::Add {
+                name,
+                calendar,
+                cron,
+            } => {
+                if !app.db.script_exists(app.root_id, name)? {
+                    bail!("No script named '{name}' for the current tree");
+                }
+                let exe =
+                    std::env::current_exe().context("Failed to determine own executable path")?;
+                match (calendar, cron) {
+                    (_, Some(cron)) => {
+                        schedule::install_cron(&exe, root_path, &uuid, name, cron)
+                            .context("Failed to add cron job")?;
+                        eprintln!("Added cron job running '{name}' on '{cron}'");
+                    }
+                    (Some(calendar), None) => {
+                        schedule::install_systemd(&exe, root_path, &uuid, name, calendar)
+                            .context("Failed to add systemd timer")?;
+                        eprintln!("Added systemd timer running '{name}' on '{calendar}'");
+                    }
+                    (None, None) => unreachable!(),
+                }
+            }
+            ScheduleSubCmd::List => {
+                let schedules = schedule::list(&uuid)?;
+                if schedules.is_empty() {
+                    eprintln!("No scripts of the current tree are scheduled");
+                }
+                for s in schedules {
+                    eprintln!("{}: {} ({})", s.name, s.when, s.scheduler);
+                }
+            }
+            ScheduleSubCmd::Remove { name } => match schedule::remove(&uuid, name)? {
+                0 => eprintln!("'{name}' is not scheduled"),
+                _ => eprintln!("'{name}' is no longer scheduled"),
+            },
+        }
+        Ok(())
+    }
+
+    pub(crate) fn shims_install(app: &AppContext, dir: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let dir = std::fs::canonicalize(dir)?;
+        if !app.db.add_shim_dir(app.root_id, &dir)? {
+            eprintln!(
+                "{} is already a shim directory, regenerating",
+                dir.display()
+            );
+        }
+        otkeep::shims::refresh_shims(&app.db, app.root_id)?;
+        eprintln!("Installed shims into {}", dir.display());
+        Ok(())
+    }
+
+    pub(crate) fn shims_uninstall(app: &AppContext, dir: &Path) -> anyhow::Result<()> {
+        let dir = std::fs::canonicalize(dir)?;
+        if !app.db.remove_shim_dir(app.root_id, &dir)? {
+            bail!(
+                "{} is not a shim directory of the current tree",
+                dir.display()
+            );
+        }
+        otkeep::shims::remove_shims(&dir, app.root_id, &HashSet::new())?;
+        eprintln!("Removed shims from {}", dir.display());
+        Ok(())
+    }
+
+    /// Adds the line that loads the hook to the config file of `shell`, or of the shell in $SHELL
+    pub(crate) fn install_hook(shell: Option<ShellKind>, yes: bool) -> anyhow::Result<()> {
+        let shell = match shell {
+            Some(shell) => shell,
+            None => {
+                let var = std::env::var_os("SHELL").unwrap_or_default();
+                match Path::new(&var).file_name().and_then(|name| name.to_str()) {
+                    Some("bash") => ShellKind::Bash,
+                    Some("zsh") => ShellKind::Zsh,
+                    Some("fish") => ShellKind::Fish,
+                    _ => {
+                        eprintln!(
+                            "Can't tell which shell you use. Add the hook with `okeep init --shell <SHELL>`, \
+                             or see `okeep hook --help`"
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        let dirs = directories::BaseDirs::new().context("Failed to get the home directory")?;
+        let (config, line) = match shell {
+            ShellKind::Bash => (
+                dirs.home_dir().join(".bashrc"),
+                "eval \"$(okeep hook bash)\"",
+            ),
+            ShellKind::Zsh => (
+                std::env::var_os("ZDOTDIR")
+                    .map_or_else(|| dirs.home_dir().to_owned(), PathBuf::from)
+                    .join(".zshrc"),
+                "eval \"$(okeep hook zsh)\"",
+            ),
+            ShellKind::Fish => (
+                dirs.config_dir().join("fish/config.fish"),
+                "okeep hook fish | source",
+            ),
+        };
+        let contents = match std::fs::read_to_string(&config) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(format!("Failed to read {}", config.display())),
+        };
+        if contents.contains("okeep hook") {
+            eprintln!("The shell hook is in {} already", config.display());
+            return Ok(());
+        }
+        if !yes && !confirm(&format!("Add the shell hook to {}?", config.display()))? {
+            return Ok(());
+        }
+        if let Some(dir) = config.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&config)?;
+        let newline = if contents.is_empty() || contents.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        write!(file, "{newline}# Added by okeep init\n{line}\n")?;
+        eprintln!(
+            "Added the shell hook to {}. It's loaded in new shells",
+            config.display()
+        );
+        Ok(())
+    }
+
+    pub(crate) fn hook_script(shell: ShellKind) -> &'static str {
+        match shell {
+            ShellKind::Bash => include_str!("hooks/bash.sh"),
+            ShellKind::Zsh => include_str!("hooks/zsh.sh"),
+            ShellKind::Fish => include_str!("hooks/fish.fish"),
+        }
+    }
+
+    /// Prints the tree root, then a one line summary of the scripts on the next line.
+    ///
+    /// Prints nothing outside of trees, or if the database is encrypted and its passphrase isn't
+    /// stored. Summaries are cached like the prompt indicators, except for encrypted databases,
+    /// whose script names shouldn't end up in the cache.
+    pub(crate) fn hook_summary() -> anyhow::Result<()> {
+        let db_path = otkeep::db_file_path()?;
+        let Ok(db_meta) = std::fs::metadata(&db_path) else {
+            return Ok(());
+        };
+        if otkeep::encryption::is_encrypted(&db_path)? {
+            let Some(db) = otkeep::load_db_unattended(false)? else {
+                return Ok(());
+            };
+            if let Some((root_id, root_path)) = otkeep::find_root(&db)? {
+                let summary = tree_summary(&db, root_id)?;
+                println!("{}\n{summary}", root_path.display());
+            }
+            return Ok(());
+        }
+        // The root is encoded, so the entry stays on one line
+        let entry = cached_for_current_dir("hook-summary", &db_meta, |dir| {
+            let db = otkeep::load_db(false)?;
+            Ok(match otkeep::find_root_for_path(&db, dir)? {
+                Some((root_id, root_path)) => format!(
+                    "{}\t{}",
+                    paths_as_strings::encode_path(&root_path),
+                    tree_summary(&db, root_id)?
+                ),
+                None => String::new(),
+            })
+        })?;
+        if let Some((root, summary)) = entry.split_once('\t') {
+            let root_path = paths_as_strings::decode_path(root)?;
+            println!("{}\n{summary}", root_path.display());
+        }
+        Ok(())
+    }
+
+    /// The number of scripts of a tree, and the first few of their names
+    fn tree_summary(db: &Database, root_id: i64) -> anyhow::Result<String> {
+        const MAX_LISTED: usize = 5;
+        let scripts = db.scripts_for_tree(root_id)?;
+        let mut names: Vec<&str> = scripts.iter().map(|s| s.name.as_str()).collect();
+        let more = names.len().saturating_sub(MAX_LISTED);
+        names.truncate(MAX_LISTED);
+        let mut summary = format!(
+            "OtKeep: {} script{}",
+            scripts.len(),
+            if scripts.len() == 1 { "" } else { "s" }
+        );
+        if !names.is_empty() {
+            summary.push_str(&format!(" ({}", names.join(", ")));
+            if more > 0 {
+                summary.push_str(&format!(", +{more} more"));
+            }
+            summary.push(')');
+        }
+        // Script names can contain anything, and the summary has to stay on one line
+        Ok(summary.replace(char::is_control, "?"))
+    }
+
+    /// Prints the prompt indicator for the current directory
+    pub(crate) fn prompt() -> anyhow::Result<()> {
+        let db_path = otkeep::db_file_path()?;
+        let Ok(db_meta) = std::fs::metadata(&db_path) else {
+            return Ok(());
+        };
+        // Asking for the passphrase on every prompt would be worse than showing nothing
+        if otkeep::encryption::is_encrypted(&db_path)? {
+            return Ok(());
+        }
+        let indicator = cached_for_current_dir("prompt", &db_meta, |dir| {
+            let db = otkeep::load_db(false)?;
+            Ok(match otkeep::find_root_for_path(&db, dir)? {
+                Some((root_id, root_path)) => {
+                    let name = root_path
+                        .file_name()
+                        .unwrap_or(root_path.as_os_str())
+                        .to_string_lossy()
+                        .replace(char::is_control, "?");
+                    format!("{name}:{}", db.scripts_for_tree(root_id)?.len())
+                }
+                None => String::new(),
+            })
+        })?;
+        println!("{indicator}");
+        Ok(())
+    }
+
+    /// Returns the entry for the current directory from the cache file `name`, or computes it
+    /// with `compute` and caches it. Entries must be single lines.
+    ///
+    /// The whole cache is thrown away whenever the database file, whose metadata is `db_meta`, is
+    /// modified.
+    fn cached_for_current_dir(
+        name: &str,
+        db_meta: &std::fs::Metadata,
+        compute: impl FnOnce(&Path) -> anyhow::Result<String>,
+    ) -> anyhow::Result<String> {
+        const MAX_CACHE_ENTRIES: usize = 64;
+        let db_mtime = db_meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos()
+            .to_string();
+        let current_dir = std::env::current_dir()?;
+        let key = paths_as_strings::encode_path(&current_dir);
+        let cache_path = otkeep::cache_dir()?.join(name);
+        let cache = std::fs::read_to_string(&cache_path).unwrap_or_default();
+        let mut lines = cache.lines();
+        let mut entries: Vec<(&str, &str)> = Vec::new();
+        if lines.next() == Some(db_mtime.as_str()) {
+            entries.extend(lines.filter_map(|line| line.split_once('\t')));
+        }
+        if let Some((_, entry)) = entries.iter().find(|(dir, _)| *dir == key) {
+            return Ok(entry.to_string());
+        }
+        let entry = compute(&current_dir)?;
+        entries.retain(|(dir, _)| *dir != key);
+        let skip = (entries.len() + 1).saturating_sub(MAX_CACHE_ENTRIES);
+        let mut new_cache = format!("{db_mtime}\n");
+        for (dir, entry) in entries.iter().skip(skip) {
+            new_cache.push_str(&format!("{dir}\t{entry}\n"));
+        }
+        new_cache.push_str(&format!("{key}\t{entry}\n"));
+        std::fs::write(cache_path, new_cache)?;
+        Ok(entry)
+    }
+
+    pub(crate) fn export_aliases(
+        app: &AppContext,
+        shell: ShellKind,
+        prefix: &str,
+    ) -> anyhow::Result<()> {
+        for script in app.db.scripts_for_tree(app.root_id)? {
+            let alias = format!("{prefix}{}", script.name);
+            if alias.is_empty()
+                || alias
+                    .chars()
+                    .any(|c| c.is_whitespace() || "/$`='\"\\;&|<>()".contains(c))
+            {
+                eprintln!("Skipping '{alias}', it's not a valid alias name");
+                continue;
+            }
+            // The script name is part of a valid alias name, so it needs no quoting
+            let command = format!("orun {}", script.name);
+            match shell {
+                ShellKind::Bash | ShellKind::Zsh => {
+                    println!("alias {alias}={}", shell_quote(&command))
+                }
+                ShellKind::Fish => println!("alias {alias} {}", fish_quote(&command)),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn export_bundle(app: &AppContext, path: &Path, force: bool) -> anyhow::Result<()> {
+        if path.exists() && !force {
+            bail!(
+                "{} already exists. Use --force to overwrite it",
+                path.display()
+            );
+        }
+        for script in app.db.scripts_for_tree(app.root_id)? {
+            let name = &script.name;
+            if app.db.script_sandbox(app.root_id, name)?.is_some() {
+                eprintln!("Note: '{name}' will run without its sandbox");
+            }
+            if let Some(image) = app.db.script_container(app.root_id, name)? {
+                eprintln!("Note: '{name}' will run outside of its container ({image})");
+            }
+            if !app.db.script_env(app.root_id, name)?.is_empty() {
+                eprintln!("Note: '{name}' will run without its environment variables");
+            }
+        }
+        if app.db.tree_dev_shell(app.root_id)?.is_some() {
+            eprintln!("Note: the scripts will run outside of the nix dev shell of the tree");
+        }
+        let bundle = otkeep::bundle::bundle(&app.db, app.root_id, &mut progress_bar)?;
+        std::fs::write(path, bundle)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+        eprintln!("Wrote {}", path.display());
+        Ok(())
+    }
+
+    pub(crate) fn export_pre_commit(app: &AppContext, tag: &str) -> anyhow::Result<()> {
+        let mut hooks = Vec::new();
+        for script in app.db.scripts_for_tree(app.root_id)? {
+            if app
+                .db
+                .script_tags(app.root_id, &script.name)?
+                .iter()
+                .any(|t| t == tag)
+            {
+                hooks.push(script);
+            }
+        }
+        if hooks.is_empty() {
+            bail!(
+                "No scripts of the current tree are tagged '{tag}'. \
+                 Tag them with okeep mod <name> --tags {tag}"
+            );
+        }
+        // Double quoted JSON strings are valid YAML too
+        println!("# Generated by okeep export pre-commit");
+        println!("repos:");
+        println!("  - repo: local");
+        println!("    hooks:");
+        for script in hooks {
+            let name = match script.description.as_str() {
+                "" => &script.name,
+                desc => desc,
+            };
+            println!("      - id: {}", json_string(&script.name));
+            println!("        name: {}", json_string(name));
+            let entry = format!("orun {}", shell_quote(&script.name));
+            println!("        entry: {}", json_string(&entry));
+            println!("        language: system");
+            println!("        pass_filenames: false");
+            println!("        always_run: true");
+        }
+        Ok(())
+    }
+
+    /// Built-in cargo commands, which take precedence over aliases
+    const CARGO_COMMANDS: &[&str] = &[
+        "add",
+        "b",
+        "bench",
+        "build",
+        "c",
+        "check",
+        "clean",
+        "config",
+        "d",
+        "doc",
+        "fetch",
+        "fix",
+        "generate-lockfile",
+        "help",
+        "info",
+        "init",
+        "install",
+        "locate-project",
+        "login",
+        "logout",
+        "metadata",
+        "new",
+        "owner",
+        "package",
+        "pkgid",
+        "publish",
+        "r",
+        "read-manifest",
+        "remove",
+        "report",
+        "rm",
+        "run",
+        "rustc",
+        "rustdoc",
+        "search",
+        "t",
+        "test",
+        "tree",
+        "uninstall",
+        "update",
+        "vendor",
+        "verify-project",
+        "version",
+        "yank",
+    ];
+
+    /// Adds the cargo aliases to the `[alias]` table of `.cargo/config.toml` in `root`, keeping the
+    /// rest of the file as it is
+    pub(crate) fn export_cargo_aliases(
+        app: &AppContext,
+        root: &Path,
+        prefix: &str,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let path = root.join(".cargo/config.toml");
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+        };
+        let mut doc: toml_edit::DocumentMut = contents
+            .parse()
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        // Also finds aliases given as dotted keys or inline tables
+        let table = doc
+            .entry("alias")
+            .or_insert(toml_edit::table())
+            .as_table_like_mut()
+            .with_context(|| format!("`alias` in {} isn't a table", path.display()))?;
+        let mut written = 0;
+        for script in app.db.scripts_for_tree(app.root_id)? {
+            let alias = format!("{prefix}{}", script.name);
+            if alias.is_empty()
+                || !alias
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                eprintln!("Skipping '{alias}', it's not a valid alias name");
+                continue;
+            }
+            if CARGO_COMMANDS.contains(&alias.as_str()) {
+                eprintln!("Skipping '{alias}', it's a built-in cargo command");
+                continue;
+            }
+            let command = format!("orun {}", script.name);
+            match table.get(&alias) {
+                Some(existing) if existing.as_str() == Some(command.as_str()) => continue,
+                Some(_) if !force => {
+                    eprintln!(
+                        "Skipping '{alias}', it's an alias for something else already. \
+                         Use --force to replace it"
+                    );
+                    continue;
+                }
+                _ => {}
+            }
+            table.insert(&alias, toml_edit::value(command));
+            written += 1;
+        }
+        if written == 0 {
+            eprintln!("{} has all the aliases already", path.display());
+            return Ok(());
+        }
+        std::fs::create_dir_all(root.join(".cargo"))?;
+        std::fs::write(&path, doc.to_string())?;
+        eprintln!(
+            "Wrote {written} {} to {}",
+            plural(written, "alias", "aliases"),
+            path.display()
+        );
+        eprintln!(
+            "They run through cargo-orun, which `otkeep --install-links` links in the same \
+             directory as orun"
+        );
+        Ok(())
+    }
+
+    /// Quotes `s` as a single quoted fish string
+    fn fish_quote(s: &str) -> String {
+        format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+    }
+
+    /// How to handle items that already exist when cloning or importing
+    #[derive(Clone, Copy)]
+    pub(crate) enum ConflictPolicy {
+        Fail,
+        Skip,
+        Overwrite,
+        Ask,
+    }
+
+    impl ConflictPolicy {
+        pub(crate) fn from_flags(skip_existing: bool, overwrite: bool, interactive: bool) -> Self {
+            if skip_existing {
+                Self::Skip
+            } else if overwrite {
+                Self::Overwrite
+            } else if interactive {
+                Self::Ask
+            } else {
+                Self::Fail
+            }
+        }
+
+        /// Decides what to do with an existing item, described by `what`
+        fn resolve(self, what: &str) -> anyhow::Result<ConflictResolution> {
+            Ok(match self {
+                Self::Fail => bail!(
+                    "{what} already exists. Use --skip-existing, --overwrite or --interactive"
+                ),
+                Self::Skip => ConflictResolution::Skip,
+                Self::Overwrite => ConflictResolution::Overwrite,
+                Self::Ask => {
+                    if confirm(&format!("{what} already exists. Overwrite?"))? {
+                        ConflictResolution::Overwrite
+                    } else {
+                        ConflictResolution::Skip
+                    }
+                }
+            })
+        }
+    }
+
+    pub(crate) fn clone(
+        app: &mut AppContext,
+        tree: &Path,
+        opts: CloneOpts,
+        policy: ConflictPolicy,
+    ) -> anyhow::Result<()> {
+        let dst = app.root_id;
+        let src = app.db.query_tree(tree)?.context("Missing tree")?;
+        if src == dst {
+            bail!("{} is the current tree", tree.display());
+        }
+        // Conflicts are resolved before anything is cloned, so questions don't mix with the bar
+        let report = app.db.clone_tree(
+            src,
+            dst,
+            opts,
+            |kind, name| {
+                policy
+                    .resolve(&format!("{kind} '{name}' in the current tree"))
+                    .map_err(|e| otkeep::Error::Callback(e.into()))
+            },
+            &mut progress_bar,
+        )?;
+        let mut anything_cloned = false;
+        for (kind, outcome) in [("scripts", &report.scripts), ("files", &report.files)] {
+            for (label, names) in [
+                ("Cloned", &outcome.cloned),
+                ("Overwritten", &outcome.overwritten),
+                ("Skipped", &outcome.skipped),
+                ("Kept locked", &outcome.locked),
+            ] {
+                if !names.is_empty() {
+                    eprintln!("{label} {kind}: {}", names.join(", "));
+                }
+            }
+            anything_cloned |= !outcome.cloned.is_empty() || !outcome.overwritten.is_empty();
+        }
+        if !anything_cloned {
+            eprintln!("Nothing was cloned.");
+        }
+        Ok(())
+    }
+
+    /// Encrypts the database with a new passphrase, which also changes the passphrase of an
+    /// encrypted one
+    pub(crate) fn db_encrypt(db: Database, keyring: bool) -> anyhow::Result<()> {
+        use otkeep::{encryption, secrets};
+        encryption::ensure_supported()?;
+        let path = otkeep::db_file_path()?;
+        let was_encrypted = encryption::is_encrypted(&path)?;
+        if keyring {
+            secrets::ensure_keyring_available()?;
+        }
+        let passphrase = secrets::ask_new("New passphrase for the database")?;
+        otkeep::set_db_passphrase(db, Some(&passphrase))?;
+        if keyring {
+            encryption::keyring_store(&path, &passphrase)?;
+            eprintln!("Stored the passphrase in the keyring");
+        } else {
+            // It would be the old one
+            encryption::keyring_clear(&path)?;
+        }
+        if was_encrypted {
+            eprintln!("Changed the passphrase of the database");
+        } else {
+            eprintln!(
+                "Encrypted the database. Keep the passphrase safe, \
+                 the database can't be opened without it"
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn db_decrypt(db: Database) -> anyhow::Result<()> {
+        let path = otkeep::db_file_path()?;
+        if !otkeep::encryption::is_encrypted(&path)? {
+            bail!("The database isn't encrypted");
+        }
+        otkeep::set_db_passphrase(db, None)?;
+        otkeep::encryption::keyring_clear(&path)?;
+        eprintln!("Decrypted the database");
+        Ok(())
+    }
+
+    /// Imports the contents of another database file
+    pub(crate) fn db_import(
+        db: &mut Database,
+        path: &Path,
+        dry_run: bool,
+        force: bool,
+        policy: ConflictPolicy,
+    ) -> anyhow::Result<()> {
+        if !path.is_file() {
+            bail!("{} is not a file", path.display());
+        }
+        // Work on a copy, because loading applies migrations to the database
+        let dir = temp_dir::TempDir::new()?;
+        std::fs::copy(path, dir.path().join(otkeep::database::DB_FILENAME))?;
+        let key = if otkeep::encryption::is_encrypted(path)? {
+            otkeep::encryption::ensure_supported()?;
+            let desc = format!("Passphrase for {}", path.display());
+            Some(otkeep::secrets::ask(&desc)?)
+        } else {
+            None
+        };
+        let mut other = Database::load(dir.path(), key.as_deref())?;
+        other.set_anchors(db.anchors().clone());
+        let resolve = |root: &Path, kind, name: &str| {
+            let what = format!("{kind} '{name}' in {}", root.display());
+            match policy {
+                // Report conflicts without failing or asking when only previewing
+                ConflictPolicy::Fail | ConflictPolicy::Ask if dry_run => {
+                    Ok(ConflictResolution::Skip)
+                }
+                _ => policy
+                    .resolve(&what)
+                    .map_err(|e| otkeep::Error::Callback(e.into())),
+            }
+        };
+        // Questions would end up in the middle of the progress bar
+        let reports = if matches!(policy, ConflictPolicy::Ask) && !dry_run {
+            db.import(&other, dry_run, force, resolve, &mut ())?
+        } else {
+            db.import(&other, dry_run, force, resolve, &mut progress_bar)?
+        };
+        let (added, overwritten, skipped, locked) = if dry_run {
+            let skipped = match policy {
+                ConflictPolicy::Fail | ConflictPolicy::Ask => "Conflicting",
+                _ => "Would skip",
+            };
+            ("Would add", "Would overwrite", skipped, "Would keep locked")
+        } else {
+            ("Added", "Overwritten", "Skipped", "Kept locked")
+        };
+        let mut anything = false;
+        for report in reports {
+            let changed = [&report.scripts, &report.files].iter().any(|outcome| {
+                !(outcome.cloned.is_empty()
+                    && outcome.overwritten.is_empty()
+                    && outcome.skipped.is_empty()
+                    && outcome.locked.is_empty())
+            });
+            if !report.new && !changed {
+                continue;
+            }
+            anything = true;
+            let new = if report.new { " (new tree)" } else { "" };
+            eprintln!("{}{new}", report.root.display());
+            for (kind, outcome) in [("scripts", &report.scripts), ("files", &report.files)] {
+                for (label, names) in [
+                    (added, &outcome.cloned),
+                    (overwritten, &outcome.overwritten),
+                    (skipped, &outcome.skipped),
+                    (locked, &outcome.locked),
+                ] {
+                    if !names.is_empty() {
+                        eprintln!("    {label} {kind}: {}", names.join(", "));
+                    }
+                }
+            }
+        }
+        if !anything {
+            eprintln!("Nothing to import.");
+        }
+        Ok(())
+    }
+
+    /// The trees tagged `tag`, or an error if there are none
+    pub(crate) fn tagged_trees(db: &Database, tag: &str) -> anyhow::Result<Vec<TreeRootInfo>> {
+        let roots: Vec<_> = db
+            .get_tree_roots()?
+            .into_iter()
+            .filter(|root| root.tags.iter().any(|t| t == tag))
+            .collect();
+        if roots.is_empty() {
+            bail!("No trees are tagged {tag}. Tag one with `okeep tree tag add {tag}`");
+        }
+        Ok(roots)
+    }
+
+    pub(crate) fn db_export_tagged(
+        db: &Database,
+        tag: &str,
+        dest: &Path,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let roots = tagged_trees(db, tag)?;
+        let count = roots.len() as u64;
+        db_export_trees(db, roots, dest, force)?;
+        eprintln!(
+            "Exported {count} {} tagged {tag} to {}",
+            plural(count, "tree", "trees"),
+            dest.display()
+        );
+        Ok(())
+    }
+
+    /// Writes `roots` with their scripts and saved files into a new database file at `dest`
+    pub(crate) fn db_export_trees(
+        db: &Database,
+        roots: Vec<TreeRootInfo>,
+        dest: &Path,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        if dest.exists() && !force {
+            bail!(
+                "{} already exists. Use --force to overwrite it",
+                dest.display()
+            );
+        }
+        let dir = temp_dir::TempDir::new()?;
+        let mut exported = Database::load(dir.path(), None)?;
+        // Keep the roots relative to anchors, so they resolve for others with the same anchors
+        exported.set_anchors(db.anchors().clone());
+        exported.import_trees(
+            db,
+            roots,
+            false,
+            false,
+            |_, _, _| {
+                Err(otkeep::Error::Other(
+                    "The new database should be empty".into(),
+                ))
+            },
+            &mut progress_bar,
+        )?;
+        drop(exported);
+        std::fs::copy(dir.path().join(otkeep::database::DB_FILENAME), dest)?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn ages_are_rounded_down() {
+            assert_eq!(format_age(0), "just now");
+            assert_eq!(format_age(59), "just now");
+            assert_eq!(format_age(60), "1 minute ago");
+            assert_eq!(format_age(3599), "59 minutes ago");
+            assert_eq!(format_age(3600), "1 hour ago");
+            assert_eq!(format_age(86399), "23 hours ago");
+            assert_eq!(format_age(86400), "1 day ago");
+            assert_eq!(format_age(10 * 86400), "10 days ago");
+            // Clocks that went backwards
+            assert_eq!(format_age(-5), "just now");
+        }
+    }
+}
@@ -0,0 +1,589 @@
+use {
+    crate::{format_duration, parse_env_var, table::json_string},
+    anyhow::{bail, Context},
+    clap::{builder::PossibleValuesParser, Parser, ValueEnum},
+    notify::{EventKind, RecursiveMode, Watcher},
+    otkeep::{completion::ArgSpec, exit_code, sandbox::Sandbox, AppContext, Error},
+    owo_colors::OwoColorize,
+    std::{
+        ffi::OsString,
+        io::IsTerminal,
+        os::unix::process::ExitStatusExt,
+        path::Path,
+        sync::mpsc,
+        time::{Duration, Instant},
+    },
+};
+
+/// How long the files have to stay unchanged before the script is run again in watch mode
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Run a script of the current tree
+///
+/// Options must come before the name of the script. Everything after it is passed to the script.
+#[derive(Parser)]
+struct Args {
+    /// Run the script again if it fails, up to this many times
+    #[clap(long, value_name = "N")]
+    retry: Option<u32>,
+    /// Seconds to wait before retrying
+    #[clap(long, value_name = "SECS", default_value_t = 1.0, requires = "retry")]
+    retry_delay: f64,
+    /// Double the delay after every retry
+    #[clap(long, requires = "retry")]
+    backoff: bool,
+    /// Run all the given scripts at the same time, instead of passing arguments to the first one
+    ///
+    /// The output of each script is prefixed with its name. Exits with the exit code of the first
+    /// script in the list that failed. The scripts they need run first, except for the ones that
+    /// are in the list themselves.
+    #[clap(short = 'p', long, conflicts_with = "retry", requires = "command")]
+    parallel: bool,
+    /// Run the script again whenever a file under the tree root matching this glob changes
+    ///
+    /// The pattern is relative to the tree root, e.g. 'src/**'. Can be given multiple times.
+    #[clap(
+        short = 'w',
+        long,
+        value_name = "GLOB",
+        conflicts_with_all = ["retry", "parallel"]
+    )]
+    watch: Vec<String>,
+    /// Run the script this many times, and print how long the runs took
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["retry", "parallel", "watch"]
+    )]
+    bench: Option<u32>,
+    /// Store the output of the script, so it can be viewed later with `okeep logs`
+    ///
+    /// The script runs as a child process, like with otrun. Its output goes through a pipe, so it
+    /// doesn't see a terminal there.
+    #[clap(long, conflicts_with_all = ["parallel", "watch", "bench"])]
+    log: bool,
+    /// Print a banner that's hard to miss when the script fails, with its name and exit code
+    ///
+    /// Also shows the hint set with `okeep mod <name> --failure-hint`. The script runs as a child
+    /// process, like with otrun.
+    #[clap(long, conflicts_with_all = ["parallel", "watch", "bench"])]
+    check: bool,
+    /// Print the result of the run to stdout when the script exits, for CI and editor tasks
+    ///
+    /// The result is a JSON object on a single line, after the output of the script, with the
+    /// script name, arguments, exit code, duration in milliseconds, the id of the recorded run and
+    /// the command that prints its output, if it was stored with `--log`.
+    /// The script runs as a child process, like with otrun.
+    #[clap(long, value_name = "FORMAT", conflicts_with_all = ["parallel", "watch", "bench"])]
+    report: Option<ReportFormat>,
+    /// Run the script with a minimal environment, like HOME, PATH, TERM and the OTKEEP_* variables
+    ///
+    /// Scripts can also be made to always run like this with `okeep mod <name> --clean-env true`.
+    #[clap(long)]
+    clean_env: bool,
+    /// Also keep this environment variable in a clean environment. Can be given multiple times.
+    #[clap(long, value_name = "VAR")]
+    keep_env: Vec<String>,
+    /// Run the script in a bubblewrap sandbox, where it can only write to the tree root
+    ///
+    /// With `read-only`, the rest of the filesystem is visible but read-only. With `hidden`, only
+    /// the system directories needed to run programs are visible.
+    /// Scripts can also be made to always run like this with `okeep mod <name> --sandbox <MODE>`.
+    #[clap(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "read-only",
+        value_parser = PossibleValuesParser::new(Sandbox::NAMES)
+    )]
+    sandbox: Option<String>,
+    /// Set an environment variable for this run, e.g. `-e RUST_LOG=debug`. Can be given multiple
+    /// times. Overrides the variables stored with `okeep mod <name> --env`.
+    #[clap(short = 'e', long = "env", value_name = "KEY=VAL", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+    /// Run a script of the tree with this UUID, instead of the tree of the current directory
+    ///
+    /// The shims of `okeep shims` use it, so they work outside of the tree too.
+    #[clap(long, value_name = "UUID")]
+    tree: Option<String>,
+    /// Open the database read-only. Runs aren't recorded then.
+    ///
+    /// This also happens automatically when the database isn't writable.
+    #[clap(long)]
+    read_only: bool,
+    /// Print the names of the scripts of the current tree starting with PREFIX, for shell
+    /// completion
+    #[clap(long, value_name = "PREFIX", hide = true)]
+    complete: Option<String>,
+    /// Print the completions of the last argument of SCRIPT, from what the script declares in its
+    /// front matter. The arguments come after `--`.
+    #[clap(long, value_name = "SCRIPT", hide = true, conflicts_with = "complete")]
+    complete_args: Option<String>,
+    /// Name of the script, followed by the arguments passed to it
+    #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<OsString>,
+}
+
+/// Format of `--report`
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Json,
+}
+
+/// Entry point for `orun` and `otrun`.
+///
+/// `orun` replaces itself with the script, while `otrun` (`supervised`) runs the script as a
+/// child process and exits with its exit status.
+pub fn main(args: impl Iterator<Item = OsString>, supervised: bool) -> anyhow::Result<!> {
+    let start = Instant::now();
+    let args = Args::parse_from(args);
+    if let Some(prefix) = &args.complete {
+        complete(prefix)?;
+        std::process::exit(0);
+    }
+    if let Some(name) = &args.complete_args {
+        complete_args(name, &args.command)?;
+        std::process::exit(0);
+    }
+    let db = otkeep::load_db(args.read_only)?;
+    let found = match &args.tree {
+        Some(uuid) => {
+            let id = db
+                .query_tree_by_uuid(uuid)?
+                .with_context(|| format!("There is no tree with the UUID {uuid}"))?;
+            Some((id, db.query_tree_root(id)?))
+        }
+        None => crate::find_root(&db)?,
+    };
+    let (root_id, root_path) = match found {
+        Some(root) => root,
+        None => {
+            otkeep::print_established_trees(&db)?;
+            bail!("No OtKeep tree root was found. To establish one, use okeep establish");
+        }
+    };
+
+    let mut app = AppContext { db, root_id };
+    if let Some(sandbox) = &args.sandbox {
+        app.db.force_sandbox(sandbox.parse()?);
+    }
+    app.db.set_run_env(args.env);
+    if args.parallel {
+        return run_parallel(&mut app, &args.command, args.clean_env, &args.keep_env)
+            .context("Failed to run scripts");
+    }
+    let mut command = args.command.into_iter();
+    let cmd_name = match command.next() {
+        Some(arg) => arg,
+        None => {
+            otkeep::list_scripts(&app)?;
+            eprintln!("\nFor more options, try okeep",);
+            std::process::exit(1);
+        }
+    };
+    let name = cmd_name.to_str().context("Command name not utf-8")?;
+    let script_args: Vec<OsString> = command.collect();
+    let wants_clean_env = match app.db.script_clean_env(app.root_id, name) {
+        Ok(clean) => clean,
+        Err(e) => handle_error(e, name, &app)?,
+    };
+    crate::ask_trust(&mut app.db, app.root_id, &[name.to_owned()])?;
+    if args.clean_env || wants_clean_env {
+        otkeep::clean_env(&args.keep_env);
+    }
+    if !args.watch.is_empty() {
+        return run_watching(name, &app, &script_args, &root_path, &args.watch)
+            .context("Failed to watch script");
+    }
+    if let Some(runs) = args.bench {
+        return bench(name, &app, &script_args, runs).context("Failed to benchmark script");
+    }
+    let opts = ChildOpts {
+        log: args.log,
+        check: args.check,
+        report: args.report.is_some(),
+    };
+    match otkeep::run_needs(&app, name) {
+        Ok(None) => {}
+        Ok(Some(status)) => {
+            let outcome = Outcome {
+                args: &script_args,
+                code: exit_code(status),
+                start,
+                recorded: false,
+            };
+            finish(&app, name, outcome, opts)
+        }
+        Err(e) => handle_error(e, name, &app).context("Failed to run dependencies")?,
+    }
+    let result = match args.retry {
+        Some(retries) => {
+            let delay =
+                Duration::try_from_secs_f64(args.retry_delay).context("Invalid retry delay")?;
+            run_with_retries(
+                name,
+                &mut app,
+                &script_args,
+                retries,
+                delay,
+                args.backoff,
+                opts,
+            )
+        }
+        None => run(name, &mut app, &script_args, supervised, opts),
+    };
+    result.context("Failed to run script")
+}
+
+/// Prints the names of the scripts of the current tree starting with `prefix`, one per line.
+///
+/// Called by the shell completions on every tab press, so it only looks up the root and the names,
+/// and never asks for the passphrase.
+fn complete(prefix: &str) -> anyhow::Result<()> {
+    let Some(db) = otkeep::load_db_unattended(true)? else {
+        return Ok(());
+    };
+    if let Some((root_id, _)) = otkeep::find_root(&db)? {
+        for name in db.script_names_with_prefix(root_id, prefix)? {
+            println!("{name}");
+        }
+    }
+    Ok(())
+}
+
+/// Prints the completions of the last of `args` passed to the script `name`, one per line
+fn complete_args(name: &str, args: &[OsString]) -> anyhow::Result<()> {
+    let Some(db) = otkeep::load_db_unattended(true)? else {
+        return Ok(());
+    };
+    let Some((root_id, _)) = otkeep::find_root(&db)? else {
+        return Ok(());
+    };
+    let spec = ArgSpec::parse(&db.get_script_by_name(root_id, name)?);
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let (current, args) = match args.split_last() {
+        Some((current, args)) => (current.as_str(), args),
+        None => ("", &args[..]),
+    };
+    for completion in spec.complete(args, current) {
+        println!("{completion}");
+    }
+    Ok(())
+}
+
+/// Options for running the script as a child process
+#[derive(Clone, Copy)]
+struct ChildOpts {
+    /// Store the output of the script
+    log: bool,
+    /// Print a banner when the script fails
+    check: bool,
+    /// Print the result of the run as JSON
+    report: bool,
+}
+
+/// How a run of the script ended
+struct Outcome<'a> {
+    args: &'a [OsString],
+    code: i32,
+    /// When the run, including its needs and retries, started
+    start: Instant,
+    /// Whether the script itself ran, so the run was recorded
+    recorded: bool,
+}
+
+fn run(
+    name: &str,
+    ctx: &mut AppContext,
+    args: &[OsString],
+    supervised: bool,
+    opts: ChildOpts,
+) -> anyhow::Result<!> {
+    let result = if opts.log || opts.check || opts.report || supervised {
+        let start = Instant::now();
+        let result = if opts.log {
+            ctx.db.run_script_logged(ctx.root_id, name, args.iter())
+        } else {
+            ctx.db.run_script_supervised(ctx.root_id, name, args.iter())
+        };
+        result.map(|status| {
+            let code = exit_code(status);
+            let elapsed = format_duration(start.elapsed());
+            match code {
+                0 => eprintln!("Finished in {elapsed}"),
+                _ => eprintln!("Finished in {elapsed} with exit code {code}"),
+            }
+            let outcome = Outcome {
+                args,
+                code,
+                start,
+                recorded: true,
+            };
+            finish(ctx, name, outcome, opts)
+        })
+    } else {
+        ctx.db.run_script(ctx.root_id, name, args.iter())
+    };
+    match result {
+        Err(e) => handle_error(e, name, ctx),
+    }
+}
+
+/// Runs the script as a child process until it succeeds, at most `retries + 1` times
+fn run_with_retries(
+    name: &str,
+    ctx: &mut AppContext,
+    args: &[OsString],
+    retries: u32,
+    mut delay: Duration,
+    backoff: bool,
+    opts: ChildOpts,
+) -> anyhow::Result<!> {
+    let start = Instant::now();
+    let attempts = retries + 1;
+    let mut attempt = 1;
+    loop {
+        let result = if opts.log {
+            ctx.db.run_script_logged(ctx.root_id, name, args.iter())
+        } else {
+            ctx.db.run_script_supervised(ctx.root_id, name, args.iter())
+        };
+        let status = match result {
+            Ok(status) => status,
+            Err(e) => handle_error(e, name, ctx)?,
+        };
+        let code = exit_code(status);
+        let outcome = Outcome {
+            args,
+            code,
+            start,
+            recorded: true,
+        };
+        if status.success() {
+            finish(ctx, name, outcome, opts);
+        }
+        if attempt == attempts {
+            eprintln!("Attempt {attempt}/{attempts} failed with exit code {code}, giving up");
+            finish(ctx, name, outcome, opts);
+        }
+        eprintln!(
+            "Attempt {attempt}/{attempts} failed with exit code {code}, retrying in {:.1}s",
+            delay.as_secs_f64()
+        );
+        std::thread::sleep(delay);
+        if backoff {
+            delay *= 2;
+        }
+        attempt += 1;
+    }
+}
+
+/// Exits with the exit code of the run, after reporting it the way `opts` asks for
+fn finish(ctx: &AppContext, name: &str, outcome: Outcome, opts: ChildOpts) -> ! {
+    let code = outcome.code;
+    if opts.check && code != 0 {
+        let hint = ctx.db.script_failure_hint(ctx.root_id, name).ok().flatten();
+        print_failure_banner(name, code, hint.as_deref());
+    }
+    if opts.report {
+        let run_id = if outcome.recorded && !ctx.db.is_read_only() {
+            ctx.db
+                .last_run(ctx.root_id, Some(name))
+                .ok()
+                .flatten()
+                .map(|run| run.id)
+        } else {
+            None
+        };
+        print_report(name, &outcome, run_id, opts.log);
+    }
+    std::process::exit(code)
+}
+
+/// Prints the outcome of the run as a JSON object on a single line
+fn print_report(name: &str, outcome: &Outcome, run_id: Option<i64>, logged: bool) {
+    let args: Vec<String> = outcome
+        .args
+        .iter()
+        .map(|arg| json_string(&arg.to_string_lossy()))
+        .collect();
+    let output = match run_id {
+        Some(id) if logged => json_string(&format!("okeep logs {name} {id}")),
+        _ => "null".into(),
+    };
+    let run_id = run_id.map_or("null".into(), |id| id.to_string());
+    println!(
+        "{{\"script\": {}, \"args\": [{}], \"exit_code\": {}, \"duration_ms\": {}, \"run_id\": {run_id}, \"output\": {output}}}",
+        json_string(name),
+        args.join(", "),
+        outcome.code,
+        outcome.start.elapsed().as_millis(),
+    );
+}
+
+/// Prints a banner about a failed script that stands out from its output
+fn print_failure_banner(name: &str, code: i32, hint: Option<&str>) {
+    let mut lines = vec![format!("FAILED: {name} exited with code {code}")];
+    lines.extend(hint.map(|hint| format!("Hint: {hint}")));
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let rule = "=".repeat(width);
+    let color = std::io::stderr().is_terminal();
+    eprintln!();
+    for line in std::iter::once(&rule)
+        .chain(lines.iter().map(|line| line as &String))
+        .chain(std::iter::once(&rule))
+    {
+        let line = format!("{line:width$}");
+        if color {
+            eprintln!("{}", line.white().on_red().bold());
+        } else {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// Runs the script, and runs it again every time a file matching `patterns` changes
+fn run_watching(
+    name: &str,
+    ctx: &AppContext,
+    args: &[OsString],
+    root: &Path,
+    patterns: &[String],
+) -> anyhow::Result<!> {
+    let patterns = patterns
+        .iter()
+        .map(|pat| glob::Pattern::new(pat).with_context(|| format!("Invalid pattern '{pat}'")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    let is_change = |event: notify::Result<notify::Event>| {
+        event.is_ok_and(|event| {
+            !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|path| {
+                    path.strip_prefix(root)
+                        .is_ok_and(|rel| patterns.iter().any(|pat| pat.matches_path(rel)))
+                })
+        })
+    };
+    loop {
+        let status = match otkeep::run_needs(ctx, name) {
+            Ok(Some(status)) => status,
+            Ok(None) => match ctx.db.run_script_supervised(ctx.root_id, name, args.iter()) {
+                Ok(status) => status,
+                Err(e) => handle_error(e, name, ctx)?,
+            },
+            Err(e) => handle_error(e, name, ctx)?,
+        };
+        // Interrupting the script with Ctrl+C also stops watching
+        if status.signal() == Some(libc::SIGINT) {
+            std::process::exit(exit_code(status));
+        }
+        eprintln!(
+            "Exited with code {}, waiting for changes",
+            exit_code(status)
+        );
+        while !is_change(rx.recv()?) {}
+        while rx.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+        eprintln!("Files changed, running '{name}' again");
+    }
+}
+
+/// Runs the script `runs` times, and prints the minimum, mean and maximum wall time
+fn bench(name: &str, ctx: &AppContext, args: &[OsString], runs: u32) -> anyhow::Result<!> {
+    if let Some(status) = otkeep::run_needs(ctx, name)? {
+        std::process::exit(exit_code(status));
+    }
+    let mut times = Vec::new();
+    for run in 1..=runs {
+        let start = Instant::now();
+        let status = match ctx.db.run_script_supervised(ctx.root_id, name, args.iter()) {
+            Ok(status) => status,
+            Err(e) => handle_error(e, name, ctx)?,
+        };
+        let elapsed = start.elapsed();
+        if !status.success() {
+            let code = exit_code(status);
+            eprintln!("Run {run}/{runs} failed with exit code {code}, stopping");
+            std::process::exit(code);
+        }
+        eprintln!("Run {run}/{runs}: {:.3}s", elapsed.as_secs_f64());
+        times.push(elapsed);
+    }
+    let min = times.iter().min().copied().unwrap_or_default();
+    let max = times.iter().max().copied().unwrap_or_default();
+    let mean = times.iter().sum::<Duration>() / runs;
+    eprintln!(
+        "\n{name}: min {:.3}s, mean {:.3}s, max {:.3}s ({runs} runs)",
+        min.as_secs_f64(),
+        mean.as_secs_f64(),
+        max.as_secs_f64()
+    );
+    std::process::exit(0)
+}
+
+/// Runs the scripts at the same time. If any of them needs a clean environment, they all get one.
+fn run_parallel(
+    ctx: &mut AppContext,
+    names: &[OsString],
+    clean_env: bool,
+    keep_env: &[String],
+) -> anyhow::Result<!> {
+    let names = names
+        .iter()
+        .map(|name| name.to_str().map(str::to_owned))
+        .collect::<Option<Vec<_>>>()
+        .context("Script name not utf-8")?;
+    for name in &names {
+        if !ctx.db.script_exists(ctx.root_id, name)? {
+            handle_error(Error::NoSuchScript, name, ctx)?;
+        }
+    }
+    crate::ask_trust(&mut ctx.db, ctx.root_id, &names)?;
+    let mut wants_clean_env = clean_env;
+    for name in &names {
+        wants_clean_env |= ctx.db.script_clean_env(ctx.root_id, name)?;
+    }
+    if wants_clean_env {
+        otkeep::clean_env(keep_env);
+    }
+    if let Some(status) =
+        otkeep::run_needs_all(ctx, &names).context("Failed to run dependencies")?
+    {
+        std::process::exit(exit_code(status));
+    }
+    let statuses = ctx.db.run_scripts_parallel(ctx.root_id, &names)?;
+    let mut code = 0;
+    for (name, status) in names.iter().zip(statuses) {
+        if !status.success() {
+            eprintln!("'{name}' failed with exit code {}", exit_code(status));
+            if code == 0 {
+                code = exit_code(status);
+            }
+        }
+    }
+    std::process::exit(code)
+}
+
+/// Lists the available scripts if the script wasn't found, otherwise passes on the error
+fn handle_error(e: Error, name: &str, ctx: &AppContext) -> anyhow::Result<!> {
+    match e {
+        Error::NoSuchScript => {
+            eprintln!("No script named '{}' for the current tree.\n", name);
+            otkeep::list_scripts(ctx)?;
+            eprintln!("\nFor more options, try okeep");
+            std::process::exit(1)
+        }
+        e => Err(e.into()),
+    }
+}
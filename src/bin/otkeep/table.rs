@@ -0,0 +1,164 @@
+//! Machine-readable output of listings, for `--format`
+
+use {clap::ValueEnum, std::io::Write};
+
+/// How a listing is printed
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Format {
+    /// For reading by humans
+    Text,
+    /// Comma-separated values, with a header row
+    Csv,
+    /// Tab-separated values, with a header row. Tabs, newlines and backslashes in values are
+    /// escaped as `\t`, `\n` and `\\`.
+    Tsv,
+    /// An array of objects
+    Json,
+}
+
+/// A value in a [`Table`]
+pub(crate) enum Value {
+    Str(String),
+    Int(i64),
+    Null,
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Self::Str(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Self::Str(s.to_owned())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Self::Int(n)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Self::Int(n as i64)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+/// Rows of values under named columns, which can be written in the machine-readable formats
+pub(crate) struct Table {
+    columns: &'static [&'static str],
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub(crate) fn new(columns: &'static [&'static str]) -> Self {
+        Self {
+            columns,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row, with a value for each column
+    pub(crate) fn push(&mut self, row: Vec<Value>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    /// Writes the table to stdout. Does nothing for [`Format::Text`], which the commands print
+    /// themselves.
+    pub(crate) fn print(&self, format: Format) -> anyhow::Result<()> {
+        let mut out = std::io::stdout().lock();
+        match format {
+            Format::Text => {}
+            Format::Csv => self.write_separated(&mut out, ',', csv_field)?,
+            Format::Tsv => self.write_separated(&mut out, '\t', tsv_field)?,
+            Format::Json => self.write_json(&mut out)?,
+        }
+        Ok(())
+    }
+
+    fn write_separated(
+        &self,
+        out: &mut impl Write,
+        sep: char,
+        field: fn(&str) -> String,
+    ) -> std::io::Result<()> {
+        let header: Vec<_> = self.columns.iter().map(|column| field(column)).collect();
+        writeln!(out, "{}", header.join(&sep.to_string()))?;
+        for row in &self.rows {
+            let fields: Vec<_> = row
+                .iter()
+                .map(|value| match value {
+                    Value::Str(s) => field(s),
+                    Value::Int(n) => n.to_string(),
+                    Value::Null => String::new(),
+                })
+                .collect();
+            writeln!(out, "{}", fields.join(&sep.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, out: &mut impl Write) -> std::io::Result<()> {
+        write!(out, "[")?;
+        for (i, row) in self.rows.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(out, "{sep}\n  {{")?;
+            for (j, (column, value)) in self.columns.iter().zip(row).enumerate() {
+                let sep = if j == 0 { "" } else { ", " };
+                let value = match value {
+                    Value::Str(s) => json_string(s),
+                    Value::Int(n) => n.to_string(),
+                    Value::Null => "null".into(),
+                };
+                write!(out, "{sep}{}: {value}", json_string(column))?;
+            }
+            write!(out, "}}")?;
+        }
+        let end = if self.rows.is_empty() { "" } else { "\n" };
+        writeln!(out, "{end}]")
+    }
+}
+
+/// Quotes a CSV field if it contains anything special, doubling the quotes inside of it
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,101 @@
+//! Bundled sets of scripts for new trees, for `okeep establish --template`
+
+/// A script of a tree template
+pub(crate) struct TemplateScript {
+    pub name: &'static str,
+    pub desc: &'static str,
+    pub body: &'static str,
+}
+
+/// A named set of scripts a new tree can start with
+pub(crate) struct TreeTemplate {
+    pub name: &'static str,
+    pub scripts: &'static [TemplateScript],
+}
+
+const fn script(name: &'static str, desc: &'static str, body: &'static str) -> TemplateScript {
+    TemplateScript { name, desc, body }
+}
+
+pub(crate) const TEMPLATES: &[TreeTemplate] = &[
+    TreeTemplate {
+        name: "rust",
+        scripts: &[
+            script(
+                "build",
+                "Build the project",
+                "#!/bin/sh\ncargo build \"$@\"\n",
+            ),
+            script("test", "Run the tests", "#!/bin/sh\ncargo test \"$@\"\n"),
+            script("fmt", "Format the code", "#!/bin/sh\ncargo fmt \"$@\"\n"),
+            script(
+                "lint",
+                "Run clippy",
+                "#!/bin/sh\ncargo clippy --all-targets \"$@\" -- -D warnings\n",
+            ),
+        ],
+    },
+    TreeTemplate {
+        name: "go",
+        scripts: &[
+            script(
+                "build",
+                "Build the project",
+                "#!/bin/sh\ngo build \"$@\" ./...\n",
+            ),
+            script("test", "Run the tests", "#!/bin/sh\ngo test \"$@\" ./...\n"),
+            script("fmt", "Format the code", "#!/bin/sh\ngo fmt ./...\n"),
+            script("lint", "Run go vet", "#!/bin/sh\ngo vet \"$@\" ./...\n"),
+        ],
+    },
+    TreeTemplate {
+        name: "node",
+        scripts: &[
+            script(
+                "build",
+                "Build the project",
+                "#!/bin/sh\nnpm run build -- \"$@\"\n",
+            ),
+            script("test", "Run the tests", "#!/bin/sh\nnpm test -- \"$@\"\n"),
+            script(
+                "fmt",
+                "Format the code",
+                "#!/bin/sh\nnpx prettier --write . \"$@\"\n",
+            ),
+        ],
+    },
+    TreeTemplate {
+        name: "python",
+        scripts: &[
+            script(
+                "test",
+                "Run the tests",
+                "#!/bin/sh\npython3 -m pytest \"$@\"\n",
+            ),
+            script(
+                "fmt",
+                "Format the code",
+                "#!/bin/sh\npython3 -m black . \"$@\"\n",
+            ),
+            script(
+                "lint",
+                "Run ruff",
+                "#!/bin/sh\npython3 -m ruff check . \"$@\"\n",
+            ),
+        ],
+    },
+];
+
+/// Finds the bundled template called `name`
+pub(crate) fn find(name: &str) -> Option<&'static TreeTemplate> {
+    TEMPLATES.iter().find(|template| template.name == name)
+}
+
+/// The names of the bundled templates, separated by commas
+pub(crate) fn names() -> String {
+    TEMPLATES
+        .iter()
+        .map(|template| template.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
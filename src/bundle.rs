@@ -0,0 +1,104 @@
+//! Self-extracting shell scripts that contain the scripts of a tree, for running them on machines
+//! without OtKeep
+
+use {
+    crate::{
+        database::Database,
+        progress::{Observer, Progress},
+        shims::shell_quote,
+    },
+    base64::{engine::general_purpose::STANDARD, Engine},
+    std::fmt::Write,
+};
+
+/// Generates a POSIX shell script that runs the scripts of `tree_id` by name, along with the
+/// scripts they need.
+///
+/// Archived scripts aren't included. Scripts run in the current directory, with
+/// `OTKEEP_TREE_ROOT` set to the directory of the bundle. `observer` is told about every script
+/// after it's added.
+pub fn bundle(db: &Database, tree_id: i64, observer: &mut impl Observer) -> crate::Result<String> {
+    let root = db.query_tree_root(tree_id)?;
+    let scripts = db.scripts_for_tree(tree_id)?;
+    let mut out = format!(
+        "#!/bin/sh\n\
+         # Scripts of the OtKeep tree at {}, exported with `okeep export bundle`.\n\
+         # Run it without arguments to list them, or with the name of a script and its arguments\n\
+         # to run one.\n\n",
+        root.display()
+    );
+    out.push_str(PRELUDE);
+    out.push_str("\nusage() {\n    echo \"Usage: $0 SCRIPT [ARGS...]\"\n    echo\n");
+    out.push_str("    echo 'Scripts:'\n");
+    for script in &scripts {
+        let line = if script.description.is_empty() {
+            format!("  {}", script.name)
+        } else {
+            format!("  {} - {}", script.name, script.description)
+        };
+        writeln!(out, "    echo {}", shell_quote(&line))?;
+    }
+    out.push_str("}\n\nscript_body() {\n    case \"$1\" in\n");
+    for (i, script) in scripts.iter().enumerate() {
+        let body = db.get_script_by_name(tree_id, &script.name)?;
+        writeln!(
+            out,
+            "        {}) echo '{}' ;;",
+            shell_quote(&script.name),
+            STANDARD.encode(body)
+        )?;
+        observer.progress(Progress {
+            action: "Bundling",
+            done: i + 1,
+            total: scripts.len(),
+            item: &script.name,
+        });
+    }
+    out.push_str("    esac\n}\n\n[ $# -eq 0 ] && { usage; exit 0; }\nname=$1\nshift\n");
+    out.push_str("case \"$name\" in\n");
+    for script in &scripts {
+        writeln!(out, "    {})", shell_quote(&script.name))?;
+        let mut order = db.run_order(tree_id, &script.name)?;
+        order.pop();
+        for need in order {
+            let failed = format!("'{need}' failed, not running '{}'", script.name);
+            writeln!(
+                out,
+                "        run_script {} || {{ status=$?; echo {} >&2; exit $status; }}",
+                shell_quote(&need),
+                shell_quote(&failed)
+            )?;
+        }
+        writeln!(
+            out,
+            "        run_script {} \"$@\"",
+            shell_quote(&script.name)
+        )?;
+        out.push_str("        ;;\n");
+    }
+    out.push_str("    *) echo \"No such script: $name\" >&2; usage >&2; exit 1 ;;\nesac\n");
+    Ok(out)
+}
+
+/// Sets up the environment, and defines `run_script NAME ARGS...`
+const PRELUDE: &str = r#"OTKEEP_TREE_ROOT=$(cd "$(dirname "$0")" && pwd) || exit 1
+OTKEEP_INVOCATION_DIR=$PWD
+export OTKEEP_TREE_ROOT OTKEEP_INVOCATION_DIR
+
+run_script() {
+    script_name=$1
+    shift
+    script=$(mktemp) || return 1
+    script_body "$script_name" | base64 -d > "$script" || { rm -f "$script"; return 1; }
+    chmod +x "$script"
+    # Scripts without a shebang run with sh, like in OtKeep
+    if [ "$(head -c 2 "$script")" = '#!' ]; then
+        OTKEEP_SCRIPT_NAME=$script_name "$script" "$@"
+    else
+        OTKEEP_SCRIPT_NAME=$script_name sh "$script" "$@"
+    fi
+    status=$?
+    rm -f "$script"
+    return $status
+}
+"#;
@@ -0,0 +1,152 @@
+//! Content-defined chunking of large saved files.
+//!
+//! A file is cut where a rolling hash of its last bytes matches a pattern, so the cuts move along
+//! with the data when bytes are inserted or removed. Saving a slightly changed file again then
+//! gives mostly the same chunks, which are only stored once.
+
+use std::io::Read;
+
+/// Files of at least this size are stored as chunks
+pub(crate) const CHUNKED_MIN_SIZE: u64 = 1024 * 1024;
+/// Chunks are cut no earlier than this
+const MIN_CHUNK: usize = 64 * 1024;
+/// Chunks are cut no later than this
+const MAX_CHUNK: usize = 1024 * 1024;
+/// The rolling hash matches this mask after about 256 KiB on average, past [`MIN_CHUNK`]
+const MASK: u64 = !(u64::MAX >> 18);
+
+/// Random values for the bytes, for the rolling hash.
+///
+/// Changing them moves the cuts, so chunks stored before wouldn't be shared anymore.
+const GEAR: [u64; 256] = {
+    // splitmix64
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Iterator over the chunks of what a reader reads
+pub(crate) struct Chunker<R> {
+    source: R,
+    /// Read, but not yet returned data
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub(crate) fn new(source: R) -> Self {
+        Self {
+            source,
+            buf: Vec::with_capacity(MAX_CHUNK),
+            eof: false,
+        }
+    }
+
+    /// Reads until there's enough data for the largest chunk, or the end is reached
+    fn fill(&mut self) -> std::io::Result<()> {
+        while !self.eof && self.buf.len() < MAX_CHUNK {
+            let start = self.buf.len();
+            self.buf.resize(MAX_CHUNK, 0);
+            let result = self.source.read(&mut self.buf[start..]);
+            self.buf.truncate(start + *result.as_ref().unwrap_or(&0));
+            match result {
+                Ok(0) => self.eof = true,
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        let len = cut_point(&self.buf);
+        Some(Ok(self.buf.drain(..len).collect()))
+    }
+}
+
+/// Returns where the first chunk of `data` ends
+fn cut_point(data: &[u8]) -> usize {
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate().take(MAX_CHUNK).skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & MASK == 0 {
+            return i + 1;
+        }
+    }
+    data.len().min(MAX_CHUNK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Data that looks random, but is the same on every run
+    fn pseudo_random(len: usize) -> Vec<u8> {
+        let mut state: u64 = 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        Chunker::new(data).collect::<std::io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn short_data_is_one_chunk() {
+        assert_eq!(cut_point(&[]), 0);
+        assert_eq!(cut_point(&[7; 100]), 100);
+        assert_eq!(cut_point(&vec![7; MIN_CHUNK]), MIN_CHUNK);
+    }
+
+    #[test]
+    fn cuts_stay_within_limits() {
+        // The hash of zeros never matches, so only the maximum applies
+        assert_eq!(cut_point(&vec![0; 3 * MAX_CHUNK]), MAX_CHUNK);
+        let data = pseudo_random(8 * MAX_CHUNK);
+        let chunks = chunks(&data);
+        assert!(chunks.len() > 8);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert!((MIN_CHUNK..=MAX_CHUNK).contains(&chunk.len()));
+        }
+        assert!(last.len() <= MAX_CHUNK);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn cuts_move_with_inserted_prefix() {
+        let data = pseudo_random(8 * MAX_CHUNK);
+        let mut prefixed = b"inserted at the start".to_vec();
+        prefixed.extend_from_slice(&data);
+        let before = chunks(&data);
+        let after = chunks(&prefixed);
+        assert_ne!(before[0], after[0]);
+        assert_eq!(before[1..], after[1..]);
+    }
+}
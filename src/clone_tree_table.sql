@@ -1,17 +1,40 @@
-INSERT INTO tree_scripts
+INSERT INTO tree_scripts (tree_id, blob_id, name, desc, origin, needs, clean_env, sandbox, container, trusted, locked, archived, last_used, tags, created_at, updated_at, failure_hint, env)
 SELECT
     :dst,
     blob_id,
     name,
-    desc
+    desc,
+    origin,
+    needs,
+    clean_env,
+    sandbox,
+    container,
+    -- Cloned scripts have to be reviewed before running them
+    0,
+    locked,
+    archived,
+    unixepoch(),
+    tags,
+    unixepoch(),
+    updated_at,
+    failure_hint,
+    env
 FROM tree_scripts
-WHERE tree_id = :src;
+WHERE tree_id = :src
+    AND (:only IS NULL OR name GLOB :only)
+    AND (:except IS NULL OR name NOT GLOB :except)
+    AND name = :name
+    AND name NOT IN (SELECT name FROM tree_scripts WHERE tree_id = :dst);
 
-INSERT INTO tree_files
+INSERT INTO tree_files (tree_id, blob_id, name, desc, created_at, updated_at)
 SELECT
     :dst,
     blob_id,
     name,
-    desc
+    desc,
+    unixepoch(),
+    updated_at
 FROM tree_files
-WHERE tree_id = :src;
\ No newline at end of file
+WHERE tree_id = :src
+    AND name = :name
+    AND name NOT IN (SELECT name FROM tree_files WHERE tree_id = :dst);
\ No newline at end of file
@@ -0,0 +1,111 @@
+//! Completion of the arguments of scripts, from what they declare in their front matter.
+//!
+//! The front matter is the comment block at the start of a script, after the shebang:
+//!
+//! ```sh
+//! #!/bin/sh
+//! # otkeep-flag: --env=dev|staging|prod
+//! # otkeep-flag: --dry-run
+//! # otkeep-arg: web|worker
+//! ```
+//!
+//! `otkeep-flag` declares a flag, along with the values it takes, if any. `otkeep-arg` declares
+//! values for the positional arguments.
+
+/// The flags and values a script declares in its front matter
+#[derive(Debug, Default)]
+pub struct ArgSpec {
+    pub flags: Vec<Flag>,
+    /// Values the positional arguments can take
+    pub values: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Flag {
+    /// Name of the flag, including the dashes
+    pub name: String,
+    /// Values the flag takes. Empty if it takes none.
+    pub values: Vec<String>,
+}
+
+impl ArgSpec {
+    /// Reads the declarations from the front matter of `body`
+    pub fn parse(body: &[u8]) -> Self {
+        let mut spec = Self::default();
+        let body = String::from_utf8_lossy(body);
+        for line in body.lines().skip_while(|line| line.starts_with("#!")) {
+            let line = line.trim();
+            let Some(comment) = line.strip_prefix('#') else {
+                if line.is_empty() {
+                    continue;
+                }
+                break;
+            };
+            let comment = comment.trim();
+            if let Some(flag) = comment.strip_prefix("otkeep-flag:") {
+                let (name, values) = match flag.trim().split_once('=') {
+                    Some((name, values)) => (name, split_values(values)),
+                    None => (flag.trim(), Vec::new()),
+                };
+                if name.starts_with('-') {
+                    spec.flags.push(Flag {
+                        name: name.to_owned(),
+                        values,
+                    });
+                }
+            } else if let Some(values) = comment.strip_prefix("otkeep-arg:") {
+                spec.values.extend(split_values(values));
+            }
+        }
+        spec
+    }
+
+    /// Returns the completions of `current`, the argument being typed after `args`
+    pub fn complete(&self, args: &[String], current: &str) -> Vec<String> {
+        let matching = |values: &[String], prefix: &str| -> Vec<String> {
+            values
+                .iter()
+                .filter(|value| value.starts_with(prefix))
+                .cloned()
+                .collect()
+        };
+        if let Some(flag) = args.last().and_then(|arg| self.flag(arg)) {
+            if !flag.values.is_empty() {
+                return matching(&flag.values, current);
+            }
+        }
+        if let Some((name, value)) = current.split_once('=') {
+            return match self.flag(name) {
+                Some(flag) => matching(&flag.values, value)
+                    .into_iter()
+                    .map(|value| format!("{name}={value}"))
+                    .collect(),
+                None => Vec::new(),
+            };
+        }
+        if current.starts_with('-') {
+            return self
+                .flags
+                .iter()
+                .map(|flag| &flag.name)
+                .filter(|name| name.starts_with(current))
+                .cloned()
+                .collect();
+        }
+        matching(&self.values, current)
+    }
+
+    fn flag(&self, name: &str) -> Option<&Flag> {
+        self.flags.iter().find(|flag| flag.name == name)
+    }
+}
+
+/// Splits `a|b|c` into its values
+fn split_values(values: &str) -> Vec<String> {
+    values
+        .split('|')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
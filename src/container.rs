@@ -0,0 +1,91 @@
+//! Running scripts inside of a container, with podman or docker
+
+use {
+    crate::error::bail,
+    std::{
+        ffi::OsStr,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+        process::{Command, Stdio},
+    },
+};
+
+/// Writes the script passed in `OTKEEP_SCRIPT` to a file inside of the container, and runs it.
+///
+/// Like outside of containers, scripts without a shebang are run with `sh`.
+const STUB: &str = r#"f=$(mktemp) && printf '%s' "$OTKEEP_SCRIPT" > "$f" && chmod +x "$f" || exit 125
+unset OTKEEP_SCRIPT
+case $(head -c 2 "$f") in
+'#!') exec "$f" "$@" ;;
+*) exec sh "$f" "$@" ;;
+esac"#;
+
+/// Environment variables that are passed into the container
+const ENV_VARS: [&str; 6] = [
+    "OTKEEP_SCRIPT",
+    "OTKEEP_TREE_ROOT",
+    "OTKEEP_TREE_ID",
+    "OTKEEP_SCRIPT_NAME",
+    "OTKEEP_INVOCATION_DIR",
+    "OTKEEP_DB_PATH",
+];
+
+/// Returns a command running `script` in a container created from `image`.
+///
+/// The tree root is mounted at the same path inside of the container, and is the working
+/// directory, unless `cwd` is a subdirectory of it. `env` is set inside of the container.
+pub(crate) fn command(
+    image: &str,
+    script: &[u8],
+    tree_root: &Path,
+    cwd: &Path,
+    env: &[(String, String)],
+) -> crate::Result<Command> {
+    if script.contains(&0) {
+        bail!("Scripts containing NUL bytes can't run in containers");
+    }
+    let runtime = runtime()?;
+    let root = tree_root.canonicalize()?;
+    let mut volume = root.clone().into_os_string();
+    volume.push(":");
+    volume.push(&root);
+    let mut cmd = Command::new(runtime);
+    cmd.args(["run", "--rm", "-i"]);
+    if unsafe { libc::isatty(0) == 1 } {
+        cmd.arg("-t");
+    }
+    if runtime == "docker" {
+        // Otherwise files created in the tree would belong to root
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        cmd.arg(format!("--user={uid}:{gid}"));
+    }
+    for var in ENV_VARS {
+        cmd.args(["-e", var]);
+    }
+    for (key, val) in env {
+        cmd.arg("-e").arg(format!("{key}={val}"));
+    }
+    cmd.arg("-v")
+        .arg(volume)
+        .arg("-w")
+        .arg(if cwd.starts_with(&root) { cwd } else { &root })
+        .args(["--", image, "sh", "-c", STUB, "otkeep-script"])
+        .env("OTKEEP_SCRIPT", OsStr::from_bytes(script));
+    Ok(cmd)
+}
+
+/// Returns the container runtime to use, preferring podman
+fn runtime() -> crate::Result<&'static str> {
+    for runtime in ["podman", "docker"] {
+        let found = Command::new(runtime)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok();
+        if found {
+            return Ok(runtime);
+        }
+    }
+    bail!("Running scripts in containers needs podman or docker to be installed")
+}
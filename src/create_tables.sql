@@ -19,4 +19,9 @@ CREATE TABLE IF NOT EXISTS tree_files (
     desc    TEXT,
     -- The same tree cannot have multiple items with the same name
     UNIQUE(tree_id, name)
-);
\ No newline at end of file
+);
+CREATE TABLE IF NOT EXISTS tree_shim_dirs (
+    tree_id INTEGER NOT NULL,
+    dir     TEXT NOT NULL,
+    UNIQUE(tree_id, dir)
+);
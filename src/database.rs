@@ -1,291 +1,2804 @@
 use {
-    crate::fs_util::ensure_dir_exists,
-    anyhow::bail,
-    rusqlite::{named_params, params, Connection, OptionalExtension},
+    crate::{
+        anchors::Anchors,
+        chunking::{Chunker, CHUNKED_MIN_SIZE},
+        encryption,
+        error::{bail, Context, Error},
+        fs_util::ensure_dir_exists,
+        nix::DevShell,
+        progress::{Observer, Progress},
+        run::{Isolation, Tree, Wrappers},
+        sandbox::Sandbox,
+        signing::{self, AllowedSigners, Policy},
+    },
+    rusqlite::{
+        blob::Blob, named_params, params, Connection, DatabaseName, ErrorCode, OpenFlags,
+        OptionalExtension, Transaction,
+    },
+    sha2::{Digest, Sha256},
     std::{
-        collections::HashSet,
+        collections::{HashSet, VecDeque},
         ffi::OsStr,
+        io::{Cursor, Read, Seek, SeekFrom, Write},
         path::{Path, PathBuf},
+        process::ExitStatus,
+        str::FromStr,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
     },
-    thiserror::Error,
 };
 
 /// Contains all the blobs
 pub struct Database {
     conn: Connection,
+    /// Whether the database was opened with [`Self::load_read_only`]
+    read_only: bool,
+    /// Used for resolving roots stored relative to anchors
+    anchors: Anchors,
+    /// Sandbox that all scripts run in, regardless of their own setting
+    forced_sandbox: Option<Sandbox>,
+    /// Environment variables set for all script runs
+    run_env: Vec<(String, String)>,
+    /// What happens when running scripts without a valid signature
+    signature_policy: Policy,
+    /// Keys whose signatures are valid even if gpg doesn't trust them
+    allowed_signers: AllowedSigners,
+    /// Untrusted scripts that may run anyway while the database is open
+    trusted_for_now: Vec<(i64, String)>,
 }
 
-const DB_FILENAME: &str = "otkeep.sqlite3";
+pub const DB_FILENAME: &str = "otkeep.sqlite3";
+
+/// Number of migrations up to and including the one adding `blobs.sha256`
+const SHA256_MIGRATION: usize = 15;
+
+/// Schema changes applied in order on top of `create_tables.sql`.
+///
+/// The number of applied migrations is stored as the `user_version` of the database.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/01_script_origin.sql"),
+    include_str!("migrations/02_tree_desc.sql"),
+    include_str!("migrations/03_tree_last_used.sql"),
+    include_str!("migrations/04_orphaned_files.sql"),
+    include_str!("migrations/05_tree_uuid.sql"),
+    include_str!("migrations/06_script_needs.sql"),
+    include_str!("migrations/07_runs.sql"),
+    include_str!("migrations/08_run_duration.sql"),
+    include_str!("migrations/09_script_clean_env.sql"),
+    include_str!("migrations/10_script_sandbox.sql"),
+    include_str!("migrations/11_script_container.sql"),
+    include_str!("migrations/12_tree_dev_shell.sql"),
+    include_str!("migrations/13_blob_signature.sql"),
+    include_str!("migrations/14_script_trusted.sql"),
+    include_str!("migrations/15_blob_sha256.sql"),
+    include_str!("migrations/16_script_locked.sql"),
+    include_str!("migrations/17_blob_former_owner.sql"),
+    include_str!("migrations/18_stable_rowids.sql"),
+    include_str!("migrations/19_script_archived.sql"),
+    include_str!("migrations/20_script_last_used.sql"),
+    include_str!("migrations/21_templates.sql"),
+    include_str!("migrations/22_script_tags.sql"),
+    include_str!("migrations/23_item_times.sql"),
+    include_str!("migrations/24_script_failure_hint.sql"),
+    include_str!("migrations/25_script_env.sql"),
+    include_str!("migrations/26_file_bundles.sql"),
+    include_str!("migrations/27_file_versions.sql"),
+    include_str!("migrations/28_size_limits.sql"),
+    include_str!("migrations/29_blob_body_last.sql"),
+    include_str!("migrations/30_chunks.sql"),
+    include_str!("migrations/31_blob_encrypted.sql"),
+    include_str!("migrations/32_tree_tags.sql"),
+];
 
+/// How many former versions of each saved file a tree keeps, unless set otherwise
+pub const DEFAULT_FILE_VERSIONS: u32 = 10;
+
+/// Largest size of a single script or saved file, unless set otherwise for the tree
+pub const DEFAULT_MAX_ITEM_SIZE: u64 = 1024 * 1024;
+
+/// SQL expression generating a random version 4 UUID for a new tree
+const NEW_UUID: &str = "lower(
+    hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2)
+    || '-' || substr('89ab', 1 + abs(random() % 4), 1) || substr(hex(randomblob(2)), 2)
+    || '-' || hex(randomblob(6))
+)";
+
+#[derive(Debug, Clone)]
 pub struct ScriptInfo {
     pub name: String,
     pub description: String,
+    /// Size of the contents in bytes
+    pub size: u64,
 }
 
-pub struct TreeRootInfo {
-    pub id: i64,
-    pub path: PathBuf,
+/// Reads a [`ScriptInfo`] from a row of name, description and size
+fn script_info(row: &rusqlite::Row) -> rusqlite::Result<ScriptInfo> {
+    Ok(ScriptInfo {
+        name: row.get(0)?,
+        description: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+        size: row.get(2)?,
+    })
 }
 
-impl Database {
-    pub fn load(dir: &Path) -> anyhow::Result<Self> {
-        ensure_dir_exists(dir)?;
-        let mut conn = Connection::open(dir.join(DB_FILENAME))?;
-        let tx = conn.transaction()?;
-        tx.execute_batch(include_str!("create_tables.sql"))?;
-        tx.commit()?;
-        Ok(Self { conn })
+/// Iterator over the scripts or saved files of a tree, in the order of their names.
+///
+/// Items are fetched one at a time, so items added while iterating may show up.
+pub struct ItemIter<'db> {
+    db: &'db Database,
+    /// Selects the name, description and size of the item after `?2` in tree `?1`
+    query: &'static str,
+    tree_id: i64,
+    /// Name of the last returned item
+    last: Option<String>,
+    done: bool,
+}
+
+impl Iterator for ItemIter<'_> {
+    type Item = crate::Result<ScriptInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let row = self
+            .db
+            .conn
+            .prepare_cached(self.query)
+            .and_then(|mut stmt| {
+                stmt.query_row(params![self.tree_id, self.last], script_info)
+                    .optional()
+            });
+        match row {
+            Ok(Some(info)) => {
+                self.last = Some(info.name.clone());
+                Some(Ok(info))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
     }
+}
 
-    pub fn add_script(&mut self, tree_id: i64, name: &str, body: Vec<u8>) -> anyhow::Result<()> {
-        let tx = self.conn.transaction()?;
-        tx.execute("INSERT INTO blobs (body) VALUES (?)", params![body])?;
-        let blob_id = tx.last_insert_rowid();
-        tx.execute(
-            "INSERT INTO tree_scripts (tree_id, name, blob_id) VALUES (?1, ?2, ?3)",
+/// Changes to the database that are applied all at once when committed, returned by
+/// [`Database::batch`].
+///
+/// Dropping the batch without committing it discards the changes.
+pub struct Batch<'db> {
+    tx: Transaction<'db>,
+}
+
+impl Batch<'_> {
+    pub fn script_exists(&self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        Ok(script_blob_id(&self.tx, tree_id, name)?.is_some())
+    }
+
+    pub fn add_script(&mut self, tree_id: i64, name: &str, body: Vec<u8>) -> crate::Result<()> {
+        self.insert_script(tree_id, name, body, false)
+    }
+
+    /// Like [`Self::add_script`], but stores the body encrypted with gpg
+    pub fn add_script_encrypted(
+        &mut self,
+        tree_id: i64,
+        name: &str,
+        body: Vec<u8>,
+    ) -> crate::Result<()> {
+        self.insert_script(tree_id, name, body, true)
+    }
+
+    fn insert_script(
+        &mut self,
+        tree_id: i64,
+        name: &str,
+        body: Vec<u8>,
+        encrypt: bool,
+    ) -> crate::Result<()> {
+        if self.script_exists(tree_id, name)? {
+            return Err(Error::NameConflict(name.to_owned()));
+        }
+        let body = match encrypt {
+            true => encryption::encrypt_item(&body)?,
+            false => body,
+        };
+        self.tx.execute(
+            "INSERT INTO blobs (body, sha256, encrypted) VALUES (?1, ?2, ?3)",
+            params![body, content_hash(&body), encrypt],
+        )?;
+        let blob_id = self.tx.last_insert_rowid();
+        self.tx.execute(
+            "INSERT INTO tree_scripts (tree_id, name, blob_id, last_used, created_at, updated_at)
+             VALUES (?1, ?2, ?3, unixepoch(), unixepoch(), unixepoch())",
             params![tree_id, name, blob_id],
         )?;
-        tx.commit()?;
         Ok(())
     }
 
-    pub fn update_script(&mut self, tree_id: i64, name: &str, body: Vec<u8>) -> anyhow::Result<()> {
-        match self.query_script_id_from_name(tree_id, name)? {
-            Some(blob_id) => {
-                self.conn.execute(
-                    "UPDATE blobs SET body=?1 WHERE _rowid_=?2",
-                    params![body, blob_id],
-                )?;
-            }
-            None => bail!("No such script"),
-        }
-        Ok(())
+    pub fn update_script(&mut self, tree_id: i64, name: &str, body: Vec<u8>) -> crate::Result<()> {
+        update_script_body(&self.tx, tree_id, name, body, false)
     }
 
-    /// Removes a script with `name` from the current tree and returns whether it actually
-    /// removed anything
-    pub fn remove_script(&mut self, tree_id: i64, name: &str) -> anyhow::Result<bool> {
-        Ok(self.conn.execute(
-            "DELETE FROM tree_scripts WHERE tree_id=?1 AND name=?2",
-            params![tree_id, name],
-        )? > 0)
+    /// Removes a script, and returns whether it existed
+    pub fn remove_script(&mut self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        delete_script(&self.tx, tree_id, name)
     }
 
-    pub fn run_script(
-        &self,
+    pub fn set_script_origin(
+        &mut self,
         tree_id: i64,
         name: &str,
-        args: impl Iterator<Item = impl AsRef<OsStr>>,
-    ) -> anyhow::Result<!> {
-        match self.query_script_id_from_name(tree_id, name)? {
-            Some(id) => {
-                let script = self.fetch_blob(id)?;
-                crate::run::run_script(&script, args, self.query_tree_root(tree_id)?)
+        origin: &str,
+    ) -> crate::Result<()> {
+        update_script_origin(&self.tx, tree_id, name, origin)
+    }
+
+    /// Saves a file, replacing the saved file with the same path. The former contents are kept as
+    /// a version, up to the number of versions the tree keeps.
+    ///
+    /// The contents are streamed from `source`, which is read twice: once to tell whether they
+    /// changed, and once to store them.
+    ///
+    /// With `encrypt`, the contents are stored encrypted with gpg, which they also are if the
+    /// saved file already was. Encrypted contents are read into memory to encrypt them.
+    ///
+    /// Returns false if the saved file already had the same contents, in which case nothing is
+    /// changed.
+    pub fn add_file(
+        &mut self,
+        tree_id: i64,
+        path: &str,
+        mut source: impl Read + Seek,
+        encrypt: bool,
+    ) -> crate::Result<bool> {
+        let size = source.seek(SeekFrom::End(0))?;
+        source.rewind()?;
+        let hash = content_hash_reader(&mut source)?;
+        source.rewind()?;
+        let existing: Option<i64> = self
+            .tx
+            .query_row(
+                "SELECT blob_id FROM tree_files WHERE tree_id=?1 AND name=?2",
+                params![tree_id, path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(blob_id) = existing else {
+            let blob_id = insert_item_blob(&self.tx, source, size, &hash, encrypt)?;
+            self.tx.execute(
+                "INSERT INTO tree_files (tree_id, name, blob_id, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, unixepoch(), unixepoch())",
+                params![tree_id, path, blob_id],
+            )?;
+            return Ok(true);
+        };
+        let was_encrypted = blob_encrypted(&self.tx, blob_id)?;
+        let encrypt = encrypt || was_encrypted;
+        let unchanged = if was_encrypted {
+            // The stored hash is of the encrypted contents, which differ every time
+            content_hash_reader(blob_reader(&self.tx, blob_id, true)?)? == hash
+        } else {
+            !encrypt
+                && self.tx.query_row(
+                    "SELECT sha256 IS ?1 AND COALESCE(length(body), chunked_size) IS ?2
+                     FROM blobs WHERE _rowid_=?3",
+                    params![hash, size, blob_id],
+                    |row| row.get(0),
+                )?
+        };
+        if unchanged {
+            return Ok(false);
+        }
+        let keep = tree_file_versions(&self.tx, tree_id)?;
+        if keep > 0 {
+            self.tx.execute(
+                "INSERT INTO file_versions (tree_id, name, version, blob_id, saved_at)
+                 SELECT ?1, ?2, (SELECT COALESCE(MAX(version), 0) + 1 FROM file_versions
+                                 WHERE tree_id=?1 AND name=?2),
+                        blob_id, updated_at
+                 FROM tree_files WHERE tree_id=?1 AND name=?2",
+                params![tree_id, path],
+            )?;
+            let new_blob_id = insert_item_blob(&self.tx, source, size, &hash, encrypt)?;
+            self.tx.execute(
+                "UPDATE tree_files SET blob_id=?1 WHERE tree_id=?2 AND name=?3",
+                params![new_blob_id, tree_id, path],
+            )?;
+            prune_file_versions(&self.tx, tree_id, Some(path), keep)?;
+        } else {
+            let new_blob_id = insert_item_blob(&self.tx, source, size, &hash, encrypt)?;
+            self.tx.execute(
+                "UPDATE tree_files SET blob_id=?1 WHERE tree_id=?2 AND name=?3",
+                params![new_blob_id, tree_id, path],
+            )?;
+            // Unless it's shared with a cloned item, which keeps the old contents
+            if blob_users(&self.tx, blob_id)? == 0 {
+                self.tx
+                    .execute("DELETE FROM blobs WHERE _rowid_=?", params![blob_id])?;
             }
-            None => bail!(NoSuchScriptForCurrentTree),
         }
+        self.tx.execute(
+            "UPDATE tree_files SET updated_at=unixepoch() WHERE tree_id=?1 AND name=?2",
+            params![tree_id, path],
+        )?;
+        Ok(true)
     }
 
-    pub fn query_tree_root(&self, id: i64) -> anyhow::Result<String> {
-        self.conn.query_row_and_then(
-            "SELECT root FROM trees WHERE _rowid_=?",
-            params![id],
-            |row| {
-                let root: String = row.get(0)?;
-                Ok(root)
-            },
-        )
+    /// Applies the changes
+    pub fn commit(self) -> crate::Result<()> {
+        Ok(self.tx.commit()?)
     }
+}
 
-    pub fn blob_is_null(&self, id: i64) -> anyhow::Result<bool> {
-        self.conn.query_row_and_then(
-            "SELECT body FROM blobs WHERE _rowid_=?",
-            params![id],
-            |row| {
-                let blob: Option<Vec<u8>> = row.get(0)?;
-                Ok(blob.is_none())
-            },
-        )
-    }
+/// The contents of a blob, read incrementally instead of all at once. Returned by
+/// [`Database::open_blob`].
+pub struct BlobReader<'db> {
+    conn: &'db Connection,
+    /// The blob being read, or the current chunk of a chunked one
+    blob: Option<Blob<'db>>,
+    /// Ids of the chunks after the current one
+    chunks: VecDeque<i64>,
+    /// The contents of an encrypted blob, which are decrypted all at once
+    decrypted: Option<Cursor<Vec<u8>>>,
+    len: u64,
+}
 
-    pub fn fetch_blob(&self, id: i64) -> Result<Vec<u8>, anyhow::Error> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT body FROM blobs WHERE _rowid_=?")?;
-        let blob: Vec<u8> = stmt.query_row(params![id], |row| row.get(0))?;
-        Ok(blob)
+impl BlobReader<'_> {
+    /// Size of the contents in bytes
+    pub fn len(&self) -> u64 {
+        self.len
     }
 
-    fn query_script_id_from_name(&self, tree_id: i64, name: &str) -> anyhow::Result<Option<i64>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT blob_id FROM tree_scripts WHERE tree_id=?1 AND name=?2")?;
-        let blob_id: Option<i64> = stmt
-            .query_row(params![tree_id, name], |row| row.get(0))
-            .optional()?;
-        Ok(blob_id)
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
+}
 
-    fn query_file_id_from_name(&self, tree_id: i64, name: &str) -> anyhow::Result<Option<i64>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT blob_id FROM tree_files WHERE tree_id=?1 AND name=?2")?;
-        let blob_id: Option<i64> = stmt
-            .query_row(params![tree_id, name], |row| row.get(0))
-            .optional()?;
-        Ok(blob_id)
+impl Read for BlobReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(decrypted) = &mut self.decrypted {
+            return decrypted.read(buf);
+        }
+        loop {
+            if let Some(blob) = &mut self.blob {
+                let n = blob.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+            }
+            let Some(chunk_id) = self.chunks.pop_front() else {
+                return Ok(0);
+            };
+            let chunk = self
+                .conn
+                .blob_open(DatabaseName::Main, "chunks", "body", chunk_id, true)
+                .map_err(std::io::Error::other)?;
+            self.blob = Some(chunk);
+        }
     }
+}
 
-    pub fn scripts_for_tree(&self, tree_id: i64) -> anyhow::Result<Vec<ScriptInfo>> {
-        let mut stmt = self
+/// Iterator over the established trees, in the order they were established
+pub struct TreeIter<'db> {
+    db: &'db Database,
+    /// Id of the last returned tree
+    last: i64,
+    done: bool,
+}
+
+impl Iterator for TreeIter<'_> {
+    type Item = crate::Result<TreeRootInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let row = self
+            .db
             .conn
-            .prepare("SELECT name, desc FROM tree_scripts WHERE tree_id=?")?;
-        let rows = stmt.query_map(params![tree_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
-        let mut vec = Vec::new();
-        for result in rows {
-            let (name, description) = result?;
-            let description: Option<String> = description;
-            vec.push(ScriptInfo {
-                name,
-                description: description.unwrap_or_default(),
+            .prepare_cached(
+                "SELECT _rowid_, root, desc, uuid, tags FROM trees WHERE _rowid_ > ?
+                 ORDER BY _rowid_ LIMIT 1",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_row(params![self.last], |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                    ))
+                })
+                .optional()
             });
-        }
-        Ok(vec)
+        let tree = match row {
+            Ok(Some((id, root, desc, uuid, tags))) => {
+                self.last = id;
+                self.db.anchors.decode(&root).map(|path| TreeRootInfo {
+                    id,
+                    path,
+                    desc,
+                    uuid,
+                    tags: split_tags(tags.as_deref()),
+                })
+            }
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => Err(e.into()),
+        };
+        self.done |= tree.is_err();
+        Some(tree)
     }
+}
 
-    pub fn files_for_tree(&self, tree_id: i64) -> anyhow::Result<Vec<ScriptInfo>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT name, desc FROM tree_files WHERE tree_id=?")?;
-        let rows = stmt.query_map(params![tree_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
-        let mut vec = Vec::new();
-        for result in rows {
-            let (name, description) = result?;
-            let description: Option<String> = description;
-            vec.push(ScriptInfo {
-                name,
-                description: description.unwrap_or_default(),
-            });
+/// How large the items of a tree may get
+pub struct SizeLimits {
+    /// Largest size of a single script or saved file
+    pub item: Option<u64>,
+    /// Largest total size of the scripts, saved files and file versions
+    pub total: Option<u64>,
+}
+
+/// How the large saved files of a tree are stored as chunks
+pub struct ChunkStats {
+    /// Number of saved files and file versions that are stored as chunks
+    pub files: u64,
+    /// Total size of their contents
+    pub size: u64,
+    /// Number of different chunks they're made of
+    pub chunks: u64,
+    /// Total size of those chunks, which is what the files actually take
+    pub stored: u64,
+}
+
+/// What [`Database::purge_history`] removes
+#[derive(Clone, Copy, Default)]
+pub struct PurgeOpts<'a> {
+    /// Only remove what's older than this many seconds
+    pub older_than: Option<i64>,
+    /// Only remove the runs of this script
+    pub script: Option<&'a str>,
+    /// Only remove the former versions of this saved file
+    pub file: Option<&'a str>,
+    /// Keep the runs, and only remove their captured output
+    pub logs_only: bool,
+    /// Only count what would be removed
+    pub dry_run: bool,
+}
+
+/// What [`Database::purge_history`] removed
+#[derive(Default)]
+pub struct PurgeReport {
+    pub runs: usize,
+    /// Runs whose captured output was removed, but not the run itself
+    pub logs: usize,
+    pub file_versions: usize,
+}
+
+/// A version of a saved file
+pub struct FileVersion {
+    /// Number of the version, counting the saves of the file from 1
+    pub version: i64,
+    /// Unix timestamp of when it was saved, if known
+    pub saved_at: Option<i64>,
+    /// Size of the contents in bytes
+    pub size: u64,
+    /// Whether this is the current contents of the file
+    pub current: bool,
+}
+
+/// An entry in the run history of a script
+pub struct RunInfo {
+    pub id: i64,
+    /// Name of the script
+    pub name: String,
+    /// Unix timestamp of when the run started
+    pub started: i64,
+    pub exit_code: i32,
+    /// Size of the captured output, if it was captured
+    pub output_size: Option<u64>,
+    /// How long the run took. Not known for runs recorded by older versions.
+    pub duration: Option<Duration>,
+}
+
+/// What to do with a script that already exists in the destination of a clone
+pub enum ConflictResolution {
+    /// Keep the existing script
+    Skip,
+    /// Replace the existing script with the cloned one
+    Overwrite,
+}
+
+/// The kinds of items a tree holds
+#[derive(Clone, Copy)]
+pub enum ItemKind {
+    Script,
+    File,
+}
+
+impl ItemKind {
+    fn table(self) -> &'static str {
+        match self {
+            Self::Script => "tree_scripts",
+            Self::File => "tree_files",
         }
-        Ok(vec)
     }
+}
 
-    pub fn query_tree(&self, path: &Path) -> anyhow::Result<Option<i64>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT _rowid_ FROM trees where root=?")?;
-        Ok(stmt
-            .query_row(params![paths_as_strings::encode_path(&path)], |row| {
-                row.get(0)
-            })
-            .optional()?)
+impl std::fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Script => "script",
+            Self::File => "file",
+        })
     }
+}
 
-    pub fn add_new_tree(&self, path: &Path) -> anyhow::Result<()> {
-        let str = paths_as_strings::encode_path(&path);
-        self.conn
-            .execute("INSERT INTO trees (root) VALUES (?)", params![str])?;
-        Ok(())
+/// The names of the items affected by a clone, by outcome
+#[derive(Default)]
+pub struct CloneOutcome {
+    pub cloned: Vec<String>,
+    pub overwritten: Vec<String>,
+    pub skipped: Vec<String>,
+    /// Scripts that were kept because they're locked in the destination
+    pub locked: Vec<String>,
+}
+
+/// Which items [`Database::clone_tree`] clones
+#[derive(Default, Clone, Copy)]
+pub struct CloneOpts<'a> {
+    /// Only clone scripts with names matching this glob pattern
+    pub only: Option<&'a str>,
+    /// Never clone scripts with names matching this glob pattern
+    pub except: Option<&'a str>,
+    /// Also clone the saved files
+    pub with_files: bool,
+    /// Also overwrite locked scripts
+    pub force: bool,
+}
+
+#[derive(Default)]
+pub struct CloneReport {
+    pub scripts: CloneOutcome,
+    pub files: CloneOutcome,
+}
+
+/// What importing from another database did (or would do) with a tree
+pub struct TreeImport {
+    pub root: PathBuf,
+    /// Whether the tree didn't exist in this database before
+    pub new: bool,
+    pub scripts: CloneOutcome,
+    pub files: CloneOutcome,
+}
+
+/// A script or file along with everything stored about it
+struct StoredItem {
+    name: String,
+    desc: Option<String>,
+    origin: Option<String>,
+    needs: Option<String>,
+    clean_env: bool,
+    sandbox: Option<String>,
+    container: Option<String>,
+    locked: bool,
+    archived: bool,
+    tags: Option<String>,
+    failure_hint: Option<String>,
+    env: Option<String>,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    /// Id of the blob with the contents, in the database the item is stored in
+    blob_id: i64,
+    signature: Option<Vec<u8>>,
+    encrypted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TreeRootInfo {
+    pub id: i64,
+    pub path: PathBuf,
+    pub desc: Option<String>,
+    /// Stable identity of the tree, which doesn't change when it's moved
+    pub uuid: String,
+    pub tags: Vec<String>,
+}
+
+/// The description and timestamps of a script or saved file
+#[derive(Debug, Clone)]
+pub struct ItemDetails {
+    pub desc: Option<String>,
+    /// When the item was added, in seconds since the Unix epoch.
+    /// Unknown for items added before this was recorded.
+    pub created_at: Option<i64>,
+    /// When the contents of the item last changed, in seconds since the Unix epoch
+    pub updated_at: Option<i64>,
+    /// Whether the contents are stored encrypted with gpg
+    pub encrypted: bool,
+}
+
+/// What [`Database::list_items`] sorts by
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ItemSort {
+    #[default]
+    Name,
+    /// Largest first
+    Size,
+    /// Most recently changed first
+    Modified,
+    /// Most recently run first. Only for scripts.
+    Usage,
+}
+
+impl ItemSort {
+    pub const NAMES: [&str; 4] = ["name", "size", "modified", "usage"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Name => Self::NAMES[0],
+            Self::Size => Self::NAMES[1],
+            Self::Modified => Self::NAMES[2],
+            Self::Usage => Self::NAMES[3],
+        }
     }
+}
 
-    pub fn rename_tree(&self, old_path: &Path, new_path: &Path) -> anyhow::Result<()> {
-        let old_path = paths_as_strings::encode_path(&old_path);
-        let new_path = paths_as_strings::encode_path(&new_path);
-        self.conn.execute(
-            "UPDATE trees SET root=?2 WHERE root=?1",
-            params![old_path, new_path],
-        )?;
-        Ok(())
+impl FromStr for ItemSort {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "name" => Ok(Self::Name),
+            "size" => Ok(Self::Size),
+            "modified" => Ok(Self::Modified),
+            "usage" => Ok(Self::Usage),
+            _ => bail!(
+                "Unknown sort key '{s}', expected one of: {}",
+                Self::NAMES.join(", ")
+            ),
+        }
     }
+}
 
-    pub fn remove_tree(&mut self, tree_id: i64) -> anyhow::Result<()> {
-        let tx = self.conn.transaction()?;
-        tx.execute("DELETE FROM trees WHERE _rowid_=?", params![tree_id])?;
-        tx.execute("DELETE FROM tree_scripts WHERE tree_id=?", params![tree_id])?;
+/// Which scripts or saved files [`Database::list_items`] returns, and in what order
+#[derive(Default, Clone, Copy)]
+pub struct ListOpts<'a> {
+    pub sort: ItemSort,
+    /// Only list the items with names matching this glob pattern
+    pub filter: Option<&'a str>,
+    /// Return at most this many items
+    pub limit: Option<u64>,
+}
+
+/// A script or saved file whose contents changed since it was stored
+pub struct HashMismatch {
+    pub root: PathBuf,
+    pub kind: ItemKind,
+    pub name: String,
+    /// The hash it was stored with, if any
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+/// A tree along with statistics about its contents
+pub struct TreeStats {
+    pub root: TreeRootInfo,
+    pub script_count: u64,
+    pub file_count: u64,
+    /// Total size of the scripts and files in bytes
+    pub size: u64,
+    /// When a script of the tree was last run, in seconds since the Unix epoch
+    pub last_used: Option<i64>,
+}
+
+impl Database {
+    /// Opens the database in `dir`, creating or upgrading it if needed.
+    ///
+    /// `key` is the passphrase of an encrypted database.
+    pub fn load(dir: &Path, key: Option<&str>) -> crate::Result<Self> {
+        ensure_dir_exists(dir)?;
+        let mut conn = open_connection(&dir.join(DB_FILENAME), OpenFlags::default(), key)?;
+        let tx = conn.transaction()?;
+        tx.execute_batch(include_str!("create_tables.sql"))?;
+        let version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            bail!("The database was created by a newer version of OtKeep");
+        }
+        if version < MIGRATIONS.len() {
+            for migration in &MIGRATIONS[version..] {
+                tx.execute_batch(migration)?;
+            }
+            tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        }
+        if version < SHA256_MIGRATION {
+            pin_blob_hashes(&tx)?;
+        }
         tx.commit()?;
-        Ok(())
+        Ok(Self::new(conn, false))
     }
 
-    pub fn add_script_description(
-        &self,
-        tree_id: i64,
-        name: &str,
-        desc: &str,
-    ) -> anyhow::Result<()> {
+    /// Opens the database in `dir` without ever writing to it, e.g. when it's on a read-only
+    /// mount.
+    ///
+    /// Commands that change the database fail, and runs aren't recorded.
+    pub fn load_read_only(dir: &Path, key: Option<&str>) -> crate::Result<Self> {
+        let path = dir.join(DB_FILENAME);
+        if !path.exists() {
+            bail!("There is no database at {}", path.display());
+        }
+        let conn = open_connection(
+            &path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            key,
+        )?;
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            bail!("The database was created by a newer version of OtKeep");
+        }
+        if version < MIGRATIONS.len() {
+            bail!(
+                "The database is read-only, but it needs to be upgraded. \
+                 Use this version of OtKeep with write access to it once."
+            );
+        }
+        Ok(Self::new(conn, true))
+    }
+
+    /// Writes a copy of the database to `dest`, encrypted with `passphrase`, or unencrypted
+    /// without one. Needs SQLCipher.
+    pub fn write_copy(&self, dest: &Path, passphrase: Option<&str>) -> crate::Result<()> {
+        let dest = dest
+            .to_str()
+            .context("The path of the copy isn't valid UTF-8")?;
         self.conn.execute(
-            "UPDATE tree_scripts SET desc=?1 WHERE tree_id=?2 AND name=?3",
-            params![desc, tree_id, name],
+            "ATTACH DATABASE ?1 AS copy KEY ?2",
+            params![dest, passphrase.unwrap_or("")],
         )?;
-        Ok(())
+        let result = self
+            .conn
+            .query_row("SELECT sqlcipher_export('copy')", [], |_| Ok(()))
+            .and_then(|()| {
+                self.conn.pragma_update(
+                    Some(DatabaseName::Attached("copy")),
+                    "user_version",
+                    MIGRATIONS.len(),
+                )
+            });
+        self.conn.execute("DETACH DATABASE copy", [])?;
+        Ok(result?)
     }
 
-    pub fn get_tree_roots(&self) -> anyhow::Result<Vec<TreeRootInfo>> {
-        let mut stmt = self.conn.prepare("SELECT _rowid_, root FROM trees")?;
-        let mut vec = Vec::new();
-        for result in stmt.query_map([], |row| {
-            let id = row.get(0)?;
-            let root_path: String = row.get(1)?;
-            Ok((id, root_path))
-        })? {
-            let (id, root) = result?;
-            let pb = paths_as_strings::decode_path(&root)?;
-            vec.push(TreeRootInfo { id, path: pb });
+    fn new(conn: Connection, read_only: bool) -> Self {
+        Self {
+            conn,
+            read_only,
+            anchors: Anchors::default(),
+            forced_sandbox: None,
+            run_env: Vec::new(),
+            signature_policy: Policy::default(),
+            allowed_signers: AllowedSigners::default(),
+            trusted_for_now: Vec::new(),
         }
-        Ok(vec)
     }
 
-    pub fn get_script_by_name(&self, tree_id: i64, name: &str) -> anyhow::Result<Vec<u8>> {
-        match self.query_script_id_from_name(tree_id, name)? {
-            Some(id) => Ok(self.fetch_blob(id)?),
-            None => bail!("No such script"),
-        }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    pub fn get_file_by_name(&self, tree_id: i64, name: &str) -> anyhow::Result<Vec<u8>> {
-        match self.query_file_id_from_name(tree_id, name)? {
-            Some(id) => Ok(self.fetch_blob(id)?),
-            None => bail!("No such file"),
-        }
+    /// Starts a batch of changes, which are applied in a single transaction when it's committed
+    pub fn batch(&mut self) -> crate::Result<Batch<'_>> {
+        Ok(Batch {
+            tx: self.conn.transaction()?,
+        })
     }
 
-    pub fn rename_script(&self, old_name: &str, new_name: &str) -> Result<(), anyhow::Error> {
-        self.conn.execute(
-            "UPDATE tree_scripts SET name=?1 WHERE name=?2",
-            params![new_name, old_name],
-        )?;
-        Ok(())
+    pub fn add_script(&mut self, tree_id: i64, name: &str, body: Vec<u8>) -> crate::Result<()> {
+        let mut batch = self.batch()?;
+        batch.add_script(tree_id, name, body)?;
+        batch.commit()
+    }
+
+    /// Like [`Self::add_script`], but stores the body encrypted with gpg
+    pub fn add_script_encrypted(
+        &mut self,
+        tree_id: i64,
+        name: &str,
+        body: Vec<u8>,
+    ) -> crate::Result<()> {
+        let mut batch = self.batch()?;
+        batch.add_script_encrypted(tree_id, name, body)?;
+        batch.commit()
+    }
+
+    /// Replaces the body of a script. It's stored encrypted if it already was.
+    pub fn update_script(&mut self, tree_id: i64, name: &str, body: Vec<u8>) -> crate::Result<()> {
+        update_script_body(&self.conn, tree_id, name, body, false)
+    }
+
+    /// Like [`Self::update_script`], but stores the body encrypted with gpg even if it wasn't
+    pub fn update_script_encrypted(
+        &mut self,
+        tree_id: i64,
+        name: &str,
+        body: Vec<u8>,
+    ) -> crate::Result<()> {
+        update_script_body(&self.conn, tree_id, name, body, true)
     }
 
-    pub fn add_file(&mut self, tree_id: i64, path: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+    /// Copies a script along with its metadata under a new name in the same tree.
+    ///
+    /// The copy gets its own blob, so the two scripts can be modified independently.
+    pub fn copy_script(&mut self, tree_id: i64, name: &str, new_name: &str) -> crate::Result<()> {
+        self.copy_script_to(tree_id, name, tree_id, new_name)
+    }
+
+    /// Like [`Self::copy_script`], but the copy is added to the tree `dst_tree_id`.
+    ///
+    /// The stored blob is copied as is, so an encrypted script stays encrypted.
+    pub fn copy_script_to(
+        &mut self,
+        tree_id: i64,
+        name: &str,
+        dst_tree_id: i64,
+        new_name: &str,
+    ) -> crate::Result<()> {
+        let Some(blob_id) = self.query_script_id_from_name(tree_id, name)? else {
+            bail!("No such script");
+        };
+        if self.script_exists(dst_tree_id, new_name)? {
+            return Err(Error::NameConflict(new_name.to_owned()));
+        }
         let tx = self.conn.transaction()?;
         tx.execute(
-            "INSERT OR REPLACE INTO blobs (body) VALUES (?)",
-            params![bytes],
+            "INSERT INTO blobs (body, sha256, signature, encrypted, chunked_size)
+             SELECT body, sha256, signature, encrypted, chunked_size FROM blobs WHERE _rowid_=?",
+            params![blob_id],
+        )?;
+        let new_blob_id = tx.last_insert_rowid();
+        // Large blobs are stored as chunks, which the copy shares
+        tx.execute(
+            "INSERT INTO blob_chunks (blob_id, seq, chunk_id)
+             SELECT ?1, seq, chunk_id FROM blob_chunks WHERE blob_id=?2",
+            params![new_blob_id, blob_id],
         )?;
-        let blob_id = tx.last_insert_rowid();
         tx.execute(
-            "INSERT OR REPLACE INTO tree_files (tree_id, name, blob_id) VALUES (?1, ?2, ?3)",
-            params![tree_id, path, blob_id],
+            "INSERT INTO tree_scripts
+             (tree_id, blob_id, name, desc, origin, needs, clean_env, sandbox, container,
+              trusted, locked, archived, last_used, tags, created_at, updated_at, failure_hint,
+              env)
+             SELECT ?5, ?1, ?2, desc, origin, needs, clean_env, sandbox, container, trusted,
+                 locked, archived, unixepoch(), tags, unixepoch(), updated_at, failure_hint, env
+             FROM tree_scripts
+             WHERE tree_id=?3 AND name=?4",
+            params![new_blob_id, new_name, tree_id, name, dst_tree_id],
         )?;
         tx.commit()?;
         Ok(())
     }
 
-    pub fn clone_tree(&mut self, src_tree: i64, dst_tree: i64) -> anyhow::Result<()> {
-        self.conn.execute(
-            include_str!("clone_tree_table.sql"),
-            named_params! {
-                ":src": src_tree,
-                ":dst": dst_tree,
-            },
-        )?;
-        Ok(())
-    }
-    /// Returns a set of blob ids that are referenced by trees
+    /// Removes a script with `name` from the current tree and returns whether it actually
+    /// removed anything
+    pub fn remove_script(&mut self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        delete_script(&self.conn, tree_id, name)
+    }
+
+    /// Removes multiple scripts from the tree in a single transaction.
+    ///
+    /// Returns the number of scripts actually removed.
+    pub fn remove_scripts(&mut self, tree_id: i64, names: &[String]) -> crate::Result<usize> {
+        let mut batch = self.batch()?;
+        let mut removed = 0;
+        for name in names {
+            removed += usize::from(batch.remove_script(tree_id, name)?);
+        }
+        batch.commit()?;
+        Ok(removed)
+    }
+
+    /// Returns the names of the scripts of the tree matching a glob pattern.
+    ///
+    /// The pattern uses the syntax of SQLite's `GLOB` operator (`*`, `?`, `[...]`).
+    pub fn script_names_matching(&self, tree_id: i64, pattern: &str) -> crate::Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM tree_scripts WHERE tree_id=?1 AND name GLOB ?2")?;
+        let rows = stmt.query_map(params![tree_id, pattern], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the names of the scripts of the tree starting with `prefix`, except the archived
+    /// ones, for completing them in shells
+    pub fn script_names_with_prefix(
+        &self,
+        tree_id: i64,
+        prefix: &str,
+    ) -> crate::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name FROM tree_scripts
+             WHERE tree_id=?1 AND NOT archived AND substr(name, 1, length(?2)) = ?2
+             ORDER BY name",
+        )?;
+        let rows = stmt.query_map(params![tree_id, prefix], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn run_script(
+        &self,
+        tree_id: i64,
+        name: &str,
+        args: impl Iterator<Item = impl AsRef<OsStr>>,
+    ) -> crate::Result<!> {
+        let (script, wrappers) = self.fetch_script_for_run(tree_id, name)?;
+        let root = self.query_tree_root(tree_id)?;
+        crate::run::run_script(
+            name,
+            &script,
+            args,
+            &self.run_tree(tree_id, &root),
+            &wrappers,
+        )
+    }
+
+    /// Like [`Self::run_script`], but runs the script as a child process and waits for it
+    pub fn run_script_supervised(
+        &self,
+        tree_id: i64,
+        name: &str,
+        args: impl Iterator<Item = impl AsRef<OsStr>>,
+    ) -> crate::Result<ExitStatus> {
+        let (script, wrappers) = self.fetch_script_for_run(tree_id, name)?;
+        let root = self.query_tree_root(tree_id)?;
+        let started = unix_now();
+        let start = Instant::now();
+        let status = crate::run::run_script_supervised(
+            name,
+            &script,
+            args,
+            &self.run_tree(tree_id, &root),
+            &wrappers,
+        )?;
+        self.record_run(tree_id, name, started, start.elapsed(), status, None)?;
+        Ok(status)
+    }
+
+    /// Like [`Self::run_script_supervised`], but also stores the output of the script in the
+    /// run history
+    pub fn run_script_logged(
+        &self,
+        tree_id: i64,
+        name: &str,
+        args: impl Iterator<Item = impl AsRef<OsStr>>,
+    ) -> crate::Result<ExitStatus> {
+        let (script, wrappers) = self.fetch_script_for_run(tree_id, name)?;
+        let root = self.query_tree_root(tree_id)?;
+        let started = unix_now();
+        let start = Instant::now();
+        let (status, output) = crate::run::run_script_logged(
+            name,
+            &script,
+            args,
+            &self.run_tree(tree_id, &root),
+            &wrappers,
+        )?;
+        self.record_run(
+            tree_id,
+            name,
+            started,
+            start.elapsed(),
+            status,
+            Some(&output),
+        )?;
+        Ok(status)
+    }
+
+    pub(crate) fn record_run(
+        &self,
+        tree_id: i64,
+        name: &str,
+        started: i64,
+        duration: Duration,
+        status: ExitStatus,
+        output: Option<&[u8]>,
+    ) -> crate::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.conn.execute(
+            "INSERT INTO runs (tree_id, name, started, duration_ms, exit_code, output)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                tree_id,
+                name,
+                started,
+                duration.as_millis() as i64,
+                crate::exit_code(status),
+                output
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the run history of a script, oldest first
+    pub fn runs(&self, tree_id: i64, name: &str) -> crate::Result<Vec<RunInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT _rowid_, name, started, exit_code, length(output), duration_ms FROM runs
+             WHERE tree_id=?1 AND name=?2 ORDER BY _rowid_",
+        )?;
+        let rows = stmt.query_map(params![tree_id, name], run_info)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the most recent run of a script, or of any script of the tree if `name` is `None`
+    pub fn last_run(&self, tree_id: i64, name: Option<&str>) -> crate::Result<Option<RunInfo>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT _rowid_, name, started, exit_code, length(output), duration_ms FROM runs
+                 WHERE tree_id=?1 AND (?2 IS NULL OR name=?2) ORDER BY _rowid_ DESC LIMIT 1",
+                params![tree_id, name],
+                run_info,
+            )
+            .optional()?)
+    }
+
+    /// Returns the captured output of a run of a script, if it was captured
+    pub fn run_output(
+        &self,
+        tree_id: i64,
+        name: &str,
+        run_id: i64,
+    ) -> crate::Result<Option<Vec<u8>>> {
+        let output = self
+            .conn
+            .query_row(
+                "SELECT output FROM runs WHERE _rowid_=?1 AND tree_id=?2 AND name=?3",
+                params![run_id, tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match output {
+            Some(output) => Ok(output),
+            None => bail!("No run {run_id} of '{name}'"),
+        }
+    }
+
+    /// Runs several scripts at the same time, and returns their exit statuses in the same order.
+    /// Each run is recorded like with [`Self::run_script_supervised`].
+    pub fn run_scripts_parallel(
+        &self,
+        tree_id: i64,
+        names: &[String],
+    ) -> crate::Result<Vec<ExitStatus>> {
+        let scripts = names
+            .iter()
+            .map(|name| {
+                let (script, wrappers) = self.fetch_script_for_run(tree_id, name)?;
+                Ok((name.clone(), script, wrappers))
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        let root = self.query_tree_root(tree_id)?;
+        let started = unix_now();
+        let results = crate::run::run_scripts_parallel(&scripts, &self.run_tree(tree_id, &root))?;
+        let mut statuses = Vec::new();
+        for (name, (status, duration)) in names.iter().zip(results) {
+            self.record_run(tree_id, name, started, duration, status, None)?;
+            statuses.push(status);
+        }
+        Ok(statuses)
+    }
+
+    pub(crate) fn run_tree<'a>(&'a self, tree_id: i64, root: &'a Path) -> Tree<'a> {
+        Tree {
+            id: tree_id,
+            root,
+            db_path: self.conn.path().map(Path::new),
+        }
+    }
+
+    /// Returns the body of a script, and what it should run inside of
+    pub(crate) fn fetch_script_for_run(
+        &self,
+        tree_id: i64,
+        name: &str,
+    ) -> crate::Result<(Vec<u8>, Wrappers)> {
+        let body = match self.query_script_id_from_name(tree_id, name)? {
+            Some(id) => self.fetch_blob(id)?,
+            None => return Err(Error::NoSuchScript),
+        };
+        if self.script_archived(tree_id, name)? {
+            return Err(Error::ScriptArchived(name.to_owned()));
+        }
+        if !self.script_trusted(tree_id, name)?
+            && !self
+                .trusted_for_now
+                .iter()
+                .any(|(id, trusted)| *id == tree_id && trusted == name)
+        {
+            return Err(Error::UntrustedScript(name.to_owned()));
+        }
+        if self.signature_policy != Policy::Ignore {
+            let problem = match self.script_signature(tree_id, name)? {
+                Some(signature) => signing::verify(&body, &signature, &self.allowed_signers).err(),
+                None => Some(Error::Other(format!("{name} is not signed"))),
+            };
+            if let Some(problem) = problem {
+                if self.signature_policy == Policy::Refuse {
+                    return Err(problem).context(format!(
+                        "Refusing to run {name}, because its signature couldn't be verified"
+                    ));
+                }
+                eprintln!("Warning: {problem:#}");
+            }
+        }
+        let sandbox = match self.forced_sandbox {
+            Some(sandbox) => Some(sandbox),
+            None => self.script_sandbox(tree_id, name)?,
+        };
+        let isolation = match (sandbox, self.script_container(tree_id, name)?) {
+            (Some(_), Some(_)) => bail!("{name} can't run both in a sandbox and in a container"),
+            (Some(sandbox), None) => {
+                crate::sandbox::check_available()?;
+                Some(Isolation::Sandbox(sandbox))
+            }
+            (None, Some(image)) => Some(Isolation::Container(image)),
+            (None, None) => None,
+        };
+        if !self.read_only {
+            self.conn.execute(
+                "UPDATE trees SET last_used=unixepoch() WHERE _rowid_=?",
+                params![tree_id],
+            )?;
+            self.conn.execute(
+                "UPDATE tree_scripts SET last_used=unixepoch() WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+            )?;
+        }
+        let dev_shell = self.tree_dev_shell(tree_id)?;
+        let in_container = matches!(isolation, Some(Isolation::Container(_)));
+        if let Some(dev_shell) = dev_shell.filter(|_| !in_container) {
+            dev_shell.check_available()?;
+        }
+        // The variables given for this run override the ones stored with the script
+        let mut env = self.script_env(tree_id, name)?;
+        env.extend(self.run_env.iter().cloned());
+        let body = self.expand_run_placeholders(tree_id, name, body, &env)?;
+        let wrappers = Wrappers {
+            isolation,
+            dev_shell,
+            env,
+            cwd: None,
+        };
+        Ok((body, wrappers))
+    }
+
+    /// Expands the `{{OTKEEP:...}}` placeholders in the body of a script that's about to run.
+    ///
+    /// `tree_name`, `tree_root`, `tree_desc` and `script_name` come from the tree, and `env:KEY`
+    /// from the environment the script runs with, where `env` is set on top of the inherited one.
+    /// Unknown placeholders are left alone.
+    fn expand_run_placeholders(
+        &self,
+        tree_id: i64,
+        name: &str,
+        body: Vec<u8>,
+        env: &[(String, String)],
+    ) -> crate::Result<Vec<u8>> {
+        if !body.windows(9).any(|w| w == b"{{OTKEEP:") {
+            return Ok(body);
+        }
+        let root = self.query_tree_root(tree_id)?;
+        let desc = self.tree_desc(tree_id)?;
+        Ok(crate::expand_placeholders_with(&body, |key| {
+            match key.strip_prefix("OTKEEP:")? {
+                "tree_name" => Some(root.file_name()?.to_string_lossy().into_owned()),
+                "tree_root" => Some(root.to_string_lossy().into_owned()),
+                "tree_desc" => Some(desc.clone().unwrap_or_default()),
+                "script_name" => Some(name.to_owned()),
+                key => {
+                    let var = key.strip_prefix("env:")?;
+                    match env.iter().rev().find(|(k, _)| k == var) {
+                        Some((_, value)) => Some(value.clone()),
+                        None => std::env::var(var).ok(),
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Sets the sandbox a script always runs in
+    pub fn set_script_sandbox(
+        &self,
+        tree_id: i64,
+        name: &str,
+        sandbox: Option<Sandbox>,
+    ) -> crate::Result<()> {
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET sandbox=?1 WHERE tree_id=?2 AND name=?3",
+            params![sandbox.map(Sandbox::name), tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    pub fn script_sandbox(&self, tree_id: i64, name: &str) -> crate::Result<Option<Sandbox>> {
+        let sandbox: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT sandbox FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match sandbox {
+            Some(sandbox) => sandbox.map(|s| s.parse()).transpose(),
+            None => Err(Error::NoSuchScript),
+        }
+    }
+
+    pub fn query_tree_root(&self, id: i64) -> crate::Result<PathBuf> {
+        let root: String = self
+            .conn
+            .query_row(
+                "SELECT root FROM trees WHERE _rowid_=?",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or(Error::NoSuchTree)?;
+        self.anchors.decode(&root)
+    }
+
+    pub fn anchors(&self) -> &Anchors {
+        &self.anchors
+    }
+
+    pub fn set_anchors(&mut self, anchors: Anchors) {
+        self.anchors = anchors;
+    }
+
+    /// Sets the image of the container a script always runs in
+    pub fn set_script_container(
+        &self,
+        tree_id: i64,
+        name: &str,
+        image: Option<&str>,
+    ) -> crate::Result<()> {
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET container=?1 WHERE tree_id=?2 AND name=?3",
+            params![image, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    pub fn script_container(&self, tree_id: i64, name: &str) -> crate::Result<Option<String>> {
+        let image: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT container FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        image.ok_or(Error::NoSuchScript)
+    }
+
+    /// Sets the message shown when the script fails under `orun --check`
+    pub fn set_script_failure_hint(
+        &self,
+        tree_id: i64,
+        name: &str,
+        hint: Option<&str>,
+    ) -> crate::Result<()> {
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET failure_hint=?1 WHERE tree_id=?2 AND name=?3",
+            params![hint, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    pub fn script_failure_hint(&self, tree_id: i64, name: &str) -> crate::Result<Option<String>> {
+        let hint: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT failure_hint FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        hint.ok_or(Error::NoSuchScript)
+    }
+
+    pub fn set_signature_policy(&mut self, policy: Policy) {
+        self.signature_policy = policy;
+    }
+
+    pub fn signature_policy(&self) -> Policy {
+        self.signature_policy
+    }
+
+    pub fn set_allowed_signers(&mut self, allowed: AllowedSigners) {
+        self.allowed_signers = allowed;
+    }
+
+    pub fn allowed_signers(&self) -> &AllowedSigners {
+        &self.allowed_signers
+    }
+
+    /// Lets an untrusted script run until the database is closed, without trusting it. For when
+    /// the database is read-only.
+    pub fn trust_script_for_now(&mut self, tree_id: i64, name: &str) {
+        self.trusted_for_now.push((tree_id, name.to_owned()));
+    }
+
+    /// Sets whether a script is protected against modification and removal
+    pub fn set_script_locked(&self, tree_id: i64, name: &str, locked: bool) -> crate::Result<()> {
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET locked=?1 WHERE tree_id=?2 AND name=?3",
+            params![locked, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    /// Whether a script is locked. Scripts that don't exist aren't.
+    pub fn script_locked(&self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        script_locked(&self.conn, tree_id, name)
+    }
+
+    /// Sets whether a script is archived
+    pub fn set_script_archived(
+        &self,
+        tree_id: i64,
+        name: &str,
+        archived: bool,
+    ) -> crate::Result<()> {
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET archived=?1 WHERE tree_id=?2 AND name=?3",
+            params![archived, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    /// Whether a script is archived. Scripts that don't exist aren't.
+    pub fn script_archived(&self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT archived FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(false))
+    }
+
+    /// Sets whether a script is trusted to run without confirmation
+    pub fn set_script_trusted(&self, tree_id: i64, name: &str, trusted: bool) -> crate::Result<()> {
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET trusted=?1 WHERE tree_id=?2 AND name=?3",
+            params![trusted, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    pub fn script_trusted(&self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        let trusted = self
+            .conn
+            .query_row(
+                "SELECT trusted FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        trusted.ok_or(Error::NoSuchScript)
+    }
+
+    /// Sets the signature of a script. `None` removes it.
+    pub fn set_script_signature(
+        &self,
+        tree_id: i64,
+        name: &str,
+        signature: Option<&[u8]>,
+    ) -> crate::Result<()> {
+        let Some(blob_id) = self.query_script_id_from_name(tree_id, name)? else {
+            bail!("No such script");
+        };
+        self.conn.execute(
+            "UPDATE blobs SET signature=?1 WHERE _rowid_=?2",
+            params![signature, blob_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn script_signature(&self, tree_id: i64, name: &str) -> crate::Result<Option<Vec<u8>>> {
+        let Some(blob_id) = self.query_script_id_from_name(tree_id, name)? else {
+            return Err(Error::NoSuchScript);
+        };
+        Ok(self.conn.query_row(
+            "SELECT signature FROM blobs WHERE _rowid_=?",
+            params![blob_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Sets environment variables for all scripts that run, in addition to the inherited ones
+    pub fn set_run_env(&mut self, env: Vec<(String, String)>) {
+        self.run_env = env;
+    }
+
+    /// Makes all scripts run in `sandbox`, regardless of their own setting
+    pub fn force_sandbox(&mut self, sandbox: Sandbox) {
+        self.forced_sandbox = Some(sandbox);
+    }
+
+    /// Stores the roots that are inside of anchors relative to them.
+    ///
+    /// Returns the number of roots that were changed.
+    pub fn anchor_roots(&self) -> crate::Result<usize> {
+        let mut changed = 0;
+        for (id, root) in self.stored_roots()? {
+            if crate::anchors::anchor_name(&root).is_some() {
+                continue;
+            }
+            let encoded = self.anchors.encode(&crate::fs_util::decode_path(&root)?);
+            if encoded != root {
+                self.conn.execute(
+                    "UPDATE trees SET root=?1 WHERE _rowid_=?2",
+                    params![encoded, id],
+                )?;
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Returns the number of roots stored relative to the anchor `name`
+    pub fn roots_using_anchor(&self, name: &str) -> crate::Result<usize> {
+        Ok(self
+            .stored_roots()?
+            .iter()
+            .filter(|(_, root)| crate::anchors::anchor_name(root) == Some(name))
+            .count())
+    }
+
+    fn stored_roots(&self) -> crate::Result<Vec<(i64, String)>> {
+        let mut stmt = self.conn.prepare("SELECT _rowid_, root FROM trees")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn blob_is_null(&self, id: i64) -> crate::Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT body IS NULL AND chunked_size IS NULL FROM blobs WHERE _rowid_=?",
+            params![id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Returns the root of the tree and the name of the script or file that last used a blob, if
+    /// it's known
+    pub fn blob_former_owner(&self, id: i64) -> crate::Result<Option<(PathBuf, String)>> {
+        let owner: Option<(String, String)> = self
+            .conn
+            .query_row(
+                "SELECT former_root, former_name FROM blobs
+                 WHERE _rowid_=? AND former_root IS NOT NULL AND former_name IS NOT NULL",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        owner
+            .map(|(root, name)| Ok((self.anchors.decode(&root)?, name)))
+            .transpose()
+    }
+
+    pub fn fetch_blob(&self, id: i64) -> crate::Result<Vec<u8>> {
+        let mut blob = self.open_blob(id)?;
+        let mut body = Vec::with_capacity(blob.len() as usize);
+        blob.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    /// Opens a blob for reading its contents incrementally, so large ones don't have to fit in
+    /// memory. Encrypted ones are decrypted first.
+    pub fn open_blob(&self, id: i64) -> crate::Result<BlobReader<'_>> {
+        blob_reader(&self.conn, id, true)
+    }
+
+    /// Like [`Self::open_blob`], but reads the contents the way they're stored, so encrypted
+    /// ones stay encrypted
+    pub fn open_stored_blob(&self, id: i64) -> crate::Result<BlobReader<'_>> {
+        blob_reader(&self.conn, id, false)
+    }
+
+    /// Returns whether the contents of a blob are stored encrypted with gpg
+    pub fn blob_encrypted(&self, id: i64) -> crate::Result<bool> {
+        blob_encrypted(&self.conn, id)
+    }
+
+    /// Returns the SHA-256 hash of the contents of a blob, as returned by [`content_hash`].
+    ///
+    /// For an encrypted blob, it's the hash of the encrypted contents.
+    pub fn blob_hash(&self, id: i64) -> crate::Result<Option<String>> {
+        Ok(self.conn.query_row(
+            "SELECT sha256 FROM blobs WHERE _rowid_=?",
+            params![id],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn script_exists(&self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        Ok(self.query_script_id_from_name(tree_id, name)?.is_some())
+    }
+
+    fn query_script_id_from_name(&self, tree_id: i64, name: &str) -> crate::Result<Option<i64>> {
+        script_blob_id(&self.conn, tree_id, name)
+    }
+
+    fn query_file_id_from_name(&self, tree_id: i64, name: &str) -> crate::Result<Option<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT blob_id FROM tree_files WHERE tree_id=?1 AND name=?2")?;
+        let blob_id: Option<i64> = stmt
+            .query_row(params![tree_id, name], |row| row.get(0))
+            .optional()?;
+        Ok(blob_id)
+    }
+
+    /// Returns the scripts of the tree, except the archived ones
+    pub fn scripts_for_tree(&self, tree_id: i64) -> crate::Result<Vec<ScriptInfo>> {
+        self.scripts_for_tree_archived(tree_id, false)
+    }
+
+    /// Returns the scripts of the tree that haven't been used since `since`, along with when they
+    /// were last used, in seconds since the Unix epoch.
+    ///
+    /// Archived scripts aren't included.
+    pub fn scripts_unused_since(
+        &self,
+        tree_id: i64,
+        since: i64,
+    ) -> crate::Result<Vec<(String, Option<i64>)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, last_used FROM tree_scripts
+             WHERE tree_id=?1 AND NOT archived AND (last_used IS NULL OR last_used < ?2)
+             ORDER BY last_used",
+        )?;
+        let rows = stmt.query_map(params![tree_id, since], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the archived scripts of the tree
+    pub fn archived_scripts_for_tree(&self, tree_id: i64) -> crate::Result<Vec<ScriptInfo>> {
+        self.scripts_for_tree_archived(tree_id, true)
+    }
+
+    fn scripts_for_tree_archived(
+        &self,
+        tree_id: i64,
+        archived: bool,
+    ) -> crate::Result<Vec<ScriptInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT s.name, s.desc, COALESCE(length(b.body), b.chunked_size, 0)
+             FROM tree_scripts s
+             JOIN blobs b ON b._rowid_ = s.blob_id WHERE s.tree_id=?1 AND s.archived=?2",
+        )?;
+        let rows = stmt.query_map(params![tree_id, archived], script_info)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Iterates over the scripts of the tree, except the archived ones
+    pub fn scripts_iter(&self, tree_id: i64) -> ItemIter<'_> {
+        ItemIter {
+            db: self,
+            query: "SELECT s.name, s.desc, COALESCE(length(b.body), b.chunked_size, 0)
+                    FROM tree_scripts s
+                    JOIN blobs b ON b._rowid_ = s.blob_id
+                    WHERE s.tree_id=?1 AND NOT s.archived AND (?2 IS NULL OR s.name > ?2)
+                    ORDER BY s.name LIMIT 1",
+            tree_id,
+            last: None,
+            done: false,
+        }
+    }
+
+    /// Iterates over the saved files of the tree
+    pub fn files_iter(&self, tree_id: i64) -> ItemIter<'_> {
+        ItemIter {
+            db: self,
+            query: "SELECT f.name, f.desc, COALESCE(length(b.body), b.chunked_size, 0)
+                    FROM tree_files f
+                    JOIN blobs b ON b._rowid_ = f.blob_id
+                    WHERE f.tree_id=?1 AND (?2 IS NULL OR f.name > ?2)
+                    ORDER BY f.name LIMIT 1",
+            tree_id,
+            last: None,
+            done: false,
+        }
+    }
+
+    /// Returns the scripts (except the archived ones) or saved files of the tree, filtered and
+    /// sorted by the database
+    pub fn list_items(
+        &self,
+        kind: ItemKind,
+        tree_id: i64,
+        opts: ListOpts,
+    ) -> crate::Result<Vec<ScriptInfo>> {
+        let order = match (opts.sort, kind) {
+            (ItemSort::Name, _) => "i.name",
+            (ItemSort::Size, _) => "COALESCE(length(b.body), b.chunked_size) DESC, i.name",
+            (ItemSort::Modified, _) => "i.updated_at DESC NULLS LAST, i.name",
+            (ItemSort::Usage, ItemKind::Script) => "i.last_used DESC NULLS LAST, i.name",
+            (ItemSort::Usage, ItemKind::File) => bail!("Saved files can't be sorted by usage"),
+        };
+        let not_archived = match kind {
+            ItemKind::Script => "AND NOT i.archived",
+            ItemKind::File => "",
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT i.name, i.desc, COALESCE(length(b.body), b.chunked_size, 0) FROM {} i
+             JOIN blobs b ON b._rowid_ = i.blob_id
+             WHERE i.tree_id=?1 {not_archived} AND (?2 IS NULL OR i.name GLOB ?2)
+             ORDER BY {order} LIMIT ?3",
+            kind.table()
+        ))?;
+        // A negative limit means no limit
+        let limit = opts.limit.map_or(-1, |limit| limit as i64);
+        let rows = stmt.query_map(params![tree_id, opts.filter, limit], script_info)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Iterates over the established trees
+    pub fn trees_iter(&self) -> TreeIter<'_> {
+        TreeIter {
+            db: self,
+            last: 0,
+            done: false,
+        }
+    }
+
+    pub fn files_for_tree(&self, tree_id: i64) -> crate::Result<Vec<ScriptInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.name, f.desc, COALESCE(length(b.body), b.chunked_size, 0)
+             FROM tree_files f
+             JOIN blobs b ON b._rowid_ = f.blob_id WHERE f.tree_id=?",
+        )?;
+        let rows = stmt.query_map(params![tree_id], script_info)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn query_tree(&self, path: &Path) -> crate::Result<Option<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT _rowid_ FROM trees where root=?")?;
+        for spelling in self.anchors.spellings(path) {
+            if let Some(id) = stmt
+                .query_row(params![spelling], |row| row.get(0))
+                .optional()?
+            {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn tree_uuid(&self, tree_id: i64) -> crate::Result<String> {
+        self.conn
+            .query_row(
+                "SELECT uuid FROM trees WHERE _rowid_=?",
+                params![tree_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or(Error::NoSuchTree)
+    }
+
+    pub fn query_tree_by_uuid(&self, uuid: &str) -> crate::Result<Option<i64>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT _rowid_ FROM trees WHERE uuid=?",
+                params![uuid],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Adds a new tree and returns its id
+    pub fn add_new_tree(&self, path: &Path) -> crate::Result<i64> {
+        let str = self.anchors.encode(path);
+        self.conn.execute(
+            &format!("INSERT INTO trees (root, uuid) VALUES (?, {NEW_UUID})"),
+            params![str],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Sets the description of a tree. `None` removes it.
+    pub fn set_tree_desc(&self, tree_id: i64, desc: Option<&str>) -> crate::Result<()> {
+        self.conn.execute(
+            "UPDATE trees SET desc=?1 WHERE _rowid_=?2",
+            params![desc, tree_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_tree_tags(&self, tree_id: i64, tags: &[String]) -> crate::Result<()> {
+        self.conn.execute(
+            "UPDATE trees SET tags=?1 WHERE _rowid_=?2",
+            params![join_tags(tags)?, tree_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn tree_tags(&self, tree_id: i64) -> crate::Result<Vec<String>> {
+        let tags: Option<String> = self.conn.query_row(
+            "SELECT tags FROM trees WHERE _rowid_=?",
+            params![tree_id],
+            |row| row.get(0),
+        )?;
+        Ok(split_tags(tags.as_deref()))
+    }
+
+    pub fn tree_desc(&self, tree_id: i64) -> crate::Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT desc FROM trees WHERE _rowid_=?",
+                params![tree_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or(Error::NoSuchTree)
+    }
+
+    /// Sets the dev shell the scripts of a tree run in. `None` makes them run directly.
+    pub fn set_tree_dev_shell(
+        &self,
+        tree_id: i64,
+        dev_shell: Option<DevShell>,
+    ) -> crate::Result<()> {
+        self.conn.execute(
+            "UPDATE trees SET dev_shell=?1 WHERE _rowid_=?2",
+            params![dev_shell.map(DevShell::name), tree_id],
+        )?;
+        Ok(())
+    }
+
+    /// Sets how many former versions of each saved file the tree keeps, and drops the versions
+    /// beyond that. `None` sets it back to [`DEFAULT_FILE_VERSIONS`].
+    pub fn set_tree_file_versions(&mut self, tree_id: i64, keep: Option<u32>) -> crate::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "UPDATE trees SET file_versions=?1 WHERE _rowid_=?2",
+            params![keep, tree_id],
+        )?;
+        prune_file_versions(&tx, tree_id, None, keep.unwrap_or(DEFAULT_FILE_VERSIONS))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns how many former versions of each saved file the tree keeps
+    pub fn tree_file_versions(&self, tree_id: i64) -> crate::Result<u32> {
+        tree_file_versions(&self.conn, tree_id)
+    }
+
+    /// Sets the largest size of a single item of the tree. `None` sets it back to
+    /// [`DEFAULT_MAX_ITEM_SIZE`], and 0 turns the limit off.
+    pub fn set_tree_max_item_size(&self, tree_id: i64, size: Option<u64>) -> crate::Result<()> {
+        self.conn.execute(
+            "UPDATE trees SET max_item_size=?1 WHERE _rowid_=?2",
+            params![size, tree_id],
+        )?;
+        Ok(())
+    }
+
+    /// Sets the largest total size of the items of the tree. `None` turns the limit off.
+    pub fn set_tree_max_size(&self, tree_id: i64, size: Option<u64>) -> crate::Result<()> {
+        self.conn.execute(
+            "UPDATE trees SET max_tree_size=?1 WHERE _rowid_=?2",
+            params![size, tree_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn tree_size_limits(&self, tree_id: i64) -> crate::Result<SizeLimits> {
+        let (item, total): (Option<u64>, Option<u64>) = self.conn.query_row(
+            "SELECT max_item_size, max_tree_size FROM trees WHERE _rowid_=?",
+            params![tree_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(SizeLimits {
+            item: match item {
+                None => Some(DEFAULT_MAX_ITEM_SIZE),
+                Some(0) => None,
+                Some(size) => Some(size),
+            },
+            total,
+        })
+    }
+
+    /// Returns how the saved files and file versions of the tree are stored as chunks
+    pub fn chunk_stats(&self, tree_id: i64) -> crate::Result<ChunkStats> {
+        Ok(self.conn.query_row(
+            "WITH tree_blobs AS (
+                 SELECT blob_id FROM tree_files WHERE tree_id=?1
+                 UNION SELECT blob_id FROM file_versions WHERE tree_id=?1),
+             tree_chunks AS (
+                 SELECT DISTINCT chunk_id FROM blob_chunks
+                 WHERE blob_id IN (SELECT blob_id FROM tree_blobs))
+             SELECT COUNT(*), COALESCE(SUM(chunked_size), 0),
+                    (SELECT COUNT(*) FROM tree_chunks),
+                    (SELECT COALESCE(SUM(length(body)), 0) FROM chunks
+                     WHERE id IN (SELECT chunk_id FROM tree_chunks))
+             FROM blobs
+             WHERE chunked_size IS NOT NULL AND id IN (SELECT blob_id FROM tree_blobs)",
+            params![tree_id],
+            |row| {
+                Ok(ChunkStats {
+                    files: row.get(0)?,
+                    size: row.get(1)?,
+                    chunks: row.get(2)?,
+                    stored: row.get(3)?,
+                })
+            },
+        )?)
+    }
+
+    /// Returns the total size of the scripts, saved files and file versions of the tree
+    pub fn tree_size(&self, tree_id: i64) -> crate::Result<u64> {
+        Ok(self.conn.query_row(
+            "SELECT COALESCE(SUM(COALESCE(LENGTH(body), chunked_size)), 0) FROM blobs
+             WHERE _rowid_ IN (
+                 SELECT blob_id FROM tree_scripts WHERE tree_id=?1
+                 UNION SELECT blob_id FROM tree_files WHERE tree_id=?1
+                 UNION SELECT blob_id FROM file_versions WHERE tree_id=?1)",
+            params![tree_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Checks whether storing `size` bytes as the item `name` would go over the size limits of
+    /// the tree, and describes the limit it would go over
+    pub fn size_limit_problem(
+        &self,
+        kind: ItemKind,
+        tree_id: i64,
+        name: &str,
+        size: u64,
+    ) -> crate::Result<Option<String>> {
+        let limits = self.tree_size_limits(tree_id)?;
+        if let Some(limit) = limits.item.filter(|limit| size > *limit) {
+            return Ok(Some(format!(
+                "{name} is {}, larger than the limit of {} for a single {kind}",
+                crate::format_size(size),
+                crate::format_size(limit)
+            )));
+        }
+        if let Some(limit) = limits.total {
+            let old_size: u64 = self
+                .conn
+                .query_row(
+                    &format!(
+                        "SELECT COALESCE(length(b.body), b.chunked_size, 0) FROM {} i
+                         JOIN blobs b ON b._rowid_ = i.blob_id
+                         WHERE i.tree_id=?1 AND i.name=?2",
+                        kind.table()
+                    ),
+                    params![tree_id, name],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(0);
+            let total = self.tree_size(tree_id)?.saturating_sub(old_size) + size;
+            if total > limit {
+                return Ok(Some(format!(
+                    "Storing {name} makes this tree take {}, more than its limit of {}",
+                    crate::format_size(total),
+                    crate::format_size(limit)
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn tree_dev_shell(&self, tree_id: i64) -> crate::Result<Option<DevShell>> {
+        let dev_shell: Option<String> = self.conn.query_row(
+            "SELECT dev_shell FROM trees WHERE _rowid_=?",
+            params![tree_id],
+            |row| row.get(0),
+        )?;
+        dev_shell.map(|s| s.parse()).transpose()
+    }
+
+    pub fn rename_tree(&self, old_path: &Path, new_path: &Path) -> crate::Result<()> {
+        let new_path = self.anchors.encode(new_path);
+        for old_path in self.anchors.spellings(old_path) {
+            self.conn.execute(
+                "UPDATE trees SET root=?2 WHERE root=?1",
+                params![old_path, new_path],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_tree(&mut self, tree_id: i64) -> crate::Result<()> {
+        let tx = self.conn.transaction()?;
+        // The tree goes last, so the blobs of its items can still record where they were used
+        tx.execute("DELETE FROM tree_scripts WHERE tree_id=?", params![tree_id])?;
+        tx.execute("DELETE FROM tree_files WHERE tree_id=?", params![tree_id])?;
+        tx.execute("DELETE FROM trees WHERE _rowid_=?", params![tree_id])?;
+        tx.execute("DELETE FROM runs WHERE tree_id=?", params![tree_id])?;
+        tx.execute("DELETE FROM file_bundles WHERE tree_id=?", params![tree_id])?;
+        tx.execute(
+            "DELETE FROM file_versions WHERE tree_id=?",
+            params![tree_id],
+        )?;
+        tx.execute(
+            "DELETE FROM tree_shim_dirs WHERE tree_id=?",
+            params![tree_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Records where a script was added from
+    pub fn set_script_origin(&self, tree_id: i64, name: &str, origin: &str) -> crate::Result<()> {
+        update_script_origin(&self.conn, tree_id, name, origin)
+    }
+
+    pub fn script_origin(&self, tree_id: i64, name: &str) -> crate::Result<Option<String>> {
+        let origin = self
+            .conn
+            .query_row(
+                "SELECT origin FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match origin {
+            Some(origin) => Ok(origin),
+            None => bail!("No such script"),
+        }
+    }
+
+    /// Returns the names and origins of the scripts of a tree that have a recorded origin
+    pub fn scripts_with_origin(&self, tree_id: i64) -> crate::Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, origin FROM tree_scripts WHERE tree_id=? AND origin IS NOT NULL",
+        )?;
+        let rows = stmt.query_map(params![tree_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Sets the scripts that have to run successfully before a script
+    pub fn set_script_needs(
+        &self,
+        tree_id: i64,
+        name: &str,
+        needs: &[String],
+    ) -> crate::Result<()> {
+        for need in needs {
+            if need.contains(',') {
+                bail!("Script names in dependencies can't contain ','");
+            }
+            if !self.script_exists(tree_id, need)? {
+                bail!("No script named '{need}' for the current tree");
+            }
+            if need == name || self.run_order(tree_id, need)?.iter().any(|n| n == name) {
+                bail!("'{name}' can't depend on '{need}', because '{need}' depends on '{name}'");
+            }
+        }
+        let needs = (!needs.is_empty()).then(|| needs.join(","));
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET needs=?1 WHERE tree_id=?2 AND name=?3",
+            params![needs, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    /// Sets the tags of a script
+    pub fn set_script_tags(&self, tree_id: i64, name: &str, tags: &[String]) -> crate::Result<()> {
+        let tags = join_tags(tags)?;
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET tags=?1 WHERE tree_id=?2 AND name=?3",
+            params![tags, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    /// Returns the tags of a script
+    pub fn script_tags(&self, tree_id: i64, name: &str) -> crate::Result<Vec<String>> {
+        let tags: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT tags FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match tags {
+            Some(tags) => Ok(split_tags(tags.as_deref())),
+            None => Err(Error::NoSuchScript),
+        }
+    }
+
+    /// Sets the environment variables set for every run of a script
+    pub fn set_script_env(
+        &self,
+        tree_id: i64,
+        name: &str,
+        env: &[(String, String)],
+    ) -> crate::Result<()> {
+        for (key, val) in env {
+            if key.is_empty() || key.contains(['=', '\n', '\0']) {
+                bail!("Invalid environment variable name '{key}'");
+            }
+            if val.contains(['\n', '\0']) {
+                bail!("The value of {key} can't contain newlines");
+            }
+        }
+        let env = (!env.is_empty()).then(|| {
+            env.iter()
+                .map(|(key, val)| format!("{key}={val}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        });
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET env=?1 WHERE tree_id=?2 AND name=?3",
+            params![env, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    /// Returns the environment variables set for every run of a script
+    pub fn script_env(&self, tree_id: i64, name: &str) -> crate::Result<Vec<(String, String)>> {
+        let env: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT env FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match env {
+            Some(env) => Ok(env
+                .as_deref()
+                .unwrap_or_default()
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, val)| (key.to_owned(), val.to_owned()))
+                .collect()),
+            None => Err(Error::NoSuchScript),
+        }
+    }
+
+    /// Sets whether a script always runs with a minimal environment
+    pub fn set_script_clean_env(&self, tree_id: i64, name: &str, clean: bool) -> crate::Result<()> {
+        let changed = self.conn.execute(
+            "UPDATE tree_scripts SET clean_env=?1 WHERE tree_id=?2 AND name=?3",
+            params![clean, tree_id, name],
+        )?;
+        if changed == 0 {
+            bail!("No such script");
+        }
+        Ok(())
+    }
+
+    pub fn script_clean_env(&self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        let clean = self
+            .conn
+            .query_row(
+                "SELECT clean_env FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match clean {
+            Some(clean) => Ok(clean),
+            None => Err(Error::NoSuchScript),
+        }
+    }
+
+    /// Returns the description of a script or saved file, and when it was added and changed
+    pub fn item_details(
+        &self,
+        kind: ItemKind,
+        tree_id: i64,
+        name: &str,
+    ) -> crate::Result<ItemDetails> {
+        let details = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT t.desc, t.created_at, t.updated_at, b.encrypted FROM {} t
+                     JOIN blobs b ON b._rowid_ = t.blob_id
+                     WHERE t.tree_id=?1 AND t.name=?2",
+                    kind.table()
+                ),
+                params![tree_id, name],
+                |row| {
+                    Ok(ItemDetails {
+                        desc: row.get(0)?,
+                        created_at: row.get(1)?,
+                        updated_at: row.get(2)?,
+                        encrypted: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+        match (details, kind) {
+            (Some(details), _) => Ok(details),
+            (None, ItemKind::Script) => Err(Error::NoSuchScript),
+            (None, ItemKind::File) => bail!("No such saved file: {name}"),
+        }
+    }
+
+    /// Returns the scripts that have to run successfully before a script
+    pub fn script_needs(&self, tree_id: i64, name: &str) -> crate::Result<Vec<String>> {
+        let needs: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT needs FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+                params![tree_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match needs {
+            Some(needs) => Ok(needs
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+                .collect()),
+            None => Err(Error::NoSuchScript),
+        }
+    }
+
+    /// Returns the order to run a script and everything it depends on in, ending with the script
+    pub fn run_order(&self, tree_id: i64, name: &str) -> crate::Result<Vec<String>> {
+        let mut order = Vec::new();
+        self.visit_needs(tree_id, name, &mut Vec::new(), &mut order)?;
+        Ok(order)
+    }
+
+    fn visit_needs(
+        &self,
+        tree_id: i64,
+        name: &str,
+        chain: &mut Vec<String>,
+        order: &mut Vec<String>,
+    ) -> crate::Result<()> {
+        if order.iter().any(|n| n == name) {
+            return Ok(());
+        }
+        if chain.iter().any(|n| n == name) {
+            bail!("Dependency cycle: {} -> {name}", chain.join(" -> "));
+        }
+        chain.push(name.to_owned());
+        for need in self.script_needs(tree_id, name)? {
+            if !self.script_exists(tree_id, &need)? {
+                bail!("'{name}' needs '{need}', which doesn't exist");
+            }
+            self.visit_needs(tree_id, &need, chain, order)?;
+        }
+        chain.pop();
+        order.push(name.to_owned());
+        Ok(())
+    }
+
+    pub fn add_script_description(
+        &self,
+        tree_id: i64,
+        name: &str,
+        desc: &str,
+    ) -> crate::Result<()> {
+        self.conn.execute(
+            "UPDATE tree_scripts SET desc=?1 WHERE tree_id=?2 AND name=?3",
+            params![desc, tree_id, name],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_tree_roots(&self) -> crate::Result<Vec<TreeRootInfo>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT _rowid_, root, desc, uuid, tags FROM trees")?;
+        let mut vec = Vec::new();
+        for result in stmt.query_map([], |row| {
+            let id = row.get(0)?;
+            let root_path: String = row.get(1)?;
+            let tags: Option<String> = row.get(4)?;
+            Ok((id, root_path, row.get(2)?, row.get(3)?, tags))
+        })? {
+            let (id, root, desc, uuid, tags) = result?;
+            let pb = self.anchors.decode(&root)?;
+            vec.push(TreeRootInfo {
+                id,
+                path: pb,
+                desc,
+                uuid,
+                tags: split_tags(tags.as_deref()),
+            });
+        }
+        Ok(vec)
+    }
+
+    pub fn tree_stats(&self) -> crate::Result<Vec<TreeStats>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t._rowid_, t.root, t.desc, t.uuid, t.last_used,
+                (SELECT COUNT(*) FROM tree_scripts WHERE tree_id = t._rowid_),
+                (SELECT COUNT(*) FROM tree_files WHERE tree_id = t._rowid_),
+                (SELECT COALESCE(SUM(COALESCE(LENGTH(body), chunked_size)), 0) FROM blobs
+                 WHERE _rowid_ IN (
+                    SELECT blob_id FROM tree_scripts WHERE tree_id = t._rowid_
+                    UNION SELECT blob_id FROM tree_files WHERE tree_id = t._rowid_
+                    UNION SELECT blob_id FROM file_versions WHERE tree_id = t._rowid_)),
+                t.tags
+            FROM trees t",
+        )?;
+        let mut vec = Vec::new();
+        for result in stmt.query_map([], |row| {
+            let root: String = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                root,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+                row.get(6)?,
+                row.get(7)?,
+                row.get::<_, Option<String>>(8)?,
+            ))
+        })? {
+            let (id, root, desc, uuid, last_used, script_count, file_count, size, tags) = result?;
+            vec.push(TreeStats {
+                root: TreeRootInfo {
+                    id,
+                    path: self.anchors.decode(&root)?,
+                    desc,
+                    uuid,
+                    tags: split_tags(tags.as_deref()),
+                },
+                script_count,
+                file_count,
+                size,
+                last_used,
+            });
+        }
+        Ok(vec)
+    }
+
+    pub fn get_script_by_name(&self, tree_id: i64, name: &str) -> crate::Result<Vec<u8>> {
+        match self.query_script_id_from_name(tree_id, name)? {
+            Some(id) => Ok(self.fetch_blob(id)?),
+            None => bail!("No such script"),
+        }
+    }
+
+    /// Opens a script for reading its body with [`Database::open_blob`]
+    pub fn open_script(&self, tree_id: i64, name: &str) -> crate::Result<BlobReader<'_>> {
+        match self.query_script_id_from_name(tree_id, name)? {
+            Some(id) => self.open_blob(id),
+            None => bail!("No such script"),
+        }
+    }
+
+    pub fn get_file_by_name(&self, tree_id: i64, name: &str) -> crate::Result<Vec<u8>> {
+        self.fetch_blob(self.file_blob_id(tree_id, name, None)?)
+    }
+
+    /// Returns the id of the blob with the contents of a saved file, or of one of its versions,
+    /// for reading it with [`Database::open_blob`]
+    pub fn file_blob_id(
+        &self,
+        tree_id: i64,
+        name: &str,
+        version: Option<i64>,
+    ) -> crate::Result<i64> {
+        let Some(version) = version else {
+            return match self.query_file_id_from_name(tree_id, name)? {
+                Some(id) => Ok(id),
+                None => bail!("No such file"),
+            };
+        };
+        let blob_id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT blob_id FROM file_versions WHERE tree_id=?1 AND name=?2 AND version=?3",
+                params![tree_id, name, version],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(blob_id) = blob_id {
+            return Ok(blob_id);
+        }
+        match self.file_versions(tree_id, name)?.last() {
+            Some(current) if current.version == version => self.file_blob_id(tree_id, name, None),
+            _ => bail!("There is no version {version} of {name}. See okeep file-versions {name}"),
+        }
+    }
+
+    /// Returns the versions of a saved file, oldest first. The last one is the current one.
+    pub fn file_versions(&self, tree_id: i64, name: &str) -> crate::Result<Vec<FileVersion>> {
+        if self.query_file_id_from_name(tree_id, name)?.is_none() {
+            bail!("No such file");
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT v.version, v.saved_at, COALESCE(length(b.body), b.chunked_size, 0), 0
+             FROM file_versions v
+             JOIN blobs b ON b._rowid_ = v.blob_id
+             WHERE v.tree_id=?1 AND v.name=?2
+             UNION ALL
+             SELECT (SELECT COALESCE(MAX(version), 0) + 1 FROM file_versions
+                     WHERE tree_id=?1 AND name=?2),
+                    f.updated_at, COALESCE(length(b.body), b.chunked_size, 0), 1
+             FROM tree_files f
+             JOIN blobs b ON b._rowid_ = f.blob_id
+             WHERE f.tree_id=?1 AND f.name=?2
+             ORDER BY 1",
+        )?;
+        let rows = stmt.query_map(params![tree_id, name], |row| {
+            Ok(FileVersion {
+                version: row.get(0)?,
+                saved_at: row.get(1)?,
+                size: row.get(2)?,
+                current: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Removes the run history, captured output and former file versions of a tree.
+    ///
+    /// Runs are removed unless only a file is given, and file versions unless only a script is
+    /// given or `logs_only` is set. The space isn't given back until [`Self::vacuum`].
+    pub fn purge_history(&mut self, tree_id: i64, opts: PurgeOpts) -> crate::Result<PurgeReport> {
+        let mut report = PurgeReport::default();
+        let cutoff = opts.older_than.map(|secs| unix_now() - secs);
+        let tx = self.conn.transaction()?;
+        if opts.file.is_none() || opts.script.is_some() {
+            let filter = "tree_id=?1 AND (?2 IS NULL OR name=?2)
+                          AND (?3 IS NULL OR started < ?3)";
+            if opts.logs_only {
+                report.logs = tx.execute(
+                    &format!("UPDATE runs SET output=NULL WHERE {filter} AND output IS NOT NULL"),
+                    params![tree_id, opts.script, cutoff],
+                )?;
+            } else {
+                report.logs = tx.query_row(
+                    &format!("SELECT COUNT(*) FROM runs WHERE {filter} AND output IS NOT NULL"),
+                    params![tree_id, opts.script, cutoff],
+                    |row| row.get(0),
+                )?;
+                report.runs = tx.execute(
+                    &format!("DELETE FROM runs WHERE {filter}"),
+                    params![tree_id, opts.script, cutoff],
+                )?;
+            }
+        }
+        if (opts.script.is_none() || opts.file.is_some()) && !opts.logs_only {
+            let versions: Vec<(String, i64, i64)> = tx
+                .prepare(
+                    "SELECT name, version, blob_id FROM file_versions
+                     WHERE tree_id=?1 AND (?2 IS NULL OR name=?2)
+                         AND (?3 IS NULL OR COALESCE(saved_at, 0) < ?3)",
+                )?
+                .query_map(params![tree_id, opts.file, cutoff], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<Result<_, _>>()?;
+            for (name, version, blob_id) in versions {
+                tx.execute(
+                    "DELETE FROM file_versions WHERE tree_id=?1 AND name=?2 AND version=?3",
+                    params![tree_id, name, version],
+                )?;
+                if blob_users(&tx, blob_id)? == 0 {
+                    tx.execute("DELETE FROM blobs WHERE _rowid_=?", params![blob_id])?;
+                }
+                report.file_versions += 1;
+            }
+        }
+        if !opts.dry_run {
+            tx.commit()?;
+        }
+        Ok(report)
+    }
+
+    /// Renames a script in the tree. Fails if there's no script named `old_name`, or there's
+    /// already one named `new_name`.
+    pub fn rename_script(&self, tree_id: i64, old_name: &str, new_name: &str) -> crate::Result<()> {
+        if self.script_exists(tree_id, new_name)? {
+            return Err(Error::NameConflict(new_name.to_owned()));
+        }
+        let updated = self.conn.execute(
+            "UPDATE tree_scripts SET name=?1 WHERE tree_id=?2 AND name=?3",
+            params![new_name, tree_id, old_name],
+        )?;
+        if updated == 0 {
+            return Err(Error::NoSuchScript);
+        }
+        Ok(())
+    }
+
+    /// Saves a file. Returns false if it was already saved with the same contents.
+    pub fn add_file(
+        &mut self,
+        tree_id: i64,
+        path: &str,
+        source: impl Read + Seek,
+        encrypt: bool,
+    ) -> crate::Result<bool> {
+        let mut batch = self.batch()?;
+        let changed = batch.add_file(tree_id, path, source, encrypt)?;
+        batch.commit()?;
+        Ok(changed)
+    }
+
+    /// Clones the scripts of `src_tree` into `dst_tree`, and also the saved files if
+    /// [`CloneOpts::with_files`] is true.
+    ///
+    /// `resolve` is called for every item that already exists in `dst_tree` to decide what to
+    /// do with it. If it returns an error, the clone is aborted without changing anything.
+    ///
+    /// `observer` is told about every item after it's cloned or skipped.
+    pub fn clone_tree(
+        &mut self,
+        src_tree: i64,
+        dst_tree: i64,
+        opts: CloneOpts,
+        mut resolve: impl FnMut(ItemKind, &str) -> crate::Result<ConflictResolution>,
+        observer: &mut impl Observer,
+    ) -> crate::Result<CloneReport> {
+        let CloneOpts {
+            only,
+            except,
+            with_files,
+            force,
+        } = opts;
+        // Overwriting deletes the conflicting items before copying them from the source
+        if src_tree == dst_tree {
+            bail!("Can't clone a tree into itself");
+        }
+        let mut report = CloneReport::default();
+        let tx = self.conn.transaction()?;
+        let mut statements = include_str!("clone_tree_table.sql")
+            .split_terminator(';')
+            .map(str::trim)
+            .filter(|stmt| !stmt.is_empty());
+        let (clone_scripts, clone_files) = (statements.next(), statements.next());
+        let script_names = item_names(&tx, "tree_scripts", src_tree, only, except)?;
+        let file_names = if with_files {
+            item_names(&tx, "tree_files", src_tree, None, None)?
+        } else {
+            Vec::new()
+        };
+        let total = script_names.len() + file_names.len();
+        let mut done = 0;
+        report.scripts = resolve_clone_conflicts(
+            &tx,
+            ItemKind::Script,
+            dst_tree,
+            script_names.clone(),
+            force,
+            &mut resolve,
+        )?;
+        // Items that still exist in the destination at this point are the skipped ones
+        let mut stmt = tx.prepare(clone_scripts.context("Missing statement")?)?;
+        for name in &script_names {
+            stmt.execute(named_params! {
+                ":src": src_tree,
+                ":dst": dst_tree,
+                ":only": only,
+                ":except": except,
+                ":name": name,
+            })?;
+            done += 1;
+            observer.progress(Progress {
+                action: "Cloning",
+                done,
+                total,
+                item: name,
+            });
+        }
+        drop(stmt);
+        if with_files {
+            report.files = resolve_clone_conflicts(
+                &tx,
+                ItemKind::File,
+                dst_tree,
+                file_names.clone(),
+                force,
+                &mut resolve,
+            )?;
+            let mut stmt = tx.prepare(clone_files.context("Missing statement")?)?;
+            for name in &file_names {
+                stmt.execute(named_params! {
+                    ":src": src_tree,
+                    ":dst": dst_tree,
+                    ":name": name,
+                })?;
+                done += 1;
+                observer.progress(Progress {
+                    action: "Cloning",
+                    done,
+                    total,
+                    item: name,
+                });
+            }
+        }
+        tx.commit()?;
+        Ok(report)
+    }
+    /// Merges the trees, scripts and saved files of `other` into this database.
+    ///
+    /// Trees are matched by their UUIDs, then by their roots. `resolve` is called for every item
+    /// that exists in both databases with different contents, to decide what to do with it.
+    /// Locked scripts are kept without asking, unless `force` is true.
+    /// If `dry_run` is true, nothing is changed, but the report is still produced.
+    pub fn import(
+        &mut self,
+        other: &Database,
+        dry_run: bool,
+        force: bool,
+        resolve: impl FnMut(&Path, ItemKind, &str) -> crate::Result<ConflictResolution>,
+        observer: &mut impl Observer,
+    ) -> crate::Result<Vec<TreeImport>> {
+        let roots = other.get_tree_roots()?;
+        self.import_trees(other, roots, dry_run, force, resolve, observer)
+    }
+
+    /// Like [`Self::import`], but only imports the given trees of `other`
+    pub fn import_trees(
+        &mut self,
+        other: &Database,
+        other_roots: Vec<TreeRootInfo>,
+        dry_run: bool,
+        force: bool,
+        mut resolve: impl FnMut(&Path, ItemKind, &str) -> crate::Result<ConflictResolution>,
+        observer: &mut impl Observer,
+    ) -> crate::Result<Vec<TreeImport>> {
+        let mut roots = Vec::new();
+        let (mut done, mut total) = (0, 0);
+        for root in other_roots {
+            for kind in [ItemKind::Script, ItemKind::File] {
+                total += other.item_count(kind, root.id)?;
+            }
+            let existing = match self.query_tree_by_uuid(&root.uuid)? {
+                Some(id) => Some(id),
+                None => self.query_tree(&root.path)?,
+            };
+            roots.push((root, existing));
+        }
+        let anchors = &self.anchors;
+        let tx = self.conn.transaction()?;
+        let mut reports = Vec::new();
+        for (root, existing) in roots {
+            let tree_id = match existing {
+                Some(id) => id,
+                None => {
+                    tx.execute(
+                        "INSERT INTO trees (root, desc, uuid, dev_shell, tags)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![
+                            anchors.encode(&root.path),
+                            root.desc,
+                            root.uuid,
+                            other.tree_dev_shell(root.id)?.map(DevShell::name),
+                            join_tags(&root.tags)?
+                        ],
+                    )?;
+                    tx.last_insert_rowid()
+                }
+            };
+            let mut report = TreeImport {
+                root: root.path,
+                new: existing.is_none(),
+                scripts: CloneOutcome::default(),
+                files: CloneOutcome::default(),
+            };
+            for kind in [ItemKind::Script, ItemKind::File] {
+                let outcome = match kind {
+                    ItemKind::Script => &mut report.scripts,
+                    ItemKind::File => &mut report.files,
+                };
+                let table = kind.table();
+                for item in other.stored_items(kind, root.id)? {
+                    done += 1;
+                    observer.progress(Progress {
+                        action: "Importing",
+                        done,
+                        total,
+                        item: &item.name,
+                    });
+                    let hash = content_hash_reader(other.open_stored_blob(item.blob_id)?)?;
+                    let existing_hash: Option<Option<String>> = tx
+                        .query_row(
+                            &format!(
+                                "SELECT b.sha256 FROM {table} t
+                                 JOIN blobs b ON b._rowid_ = t.blob_id
+                                 WHERE t.tree_id=?1 AND t.name=?2"
+                            ),
+                            params![tree_id, item.name],
+                            |row| row.get(0),
+                        )
+                        .optional()?;
+                    match existing_hash {
+                        None => {}
+                        Some(existing) if existing.as_ref() == Some(&hash) => continue,
+                        Some(_)
+                            if matches!(kind, ItemKind::Script)
+                                && !force
+                                && script_locked(&tx, tree_id, &item.name)? =>
+                        {
+                            outcome.locked.push(item.name);
+                            continue;
+                        }
+                        Some(_) => match resolve(&report.root, kind, &item.name)? {
+                            ConflictResolution::Skip => {
+                                outcome.skipped.push(item.name);
+                                continue;
+                            }
+                            ConflictResolution::Overwrite => {
+                                tx.execute(
+                                    &format!("DELETE FROM {table} WHERE tree_id=?1 AND name=?2"),
+                                    params![tree_id, item.name],
+                                )?;
+                                outcome.overwritten.push(item.name.clone());
+                            }
+                        },
+                    }
+                    let mut contents = other.open_stored_blob(item.blob_id)?;
+                    let size = contents.len();
+                    let blob_id = insert_blob(&tx, &mut contents, size, &hash)?;
+                    tx.execute(
+                        "UPDATE blobs SET signature=?1, encrypted=?2 WHERE _rowid_=?3",
+                        params![item.signature, item.encrypted, blob_id],
+                    )?;
+                    match kind {
+                        ItemKind::Script => tx.execute(
+                            "INSERT INTO tree_scripts
+                             (tree_id, blob_id, name, desc, origin, needs, clean_env, sandbox,
+                              container, trusted, locked, archived, last_used, tags, created_at,
+                              updated_at, failure_hint, env)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, 0, ?10, ?11, unixepoch(),
+                                     ?12, ?13, ?14, ?15, ?16)",
+                            params![
+                                tree_id,
+                                blob_id,
+                                item.name,
+                                item.desc,
+                                item.origin,
+                                item.needs,
+                                item.clean_env,
+                                item.sandbox,
+                                item.container,
+                                item.locked,
+                                item.archived,
+                                item.tags,
+                                item.created_at,
+                                item.updated_at,
+                                item.failure_hint,
+                                item.env
+                            ],
+                        )?,
+                        ItemKind::File => tx.execute(
+                            "INSERT INTO tree_files
+                             (tree_id, blob_id, name, desc, created_at, updated_at)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                            params![
+                                tree_id,
+                                blob_id,
+                                item.name,
+                                item.desc,
+                                item.created_at,
+                                item.updated_at
+                            ],
+                        )?,
+                    };
+                    if existing_hash.is_none() {
+                        outcome.cloned.push(item.name);
+                    }
+                }
+            }
+            reports.push(report);
+        }
+        if !dry_run {
+            tx.commit()?;
+        }
+        Ok(reports)
+    }
+
+    fn item_count(&self, kind: ItemKind, tree_id: i64) -> crate::Result<usize> {
+        Ok(self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE tree_id=?", kind.table()),
+            params![tree_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn stored_items(&self, kind: ItemKind, tree_id: i64) -> crate::Result<Vec<StoredItem>> {
+        let script_columns = match kind {
+            ItemKind::Script => {
+                "t.origin, t.needs, t.clean_env, t.sandbox, t.container, t.locked, t.archived, \
+                 t.tags, t.failure_hint, t.env"
+            }
+            ItemKind::File => "NULL, NULL, 0, NULL, NULL, 0, 0, NULL, NULL, NULL",
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT t.name, t.desc, {script_columns}, t.created_at, t.updated_at, t.blob_id,
+                 b.signature, b.encrypted FROM {} t
+             JOIN blobs b ON b._rowid_ = t.blob_id WHERE t.tree_id=?",
+            kind.table()
+        ))?;
+        let rows = stmt.query_map(params![tree_id], |row| {
+            Ok(StoredItem {
+                name: row.get(0)?,
+                desc: row.get(1)?,
+                origin: row.get(2)?,
+                needs: row.get(3)?,
+                clean_env: row.get(4)?,
+                sandbox: row.get(5)?,
+                container: row.get(6)?,
+                locked: row.get(7)?,
+                archived: row.get(8)?,
+                tags: row.get(9)?,
+                failure_hint: row.get(10)?,
+                env: row.get(11)?,
+                created_at: row.get(12)?,
+                updated_at: row.get(13)?,
+                blob_id: row.get(14)?,
+                signature: row.get(15)?,
+                encrypted: row.get(16)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns a set of blob ids that are referenced by the scripts and files of trees, including
+    /// the former versions of files
     ///
     /// Can be used to check whether a blob is part of any tree
-    pub fn tree_script_blob_ids(&self) -> anyhow::Result<HashSet<i64>> {
-        let mut stmt = self.conn.prepare("SELECT blob_id FROM tree_scripts")?;
+    pub fn tree_script_blob_ids(&self) -> crate::Result<HashSet<i64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT blob_id FROM tree_scripts UNION SELECT blob_id FROM tree_files
+             UNION SELECT blob_id FROM file_versions",
+        )?;
         let mut set = HashSet::new();
         let rows = stmt.query_map(params![], |row| {
             let id: i64 = row.get(0)?;
@@ -297,22 +2810,942 @@ impl Database {
         }
         Ok(set)
     }
-    pub fn blobs_table_len(&self) -> anyhow::Result<i64> {
-        let result = self
+    /// Checks that the scripts and saved files still have the contents they were stored with.
+    ///
+    /// Only checks the items of `tree_id` if it's given. Returns how many items were checked, and
+    /// the ones whose contents don't match.
+    pub fn verify_hashes(&self, tree_id: Option<i64>) -> crate::Result<(usize, Vec<HashMismatch>)> {
+        let mut checked = 0;
+        let mut mismatches = Vec::new();
+        for kind in [ItemKind::Script, ItemKind::File] {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT t.tree_id, t.name, b.sha256, t.blob_id FROM {} t
+                 JOIN blobs b ON b._rowid_ = t.blob_id
+                 WHERE (?1 IS NULL OR t.tree_id = ?1)
+                     AND (b.body IS NOT NULL OR b.chunked_size IS NOT NULL)
+                 ORDER BY t.tree_id, t.name",
+                kind.table()
+            ))?;
+            let rows = stmt.query_map(params![tree_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?;
+            for row in rows {
+                let (tree_id, name, expected, blob_id) = row?;
+                checked += 1;
+                let actual = content_hash_reader(self.open_stored_blob(blob_id)?)?;
+                if expected.as_ref() != Some(&actual) {
+                    mismatches.push(HashMismatch {
+                        root: self.query_tree_root(tree_id)?,
+                        kind,
+                        name,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok((checked, mismatches))
+    }
+
+    /// Returns the ids of all blobs, in the order they were added.
+    ///
+    /// There can be gaps between them, because vacuuming deletes blobs.
+    pub fn blob_ids(&self) -> crate::Result<Vec<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT _rowid_ FROM blobs ORDER BY _rowid_")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Deletes the blobs emptied by pruning, then rebuilds the database file so the space they
+    /// took is given back.
+    ///
+    /// Returns the number of deleted blobs.
+    pub fn vacuum(&self, observer: &mut impl Observer) -> crate::Result<usize> {
+        let ids: Vec<i64> = self
+            .conn
+            .prepare(
+                "SELECT _rowid_ FROM blobs WHERE body IS NULL AND chunked_size IS NULL
+                 AND _rowid_ NOT IN (SELECT blob_id FROM tree_scripts)
+                 AND _rowid_ NOT IN (SELECT blob_id FROM tree_files)
+                 AND _rowid_ NOT IN (SELECT blob_id FROM file_versions)",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        for (i, id) in ids.iter().enumerate() {
+            self.conn
+                .execute("DELETE FROM blobs WHERE _rowid_=?", params![id])?;
+            observer.progress(Progress {
+                action: "Deleting pruned blobs",
+                done: i + 1,
+                total: ids.len(),
+                item: &id.to_string(),
+            });
+        }
+        self.conn.execute_batch("VACUUM")?;
+        Ok(ids.len())
+    }
+
+    /// Stores a template. Fails if it already exists, unless `overwrite` is true.
+    pub fn add_template(&self, name: &str, body: &[u8], overwrite: bool) -> crate::Result<()> {
+        if !overwrite && self.template(name)?.is_some() {
+            bail!("A template named '{name}' already exists");
+        }
+        self.conn.execute(
+            "INSERT OR REPLACE INTO templates (name, body) VALUES (?1, ?2)",
+            params![name, body],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the body of a template, if it exists
+    pub fn template(&self, name: &str) -> crate::Result<Option<Vec<u8>>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT body FROM templates WHERE name=?",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Returns the names of all templates
+    pub fn template_names(&self) -> crate::Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM templates ORDER BY name")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Removes a template and returns whether it existed
+    pub fn remove_template(&self, name: &str) -> crate::Result<bool> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM templates WHERE name=?", params![name])?
+            > 0)
+    }
+
+    /// Stores a named bundle of saved files of the tree. Fails if it already exists, unless
+    /// `overwrite` is true.
+    pub fn add_file_bundle(
+        &mut self,
+        tree_id: i64,
+        name: &str,
+        files: &[String],
+        overwrite: bool,
+    ) -> crate::Result<()> {
+        for file in files {
+            if self.query_file_id_from_name(tree_id, file)?.is_none() {
+                bail!("{file} isn't saved");
+            }
+        }
+        if !overwrite && self.file_bundle(tree_id, name)?.is_some() {
+            bail!("A bundle named '{name}' already exists");
+        }
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM file_bundles WHERE tree_id=?1 AND name=?2",
+            params![tree_id, name],
+        )?;
+        for file in files {
+            tx.execute(
+                "INSERT OR IGNORE INTO file_bundles (tree_id, name, file) VALUES (?1, ?2, ?3)",
+                params![tree_id, name, file],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns the files of a bundle, if it exists
+    pub fn file_bundle(&self, tree_id: i64, name: &str) -> crate::Result<Option<Vec<String>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT file FROM file_bundles WHERE tree_id=?1 AND name=?2 ORDER BY file")?;
+        let files = stmt
+            .query_map(params![tree_id, name], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok((!files.is_empty()).then_some(files))
+    }
+
+    /// Returns the names of the bundles of the tree, along with their files
+    pub fn file_bundles(&self, tree_id: i64) -> crate::Result<Vec<(String, Vec<String>)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, file FROM file_bundles WHERE tree_id=? ORDER BY name, file")?;
+        let mut bundles: Vec<(String, Vec<String>)> = Vec::new();
+        for row in stmt.query_map(params![tree_id], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (name, file): (String, String) = row?;
+            match bundles.last_mut() {
+                Some((last, files)) if *last == name => files.push(file),
+                _ => bundles.push((name, vec![file])),
+            }
+        }
+        Ok(bundles)
+    }
+
+    /// Removes a bundle and returns whether it existed. The saved files stay.
+    pub fn remove_file_bundle(&self, tree_id: i64, name: &str) -> crate::Result<bool> {
+        Ok(self.conn.execute(
+            "DELETE FROM file_bundles WHERE tree_id=?1 AND name=?2",
+            params![tree_id, name],
+        )? > 0)
+    }
+
+    /// Registers `dir` as a shim directory for the tree. Returns false if it already was.
+    pub fn add_shim_dir(&self, tree_id: i64, dir: &Path) -> crate::Result<bool> {
+        Ok(self.conn.execute(
+            "INSERT OR IGNORE INTO tree_shim_dirs (tree_id, dir) VALUES (?1, ?2)",
+            params![tree_id, paths_as_strings::encode_path(&dir)],
+        )? > 0)
+    }
+
+    /// Unregisters `dir` as a shim directory for the tree and returns whether it was registered
+    pub fn remove_shim_dir(&self, tree_id: i64, dir: &Path) -> crate::Result<bool> {
+        Ok(self.conn.execute(
+            "DELETE FROM tree_shim_dirs WHERE tree_id=?1 AND dir=?2",
+            params![tree_id, paths_as_strings::encode_path(&dir)],
+        )? > 0)
+    }
+
+    pub fn shim_dirs_for_tree(&self, tree_id: i64) -> crate::Result<Vec<PathBuf>> {
+        let mut stmt = self
             .conn
-            .query_row("SELECT COUNT() FROM blobs", params![], |row| row.get(0))?;
-        Ok(result)
+            .prepare("SELECT dir FROM tree_shim_dirs WHERE tree_id=?")?;
+        let mut vec = Vec::new();
+        for result in stmt.query_map(params![tree_id], |row| row.get::<_, String>(0))? {
+            vec.push(crate::fs_util::decode_path(&result?)?);
+        }
+        Ok(vec)
     }
 
-    pub fn nullify_blob(&self, rowid: i64) -> anyhow::Result<()> {
+    pub fn nullify_blob(&self, rowid: i64) -> crate::Result<()> {
         self.conn.execute(
-            "UPDATE blobs SET body = NULL where _rowid_=?",
+            "UPDATE blobs SET body = NULL, chunked_size = NULL where _rowid_=?",
             params![rowid],
         )?;
+        self.conn
+            .execute("DELETE FROM blob_chunks WHERE blob_id=?", params![rowid])?;
         Ok(())
     }
 }
 
-#[derive(Error, Debug)]
-#[error("No such script found for current tree")]
-pub struct NoSuchScriptForCurrentTree;
+/// Opens the database file at `path`, unlocking it with `key` if it's encrypted
+fn open_connection(path: &Path, flags: OpenFlags, key: Option<&str>) -> crate::Result<Connection> {
+    let conn = Connection::open_with_flags(path, flags)?;
+    let Some(key) = key else {
+        return Ok(conn);
+    };
+    conn.pragma_update(None, "key", key)?;
+    // The key is only checked once something is read
+    match conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(())) {
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::NotADatabase => {
+            bail!("Wrong passphrase for the database")
+        }
+        result => result?,
+    }
+    Ok(conn)
+}
+
+/// Returns the SHA-256 of `body`, in hex
+pub fn content_hash(body: &[u8]) -> String {
+    hex(&Sha256::digest(body))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Like [`content_hash`], but hashes everything `source` reads, without keeping it in memory
+pub fn content_hash_reader(source: impl Read) -> std::io::Result<String> {
+    Ok(copy_hashing(source, &mut std::io::sink())?.1)
+}
+
+/// Copies `source` into `out`, and returns how many bytes were copied along with their hash
+fn copy_hashing(mut source: impl Read, out: &mut impl Write) -> std::io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    Ok((copied, hex(&hasher.finalize())))
+}
+
+/// Adds a blob with the `size` bytes that `source` reads, streaming them in, and returns its id.
+///
+/// Fails if the contents don't match `hash` anymore, like when a file changes while it's being
+/// saved.
+fn insert_blob(conn: &Connection, source: impl Read, size: u64, hash: &str) -> crate::Result<i64> {
+    let (id, written, written_hash) = if size >= CHUNKED_MIN_SIZE {
+        insert_chunked_blob(conn, source.take(size), size, hash)?
+    } else {
+        conn.execute(
+            "INSERT INTO blobs (body, sha256) VALUES (zeroblob(?1), ?2)",
+            params![size, hash],
+        )?;
+        let id = conn.last_insert_rowid();
+        let mut blob = conn.blob_open(DatabaseName::Main, "blobs", "body", id, false)?;
+        let (written, written_hash) = copy_hashing(source.take(size), &mut blob)?;
+        (id, written, written_hash)
+    };
+    if written != size || written_hash != hash {
+        bail!("The contents changed while they were being stored");
+    }
+    Ok(id)
+}
+
+/// Like [`insert_blob`], but encrypts the contents with gpg first if `encrypt` is true. `hash` is
+/// of the contents before they're encrypted.
+fn insert_item_blob(
+    conn: &Connection,
+    mut source: impl Read,
+    size: u64,
+    hash: &str,
+    encrypt: bool,
+) -> crate::Result<i64> {
+    if !encrypt {
+        return insert_blob(conn, source, size, hash);
+    }
+    let mut contents = Vec::with_capacity(size as usize);
+    (&mut source).take(size).read_to_end(&mut contents)?;
+    if contents.len() as u64 != size || content_hash(&contents) != hash {
+        bail!("The contents changed while they were being stored");
+    }
+    let encrypted = encryption::encrypt_item(&contents)?;
+    let id = insert_blob(
+        conn,
+        &encrypted[..],
+        encrypted.len() as u64,
+        &content_hash(&encrypted),
+    )?;
+    conn.execute("UPDATE blobs SET encrypted=1 WHERE _rowid_=?", params![id])?;
+    Ok(id)
+}
+
+/// Opens a blob for [`Database::open_blob`], decrypting it if it's encrypted and `decrypt` is
+/// true
+fn blob_reader(conn: &Connection, id: i64, decrypt: bool) -> crate::Result<BlobReader<'_>> {
+    let (chunked_size, encrypted): (Option<u64>, bool) = conn.query_row(
+        "SELECT chunked_size, encrypted FROM blobs WHERE _rowid_=?",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let mut reader = match chunked_size {
+        Some(len) => BlobReader {
+            conn,
+            blob: None,
+            chunks: conn
+                .prepare("SELECT chunk_id FROM blob_chunks WHERE blob_id=? ORDER BY seq")?
+                .query_map(params![id], |row| row.get(0))?
+                .collect::<Result<_, _>>()?,
+            decrypted: None,
+            len,
+        },
+        None => {
+            let blob = conn.blob_open(DatabaseName::Main, "blobs", "body", id, true)?;
+            BlobReader {
+                conn,
+                len: blob.len() as u64,
+                blob: Some(blob),
+                chunks: VecDeque::new(),
+                decrypted: None,
+            }
+        }
+    };
+    if encrypted && decrypt {
+        let mut contents = Vec::with_capacity(reader.len() as usize);
+        reader.read_to_end(&mut contents)?;
+        let contents = encryption::decrypt_item(&contents)?;
+        reader.len = contents.len() as u64;
+        reader.decrypted = Some(Cursor::new(contents));
+    }
+    Ok(reader)
+}
+
+fn blob_encrypted(conn: &Connection, id: i64) -> crate::Result<bool> {
+    Ok(conn.query_row(
+        "SELECT encrypted FROM blobs WHERE _rowid_=?",
+        params![id],
+        |row| row.get(0),
+    )?)
+}
+
+/// Adds a blob whose contents are stored as chunks, reusing the chunks that are already stored.
+///
+/// Returns its id, along with how many bytes were stored and their hash.
+fn insert_chunked_blob(
+    conn: &Connection,
+    source: impl Read,
+    size: u64,
+    hash: &str,
+) -> crate::Result<(i64, u64, String)> {
+    conn.execute(
+        "INSERT INTO blobs (sha256, chunked_size) VALUES (?1, ?2)",
+        params![hash, size],
+    )?;
+    let id = conn.last_insert_rowid();
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    for (seq, chunk) in Chunker::new(source).enumerate() {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        written += chunk.len() as u64;
+        let chunk_hash = content_hash(&chunk);
+        conn.execute(
+            "INSERT OR IGNORE INTO chunks (sha256, body) VALUES (?1, ?2)",
+            params![chunk_hash, chunk],
+        )?;
+        conn.execute(
+            "INSERT INTO blob_chunks (blob_id, seq, chunk_id)
+             SELECT ?1, ?2, id FROM chunks WHERE sha256=?3",
+            params![id, seq, chunk_hash],
+        )?;
+    }
+    Ok((id, written, hex(&hasher.finalize())))
+}
+
+/// Stores the hash of every blob that doesn't have one yet
+fn pin_blob_hashes(tx: &Transaction) -> crate::Result<()> {
+    let mut stmt =
+        tx.prepare("SELECT _rowid_, body FROM blobs WHERE sha256 IS NULL AND body IS NOT NULL")?;
+    let blobs = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (id, body) in blobs {
+        tx.execute(
+            "UPDATE blobs SET sha256=?1 WHERE _rowid_=?2",
+            params![content_hash(&body), id],
+        )?;
+    }
+    Ok(())
+}
+
+fn run_info(row: &rusqlite::Row) -> rusqlite::Result<RunInfo> {
+    Ok(RunInfo {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        started: row.get(2)?,
+        exit_code: row.get(3)?,
+        output_size: row.get(4)?,
+        duration: row.get::<_, Option<u64>>(5)?.map(Duration::from_millis),
+    })
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Returns the names of the items in `table` (`tree_scripts` or `tree_files`) of a tree,
+/// filtered by optional glob patterns
+fn item_names(
+    tx: &Transaction,
+    table: &str,
+    tree_id: i64,
+    only: Option<&str>,
+    except: Option<&str>,
+) -> crate::Result<Vec<String>> {
+    let mut stmt = tx.prepare(&format!(
+        "SELECT name FROM {table} WHERE tree_id = :tree
+            AND (:only IS NULL OR name GLOB :only)
+            AND (:except IS NULL OR name NOT GLOB :except)"
+    ))?;
+    let rows = stmt.query_map(
+        named_params! {":tree": tree_id, ":only": only, ":except": except},
+        |row| row.get(0),
+    )?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Resolves the conflicts of cloning the named items into `dst_tree`.
+///
+/// Overwritten items are removed from the destination, so only the skipped ones remain.
+fn resolve_clone_conflicts(
+    tx: &Transaction,
+    kind: ItemKind,
+    dst_tree: i64,
+    names: Vec<String>,
+    force: bool,
+    resolve: &mut impl FnMut(ItemKind, &str) -> crate::Result<ConflictResolution>,
+) -> crate::Result<CloneOutcome> {
+    let table = kind.table();
+    let mut outcome = CloneOutcome::default();
+    for name in names {
+        let exists = tx
+            .query_row(
+                &format!("SELECT 1 FROM {table} WHERE tree_id=?1 AND name=?2"),
+                params![dst_tree, name],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            outcome.cloned.push(name);
+            continue;
+        }
+        // Locked scripts stay, and the clone statement skips them like any existing item
+        if matches!(kind, ItemKind::Script) && !force && script_locked(tx, dst_tree, &name)? {
+            outcome.locked.push(name);
+            continue;
+        }
+        match resolve(kind, &name)? {
+            ConflictResolution::Skip => outcome.skipped.push(name),
+            ConflictResolution::Overwrite => {
+                tx.execute(
+                    &format!("DELETE FROM {table} WHERE tree_id=?1 AND name=?2"),
+                    params![dst_tree, name],
+                )?;
+                outcome.overwritten.push(name);
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+/// Splits the stored tags of a script or tree
+fn split_tags(tags: Option<&str>) -> Vec<String> {
+    tags.unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Joins tags for storing them, or returns `None` if there are none
+fn join_tags(tags: &[String]) -> crate::Result<Option<String>> {
+    if tags.iter().any(|tag| tag.contains(',')) {
+        bail!("Tags can't contain ','");
+    }
+    Ok((!tags.is_empty()).then(|| tags.join(",")))
+}
+
+/// Whether a script is locked. Scripts that don't exist aren't.
+fn script_locked(conn: &Connection, tree_id: i64, name: &str) -> crate::Result<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT locked FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+            params![tree_id, name],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(false))
+}
+
+fn script_blob_id(conn: &Connection, tree_id: i64, name: &str) -> crate::Result<Option<i64>> {
+    Ok(conn
+        .query_row(
+            "SELECT blob_id FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+            params![tree_id, name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// Replaces the body of a script, encrypting it if `encrypt` is true or it already was encrypted
+fn update_script_body(
+    conn: &Connection,
+    tree_id: i64,
+    name: &str,
+    body: Vec<u8>,
+    encrypt: bool,
+) -> crate::Result<()> {
+    let Some(blob_id) = script_blob_id(conn, tree_id, name)? else {
+        bail!("No such script");
+    };
+    let encrypt = encrypt || blob_encrypted(conn, blob_id)?;
+    let body = match encrypt {
+        true => encryption::encrypt_item(&body)?,
+        false => body,
+    };
+    if blob_users(conn, blob_id)? > 1 {
+        // Shared with a cloned script, which keeps the old body, its signature and its lock
+        conn.execute(
+            "INSERT INTO blobs (body, sha256, encrypted) VALUES (?1, ?2, ?3)",
+            params![body, content_hash(&body), encrypt],
+        )?;
+        conn.execute(
+            "UPDATE tree_scripts SET blob_id=?1 WHERE tree_id=?2 AND name=?3",
+            params![conn.last_insert_rowid(), tree_id, name],
+        )?;
+    } else {
+        // The old body may have been stored as chunks, which would be read instead
+        conn.execute("DELETE FROM blob_chunks WHERE blob_id=?", params![blob_id])?;
+        // The signature is of the old body
+        conn.execute(
+            "UPDATE blobs SET body=?1, sha256=?2, signature=NULL, encrypted=?3, chunked_size=NULL
+             WHERE _rowid_=?4",
+            params![body, content_hash(&body), encrypt, blob_id],
+        )?;
+    }
+    conn.execute(
+        "UPDATE tree_scripts SET updated_at=unixepoch() WHERE tree_id=?1 AND name=?2",
+        params![tree_id, name],
+    )?;
+    Ok(())
+}
+
+/// Returns how many scripts, saved files and file versions use a blob. Cloned items share their
+/// blobs.
+fn blob_users(conn: &Connection, blob_id: i64) -> crate::Result<i64> {
+    Ok(conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM tree_scripts WHERE blob_id=?1)
+              + (SELECT COUNT(*) FROM tree_files WHERE blob_id=?1)
+              + (SELECT COUNT(*) FROM file_versions WHERE blob_id=?1)",
+        params![blob_id],
+        |row| row.get(0),
+    )?)
+}
+
+fn tree_file_versions(conn: &Connection, tree_id: i64) -> crate::Result<u32> {
+    let keep: Option<Option<u32>> = conn
+        .query_row(
+            "SELECT file_versions FROM trees WHERE _rowid_=?",
+            params![tree_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match keep {
+        Some(keep) => Ok(keep.unwrap_or(DEFAULT_FILE_VERSIONS)),
+        None => Err(Error::NoSuchTree),
+    }
+}
+
+/// Drops all but the newest `keep` former versions of the saved file `name` of a tree, or of all
+/// of its files if `name` is `None`. Blobs that nothing else uses are deleted along with them.
+fn prune_file_versions(
+    conn: &Connection,
+    tree_id: i64,
+    name: Option<&str>,
+    keep: u32,
+) -> crate::Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT name, version, blob_id FROM file_versions v
+         WHERE tree_id=?1 AND (?2 IS NULL OR name=?2)
+             AND (SELECT COUNT(*) FROM file_versions
+                  WHERE tree_id=v.tree_id AND name=v.name AND version > v.version) >= ?3",
+    )?;
+    let dropped = stmt
+        .query_map(params![tree_id, name, keep], |row| {
+            Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<(String, i64, i64)>, _>>()?;
+    for (name, version, blob_id) in dropped {
+        conn.execute(
+            "DELETE FROM file_versions WHERE tree_id=?1 AND name=?2 AND version=?3",
+            params![tree_id, name, version],
+        )?;
+        if blob_users(conn, blob_id)? == 0 {
+            conn.execute("DELETE FROM blobs WHERE _rowid_=?", params![blob_id])?;
+        }
+    }
+    Ok(())
+}
+
+fn delete_script(conn: &Connection, tree_id: i64, name: &str) -> crate::Result<bool> {
+    Ok(conn.execute(
+        "DELETE FROM tree_scripts WHERE tree_id=?1 AND name=?2",
+        params![tree_id, name],
+    )? > 0)
+}
+
+fn update_script_origin(
+    conn: &Connection,
+    tree_id: i64,
+    name: &str,
+    origin: &str,
+) -> crate::Result<()> {
+    conn.execute(
+        "UPDATE tree_scripts SET origin=?1 WHERE tree_id=?2 AND name=?3",
+        params![origin, tree_id, name],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Cursor, temp_dir::TempDir};
+
+    fn test_db() -> (TempDir, Database) {
+        let dir = TempDir::new().unwrap();
+        let db = Database::load(dir.path(), None).unwrap();
+        (dir, db)
+    }
+
+    /// Imports a script big enough to be stored as chunks into a new tree
+    fn import_big_script(db: &mut Database) -> (i64, Vec<u8>) {
+        let (_other_dir, mut other) = test_db();
+        let tree = other.add_new_tree(Path::new("/big")).unwrap();
+        let body: Vec<u8> = (0..2 * CHUNKED_MIN_SIZE as usize)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        other.add_script(tree, "big", body.clone()).unwrap();
+        db.import(&other, false, false, |_, _, _| unreachable!(), &mut ())
+            .unwrap();
+        let tree = db.query_tree(Path::new("/big")).unwrap().unwrap();
+        let blob_id = db.query_script_id_from_name(tree, "big").unwrap().unwrap();
+        let chunked: Option<u64> = db
+            .conn
+            .query_row(
+                "SELECT chunked_size FROM blobs WHERE _rowid_=?",
+                params![blob_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(chunked, Some(body.len() as u64));
+        (tree, body)
+    }
+
+    #[test]
+    fn copy_chunked_script() {
+        let (_dir, mut db) = test_db();
+        let (tree, body) = import_big_script(&mut db);
+        db.copy_script(tree, "big", "big2").unwrap();
+        assert_eq!(db.get_script_by_name(tree, "big2").unwrap(), body);
+        db.remove_script(tree, "big").unwrap();
+        assert_eq!(db.get_script_by_name(tree, "big2").unwrap(), body);
+    }
+
+    #[test]
+    fn update_chunked_script() {
+        let (_dir, mut db) = test_db();
+        let (tree, _) = import_big_script(&mut db);
+        db.update_script(tree, "big", b"small".to_vec()).unwrap();
+        assert_eq!(db.get_script_by_name(tree, "big").unwrap(), b"small");
+    }
+
+    /// Sets up a tree in `db` and a database to import with conflicting versions of its scripts
+    fn conflicting_import(db: &mut Database) -> (TempDir, Database, i64) {
+        let tree = db.add_new_tree(Path::new("/tree")).unwrap();
+        for name in ["same", "skip", "over", "locked"] {
+            db.add_script(tree, name, format!("old {name}").into_bytes())
+                .unwrap();
+        }
+        db.update_script(tree, "same", b"same".to_vec()).unwrap();
+        db.set_script_locked(tree, "locked", true).unwrap();
+        let (other_dir, mut other) = test_db();
+        let other_tree = other.add_new_tree(Path::new("/tree")).unwrap();
+        for name in ["skip", "over", "locked", "new"] {
+            other
+                .add_script(other_tree, name, format!("new {name}").into_bytes())
+                .unwrap();
+        }
+        other
+            .add_script(other_tree, "same", b"same".to_vec())
+            .unwrap();
+        (other_dir, other, tree)
+    }
+
+    /// Resolves the conflicts of [`conflicting_import`], which never asks about locked scripts
+    fn resolve_conflict(name: &str) -> crate::Result<ConflictResolution> {
+        match name {
+            "skip" => Ok(ConflictResolution::Skip),
+            "over" => Ok(ConflictResolution::Overwrite),
+            _ => panic!("Asked about '{name}'"),
+        }
+    }
+
+    #[test]
+    fn import_conflicts() {
+        let (_dir, mut db) = test_db();
+        let (_other_dir, other, tree) = conflicting_import(&mut db);
+        let reports = db
+            .import(
+                &other,
+                false,
+                false,
+                |_, _, name| resolve_conflict(name),
+                &mut (),
+            )
+            .unwrap();
+        let [report] = &reports[..] else {
+            panic!("Expected one tree");
+        };
+        assert!(!report.new);
+        assert_eq!(report.scripts.cloned, ["new"]);
+        assert_eq!(report.scripts.overwritten, ["over"]);
+        assert_eq!(report.scripts.skipped, ["skip"]);
+        assert_eq!(report.scripts.locked, ["locked"]);
+        for (name, body) in [
+            ("same", "same"),
+            ("skip", "old skip"),
+            ("over", "new over"),
+            ("locked", "old locked"),
+            ("new", "new new"),
+        ] {
+            assert_eq!(db.get_script_by_name(tree, name).unwrap(), body.as_bytes());
+        }
+    }
+
+    #[test]
+    fn import_force_overwrites_locked() {
+        let (_dir, mut db) = test_db();
+        let (_other_dir, other, tree) = conflicting_import(&mut db);
+        let reports = db
+            .import(
+                &other,
+                false,
+                true,
+                |_, _, name| match name {
+                    "locked" => Ok(ConflictResolution::Overwrite),
+                    name => resolve_conflict(name),
+                },
+                &mut (),
+            )
+            .unwrap();
+        assert_eq!(reports[0].scripts.overwritten, ["locked", "over"]);
+        assert!(reports[0].scripts.locked.is_empty());
+        assert_eq!(
+            db.get_script_by_name(tree, "locked").unwrap(),
+            b"new locked"
+        );
+    }
+
+    #[test]
+    fn import_dry_run() {
+        let (_dir, mut db) = test_db();
+        let (_other_dir, other, tree) = conflicting_import(&mut db);
+        other.add_new_tree(Path::new("/other")).unwrap();
+        let reports = db
+            .import(
+                &other,
+                true,
+                false,
+                |_, _, name| resolve_conflict(name),
+                &mut (),
+            )
+            .unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].scripts.cloned, ["new"]);
+        assert_eq!(reports[0].scripts.overwritten, ["over"]);
+        assert!(reports[1].new);
+        assert!(db.query_tree(Path::new("/other")).unwrap().is_none());
+        assert!(!db.script_exists(tree, "new").unwrap());
+        assert_eq!(db.get_script_by_name(tree, "over").unwrap(), b"old over");
+    }
+
+    #[test]
+    fn clone_tree_conflicts() {
+        let (_dir, mut db) = test_db();
+        let src = db.add_new_tree(Path::new("/src")).unwrap();
+        let dst = db.add_new_tree(Path::new("/dst")).unwrap();
+        for name in ["skip", "over", "locked", "new"] {
+            db.add_script(src, name, format!("new {name}").into_bytes())
+                .unwrap();
+        }
+        for name in ["skip", "over", "locked"] {
+            db.add_script(dst, name, format!("old {name}").into_bytes())
+                .unwrap();
+        }
+        db.set_script_locked(dst, "locked", true).unwrap();
+        let report = db
+            .clone_tree(
+                src,
+                dst,
+                CloneOpts::default(),
+                |_, name| resolve_conflict(name),
+                &mut (),
+            )
+            .unwrap();
+        assert_eq!(report.scripts.cloned, ["new"]);
+        assert_eq!(report.scripts.overwritten, ["over"]);
+        assert_eq!(report.scripts.skipped, ["skip"]);
+        assert_eq!(report.scripts.locked, ["locked"]);
+        assert_eq!(db.get_script_by_name(dst, "over").unwrap(), b"new over");
+        assert_eq!(db.get_script_by_name(dst, "locked").unwrap(), b"old locked");
+        // The clone shares the blob, which must not change along with the source
+        db.update_script(src, "new", b"changed".to_vec()).unwrap();
+        assert_eq!(db.get_script_by_name(dst, "new").unwrap(), b"new new");
+    }
+
+    /// Contents of a saved file, or of one of its former versions
+    fn file_version(db: &Database, tree: i64, version: Option<i64>) -> Vec<u8> {
+        db.fetch_blob(db.file_blob_id(tree, "file", version).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn file_version_retention() {
+        let (_dir, mut db) = test_db();
+        let tree = db.add_new_tree(Path::new("/tree")).unwrap();
+        db.set_tree_file_versions(tree, Some(2)).unwrap();
+        for body in ["v1", "v2", "v3", "v4"] {
+            assert!(db.add_file(tree, "file", Cursor::new(body), false).unwrap());
+        }
+        // Saving the same contents again isn't a new version
+        assert!(!db.add_file(tree, "file", Cursor::new("v4"), false).unwrap());
+        let versions: Vec<_> = db
+            .file_versions(tree, "file")
+            .unwrap()
+            .iter()
+            .map(|v| (v.version, v.current))
+            .collect();
+        assert_eq!(versions, [(2, false), (3, false), (4, true)]);
+        assert!(db.file_blob_id(tree, "file", Some(1)).is_err());
+        db.set_tree_file_versions(tree, Some(1)).unwrap();
+        let versions: Vec<_> = db
+            .file_versions(tree, "file")
+            .unwrap()
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, [3, 4]);
+    }
+
+    #[test]
+    fn restore_file_version() {
+        let (_dir, mut db) = test_db();
+        let tree = db.add_new_tree(Path::new("/tree")).unwrap();
+        for body in ["v1", "v2", "v3"] {
+            db.add_file(tree, "file", Cursor::new(body), false).unwrap();
+        }
+        assert_eq!(file_version(&db, tree, Some(1)), b"v1");
+        assert_eq!(file_version(&db, tree, Some(2)), b"v2");
+        assert_eq!(file_version(&db, tree, Some(3)), b"v3");
+        assert_eq!(file_version(&db, tree, None), b"v3");
+        // Saving restored contents again makes them the newest version
+        let old = file_version(&db, tree, Some(1));
+        db.add_file(tree, "file", Cursor::new(old), false).unwrap();
+        assert_eq!(file_version(&db, tree, None), b"v1");
+        assert_eq!(file_version(&db, tree, Some(3)), b"v3");
+        assert_eq!(db.file_versions(tree, "file").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn rename_script_errors() {
+        let (_dir, mut db) = test_db();
+        let tree = db.add_new_tree(Path::new("/tree")).unwrap();
+        db.add_script(tree, "a", b"a".to_vec()).unwrap();
+        db.add_script(tree, "b", b"b".to_vec()).unwrap();
+        assert!(matches!(
+            db.rename_script(tree, "missing", "c"),
+            Err(Error::NoSuchScript)
+        ));
+        assert!(matches!(
+            db.rename_script(tree, "a", "b"),
+            Err(Error::NameConflict(name)) if name == "b"
+        ));
+        db.rename_script(tree, "a", "c").unwrap();
+        assert_eq!(db.get_script_by_name(tree, "c").unwrap(), b"a");
+        assert_eq!(db.get_script_by_name(tree, "b").unwrap(), b"b");
+    }
+}
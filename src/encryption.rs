@@ -0,0 +1,136 @@
+//! Encrypting the database with SQLCipher, for when scripts and saved files contain credentials.
+//!
+//! Only available when OtKeep is built with the `sqlcipher` feature. The passphrase of an
+//! encrypted database is taken from `OTKEEP_PASSPHRASE`, then from the keyring, and is asked for
+//! otherwise, see [`crate::secrets`].
+//!
+//! Single scripts and saved files can be encrypted instead, with gpg, which works with any build.
+//! They're encrypted for the default key of the user, which gpg-agent asks the passphrase of when
+//! they're decrypted, unless `OTKEEP_GPG_PASSPHRASE` is set.
+
+use {
+    crate::{
+        error::{bail, Context},
+        secrets::{gpg_command, KeyringEntry, Secret},
+        signing::run_gpg,
+    },
+    std::{
+        fs::File,
+        io::{ErrorKind, Read},
+        path::Path,
+    },
+};
+
+/// Whether this build of OtKeep can open encrypted databases
+pub const SUPPORTED: bool = cfg!(feature = "sqlcipher");
+
+/// Environment variable the passphrase can be given in, e.g. for scheduled runs
+pub const PASSPHRASE_VAR: &str = "OTKEEP_PASSPHRASE";
+
+/// Every unencrypted SQLite database starts with this
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Returns whether the database file at `path` is encrypted. A file that doesn't exist, or is
+/// still empty, isn't.
+pub fn is_encrypted(path: &Path) -> crate::Result<bool> {
+    let mut header = Vec::new();
+    match File::open(path) {
+        Ok(file) => file
+            .take(SQLITE_HEADER.len() as u64)
+            .read_to_end(&mut header)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(!header.is_empty() && header != SQLITE_HEADER)
+}
+
+/// Returns an error explaining how to get a build that supports encryption, unless this one does
+pub fn ensure_supported() -> crate::Result<()> {
+    if !SUPPORTED {
+        bail!(
+            "This build of OtKeep doesn't support encrypted databases. \
+             Build it with `cargo install otkeep --features sqlcipher`"
+        );
+    }
+    Ok(())
+}
+
+/// Gets the passphrase of the encrypted database at `path`, see [`crate::secrets`]
+pub(crate) fn passphrase(path: &Path) -> crate::Result<String> {
+    ensure_supported()?;
+    db_secret(path).get().with_context(|| {
+        format!(
+            "The database is encrypted. Store its passphrase in the keyring with \
+             `okeep db encrypt --keyring`, or set {PASSPHRASE_VAR}"
+        )
+    })
+}
+
+/// Gets the passphrase of the encrypted database at `path` from the environment or the keyring,
+/// without asking for it. Returns `None` if this build can't open it anyway.
+pub(crate) fn stored_passphrase(path: &Path) -> crate::Result<Option<String>> {
+    if !SUPPORTED {
+        return Ok(None);
+    }
+    db_secret(path).stored()
+}
+
+fn db_secret(path: &Path) -> Secret {
+    Secret {
+        desc: format!("Passphrase for {}", path.display()),
+        env_var: PASSPHRASE_VAR,
+        keyring: Some(keyring_entry(path)),
+    }
+}
+
+/// Stores the passphrase of the database at `path` in the keyring
+pub fn keyring_store(path: &Path, passphrase: &str) -> crate::Result<()> {
+    keyring_entry(path).store(passphrase)
+}
+
+/// Removes the passphrase of the database at `path` from the keyring, if it's there
+pub fn keyring_clear(path: &Path) -> crate::Result<()> {
+    keyring_entry(path).clear()
+}
+
+fn keyring_entry(path: &Path) -> KeyringEntry {
+    KeyringEntry {
+        label: "OtKeep database",
+        attributes: vec![("application", "otkeep".into()), ("database", path.into())],
+    }
+}
+
+/// Encrypts the contents of a script or saved file with gpg, for the default key of the user
+/// (`default-key` in gpg.conf, or else the first secret key)
+pub(crate) fn encrypt_item(contents: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut cmd = gpg_command(false)?;
+    cmd.args([
+        "--batch",
+        "--encrypt",
+        "--default-recipient-self",
+        "--output",
+        "-",
+    ]);
+    let output = run_gpg(cmd, contents)?;
+    if !output.status.success() {
+        bail!(
+            "gpg failed to encrypt: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Decrypts the contents of a script or saved file that [`encrypt_item`] encrypted
+pub(crate) fn decrypt_item(encrypted: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut cmd = gpg_command(true)?;
+    cmd.args(["--batch", "--quiet", "--decrypt"]);
+    let output = run_gpg(cmd, encrypted)?;
+    if !output.status.success() {
+        bail!(
+            "gpg failed to decrypt: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,85 @@
+//! The errors of the library
+
+use {std::fmt::Display, thiserror::Error};
+
+/// Everything that can go wrong in the library
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No such script found for current tree")]
+    NoSuchScript,
+    #[error("No such tree")]
+    NoSuchTree,
+    /// A script with the same name already exists
+    #[error("A script named '{0}' already exists in this tree")]
+    NameConflict(String),
+    #[error(
+        "'{0}' came from elsewhere, and hasn't been trusted yet. \
+         Review it with `okeep cat {0}`, then trust it with `okeep trust {0}`"
+    )]
+    UntrustedScript(String),
+    #[error(
+        "'{0}' is locked. Use --force to change it anyway, or unlock it with `okeep mod {0} --unlock`"
+    )]
+    ScriptLocked(String),
+    #[error("'{0}' is archived. Use `okeep unarchive {0}` to use it again")]
+    ScriptArchived(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Fmt(#[from] std::fmt::Error),
+    /// An error returned by a callback, like the conflict resolver of
+    /// [`Database::clone_tree`](crate::database::Database::clone_tree)
+    #[error(transparent)]
+    Callback(Box<dyn std::error::Error + Send + Sync>),
+    /// Another error, with a description of what was being done when it happened
+    #[error("{context}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<Error>,
+    },
+    /// Any other problem, described by the message
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns early with an [`Error::Other`] built from a format string
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::error::Error::Other(format!($($arg)*)))
+    };
+}
+pub(crate) use bail;
+
+/// Adds a description of what was being done to errors, and turns missing values into errors
+pub(crate) trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T>;
+    fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.with_context(|| context)
+    }
+
+    fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|e| Error::Context {
+            context: f().to_string(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.ok_or_else(|| Error::Other(context.to_string()))
+    }
+
+    fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T> {
+        self.ok_or_else(|| Error::Other(f().to_string()))
+    }
+}
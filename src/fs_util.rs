@@ -1,8 +1,33 @@
-use std::path::Path;
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
 
-pub fn ensure_dir_exists(dir: &Path) -> anyhow::Result<()> {
+pub fn ensure_dir_exists(dir: &Path) -> crate::Result<()> {
     if !dir.exists() {
         std::fs::create_dir_all(dir)?;
     }
     Ok(())
 }
+
+/// Decodes a path stored with [`paths_as_strings::encode_path`]
+pub fn decode_path(stored: &str) -> crate::Result<PathBuf> {
+    paths_as_strings::decode_path(stored)
+        .map_err(|e| crate::Error::Other(format!("Invalid stored path '{stored}': {e}")))
+}
+
+/// Whether the database in `dir` can be written to, or created if it doesn't exist yet.
+///
+/// SQLite creates its journal next to the database, so the directory has to be writable too.
+pub fn is_writable_db(dir: &Path) -> bool {
+    let file = dir.join(crate::database::DB_FILENAME);
+    !file.exists() || (is_writable(&file) && is_writable(dir))
+}
+
+fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
@@ -0,0 +1,52 @@
+//! Asking git about the working tree the current directory is in
+
+use {
+    crate::error::{bail, Error},
+    std::{io::ErrorKind, process::Command},
+};
+
+/// Returns the files under the current directory that git ignores, relative to it.
+///
+/// Ignored directories, like build directories, are returned as a single entry ending with `/`,
+/// instead of the files inside of them.
+pub fn ignored_files() -> crate::Result<Vec<String>> {
+    git(&[
+        "ls-files",
+        "-z",
+        "--others",
+        "--ignored",
+        "--exclude-standard",
+        "--directory",
+    ])
+}
+
+/// Returns whether git tracks the file at `path`. Outside of a git repository, or without git,
+/// nothing is tracked.
+pub fn is_tracked(path: &str) -> crate::Result<bool> {
+    match git(&["ls-files", "-z", "--", path]) {
+        Ok(files) => Ok(!files.is_empty()),
+        Err(Error::Io(e)) => Err(e.into()),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Runs git with `args`, and splits its output at NUL bytes
+fn git(args: &[&str]) -> crate::Result<Vec<String>> {
+    let output = match Command::new("git").args(args).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => bail!("git isn't installed"),
+        Err(e) => return Err(e.into()),
+    };
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
@@ -1,19 +1,46 @@
 #![feature(never_type)]
 
 use {
-    crate::database::ScriptInfo,
-    anyhow::Context,
+    crate::{
+        anchors::Anchors,
+        database::ScriptInfo,
+        error::Context,
+        signing::{AllowedSigners, Policy},
+    },
     database::Database,
     directories::ProjectDirs,
     std::{
-        io::Write,
+        io::{Read, Seek},
+        os::unix::process::ExitStatusExt,
         path::{Path, PathBuf},
+        process::ExitStatus,
     },
 };
 
+pub mod anchors;
+pub mod bundle;
+mod chunking;
+pub mod completion;
+mod container;
 pub mod database;
+pub mod encryption;
+pub mod error;
 mod fs_util;
-mod run;
+pub mod git;
+pub mod lint;
+pub mod marker;
+pub mod nix;
+pub mod progress;
+pub mod run;
+pub mod sandbox;
+pub mod save_suggestions;
+pub mod schedule;
+pub mod script_suggestions;
+pub mod secrets;
+pub mod shims;
+pub mod signing;
+
+pub use error::{Error, Result};
 
 /// Contains the settings and the script database.
 pub struct AppContext {
@@ -21,108 +48,466 @@ pub struct AppContext {
     pub root_id: i64,
 }
 
-pub fn load_db() -> anyhow::Result<Database> {
-    let dirs =
-        ProjectDirs::from("", "crumblingstatue", "otkeep").context("Failed to get project dirs")?;
+fn project_dirs() -> crate::Result<ProjectDirs> {
+    ProjectDirs::from("", "crumblingstatue", "otkeep").context("Failed to get project dirs")
+}
+
+/// Loads the database, along with the settings of this device.
+///
+/// The database is opened read-only if `read_only` is true, or if we can't write to it. If it's
+/// encrypted, its passphrase is looked up, or asked for.
+pub fn load_db(read_only: bool) -> crate::Result<Database> {
+    let path = db_file_path()?;
+    let key = match encryption::is_encrypted(&path)? {
+        true => Some(encryption::passphrase(&path)?),
+        false => None,
+    };
+    open_db(read_only, key)
+}
+
+/// Like [`load_db`], but never asks for the passphrase of an encrypted database.
+///
+/// Returns `None` if the passphrase is neither in the environment nor in the keyring. For the shell
+/// hooks and completions, which run without being asked to.
+pub fn load_db_unattended(read_only: bool) -> crate::Result<Option<Database>> {
+    let path = db_file_path()?;
+    let key = match encryption::is_encrypted(&path)? {
+        true => match encryption::stored_passphrase(&path)? {
+            Some(key) => Some(key),
+            None => return Ok(None),
+        },
+        false => None,
+    };
+    open_db(read_only, key).map(Some)
+}
+
+fn open_db(read_only: bool, key: Option<String>) -> crate::Result<Database> {
+    let dirs = project_dirs()?;
     let data_dir = dirs.data_dir();
-    let db = Database::load(data_dir)?;
+    secrets::hide_from_scripts()?;
+    let mut db = if read_only || !fs_util::is_writable_db(data_dir) {
+        Database::load_read_only(data_dir, key.as_deref())?
+    } else {
+        Database::load(data_dir, key.as_deref())?
+    };
+    db.set_anchors(Anchors::load(dirs.config_dir())?);
+    db.set_signature_policy(Policy::load(dirs.config_dir())?);
+    db.set_allowed_signers(AllowedSigners::load(dirs.config_dir())?);
     Ok(db)
 }
 
-pub fn find_root(database: &Database) -> anyhow::Result<Option<(i64, PathBuf)>> {
+/// Loads the linters configured on this device
+pub fn load_linters() -> crate::Result<lint::Linters> {
+    lint::Linters::load(project_dirs()?.config_dir())
+}
+
+/// Saves the signature policy of this device
+pub fn save_signature_policy(policy: Policy) -> crate::Result<()> {
+    policy.save(project_dirs()?.config_dir())
+}
+
+/// Saves the anchors of this device
+pub fn save_anchors(anchors: &Anchors) -> crate::Result<()> {
+    anchors.save(project_dirs()?.config_dir())
+}
+
+/// Encrypts the database with `passphrase`, or decrypts it without one, by replacing its file
+/// with a converted copy
+pub fn set_db_passphrase(db: Database, passphrase: Option<&str>) -> crate::Result<()> {
+    encryption::ensure_supported()?;
+    let path = db_file_path()?;
+    let converted = path.with_extension("sqlite3.converting");
+    if converted.exists() {
+        std::fs::remove_file(&converted)?;
+    }
+    let result = db.write_copy(&converted, passphrase);
+    drop(db);
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&converted);
+        return Err(e);
+    }
+    std::fs::rename(converted, path)?;
+    Ok(())
+}
+
+/// Path of the database file, without opening it
+pub fn db_file_path() -> crate::Result<PathBuf> {
+    Ok(project_dirs()?.data_dir().join(database::DB_FILENAME))
+}
+
+/// Directory for caches that can be thrown away at any time
+pub fn cache_dir() -> crate::Result<PathBuf> {
+    let dir = project_dirs()?.cache_dir().to_owned();
+    fs_util::ensure_dir_exists(&dir)?;
+    Ok(dir)
+}
+
+/// A root found by [`locate_root`]
+pub struct FoundRoot {
+    pub id: i64,
+    pub path: PathBuf,
+    /// The recorded root of the tree, if it was found by its marker because it moved from there.
+    /// Updating it with [`Database::rename_tree`] is left to the caller.
+    pub moved_from: Option<PathBuf>,
+}
+
+/// Finds the root the current directory belongs to, see [`locate_root`]
+pub fn find_root(database: &Database) -> crate::Result<Option<(i64, PathBuf)>> {
+    Ok(locate_root(database)?.map(|root| (root.id, root.path)))
+}
+
+/// Finds the root the current directory belongs to, and whether the tree moved there.
+///
+/// If the physical current directory doesn't belong to a root, the logical one from `$PWD` is
+/// tried as well, so roots that were established through a symlink are still found.
+pub fn locate_root(database: &Database) -> crate::Result<Option<FoundRoot>> {
     let current_dir = std::env::current_dir()?;
-    find_root_for_path(database, &current_dir)
+    if let Some(root) = locate_root_for_path(database, &current_dir)? {
+        return Ok(Some(root));
+    }
+    match logical_current_dir(&current_dir) {
+        Some(pwd) => find_root_for_spelling(database, &pwd),
+        None => Ok(None),
+    }
 }
 
+/// Finds the root `path` belongs to.
+///
+/// The canonical form of `path` is tried first, then the path as it's spelled.
 pub fn find_root_for_path(
     database: &Database,
     path: &Path,
-) -> anyhow::Result<Option<(i64, PathBuf)>> {
+) -> crate::Result<Option<(i64, PathBuf)>> {
+    Ok(locate_root_for_path(database, path)?.map(|root| (root.id, root.path)))
+}
+
+fn locate_root_for_path(database: &Database, path: &Path) -> crate::Result<Option<FoundRoot>> {
+    if let Ok(canonical) = path.canonicalize() {
+        if let Some(root) = find_root_for_spelling(database, &canonical)? {
+            return Ok(Some(root));
+        }
+    }
+    find_root_for_spelling(database, &std::path::absolute(path)?)
+}
+
+/// Walks up from `path` looking for a root.
+///
+/// Directories with a marker file of a tree count as its root, which the tree moved to if its
+/// recorded root no longer exists.
+fn find_root_for_spelling(database: &Database, path: &Path) -> crate::Result<Option<FoundRoot>> {
     let mut opt_path: Option<&Path> = Some(path);
     while let Some(path) = opt_path {
-        match database.query_tree(path)? {
-            Some(id) => return Ok(Some((id, path.to_owned()))),
-            None => {
-                opt_path = path.parent();
+        if let Some(id) = database.query_tree(path)? {
+            return Ok(Some(FoundRoot {
+                id,
+                path: path.to_owned(),
+                moved_from: None,
+            }));
+        }
+        if let Some(uuid) = marker::read(path) {
+            if let Some(id) = database.query_tree_by_uuid(&uuid)? {
+                let old_root = database.query_tree_root(id)?;
+                // Relative roots are relative to anchors missing on this device, leave them be
+                let moved = old_root.is_absolute() && !old_root.exists();
+                return Ok(Some(FoundRoot {
+                    id,
+                    path: path.to_owned(),
+                    moved_from: moved.then_some(old_root),
+                }));
             }
         }
+        opt_path = path.parent();
     }
     Ok(None)
 }
 
-pub fn print_established_trees(db: &Database) -> anyhow::Result<()> {
+/// Returns `$PWD` if it's a different spelling of `current_dir`
+fn logical_current_dir(current_dir: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+    let pwd = PathBuf::from(std::env::var_os("PWD")?);
+    if !pwd.is_absolute() || pwd == current_dir {
+        return None;
+    }
+    let (pwd_meta, cur_meta) = (pwd.metadata().ok()?, current_dir.metadata().ok()?);
+    (pwd_meta.dev() == cur_meta.dev() && pwd_meta.ino() == cur_meta.ino()).then_some(pwd)
+}
+
+pub fn print_established_trees(db: &Database) -> crate::Result<()> {
     let roots = db.get_tree_roots()?;
     if !roots.is_empty() {
         eprintln!("The following trees are established:");
         for root in roots {
-            eprintln!("{}", root.path.display());
+            match root.desc {
+                Some(desc) => eprintln!("{} - {desc}", root.path.display()),
+                None => eprintln!("{}", root.path.display()),
+            }
         }
     }
     eprintln!();
     Ok(())
 }
 
-pub fn checkout(name: &str, ctx: &mut AppContext) -> anyhow::Result<()> {
+pub fn checkout(name: &str, ctx: &mut AppContext) -> crate::Result<()> {
     let script = ctx.db.get_script_by_name(ctx.root_id, name)?;
     std::fs::write(name, script)?;
     Ok(())
 }
 
-pub fn cat(name: &str, ctx: &mut AppContext) -> anyhow::Result<()> {
-    let script = ctx.db.get_script_by_name(ctx.root_id, name)?;
-    std::io::stdout().write_all(&script)?;
+pub fn cat(name: &str, ctx: &mut AppContext) -> crate::Result<()> {
+    let mut script = ctx.db.open_script(ctx.root_id, name)?;
+    std::io::copy(&mut script, &mut std::io::stdout().lock())?;
     Ok(())
 }
 
-pub fn rename_script(old_name: &str, new_name: &str, ctx: &mut AppContext) -> anyhow::Result<()> {
-    ctx.db.rename_script(old_name, new_name)
+/// Replaces the `{{key}}` placeholders in `body` with their values.
+///
+/// Placeholders with unknown keys are left alone.
+pub fn expand_placeholders(body: &[u8], vars: &[(&str, &str)]) -> Vec<u8> {
+    expand_placeholders_with(body, |key| {
+        vars.iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+/// Like [`expand_placeholders`], but the value of each placeholder is looked up with `lookup`.
+///
+/// Placeholders `lookup` returns `None` for are left alone.
+pub fn expand_placeholders_with(
+    body: &[u8],
+    mut lookup: impl FnMut(&str) -> Option<String>,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(start) = rest.windows(2).position(|w| w == b"{{") {
+        out.extend_from_slice(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest[2..]
+            .windows(2)
+            .position(|w| w == b"}}")
+            .and_then(|len| {
+                let key = std::str::from_utf8(&rest[2..2 + len]).ok()?;
+                Some((lookup(key)?, len))
+            });
+        match value {
+            Some((value, len)) => {
+                out.extend_from_slice(value.as_bytes());
+                rest = &rest[len + 4..];
+            }
+            None => {
+                out.extend_from_slice(b"{{");
+                rest = &rest[2..];
+            }
+        }
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+/// Describes what kind of binary data `data` is, or returns `None` if it's text.
+///
+/// `data` can also be just the start of the contents, like the first few KiB of a large file.
+pub fn binary_kind(data: &[u8]) -> Option<&'static str> {
+    const MAGIC: [(&[u8], &str); 11] = [
+        (b"\x7fELF", "ELF executable"),
+        (b"\x1f\x8b", "gzip archive"),
+        (b"PK\x03\x04", "zip archive"),
+        (b"BZh", "bzip2 archive"),
+        (b"\xfd7zXZ\0", "xz archive"),
+        (b"\x28\xb5\x2f\xfd", "zstd archive"),
+        (b"\x89PNG", "PNG image"),
+        (b"\xff\xd8\xff", "JPEG image"),
+        (b"GIF8", "GIF image"),
+        (b"%PDF", "PDF document"),
+        (b"SQLite format 3\0", "SQLite database"),
+    ];
+    if let Some((_, kind)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(kind);
+    }
+    // A character cut off at the end isn't invalid, since `data` can be cut off there
+    let invalid_utf8 = std::str::from_utf8(data).is_err_and(|e| e.error_len().is_some());
+    (data.contains(&0) || invalid_utf8).then_some("binary data")
+}
+
+/// Formats an amount of bytes in a human readable way, e.g. `1.5 KiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        // Compare the value as printed, so it's never shown as 1024.0 of a unit
+        if (size * 10.).round() < 10240. {
+            break;
+        }
+        size /= 1024.;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+pub fn rename_script(old_name: &str, new_name: &str, ctx: &mut AppContext) -> crate::Result<()> {
+    ctx.db.rename_script(ctx.root_id, old_name, new_name)
+}
+
+/// Exit code of a finished script, following the shell convention for signals
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+/// Environment variables kept by [`clean_env`], besides the `LC_*` and `OTKEEP_*` ones
+const CLEAN_ENV_KEEP: [&str; 14] = [
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "PATH",
+    "SHELL",
+    "TERM",
+    "COLORTERM",
+    "LANG",
+    "LANGUAGE",
+    "TZ",
+    "TMPDIR",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XDG_RUNTIME_DIR",
+];
+
+/// Removes every environment variable of this process, except a few basic ones and `keep`.
+///
+/// Scripts run afterwards inherit the minimal environment.
+pub fn clean_env(keep: &[String]) {
+    for (key, _) in std::env::vars_os() {
+        let kept = key.to_str().is_some_and(|key| {
+            CLEAN_ENV_KEEP.contains(&key)
+                || key.starts_with("LC_")
+                || key.starts_with("OTKEEP_")
+                || keep.iter().any(|k| k == key)
+        });
+        if !kept {
+            std::env::remove_var(&key);
+        }
+    }
+}
+
+/// Runs the scripts `name` depends on, in order, stopping at the first one that fails.
+///
+/// Returns the exit status of the failed script, if any.
+pub fn run_needs(ctx: &AppContext, name: &str) -> crate::Result<Option<ExitStatus>> {
+    run_needs_all(ctx, &[name.to_owned()])
+}
+
+/// Like [`run_needs`], for scripts that run at the same time. Scripts more than one of them
+/// depend on run once, and the ones among `names` don't run beforehand.
+pub fn run_needs_all(ctx: &AppContext, names: &[String]) -> crate::Result<Option<ExitStatus>> {
+    let mut needs: Vec<(String, &str)> = Vec::new();
+    for name in names {
+        let mut order = ctx.db.run_order(ctx.root_id, name)?;
+        order.pop();
+        for need in order {
+            if !names.contains(&need) && !needs.iter().any(|(n, _)| *n == need) {
+                needs.push((need, name));
+            }
+        }
+    }
+    for (need, name) in needs {
+        eprintln!("Running '{need}', needed by '{name}'");
+        let status =
+            ctx.db
+                .run_script_supervised(ctx.root_id, &need, std::iter::empty::<&str>())?;
+        if !status.success() {
+            eprintln!("'{need}' failed, not running '{name}'");
+            return Ok(Some(status));
+        }
+    }
+    Ok(None)
 }
 
-pub fn list_scripts(ctx: &AppContext) -> anyhow::Result<()> {
+pub fn list_scripts(ctx: &AppContext) -> crate::Result<()> {
     list_scripts_for_tree(ctx, ctx.root_id)
 }
 
-pub fn list_scripts_for_tree(ctx: &AppContext, id: i64) -> anyhow::Result<()> {
-    let scripts = ctx.db.scripts_for_tree(id)?;
+/// Returns the scripts of the current tree, without printing anything
+pub fn script_list(ctx: &AppContext) -> crate::Result<Vec<ScriptInfo>> {
+    ctx.db.scripts_iter(ctx.root_id).collect()
+}
+
+/// Returns the saved files of the current tree, without printing anything
+pub fn file_list(ctx: &AppContext) -> crate::Result<Vec<ScriptInfo>> {
+    ctx.db.files_iter(ctx.root_id).collect()
+}
+
+pub fn list_scripts_for_tree(ctx: &AppContext, id: i64) -> crate::Result<()> {
+    let scripts = ctx.db.scripts_iter(id).collect::<crate::Result<Vec<_>>>()?;
     if scripts.is_empty() {
         eprintln!("No scripts have been added yet. To add one, use okeep add.");
     } else {
         eprintln!("The following scripts are available (orun):\n");
-        for ScriptInfo { name, description } in scripts {
-            eprintln!(
-                "{}{}{}",
-                name,
-                if description.is_empty() { "" } else { " - " },
-                description
-            );
-        }
+        print_items(&scripts);
     }
     Ok(())
 }
 
-pub fn list_files(ctx: &AppContext) -> anyhow::Result<()> {
-    let files = ctx.db.files_for_tree(ctx.root_id)?;
+/// Prints the names of the items along with their sizes and descriptions
+fn print_items(items: &[ScriptInfo]) {
+    for ScriptInfo {
+        name,
+        description,
+        size,
+    } in items
+    {
+        eprintln!(
+            "{name} ({}){}{description}",
+            format_size(*size),
+            if description.is_empty() { "" } else { " - " },
+        );
+    }
+}
+
+pub fn list_files(ctx: &AppContext) -> crate::Result<()> {
+    let files = file_list(ctx)?;
     if files.is_empty() {
         eprintln!("No files have been saved yet. To add one, use okeep save.");
     } else {
         eprintln!("The following files are available (okeep restore):\n");
-        for ScriptInfo { name, description } in files {
-            eprintln!(
-                "{}{}{}",
-                name,
-                if description.is_empty() { "" } else { " - " },
-                description
-            );
-        }
+        print_items(&files);
     }
     Ok(())
 }
 
-pub fn add_file(ctx: &mut AppContext, path: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
-    ctx.db.add_file(ctx.root_id, path, bytes)?;
-    Ok(())
+/// Saves a file, encrypted with gpg if `encrypt` is true. Returns false if it was already saved
+/// with the same contents.
+pub fn add_file(
+    ctx: &mut AppContext,
+    path: &str,
+    source: impl Read + Seek,
+    encrypt: bool,
+) -> crate::Result<bool> {
+    ctx.db.add_file(ctx.root_id, path, source, encrypt)
 }
 
-pub fn get_file(ctx: &mut AppContext, path: &str) -> anyhow::Result<Vec<u8>> {
+pub fn get_file(ctx: &mut AppContext, path: &str) -> crate::Result<Vec<u8>> {
     ctx.db.get_file_by_name(ctx.root_id, path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_formatted_with_binary_units() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_size(3 << 40), "3.0 TiB");
+        assert_eq!(format_size(2048 << 40), "2048.0 TiB");
+    }
+}
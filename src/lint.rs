@@ -0,0 +1,175 @@
+//! Checking scripts for problems, like a broken shebang or linter findings, before storing them
+
+use {
+    crate::error::Context,
+    std::{
+        io::Write,
+        path::Path,
+        process::{Command, Stdio},
+    },
+};
+
+const LINTERS_FILENAME: &str = "linters";
+
+/// Shells that shellcheck is used for if no other linter is configured
+const SHELLCHECK_SHELLS: [&str; 4] = ["sh", "bash", "dash", "ksh"];
+
+/// The linter command for each interpreter, as `interpreter=command` lines in the config
+/// directory.
+///
+/// The script is passed to the command on standard input. An empty command disables linting for
+/// that interpreter.
+pub struct Linters {
+    entries: Vec<(String, String)>,
+}
+
+impl Linters {
+    pub(crate) fn load(config_dir: &Path) -> crate::Result<Self> {
+        let mut entries: Vec<_> = SHELLCHECK_SHELLS
+            .iter()
+            .map(|shell| (shell.to_string(), format!("shellcheck --shell={shell} -")))
+            .collect();
+        let path = config_dir.join(LINTERS_FILENAME);
+        if path.exists() {
+            for line in std::fs::read_to_string(path)?.lines() {
+                if let Some((interpreter, command)) = line.split_once('=') {
+                    entries.retain(|(i, _)| i != interpreter);
+                    entries.push((interpreter.trim().to_owned(), command.trim().to_owned()));
+                }
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// Runs the linter for the interpreter of `body`, and returns its findings if it failed.
+    ///
+    /// Nothing is checked if there is no linter for the interpreter, or it's not installed.
+    pub fn lint(&self, body: &[u8]) -> crate::Result<Option<String>> {
+        let interpreter = interpreter(body);
+        let Some((_, command)) = self.entries.iter().find(|(i, _)| *i == interpreter) else {
+            return Ok(None);
+        };
+        let mut words = command.split_whitespace();
+        match words.next() {
+            Some(program) => run_check(program, words, body),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Checks the shebang of `body`, and describes why it wouldn't work on this device
+pub fn check_shebang(body: &[u8]) -> Option<String> {
+    let Some(line) = body.strip_prefix(b"#!") else {
+        let trimmed = body
+            .strip_prefix(b"\xEF\xBB\xBF")
+            .unwrap_or(body)
+            .trim_ascii_start();
+        if trimmed.starts_with(b"#!") {
+            return Some(
+                "The shebang isn't at the very start of the script, so it will be run with /bin/sh"
+                    .into(),
+            );
+        }
+        return None;
+    };
+    let line = line.split(|&b| b == b'\n').next().unwrap_or_default();
+    if line.ends_with(b"\r") {
+        return Some(
+            "The shebang line ends with a carriage return (Windows line endings), \
+             so the interpreter won't be found"
+                .into(),
+        );
+    }
+    let line = String::from_utf8_lossy(line);
+    let Some(program) = line.split_whitespace().next() else {
+        return Some("The shebang doesn't name an interpreter".into());
+    };
+    if !program.starts_with('/') {
+        return Some(format!(
+            "The interpreter in the shebang ({program}) isn't an absolute path"
+        ));
+    }
+    if !Path::new(program).exists() {
+        return Some(format!(
+            "The interpreter {program} doesn't exist on this device"
+        ));
+    }
+    let name = interpreter(body);
+    if program.ends_with("/env") && !in_path(&name) {
+        return Some(format!(
+            "The interpreter {name} isn't in PATH on this device"
+        ));
+    }
+    None
+}
+
+/// Checks the syntax of `body` with its interpreter, if it's one that can do that without running
+/// the script, and returns the errors.
+pub fn check_syntax(body: &[u8]) -> crate::Result<Option<String>> {
+    const PYTHON_COMPILE: &str = "import sys; compile(sys.stdin.buffer.read(), '<script>', 'exec')";
+    let interpreter = interpreter(body);
+    match interpreter.as_str() {
+        "sh" | "bash" | "dash" | "ksh" | "zsh" | "fish" => run_check(&interpreter, ["-n"], body),
+        "python" | "python3" => run_check(&interpreter, ["-c", PYTHON_COMPILE], body),
+        "ruby" => run_check(&interpreter, ["-c"], body),
+        _ => Ok(None),
+    }
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).exists()))
+}
+
+/// Runs `program` with `body` on standard input, and returns its output if it failed.
+///
+/// Nothing is checked if `program` isn't installed.
+fn run_check<I, S>(program: &str, args: I, body: &[u8]) -> crate::Result<Option<String>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to run {program}")),
+    };
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = body.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if output.status.success() {
+        return Ok(None);
+    }
+    let mut findings = String::from_utf8_lossy(&output.stdout).into_owned();
+    findings.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(Some(findings))
+}
+
+/// Name of the interpreter that runs `body`, according to its shebang.
+///
+/// Scripts without a shebang are run with `sh`.
+pub fn interpreter(body: &[u8]) -> String {
+    let Some(line) = body.strip_prefix(b"#!") else {
+        return "sh".into();
+    };
+    let line = line.split(|&b| b == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let mut words = line.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    let name = program.rsplit('/').next().unwrap_or(program);
+    if name == "env" {
+        // Skip options like `-S`
+        if let Some(program) = words.find(|word| !word.starts_with('-') && !word.contains('=')) {
+            return program.to_owned();
+        }
+    }
+    name.to_owned()
+}
@@ -0,0 +1,25 @@
+//! `.otkeep` marker files, which let a tree be found by its UUID after it's moved
+
+use std::path::Path;
+
+pub const MARKER_FILENAME: &str = ".otkeep";
+
+/// Writes a marker file for the tree with `uuid` into `root`
+pub fn write(root: &Path, uuid: &str) -> crate::Result<()> {
+    std::fs::write(
+        root.join(MARKER_FILENAME),
+        format!(
+            "# OtKeep tree marker. Lets OtKeep find this tree after it's moved.\nuuid={uuid}\n"
+        ),
+    )?;
+    Ok(())
+}
+
+/// Reads the tree UUID from the marker file in `dir`, if there is one
+pub fn read(dir: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(dir.join(MARKER_FILENAME)).ok()?;
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("uuid="))
+        .map(|uuid| uuid.trim().to_owned())
+}
@@ -0,0 +1,2 @@
+-- Where a script was added from (an URL or a path), if known
+ALTER TABLE tree_scripts ADD COLUMN origin TEXT;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- A short description of what a tree is, shown when listing trees
+ALTER TABLE trees ADD COLUMN desc TEXT;
@@ -0,0 +1,2 @@
+-- When a script of the tree was last run, in seconds since the Unix epoch
+ALTER TABLE trees ADD COLUMN last_used INTEGER;
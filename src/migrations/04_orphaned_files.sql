@@ -0,0 +1,2 @@
+-- Removing a tree used to leave its saved files behind, which a new tree could then inherit
+DELETE FROM tree_files WHERE tree_id NOT IN (SELECT _rowid_ FROM trees);
@@ -0,0 +1,9 @@
+-- A stable identity for trees, which doesn't change when a tree is moved.
+-- Random version 4 UUIDs, the same as `NEW_UUID` in database.rs.
+ALTER TABLE trees ADD COLUMN uuid TEXT;
+UPDATE trees SET uuid = lower(
+    hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2)
+    || '-' || substr('89ab', 1 + abs(random() % 4), 1) || substr(hex(randomblob(2)), 2)
+    || '-' || hex(randomblob(6))
+);
+CREATE UNIQUE INDEX trees_uuid ON trees (uuid);
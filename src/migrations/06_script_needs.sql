@@ -0,0 +1,2 @@
+-- Comma separated names of the scripts that have to run successfully before this one
+ALTER TABLE tree_scripts ADD COLUMN needs TEXT;
@@ -0,0 +1,11 @@
+-- History of supervised script runs
+CREATE TABLE runs (
+    tree_id   INTEGER NOT NULL,
+    name      TEXT NOT NULL,
+    -- Unix timestamp of when the run started
+    started   INTEGER NOT NULL,
+    exit_code INTEGER NOT NULL,
+    -- Everything the script wrote to stdout and stderr, if it was captured
+    output    BLOB
+);
+CREATE INDEX runs_script ON runs (tree_id, name);
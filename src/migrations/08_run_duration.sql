@@ -0,0 +1,2 @@
+-- How long the run took, in milliseconds
+ALTER TABLE runs ADD COLUMN duration_ms INTEGER;
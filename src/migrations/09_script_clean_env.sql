@@ -0,0 +1,2 @@
+-- Whether the script always runs with a minimal environment
+ALTER TABLE tree_scripts ADD COLUMN clean_env INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Name of the sandbox the script always runs in, if any
+ALTER TABLE tree_scripts ADD COLUMN sandbox TEXT;
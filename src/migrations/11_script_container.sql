@@ -0,0 +1,2 @@
+-- Image of the container the script runs in, if any
+ALTER TABLE tree_scripts ADD COLUMN container TEXT;
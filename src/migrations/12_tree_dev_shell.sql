@@ -0,0 +1,2 @@
+-- Name of the kind of nix dev shell the scripts of the tree run in, if any
+ALTER TABLE trees ADD COLUMN dev_shell TEXT;
@@ -0,0 +1,2 @@
+-- Detached GPG signature of the body, made with `okeep sign`
+ALTER TABLE blobs ADD COLUMN signature BLOB;
@@ -0,0 +1,3 @@
+-- Whether the user has confirmed running the script. Scripts that came from other trees,
+-- databases or URLs start out untrusted.
+ALTER TABLE tree_scripts ADD COLUMN trusted INTEGER NOT NULL DEFAULT 1;
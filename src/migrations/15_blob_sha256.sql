@@ -0,0 +1,3 @@
+-- SHA-256 of the body when it was stored, in hex, for detecting unexpected changes.
+-- Filled in for existing blobs after the migration.
+ALTER TABLE blobs ADD COLUMN sha256 TEXT;
@@ -0,0 +1,2 @@
+-- Whether the script is protected against being updated, renamed, edited or removed
+ALTER TABLE tree_scripts ADD COLUMN locked INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,18 @@
+-- Where a blob was last used, so unreferenced blobs can be told apart when pruning.
+-- Filled in when the script or file using it is removed.
+ALTER TABLE blobs ADD COLUMN former_root TEXT;
+ALTER TABLE blobs ADD COLUMN former_name TEXT;
+CREATE TRIGGER tree_scripts_former_owner AFTER DELETE ON tree_scripts
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
+CREATE TRIGGER tree_files_former_owner AFTER DELETE ON tree_files
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
@@ -0,0 +1,61 @@
+-- VACUUM may renumber the rowids of tables without an INTEGER PRIMARY KEY, but other tables
+-- refer to blobs, trees and runs by rowid. Rebuild them with one, keeping the rowids.
+DROP TRIGGER tree_scripts_former_owner;
+DROP TRIGGER tree_files_former_owner;
+
+CREATE TABLE blobs_new (
+    id          INTEGER PRIMARY KEY,
+    body        BLOB,
+    signature   BLOB,
+    sha256      TEXT,
+    former_root TEXT,
+    former_name TEXT
+);
+INSERT INTO blobs_new (id, body, signature, sha256, former_root, former_name)
+SELECT _rowid_, body, signature, sha256, former_root, former_name FROM blobs;
+DROP TABLE blobs;
+ALTER TABLE blobs_new RENAME TO blobs;
+
+CREATE TABLE trees_new (
+    id        INTEGER PRIMARY KEY,
+    root      TEXT NOT NULL UNIQUE,
+    desc      TEXT,
+    last_used INTEGER,
+    uuid      TEXT,
+    dev_shell TEXT
+);
+INSERT INTO trees_new (id, root, desc, last_used, uuid, dev_shell)
+SELECT _rowid_, root, desc, last_used, uuid, dev_shell FROM trees;
+DROP TABLE trees;
+ALTER TABLE trees_new RENAME TO trees;
+CREATE UNIQUE INDEX trees_uuid ON trees (uuid);
+
+CREATE TABLE runs_new (
+    id          INTEGER PRIMARY KEY,
+    tree_id     INTEGER NOT NULL,
+    name        TEXT NOT NULL,
+    started     INTEGER NOT NULL,
+    exit_code   INTEGER NOT NULL,
+    output      BLOB,
+    duration_ms INTEGER
+);
+INSERT INTO runs_new (id, tree_id, name, started, exit_code, output, duration_ms)
+SELECT _rowid_, tree_id, name, started, exit_code, output, duration_ms FROM runs;
+DROP TABLE runs;
+ALTER TABLE runs_new RENAME TO runs;
+CREATE INDEX runs_script ON runs (tree_id, name);
+
+CREATE TRIGGER tree_scripts_former_owner AFTER DELETE ON tree_scripts
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
+CREATE TRIGGER tree_files_former_owner AFTER DELETE ON tree_files
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
@@ -0,0 +1,3 @@
+-- Whether the script was put away with `okeep archive`. Archived scripts aren't listed and can't
+-- be run, but they're kept around in case they're needed again.
+ALTER TABLE tree_scripts ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
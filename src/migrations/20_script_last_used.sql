@@ -0,0 +1,8 @@
+-- When the script was last run, or added if it hasn't run since, in seconds since the Unix epoch.
+-- Existing scripts that never had a recorded run count as used when this was added.
+ALTER TABLE tree_scripts ADD COLUMN last_used INTEGER;
+UPDATE tree_scripts SET last_used = COALESCE(
+    (SELECT MAX(started) FROM runs WHERE runs.tree_id = tree_scripts.tree_id
+        AND runs.name = tree_scripts.name),
+    unixepoch()
+);
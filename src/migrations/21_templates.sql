@@ -0,0 +1,5 @@
+-- Boilerplate that new scripts can start from, with `okeep add --template`
+CREATE TABLE templates (
+    name TEXT NOT NULL UNIQUE,
+    body BLOB NOT NULL
+);
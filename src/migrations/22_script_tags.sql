@@ -0,0 +1,2 @@
+-- Free-form tags for grouping scripts, separated by commas
+ALTER TABLE tree_scripts ADD COLUMN tags TEXT;
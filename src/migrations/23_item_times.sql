@@ -0,0 +1,6 @@
+-- When scripts and saved files were added and last changed, in seconds since the Unix epoch.
+-- Unknown for the items that already exist.
+ALTER TABLE tree_scripts ADD COLUMN created_at INTEGER;
+ALTER TABLE tree_scripts ADD COLUMN updated_at INTEGER;
+ALTER TABLE tree_files ADD COLUMN created_at INTEGER;
+ALTER TABLE tree_files ADD COLUMN updated_at INTEGER;
@@ -0,0 +1,2 @@
+-- A message shown along with the failure banner of `orun --check` when the script fails
+ALTER TABLE tree_scripts ADD COLUMN failure_hint TEXT;
@@ -0,0 +1,2 @@
+-- Environment variables set for every run of the script, as KEY=VAL lines
+ALTER TABLE tree_scripts ADD COLUMN env TEXT;
@@ -0,0 +1,7 @@
+-- Named sets of saved files of a tree, restored together with `okeep restore --bundle`
+CREATE TABLE file_bundles (
+    tree_id INTEGER NOT NULL,
+    name    TEXT NOT NULL,
+    file    TEXT NOT NULL,
+    UNIQUE(tree_id, name, file)
+);
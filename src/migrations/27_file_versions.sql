@@ -0,0 +1,13 @@
+-- Former contents of saved files, kept when they're saved again with different contents.
+-- Versions are numbered from 1 for the first save. The current contents are in `tree_files`.
+CREATE TABLE file_versions (
+    tree_id  INTEGER NOT NULL,
+    name     TEXT NOT NULL,
+    version  INTEGER NOT NULL,
+    blob_id  INTEGER NOT NULL,
+    -- When this version was saved
+    saved_at INTEGER,
+    UNIQUE(tree_id, name, version)
+);
+-- How many former versions of each saved file the tree keeps. NULL means the default.
+ALTER TABLE trees ADD COLUMN file_versions INTEGER;
@@ -0,0 +1,4 @@
+-- Largest size of a single script or saved file of the tree. NULL means the default, 0 no limit.
+ALTER TABLE trees ADD COLUMN max_item_size INTEGER;
+-- Largest total size of the scripts, saved files and file versions of the tree. NULL means no limit.
+ALTER TABLE trees ADD COLUMN max_tree_size INTEGER;
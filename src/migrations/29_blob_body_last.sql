@@ -0,0 +1,32 @@
+-- SQLite only writes a zeroblob() lazily when it's the last column of the row. Make the body the
+-- last column, so large blobs can be streamed in without building them in memory first.
+DROP TRIGGER tree_scripts_former_owner;
+DROP TRIGGER tree_files_former_owner;
+
+CREATE TABLE blobs_new (
+    id          INTEGER PRIMARY KEY,
+    signature   BLOB,
+    sha256      TEXT,
+    former_root TEXT,
+    former_name TEXT,
+    body        BLOB
+);
+INSERT INTO blobs_new (id, signature, sha256, former_root, former_name, body)
+SELECT id, signature, sha256, former_root, former_name, body FROM blobs;
+DROP TABLE blobs;
+ALTER TABLE blobs_new RENAME TO blobs;
+
+CREATE TRIGGER tree_scripts_former_owner AFTER DELETE ON tree_scripts
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
+CREATE TRIGGER tree_files_former_owner AFTER DELETE ON tree_files
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
@@ -0,0 +1,58 @@
+-- Large saved files are stored as chunks, which blobs with the same data share. The body of such
+-- a blob is NULL, and `chunked_size` is the size of its contents.
+DROP TRIGGER tree_scripts_former_owner;
+DROP TRIGGER tree_files_former_owner;
+
+CREATE TABLE blobs_new (
+    id           INTEGER PRIMARY KEY,
+    signature    BLOB,
+    sha256       TEXT,
+    former_root  TEXT,
+    former_name  TEXT,
+    chunked_size INTEGER,
+    body         BLOB
+);
+INSERT INTO blobs_new (id, signature, sha256, former_root, former_name, body)
+SELECT id, signature, sha256, former_root, former_name, body FROM blobs;
+DROP TABLE blobs;
+ALTER TABLE blobs_new RENAME TO blobs;
+
+CREATE TRIGGER tree_scripts_former_owner AFTER DELETE ON tree_scripts
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
+CREATE TRIGGER tree_files_former_owner AFTER DELETE ON tree_files
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
+
+CREATE TABLE chunks (
+    id     INTEGER PRIMARY KEY,
+    sha256 TEXT NOT NULL UNIQUE,
+    body   BLOB NOT NULL
+);
+-- The chunks of a blob, in order
+CREATE TABLE blob_chunks (
+    blob_id  INTEGER NOT NULL,
+    seq      INTEGER NOT NULL,
+    chunk_id INTEGER NOT NULL,
+    PRIMARY KEY (blob_id, seq)
+);
+CREATE INDEX blob_chunks_chunk ON blob_chunks (chunk_id);
+
+-- Chunks go away along with the last blob using them
+CREATE TRIGGER blobs_chunks AFTER DELETE ON blobs
+BEGIN
+    DELETE FROM blob_chunks WHERE blob_id = OLD.id;
+END;
+CREATE TRIGGER blob_chunks_unused AFTER DELETE ON blob_chunks
+BEGIN
+    DELETE FROM chunks WHERE id = OLD.chunk_id
+    AND NOT EXISTS (SELECT 1 FROM blob_chunks WHERE chunk_id = OLD.chunk_id);
+END;
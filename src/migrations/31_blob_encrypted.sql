@@ -0,0 +1,38 @@
+-- Scripts and saved files can be stored encrypted with gpg, in which case `encrypted` is 1. The
+-- body stays the last column, see 29_blob_body_last.sql.
+DROP TRIGGER tree_scripts_former_owner;
+DROP TRIGGER tree_files_former_owner;
+
+CREATE TABLE blobs_new (
+    id           INTEGER PRIMARY KEY,
+    signature    BLOB,
+    sha256       TEXT,
+    former_root  TEXT,
+    former_name  TEXT,
+    chunked_size INTEGER,
+    encrypted    INTEGER NOT NULL DEFAULT 0,
+    body         BLOB
+);
+INSERT INTO blobs_new (id, signature, sha256, former_root, former_name, chunked_size, body)
+SELECT id, signature, sha256, former_root, former_name, chunked_size, body FROM blobs;
+DROP TABLE blobs;
+ALTER TABLE blobs_new RENAME TO blobs;
+
+CREATE TRIGGER tree_scripts_former_owner AFTER DELETE ON tree_scripts
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
+CREATE TRIGGER tree_files_former_owner AFTER DELETE ON tree_files
+BEGIN
+    UPDATE blobs
+    SET former_root = (SELECT root FROM trees WHERE _rowid_ = OLD.tree_id),
+        former_name = OLD.name
+    WHERE _rowid_ = OLD.blob_id;
+END;
+CREATE TRIGGER blobs_chunks AFTER DELETE ON blobs
+BEGIN
+    DELETE FROM blob_chunks WHERE blob_id = OLD.id;
+END;
@@ -0,0 +1,2 @@
+-- Free-form tags for grouping trees, separated by commas like the tags of scripts
+ALTER TABLE trees ADD COLUMN tags TEXT;
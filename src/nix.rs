@@ -0,0 +1,110 @@
+//! Running scripts in the nix development environment of their tree
+
+use {
+    crate::error::bail,
+    std::{
+        ffi::{OsStr, OsString},
+        os::unix::ffi::{OsStrExt, OsStringExt},
+        path::Path,
+        process::Command,
+        str::FromStr,
+    },
+};
+
+/// How the development environment of a tree is entered
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DevShell {
+    /// `nix develop`, using the flake of the tree
+    Develop,
+    /// `nix-shell`, using the `shell.nix` or `default.nix` of the tree
+    Shell,
+}
+
+impl DevShell {
+    pub const NAMES: [&str; 2] = ["develop", "shell"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Develop => Self::NAMES[0],
+            Self::Shell => Self::NAMES[1],
+        }
+    }
+
+    /// Fails with a helpful message if the nix command needed for this is not installed
+    pub(crate) fn check_available(self) -> crate::Result<()> {
+        let program = match self {
+            Self::Develop => "nix",
+            Self::Shell => "nix-shell",
+        };
+        match Command::new(program).arg("--version").output() {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                bail!("The dev shell of this tree needs {program} to be installed")
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns a command that runs `cmd` inside of the development environment of the tree
+    pub(crate) fn wrap(self, cmd: &Command, tree_root: &Path) -> Command {
+        let mut wrapped = match self {
+            Self::Develop => {
+                let mut wrapped = Command::new("nix");
+                wrapped
+                    .arg("develop")
+                    .arg(tree_root)
+                    .arg("-c")
+                    .arg(cmd.get_program())
+                    .args(cmd.get_args());
+                wrapped
+            }
+            Self::Shell => {
+                // `--run` takes a shell command, so everything has to be quoted
+                let mut line = OsString::from("exec");
+                for arg in std::iter::once(cmd.get_program()).chain(cmd.get_args()) {
+                    line.push(" ");
+                    line.push(quote(arg));
+                }
+                let mut wrapped = Command::new("nix-shell");
+                wrapped.arg(tree_root).arg("--run").arg(line);
+                wrapped
+            }
+        };
+        for (key, val) in cmd.get_envs() {
+            match val {
+                Some(val) => wrapped.env(key, val),
+                None => wrapped.env_remove(key),
+            };
+        }
+        wrapped
+    }
+}
+
+impl FromStr for DevShell {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "develop" => Ok(Self::Develop),
+            "shell" => Ok(Self::Shell),
+            _ => bail!(
+                "Unknown dev shell '{s}', expected one of: {}",
+                Self::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+/// Quotes `arg` for a POSIX shell, without requiring it to be valid UTF-8
+fn quote(arg: &OsStr) -> OsString {
+    let mut quoted = vec![b'\''];
+    for &b in arg.as_bytes() {
+        if b == b'\'' {
+            quoted.extend_from_slice(br"'\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+    OsString::from_vec(quoted)
+}
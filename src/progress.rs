@@ -0,0 +1,29 @@
+//! Progress reports of long operations, for showing how far along they are
+
+/// How far along a long operation is, reported after each item it processed
+pub struct Progress<'a> {
+    /// What is being done, like "Cloning"
+    pub action: &'a str,
+    /// How many items were processed so far, including `item`
+    pub done: usize,
+    pub total: usize,
+    /// Name of the item that was just processed
+    pub item: &'a str,
+}
+
+/// Receives the progress reports of long operations.
+///
+/// Implemented for closures taking a [`Progress`], and for `()`, which ignores them.
+pub trait Observer {
+    fn progress(&mut self, progress: Progress);
+}
+
+impl<F: FnMut(Progress)> Observer for F {
+    fn progress(&mut self, progress: Progress) {
+        self(progress)
+    }
+}
+
+impl Observer for () {
+    fn progress(&mut self, _progress: Progress) {}
+}
@@ -1,29 +1,663 @@
-use std::{
-    ffi::OsStr,
-    io::Write,
-    os::{fd::FromRawFd, unix::process::CommandExt},
-    process::Command,
+//! Running stored scripts, see [`ScriptRunner`] for running them from other programs
+
+use {
+    crate::{database::Database, nix::DevShell, sandbox::Sandbox},
+    std::{
+        ffi::{c_int, OsStr, OsString},
+        fs::File,
+        io::{BufRead, BufReader, Read, Write},
+        os::{
+            fd::FromRawFd,
+            unix::process::{CommandExt, ExitStatusExt},
+        },
+        path::{Path, PathBuf},
+        process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio},
+        sync::{
+            atomic::{AtomicI32, Ordering},
+            Arc, Mutex,
+        },
+        thread::JoinHandle,
+        time::{Duration, Instant},
+    },
 };
 
-pub(crate) fn run_script(
+/// What a script runs inside of, instead of directly on the host
+pub(crate) enum Isolation {
+    Sandbox(Sandbox),
+    /// A container created from the image with this name
+    Container(String),
+}
+
+/// Everything a script runs inside of
+pub(crate) struct Wrappers {
+    pub isolation: Option<Isolation>,
+    /// Ignored for containers, which bring their own environment
+    pub dev_shell: Option<DevShell>,
+    /// Environment variables set for the script
+    pub env: Vec<(String, String)>,
+    /// Directory the script runs in, instead of the current one
+    pub cwd: Option<PathBuf>,
+}
+
+/// The tree a script runs for
+pub(crate) struct Tree<'a> {
+    pub id: i64,
+    pub root: &'a Path,
+    /// Path of the database the script is stored in
+    pub db_path: Option<&'a Path>,
+}
+
+/// Creates a command that executes `script` from an in-memory file.
+///
+/// The script can find out about its context from the `OTKEEP_*` environment variables.
+///
+/// The returned file backs the script, so it must be kept alive until the command has started.
+fn script_command(
+    name: &str,
     script: &[u8],
     args: impl Iterator<Item = impl AsRef<OsStr>>,
-    tree_root: impl AsRef<OsStr>,
-) -> anyhow::Result<!> {
+    tree: &Tree,
+    wrappers: &Wrappers,
+) -> crate::Result<(Command, File)> {
     extern "C" {
         fn memfd_create(name: *const std::ffi::c_char, flags: std::ffi::c_uint) -> std::ffi::c_int;
     }
     let fd = unsafe { memfd_create(c"otkeep-script".as_ptr(), 0) };
     if fd == -1 {
-        anyhow::bail!("memfd_create failed when trying to create script file");
+        crate::error::bail!("memfd_create failed when trying to create script file");
     }
-    let mut f = unsafe { std::fs::File::from_raw_fd(fd) };
+    let mut f = unsafe { File::from_raw_fd(fd) };
     f.write_all(script)?;
     f.flush()?;
-    let err = Command::new(format!("/proc/self/fd/{fd}"))
-        .env("OTKEEP_TREE_ROOT", tree_root)
-        .args(args)
-        .exec()
-        .into();
+    let path = PathBuf::from(format!("/proc/self/fd/{fd}"));
+    let cwd = match &wrappers.cwd {
+        Some(cwd) => cwd.clone(),
+        None => std::env::current_dir()?,
+    };
+    // Mirror the execvp fallback for scripts without a shebang, which spawning doesn't do
+    let (program, leading_args) = if script.starts_with(b"#!") {
+        (path.as_path(), vec![])
+    } else {
+        (Path::new("/bin/sh"), vec![path.as_path()])
+    };
+    let mut cmd = match &wrappers.isolation {
+        Some(Isolation::Sandbox(sandbox)) => {
+            sandbox.command(tree.root, &cwd, program, &leading_args)?
+        }
+        Some(Isolation::Container(image)) => {
+            crate::container::command(image, script, tree.root, &cwd, &wrappers.env)?
+        }
+        None => {
+            let mut cmd = Command::new(program);
+            cmd.args(leading_args);
+            cmd
+        }
+    };
+    cmd.args(args);
+    match (&wrappers.isolation, wrappers.dev_shell) {
+        (Some(Isolation::Container(_)), _) | (_, None) => {}
+        (_, Some(dev_shell)) => cmd = dev_shell.wrap(&cmd, tree.root),
+    }
+    cmd.envs(wrappers.env.iter().map(|(key, val)| (key, val)))
+        .env("OTKEEP_TREE_ROOT", tree.root)
+        .env("OTKEEP_TREE_ID", tree.id.to_string())
+        .env("OTKEEP_SCRIPT_NAME", name)
+        .env("OTKEEP_INVOCATION_DIR", &cwd);
+    if wrappers.cwd.is_some() {
+        cmd.current_dir(&cwd);
+    }
+    if let Some(db_path) = tree.db_path {
+        cmd.env("OTKEEP_DB_PATH", db_path);
+    }
+    Ok((cmd, f))
+}
+
+pub(crate) fn run_script(
+    name: &str,
+    script: &[u8],
+    args: impl Iterator<Item = impl AsRef<OsStr>>,
+    tree: &Tree,
+    wrappers: &Wrappers,
+) -> crate::Result<!> {
+    let (mut cmd, _f) = script_command(name, script, args, tree, wrappers)?;
+    let err = cmd.exec().into();
     Err(err)
 }
+
+/// How a standard stream of a script started by a [`ScriptRunner`] is connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdioMode {
+    /// Shared with our process
+    Inherit,
+    /// Connected to `/dev/null`
+    Null,
+    /// Connected to a pipe, which is available from the [`RunHandle`]
+    Piped,
+    /// Read into memory, and returned in the [`Output`]. Same as `Null` for stdin.
+    Capture,
+}
+
+impl StdioMode {
+    fn stdio(self, stdin: bool) -> Stdio {
+        match self {
+            Self::Inherit => Stdio::inherit(),
+            Self::Null => Stdio::null(),
+            Self::Capture if stdin => Stdio::null(),
+            Self::Piped | Self::Capture => Stdio::piped(),
+        }
+    }
+}
+
+/// Runs a stored script as a child process, for programs using OtKeep as a library.
+///
+/// Works like [`Command`]: set up how the script runs, then [`spawn`](Self::spawn) it, or wait
+/// for it with [`output`](Self::output). The script runs just like with `otrun`, inside of its
+/// sandbox, container or dev shell, and the run is recorded in its history. Scripts that haven't
+/// been trusted yet fail with [`Error::UntrustedScript`](crate::error::Error::UntrustedScript)
+/// instead of asking.
+pub struct ScriptRunner<'db> {
+    db: &'db Database,
+    tree_id: i64,
+    name: String,
+    args: Vec<OsString>,
+    env: Vec<(String, String)>,
+    cwd: Option<PathBuf>,
+    stdin: StdioMode,
+    stdout: StdioMode,
+    stderr: StdioMode,
+    timeout: Option<Duration>,
+}
+
+impl<'db> ScriptRunner<'db> {
+    /// Prepares running the script `name` of the tree `tree_id`, with all streams inherited
+    pub fn new(db: &'db Database, tree_id: i64, name: &str) -> Self {
+        Self {
+            db,
+            tree_id,
+            name: name.to_owned(),
+            args: Vec::new(),
+            env: Vec::new(),
+            cwd: None,
+            stdin: StdioMode::Inherit,
+            stdout: StdioMode::Inherit,
+            stderr: StdioMode::Inherit,
+            timeout: None,
+        }
+    }
+
+    /// Passes an argument to the script
+    pub fn arg(&mut self, arg: impl AsRef<OsStr>) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Passes several arguments to the script
+    pub fn args(&mut self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> &mut Self {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets an environment variable for the script.
+    ///
+    /// These take precedence over the variables of [`Database::set_run_env`].
+    pub fn env(&mut self, key: impl Into<String>, val: impl Into<String>) -> &mut Self {
+        self.env.push((key.into(), val.into()));
+        self
+    }
+
+    /// Runs the script in `dir`, instead of the current directory
+    pub fn current_dir(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    pub fn stdin(&mut self, mode: StdioMode) -> &mut Self {
+        self.stdin = mode;
+        self
+    }
+
+    pub fn stdout(&mut self, mode: StdioMode) -> &mut Self {
+        self.stdout = mode;
+        self
+    }
+
+    pub fn stderr(&mut self, mode: StdioMode) -> &mut Self {
+        self.stderr = mode;
+        self
+    }
+
+    /// Kills the script if it's still running after `timeout`, when waiting for it.
+    ///
+    /// The script then runs in its own process group, so everything it started is killed as
+    /// well. That also means it can't read from the terminal.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Starts the script, without waiting for it
+    pub fn spawn(&self) -> crate::Result<RunHandle<'db>> {
+        let (script, mut wrappers) = self.db.fetch_script_for_run(self.tree_id, &self.name)?;
+        wrappers.env.extend(self.env.iter().cloned());
+        wrappers.cwd.clone_from(&self.cwd);
+        let root = self.db.query_tree_root(self.tree_id)?;
+        let (mut cmd, _f) = script_command(
+            &self.name,
+            &script,
+            self.args.iter(),
+            &self.db.run_tree(self.tree_id, &root),
+            &wrappers,
+        )?;
+        cmd.stdin(self.stdin.stdio(true))
+            .stdout(self.stdout.stdio(false))
+            .stderr(self.stderr.stdio(false));
+        if self.timeout.is_some() {
+            cmd.process_group(0);
+        }
+        let started = crate::database::unix_now();
+        let start = Instant::now();
+        let mut child = cmd.spawn()?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let (stdout, captured_stdout) = match self.stdout {
+            StdioMode::Capture => (None, stdout.map(capture)),
+            _ => (stdout, None),
+        };
+        let (stderr, captured_stderr) = match self.stderr {
+            StdioMode::Capture => (None, stderr.map(capture)),
+            _ => (stderr, None),
+        };
+        Ok(RunHandle {
+            stdin: child.stdin.take(),
+            stdout,
+            stderr,
+            db: self.db,
+            tree_id: self.tree_id,
+            name: self.name.clone(),
+            child,
+            started,
+            start,
+            deadline: self.timeout.map(|timeout| start + timeout),
+            captured_stdout,
+            captured_stderr,
+        })
+    }
+
+    /// Runs the script and waits for it to finish
+    pub fn output(&self) -> crate::Result<Output> {
+        self.spawn()?.wait()
+    }
+}
+
+/// Reads everything from `src` on another thread
+fn capture(mut src: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = src.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// A script started by [`ScriptRunner::spawn`]
+pub struct RunHandle<'db> {
+    /// Input of the script, if it's [`StdioMode::Piped`]
+    pub stdin: Option<ChildStdin>,
+    /// Output of the script, if it's [`StdioMode::Piped`]
+    pub stdout: Option<ChildStdout>,
+    /// Error output of the script, if it's [`StdioMode::Piped`]
+    pub stderr: Option<ChildStderr>,
+    db: &'db Database,
+    tree_id: i64,
+    name: String,
+    child: Child,
+    started: i64,
+    start: Instant,
+    deadline: Option<Instant>,
+    captured_stdout: Option<JoinHandle<Vec<u8>>>,
+    captured_stderr: Option<JoinHandle<Vec<u8>>>,
+}
+
+/// How often to check whether a script with a timeout has finished
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl RunHandle<'_> {
+    /// The process id of the script
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Kills the script, along with its process group if it has a timeout
+    pub fn kill(&mut self) -> crate::Result<()> {
+        if self.deadline.is_some() {
+            if unsafe { libc::kill(-(self.child.id() as libc::pid_t), libc::SIGKILL) } == -1 {
+                let err = std::io::Error::last_os_error();
+                // The script already finished
+                if err.raw_os_error() != Some(libc::ESRCH) {
+                    return Err(err.into());
+                }
+            }
+            return Ok(());
+        }
+        Ok(self.child.kill()?)
+    }
+
+    /// Closes the input of the script, and waits for it to finish.
+    ///
+    /// Kills the script if it runs past its timeout.
+    pub fn wait(mut self) -> crate::Result<Output> {
+        drop(self.stdin.take());
+        let mut timed_out = false;
+        let status = match self.deadline {
+            None => self.child.wait()?,
+            Some(deadline) => loop {
+                if let Some(status) = self.child.try_wait()? {
+                    break status;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    timed_out = true;
+                    self.kill()?;
+                    break self.child.wait()?;
+                }
+                std::thread::sleep(TIMEOUT_POLL_INTERVAL.min(deadline - now));
+            },
+        };
+        let join = |capture: Option<JoinHandle<Vec<u8>>>| {
+            capture
+                .and_then(|capture| capture.join().ok())
+                .unwrap_or_default()
+        };
+        let output = Output {
+            status,
+            stdout: join(self.captured_stdout),
+            stderr: join(self.captured_stderr),
+            timed_out,
+        };
+        self.db.record_run(
+            self.tree_id,
+            &self.name,
+            self.started,
+            self.start.elapsed(),
+            status,
+            None,
+        )?;
+        Ok(output)
+    }
+}
+
+/// How a script started by a [`ScriptRunner`] finished
+#[derive(Debug)]
+pub struct Output {
+    pub status: ExitStatus,
+    /// Everything the script wrote to stdout, if it's [`StdioMode::Capture`]
+    pub stdout: Vec<u8>,
+    /// Everything the script wrote to stderr, if it's [`StdioMode::Capture`]
+    pub stderr: Vec<u8>,
+    /// Whether the script was killed because it ran past its timeout
+    pub timed_out: bool,
+}
+
+/// Signals that are forwarded to the process group of a supervised script
+const FORWARDED_SIGNALS: [c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+/// Process group of the running supervised script
+static CHILD_PGID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(sig: c_int) {
+    let pgid = CHILD_PGID.load(Ordering::Relaxed);
+    if pgid > 0 {
+        unsafe { libc::kill(-pgid, sig) };
+    }
+}
+
+/// Runs `script` as a child process and waits for it to finish, see [`supervise`]
+pub(crate) fn run_script_supervised(
+    name: &str,
+    script: &[u8],
+    args: impl Iterator<Item = impl AsRef<OsStr>>,
+    tree: &Tree,
+    wrappers: &Wrappers,
+) -> crate::Result<ExitStatus> {
+    let (cmd, _f) = script_command(name, script, args, tree, wrappers)?;
+    supervise(cmd, |_| {})
+}
+
+/// Like [`run_script_supervised`], but also returns everything the script wrote to stdout and
+/// stderr.
+///
+/// The output still goes to our stdout and stderr as well, but through pipes, so the script
+/// doesn't see a terminal there.
+pub(crate) fn run_script_logged(
+    name: &str,
+    script: &[u8],
+    args: impl Iterator<Item = impl AsRef<OsStr>>,
+    tree: &Tree,
+    wrappers: &Wrappers,
+) -> crate::Result<(ExitStatus, Vec<u8>)> {
+    let (mut cmd, _f) = script_command(name, script, args, tree, wrappers)?;
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut readers = Vec::new();
+    let status = supervise(cmd, |child| {
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let out_log = log.clone();
+        let err_log = log.clone();
+        readers.push(std::thread::spawn(move || {
+            tee(stdout, std::io::stdout(), &out_log)
+        }));
+        readers.push(std::thread::spawn(move || {
+            tee(stderr, std::io::stderr(), &err_log)
+        }));
+    });
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let log = std::mem::take(&mut *log.lock().unwrap_or_else(|e| e.into_inner()));
+    Ok((status?, log))
+}
+
+/// Spawns `cmd` in its own process group, and waits for it to finish.
+///
+/// The group gets the terminal if we have it. Signals sent to us are forwarded to the whole
+/// group, so nothing is left orphaned. `on_spawn` is called with the child right after spawning.
+fn supervise(mut cmd: Command, on_spawn: impl FnOnce(&mut Child)) -> crate::Result<ExitStatus> {
+    let terminal = controlling_terminal();
+    cmd.process_group(0);
+    if let Some(fd) = terminal {
+        // Also done by the parent, but the script could otherwise start reading before that
+        unsafe {
+            cmd.pre_exec(move || {
+                set_foreground(fd, libc::getpid());
+                Ok(())
+            });
+        }
+    }
+    let mut child = cmd.spawn()?;
+    on_spawn(&mut child);
+    let pgid = child.id() as libc::pid_t;
+    CHILD_PGID.store(pgid, Ordering::Relaxed);
+    for sig in FORWARDED_SIGNALS {
+        unsafe { libc::signal(sig, forward_signal as *const () as libc::sighandler_t) };
+    }
+    if let Some(fd) = terminal {
+        set_foreground(fd, pgid);
+    }
+    let result = wait_for(pgid, terminal);
+    if let Some(fd) = terminal {
+        set_foreground(fd, unsafe { libc::getpgrp() });
+    }
+    for sig in FORWARDED_SIGNALS {
+        unsafe { libc::signal(sig, libc::SIG_DFL) };
+    }
+    CHILD_PGID.store(0, Ordering::Relaxed);
+    result
+}
+
+/// Runs several scripts at the same time, and waits for all of them to finish. Returns how each
+/// of them exited, and how long it ran.
+///
+/// Every line the scripts output is prefixed with the name of the script. The scripts share a
+/// process group that signals are forwarded to, but they don't get the terminal or stdin. If one
+/// of them can't be started, the ones that already were are terminated.
+pub(crate) fn run_scripts_parallel(
+    scripts: &[(String, Vec<u8>, Wrappers)],
+    tree: &Tree,
+) -> crate::Result<Vec<(ExitStatus, Duration)>> {
+    let width = scripts
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut waiters = Vec::new();
+    let mut files = Vec::new();
+    let mut pgid = 0;
+    let mut spawned = Ok(());
+    for (name, script, wrappers) in scripts {
+        let (mut cmd, f) =
+            match script_command(name, script, std::iter::empty::<&str>(), tree, wrappers) {
+                Ok(command) => command,
+                Err(e) => {
+                    spawned = Err(e);
+                    break;
+                }
+            };
+        cmd.process_group(pgid)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let start = Instant::now();
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                spawned = Err(e.into());
+                break;
+            }
+        };
+        if pgid == 0 {
+            pgid = child.id() as libc::pid_t;
+            CHILD_PGID.store(pgid, Ordering::Relaxed);
+            for sig in FORWARDED_SIGNALS {
+                unsafe { libc::signal(sig, forward_signal as *const () as libc::sighandler_t) };
+            }
+        }
+        let prefix = format!("[{name:width$}] ");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let out_prefix = prefix.clone();
+        let readers = [
+            std::thread::spawn(move || copy_prefixed(stdout, std::io::stdout(), &out_prefix)),
+            std::thread::spawn(move || copy_prefixed(stderr, std::io::stderr(), &prefix)),
+        ];
+        // Waited for on its own thread, so the duration is right whichever script ends first
+        waiters.push(std::thread::spawn(move || {
+            let status = child.wait();
+            let duration = start.elapsed();
+            for reader in readers {
+                let _ = reader.join();
+            }
+            status.map(|status| (status, duration))
+        }));
+        files.push(f);
+    }
+    drop(files);
+    if spawned.is_err() && pgid != 0 {
+        unsafe { libc::kill(-pgid, libc::SIGTERM) };
+    }
+    let mut results = Vec::new();
+    let mut result = Ok(());
+    for waiter in waiters {
+        match waiter.join().expect("waiter thread panicked") {
+            Ok(outcome) => results.push(outcome),
+            Err(e) => result = Err(e),
+        }
+    }
+    for sig in FORWARDED_SIGNALS {
+        unsafe { libc::signal(sig, libc::SIG_DFL) };
+    }
+    CHILD_PGID.store(0, Ordering::Relaxed);
+    spawned?;
+    result?;
+    Ok(results)
+}
+
+/// Copies `src` to `dst` line by line, putting `prefix` in front of every line
+fn copy_prefixed(src: impl Read, dst: impl Write, prefix: &str) {
+    let mut dst = dst;
+    for line in BufReader::new(src).split(b'\n') {
+        let Ok(line) = line else { break };
+        let mut buf = prefix.as_bytes().to_vec();
+        buf.extend_from_slice(&line);
+        buf.push(b'\n');
+        // Written at once, so lines from different scripts don't get mixed up
+        if dst.write_all(&buf).is_err() {
+            break;
+        }
+    }
+}
+
+/// Copies `src` to `dst` as it comes in, and appends it to `log` as well
+fn tee(mut src: impl Read, mut dst: impl Write, log: &Mutex<Vec<u8>>) {
+    let mut buf = [0; 8192];
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        // Keep reading if we can't write, so the script doesn't block on a full pipe
+        let _ = dst.write_all(&buf[..n]).and_then(|_| dst.flush());
+        log.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Waits for the script to exit.
+///
+/// If the script is stopped (e.g. by Ctrl+Z), we stop as well so the shell regains control,
+/// and continue the script once we're continued.
+fn wait_for(pid: libc::pid_t, terminal: Option<c_int>) -> crate::Result<ExitStatus> {
+    loop {
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) } == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err.into());
+        }
+        if !libc::WIFSTOPPED(status) {
+            return Ok(ExitStatus::from_raw(status));
+        }
+        unsafe {
+            if let Some(fd) = terminal {
+                set_foreground(fd, libc::getpgrp());
+            }
+            libc::raise(libc::SIGSTOP);
+            if let Some(fd) = terminal {
+                set_foreground(fd, pid);
+            }
+            libc::kill(-pid, libc::SIGCONT);
+        }
+    }
+}
+
+/// Returns a standard stream connected to our controlling terminal, if we're in its foreground
+fn controlling_terminal() -> Option<c_int> {
+    (0..=2).find(|&fd| unsafe { libc::isatty(fd) == 1 && libc::tcgetpgrp(fd) == libc::getpgrp() })
+}
+
+/// Makes `pgid` the foreground process group of the terminal.
+///
+/// Only calls async-signal-safe functions, so it can be used before exec.
+fn set_foreground(terminal: c_int, pgid: libc::pid_t) {
+    unsafe {
+        // Changing the foreground group from the background would stop us otherwise
+        let old = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(terminal, pgid);
+        libc::signal(libc::SIGTTOU, old);
+    }
+}
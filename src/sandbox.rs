@@ -0,0 +1,93 @@
+//! Running scripts in a bubblewrap sandbox, so they can only write to their tree
+
+use {
+    crate::error::{bail, Context},
+    std::{path::Path, process::Command, str::FromStr},
+};
+
+/// How much of the filesystem a sandboxed script can see
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Sandbox {
+    /// The whole filesystem is visible, but only the tree root is writable
+    ReadOnly,
+    /// Only the tree root and the system directories needed to run programs are visible,
+    /// and only the tree root is writable
+    Hidden,
+}
+
+impl Sandbox {
+    pub const NAMES: [&str; 2] = ["read-only", "hidden"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ReadOnly => Self::NAMES[0],
+            Self::Hidden => Self::NAMES[1],
+        }
+    }
+
+    /// Returns a command running `program` with `args` inside of the sandbox.
+    ///
+    /// It runs in `cwd` if that's inside of the tree root, and in the tree root otherwise.
+    pub(crate) fn command(
+        self,
+        tree_root: &Path,
+        cwd: &Path,
+        program: &Path,
+        args: &[&Path],
+    ) -> crate::Result<Command> {
+        let root = tree_root
+            .canonicalize()
+            .with_context(|| format!("Can't access tree root {}", tree_root.display()))?;
+        let mut cmd = Command::new("bwrap");
+        match self {
+            Self::ReadOnly => {
+                cmd.args(["--ro-bind", "/", "/"]);
+            }
+            Self::Hidden => {
+                for dir in SYSTEM_DIRS {
+                    cmd.args(["--ro-bind-try", dir, dir]);
+                }
+            }
+        }
+        cmd.args(["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"])
+            .arg("--bind")
+            .args([&root, &root])
+            .arg("--chdir")
+            .arg(if cwd.starts_with(&root) { cwd } else { &root })
+            .args(["--unshare-pid", "--die-with-parent", "--"])
+            .arg(program)
+            .args(args);
+        Ok(cmd)
+    }
+}
+
+impl FromStr for Sandbox {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "read-only" => Ok(Self::ReadOnly),
+            "hidden" => Ok(Self::Hidden),
+            _ => bail!(
+                "Unknown sandbox '{s}', expected one of: {}",
+                Self::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+/// Directories visible in [`Sandbox::Hidden`], if they exist
+const SYSTEM_DIRS: [&str; 8] = [
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/nix",
+];
+
+/// Fails with a helpful message if bubblewrap is not installed
+pub(crate) fn check_available() -> crate::Result<()> {
+    match Command::new("bwrap").arg("--version").output() {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("Sandboxed runs need bubblewrap (bwrap) to be installed")
+        }
+        Err(e) => Err(e.into()),
+    }
+}
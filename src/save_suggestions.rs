@@ -0,0 +1,124 @@
+//! Finding files that are worth saving with `okeep save`, among the ones git ignores.
+//!
+//! Ignored files that are neither build output nor logs are often local configuration, like `.env`
+//! files, which get lost when the working tree is cloned again.
+
+use {
+    crate::database::Database,
+    std::{collections::HashSet, io::Read, path::Path},
+};
+
+/// Larger files are most likely generated, not written by hand
+pub const MAX_SIZE: u64 = 64 * 1024;
+
+/// Directories that contain build output, dependencies or caches
+const BUILD_DIRS: &[&str] = &[
+    ".cache",
+    ".git",
+    ".venv",
+    "__pycache__",
+    "build",
+    "dist",
+    "node_modules",
+    "out",
+    "target",
+    "venv",
+];
+
+/// Extensions of files that are generated, or not worth keeping
+const NOISE_EXTENSIONS: &[&str] = &[
+    "a", "bak", "class", "log", "o", "orig", "pyc", "rej", "so", "swo", "swp", "tmp",
+];
+
+/// Extensions of configuration files
+const CONFIG_EXTENSIONS: &[&str] = &[
+    "cfg",
+    "conf",
+    "config",
+    "ini",
+    "json",
+    "properties",
+    "toml",
+    "yaml",
+    "yml",
+];
+
+/// An ignored file that looks worth saving
+pub struct Candidate {
+    /// Path relative to the current directory
+    pub path: String,
+    pub size: u64,
+    /// Why the file looks worth saving
+    pub reason: &'static str,
+}
+
+/// Returns the ignored files under the current directory that aren't saved in the tree yet, and
+/// look worth saving.
+///
+/// Ignored directories, binary files and files larger than [`MAX_SIZE`] are skipped. With `all`,
+/// every other small text file is returned too, not just the ones that look like local
+/// configuration.
+pub fn candidates(db: &Database, tree_id: i64, all: bool) -> crate::Result<Vec<Candidate>> {
+    let saved: HashSet<String> = db
+        .files_for_tree(tree_id)?
+        .into_iter()
+        .map(|file| file.name)
+        .collect();
+    let mut candidates = Vec::new();
+    for path in crate::git::ignored_files()? {
+        if path.ends_with('/') || saved.contains(&path) {
+            continue;
+        }
+        let p = Path::new(&path);
+        let in_build_dir = p
+            .components()
+            .any(|c| BUILD_DIRS.contains(&c.as_os_str().to_string_lossy().as_ref()));
+        let is_noise = p
+            .extension()
+            .is_some_and(|ext| NOISE_EXTENSIONS.contains(&ext.to_string_lossy().as_ref()));
+        if in_build_dir || is_noise {
+            continue;
+        }
+        let reason = match reason(p) {
+            Some(reason) => reason,
+            None if all => "ignored text file",
+            None => continue,
+        };
+        let Ok(meta) = p.symlink_metadata() else {
+            continue;
+        };
+        if !meta.is_file() || meta.len() == 0 || meta.len() > MAX_SIZE || is_binary(p)? {
+            continue;
+        }
+        candidates.push(Candidate {
+            path,
+            size: meta.len(),
+            reason,
+        });
+    }
+    Ok(candidates)
+}
+
+/// Tells why a file looks like local configuration, going by its name
+fn reason(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name == ".env" || name == ".envrc" || name.starts_with(".env.") || name.ends_with(".env") {
+        return Some("environment file");
+    }
+    if name.contains(".local") || name.starts_with("local.") || name.contains("override") {
+        return Some("local configuration");
+    }
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    CONFIG_EXTENSIONS
+        .contains(&ext.as_str())
+        .then_some("configuration file")
+}
+
+/// Whether the start of the file contains a NUL byte
+fn is_binary(path: &Path) -> crate::Result<bool> {
+    let mut start = Vec::new();
+    std::fs::File::open(path)?
+        .take(8192)
+        .read_to_end(&mut start)?;
+    Ok(start.contains(&0))
+}
@@ -0,0 +1,292 @@
+//! Running scripts periodically, with systemd user timers or cron jobs
+
+use {
+    crate::{
+        error::{bail, Context, Error},
+        shims::shell_quote,
+    },
+    directories::BaseDirs,
+    std::{
+        io::Write,
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+    },
+};
+
+/// What runs a scheduled script
+#[derive(Clone, Copy)]
+pub enum Scheduler {
+    /// A systemd user timer
+    Systemd,
+    /// A job in the crontab of the user
+    Cron,
+}
+
+impl std::fmt::Display for Scheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Systemd => "systemd",
+            Self::Cron => "cron",
+        })
+    }
+}
+
+/// A scheduled run of a script
+pub struct Schedule {
+    pub name: String,
+    /// When it runs, as a systemd calendar expression or a cron schedule
+    pub when: String,
+    pub scheduler: Scheduler,
+}
+
+/// Writes and enables a systemd user timer running `name` in `root` according to `calendar`.
+///
+/// `exe` is the path of the OtKeep multicall binary, and `tree_uuid` identifies the tree.
+pub fn install_systemd(
+    exe: &Path,
+    root: &Path,
+    tree_uuid: &str,
+    name: &str,
+    calendar: &str,
+) -> crate::Result<()> {
+    if let Ok(status) = Command::new("systemd-analyze")
+        .args(["calendar", calendar])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        if !status.success() {
+            bail!("'{calendar}' is not a valid systemd calendar expression");
+        }
+    }
+    let dir = systemd_user_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let unit = unit_name(tree_uuid, name);
+    let markers = format!("{}\n{}\n", tree_marker(tree_uuid), script_marker(name));
+    let root = root.to_str().context("Tree root is not valid UTF-8")?;
+    let exe = exe.to_str().context("Executable path is not valid UTF-8")?;
+    let description = format!("OtKeep script {name} of {root}").replace('%', "%%");
+    let service = format!(
+        "{markers}[Unit]\nDescription={description}\n\n\
+         [Service]\nType=oneshot\nWorkingDirectory={}\nExecStart={} otrun --log {}\n",
+        root.replace('%', "%%"),
+        systemd_quote(exe),
+        systemd_quote(name)
+    );
+    let timer = format!(
+        "{markers}[Unit]\nDescription=Run {description}\n\n\
+         [Timer]\nOnCalendar={calendar}\nPersistent=true\n\n\
+         [Install]\nWantedBy=timers.target\n"
+    );
+    std::fs::write(dir.join(format!("{unit}.service")), service)?;
+    std::fs::write(dir.join(format!("{unit}.timer")), timer)?;
+    systemctl(&["daemon-reload"])
+        .and_then(|_| systemctl(&["enable", "--now", &format!("{unit}.timer")]))
+        .with_context(|| {
+            format!(
+                "The units were written to {}, but enabling them failed",
+                dir.display()
+            )
+        })
+}
+
+/// Adds a job running `name` in `root` according to the cron schedule `when` to the crontab
+pub fn install_cron(
+    exe: &Path,
+    root: &Path,
+    tree_uuid: &str,
+    name: &str,
+    when: &str,
+) -> crate::Result<()> {
+    if when.contains('\n') {
+        bail!("The cron schedule can't contain newlines");
+    }
+    let command = format!(
+        "cd {} && {} otrun --log {}",
+        shell_quote(&root.to_string_lossy()),
+        shell_quote(&exe.to_string_lossy()),
+        shell_quote(name)
+    );
+    // `%` starts standard input in crontab commands
+    let command = command.replace('%', r"\%");
+    let mut crontab = read_crontab()?;
+    crontab.push_str(&format!(
+        "{} {name}\n{when} {command}\n",
+        tree_marker(tree_uuid)
+    ));
+    write_crontab(&crontab)
+}
+
+/// Returns the scheduled runs of the scripts of a tree
+pub fn list(tree_uuid: &str) -> crate::Result<Vec<Schedule>> {
+    let mut schedules = Vec::new();
+    let dir = systemd_user_dir()?;
+    if dir.exists() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !is_own_unit(&path, tree_uuid, "timer") {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path)?;
+            let mut lines = content.lines().skip(1);
+            let Some(name) = lines.next().and_then(|l| l.strip_prefix(SCRIPT_MARKER)) else {
+                continue;
+            };
+            let when = content
+                .lines()
+                .find_map(|l| l.strip_prefix("OnCalendar="))
+                .unwrap_or_default();
+            schedules.push(Schedule {
+                name: name.to_owned(),
+                when: when.to_owned(),
+                scheduler: Scheduler::Systemd,
+            });
+        }
+    }
+    let crontab = read_crontab()?;
+    let mut lines = crontab.lines();
+    let marker = format!("{} ", tree_marker(tree_uuid));
+    while let Some(line) = lines.next() {
+        if let Some(name) = line.strip_prefix(&marker) {
+            let job = lines.next().unwrap_or_default();
+            let when = job.split(" cd '").next().unwrap_or_default();
+            schedules.push(Schedule {
+                name: name.to_owned(),
+                when: when.to_owned(),
+                scheduler: Scheduler::Cron,
+            });
+        }
+    }
+    schedules.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(schedules)
+}
+
+/// Removes the scheduled runs of `name`, and returns how many were removed
+pub fn remove(tree_uuid: &str, name: &str) -> crate::Result<usize> {
+    let mut removed = 0;
+    let dir = systemd_user_dir()?;
+    let unit = unit_name(tree_uuid, name);
+    let timer = dir.join(format!("{unit}.timer"));
+    if is_own_unit(&timer, tree_uuid, "timer") {
+        if let Err(e) = systemctl(&["disable", "--now", &format!("{unit}.timer")]) {
+            eprintln!("Failed to disable {unit}.timer: {e}");
+        }
+        std::fs::remove_file(&timer)?;
+        let service = dir.join(format!("{unit}.service"));
+        if is_own_unit(&service, tree_uuid, "service") {
+            std::fs::remove_file(service)?;
+        }
+        let _ = systemctl(&["daemon-reload"]);
+        removed += 1;
+    }
+    let crontab = read_crontab()?;
+    let marker = format!("{} {name}", tree_marker(tree_uuid));
+    let mut kept = String::new();
+    let mut removed_jobs = 0;
+    let mut lines = crontab.lines();
+    while let Some(line) = lines.next() {
+        if line == marker {
+            // Also skip the job itself
+            lines.next();
+            removed_jobs += 1;
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    if removed_jobs > 0 {
+        write_crontab(&kept)?;
+    }
+    Ok(removed + removed_jobs)
+}
+
+const SCRIPT_MARKER: &str = "# Script: ";
+
+fn tree_marker(tree_uuid: &str) -> String {
+    format!("# Generated by okeep schedule for tree {tree_uuid}")
+}
+
+fn script_marker(name: &str) -> String {
+    format!("{SCRIPT_MARKER}{name}")
+}
+
+/// Whether `path` is a unit of the given type generated for the tree
+fn is_own_unit(path: &Path, tree_uuid: &str, ext: &str) -> bool {
+    path.extension().is_some_and(|e| e == ext)
+        && std::fs::read_to_string(path)
+            .is_ok_and(|content| content.lines().next() == Some(tree_marker(tree_uuid).as_str()))
+}
+
+fn systemd_user_dir() -> crate::Result<PathBuf> {
+    let dirs = BaseDirs::new().context("Failed to get the home directory")?;
+    Ok(dirs.config_dir().join("systemd/user"))
+}
+
+/// Name of the units of a script, without the extension.
+///
+/// Characters that aren't allowed in unit names are escaped as `_xx`, with their hex code.
+fn unit_name(tree_uuid: &str, name: &str) -> String {
+    let mut unit = format!("otkeep-{}-", &tree_uuid[..tree_uuid.len().min(8)]);
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' {
+            unit.push(b as char);
+        } else {
+            unit.push_str(&format!("_{b:02x}"));
+        }
+    }
+    unit
+}
+
+/// Quotes `s` as a single argument in a systemd unit file
+fn systemd_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+fn systemctl(args: &[&str]) -> crate::Result<()> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "systemctl --user {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the crontab of the user, which is empty if there is none
+fn read_crontab() -> crate::Result<String> {
+    match Command::new("crontab").arg("-l").output() {
+        // `crontab -l` fails if there is no crontab yet
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout)
+            .map_err(|_| Error::Other("The crontab is not valid UTF-8".into())),
+        Ok(_) => Ok(String::new()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_crontab(crontab: &str) -> crate::Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run crontab")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(crontab.as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("crontab failed to install the new crontab");
+    }
+    Ok(())
+}
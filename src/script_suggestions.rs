@@ -0,0 +1,261 @@
+//! Suggesting starter scripts for `okeep suggest`, going by the kind of project in the tree root.
+//!
+//! The project files are only read, never run. Make targets and package.json scripts are only
+//! suggested if the project defines them.
+
+use {
+    crate::database::Database,
+    std::{collections::HashSet, io::ErrorKind, path::Path},
+};
+
+/// A script that fits the project in the tree root
+pub struct Suggestion {
+    pub name: &'static str,
+    pub desc: String,
+    pub body: String,
+    /// The project file the suggestion is based on
+    pub source: &'static str,
+}
+
+/// The names of the suggested scripts, and the make targets or package.json scripts they can run
+const TASKS: &[(&str, &[&str])] = &[
+    ("build", &["build", "all"]),
+    ("test", &["test", "check"]),
+    ("fmt", &["fmt", "format"]),
+    ("lint", &["lint"]),
+    ("clean", &["clean"]),
+];
+
+/// Returns the scripts that fit the projects in `root`, leaving out the names the tree already has
+/// scripts with.
+///
+/// A Makefile comes first, since it usually wraps the other build tools, then Cargo.toml,
+/// package.json and flake.nix. Only the first suggestion with each name is kept.
+pub fn suggestions(db: &Database, tree_id: i64, root: &Path) -> crate::Result<Vec<Suggestion>> {
+    let mut all = Vec::new();
+    for makefile in ["GNUmakefile", "makefile", "Makefile"] {
+        if let Some(contents) = read(&root.join(makefile))? {
+            all.extend(make(makefile, &contents));
+            break;
+        }
+    }
+    if let Some(contents) = read(&root.join("Cargo.toml"))? {
+        all.extend(cargo(&contents));
+    }
+    if let Some(contents) = read(&root.join("package.json"))? {
+        all.extend(package_json(root, &contents));
+    }
+    if let Some(contents) = read(&root.join("flake.nix"))? {
+        all.extend(flake(&contents));
+    }
+    let mut seen = HashSet::new();
+    let mut suggestions = Vec::new();
+    for suggestion in all {
+        if seen.insert(suggestion.name) && !db.script_exists(tree_id, suggestion.name)? {
+            suggestions.push(suggestion);
+        }
+    }
+    Ok(suggestions)
+}
+
+/// Reads a project file, or returns `None` if it doesn't exist
+fn read(path: &Path) -> crate::Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn script(name: &'static str, desc: &str, command: &str, source: &'static str) -> Suggestion {
+    Suggestion {
+        name,
+        desc: desc.to_owned(),
+        body: format!("#!/bin/sh\n{command}\n"),
+        source,
+    }
+}
+
+fn make(makefile: &'static str, contents: &str) -> Vec<Suggestion> {
+    let targets = make_targets(contents);
+    let mut suggestions = Vec::new();
+    for (name, candidates) in TASKS {
+        if let Some(target) = candidates.iter().find(|t| targets.contains(**t)) {
+            suggestions.push(script(
+                name,
+                &format!("Run make {target}"),
+                // make doesn't look for the Makefile in parent directories
+                &format!("make -C \"$OTKEEP_TREE_ROOT\" {target} \"$@\""),
+                makefile,
+            ));
+        }
+    }
+    suggestions
+}
+
+/// The targets defined in a Makefile, leaving out pattern rules and special targets
+fn make_targets(contents: &str) -> HashSet<&str> {
+    let mut targets = HashSet::new();
+    for line in contents.lines() {
+        if line.starts_with(['\t', ' ', '#', '.']) {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // Variable assignments like `A := b` or `A = b:c`
+        if rest.starts_with('=') || names.contains('=') {
+            continue;
+        }
+        targets.extend(
+            names
+                .split_whitespace()
+                .filter(|name| !name.contains(['%', '$'])),
+        );
+    }
+    targets
+}
+
+fn cargo(contents: &str) -> Vec<Suggestion> {
+    let workspace = contents.lines().any(|line| line.trim() == "[workspace]");
+    let (all, fmt_all) = if workspace {
+        (" --workspace", " --all")
+    } else {
+        ("", "")
+    };
+    vec![
+        script(
+            "build",
+            "Build the project",
+            &format!("cargo build{all} \"$@\""),
+            "Cargo.toml",
+        ),
+        script(
+            "test",
+            "Run the tests",
+            &format!("cargo test{all} \"$@\""),
+            "Cargo.toml",
+        ),
+        script(
+            "fmt",
+            "Format the code",
+            &format!("cargo fmt{fmt_all} \"$@\""),
+            "Cargo.toml",
+        ),
+        script(
+            "lint",
+            "Run clippy",
+            &format!("cargo clippy{all} --all-targets \"$@\" -- -D warnings"),
+            "Cargo.toml",
+        ),
+        script(
+            "clean",
+            "Remove the build output",
+            "cargo clean \"$@\"",
+            "Cargo.toml",
+        ),
+    ]
+}
+
+fn package_json(root: &Path, contents: &str) -> Vec<Suggestion> {
+    let scripts = package_scripts(contents);
+    // Use the package manager the lock file belongs to
+    let run = if root.join("pnpm-lock.yaml").exists() {
+        "pnpm run"
+    } else if root.join("yarn.lock").exists() {
+        "yarn run"
+    } else if root.join("bun.lockb").exists() || root.join("bun.lock").exists() {
+        "bun run"
+    } else {
+        "npm run"
+    };
+    // npm passes on the arguments after `--`, the others pass them on as they are
+    let args = if run == "npm run" {
+        "-- \"$@\""
+    } else {
+        "\"$@\""
+    };
+    let mut suggestions = Vec::new();
+    for (name, candidates) in TASKS {
+        if let Some(key) = candidates
+            .iter()
+            .find(|key| scripts.iter().any(|s| s == *key))
+        {
+            suggestions.push(script(
+                name,
+                &format!("Run the {key} script of package.json"),
+                &format!("{run} {key} {args}"),
+                "package.json",
+            ));
+        }
+    }
+    suggestions
+}
+
+/// The names in the `scripts` object of a package.json. It's only scanned for them, so invalid
+/// JSON gives partial results rather than an error.
+fn package_scripts(json: &str) -> Vec<String> {
+    let Some(object) = json.match_indices("\"scripts\"").find_map(|(i, key)| {
+        let rest = json[i + key.len()..].trim_start().strip_prefix(':')?;
+        rest.trim_start().strip_prefix('{')
+    }) else {
+        return Vec::new();
+    };
+    let mut names = Vec::new();
+    let mut depth = 1;
+    // The string being read, and the last one read if nothing but whitespace came after it
+    let mut string: Option<String> = None;
+    let mut last_string = None;
+    let mut chars = object.chars();
+    while let Some(c) = chars.next() {
+        if let Some(s) = &mut string {
+            match c {
+                '\\' => s.extend(chars.next()),
+                '"' => last_string = string.take(),
+                c => s.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => string = Some(String::new()),
+            ':' if depth == 1 => names.extend(last_string.take()),
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            c if c.is_whitespace() => {}
+            _ => last_string = None,
+        }
+    }
+    names
+}
+
+fn flake(contents: &str) -> Vec<Suggestion> {
+    let mut suggestions = vec![
+        script("build", "Build the flake", "nix build \"$@\"", "flake.nix"),
+        script(
+            "test",
+            "Run the checks of the flake",
+            "nix flake check \"$@\"",
+            "flake.nix",
+        ),
+    ];
+    if contents.contains("formatter") {
+        suggestions.push(script(
+            "fmt",
+            "Format the code",
+            "nix fmt \"$@\"",
+            "flake.nix",
+        ));
+    }
+    suggestions.push(script(
+        "update",
+        "Update the inputs of the flake",
+        "nix flake update \"$@\"",
+        "flake.nix",
+    ));
+    suggestions
+}
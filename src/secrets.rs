@@ -0,0 +1,385 @@
+//! Getting secrets, like the passphrase of an encrypted database or of a gpg key.
+//!
+//! A secret is taken from its environment variable if that's set, for scheduled runs and other
+//! places without anyone to ask. Then it's looked up in the keyring, if it can be stored there,
+//! and asked for otherwise.
+//!
+//! `OTKEEP_PINENTRY` picks how secrets are asked for: `tty` asks on the terminal, and anything
+//! else names a pinentry program to ask with, like `pinentry-gnome3`. Without it, the terminal is
+//! used if there is one, and `pinentry` otherwise.
+//!
+//! gpg asks for the passphrases of keys itself, through the pinentry of gpg-agent, unless
+//! `OTKEEP_GPG_PASSPHRASE` is set.
+
+use {
+    crate::error::{bail, Context},
+    std::{
+        ffi::{CStr, OsString},
+        fs::File,
+        io::{BufRead, BufReader, ErrorKind, Write},
+        os::{fd::AsRawFd, unix::process::CommandExt},
+        process::{Command, Stdio},
+        sync::Mutex,
+    },
+};
+
+/// Environment variable picking how secrets are asked for
+pub const PINENTRY_VAR: &str = "OTKEEP_PINENTRY";
+
+/// Environment variable the passphrase of the gpg key can be given in
+pub const GPG_PASSPHRASE_VAR: &str = "OTKEEP_GPG_PASSPHRASE";
+
+/// All environment variables secrets are taken from
+const SECRET_VARS: [&str; 2] = [crate::encryption::PASSPHRASE_VAR, GPG_PASSPHRASE_VAR];
+
+/// Secrets that were taken from the environment, which they're removed from
+static FROM_ENV: Mutex<Vec<(&str, String)>> = Mutex::new(Vec::new());
+
+/// A secret, and where it's looked for
+pub struct Secret {
+    /// What the secret is, shown when it's asked for
+    pub desc: String,
+    /// Environment variable it can be given in
+    pub env_var: &'static str,
+    /// Its entry in the keyring, if it can be stored there
+    pub keyring: Option<KeyringEntry>,
+}
+
+impl Secret {
+    /// Takes the secret from the environment or the keyring, or asks for it
+    pub fn get(&self) -> crate::Result<String> {
+        match self.stored()? {
+            Some(secret) => Ok(secret),
+            None => ask(&self.desc),
+        }
+    }
+
+    /// Takes the secret from the environment or the keyring, without asking for it
+    pub fn stored(&self) -> crate::Result<Option<String>> {
+        if let Some(secret) = from_env(self.env_var)? {
+            return Ok(Some(secret));
+        }
+        match &self.keyring {
+            Some(entry) => entry.lookup(),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns the secret given in the environment variable `var`.
+///
+/// The variable is removed from the environment once it's read, so the scripts that are run
+/// don't see it. The secret is remembered for later calls.
+pub fn from_env(var: &'static str) -> crate::Result<Option<String>> {
+    let mut taken = FROM_ENV.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, secret)) = taken.iter().find(|(taken_var, _)| *taken_var == var) {
+        return Ok(Some(secret.clone()));
+    }
+    let Some(secret) = std::env::var_os(var) else {
+        return Ok(None);
+    };
+    std::env::remove_var(var);
+    let secret = secret
+        .into_string()
+        .ok()
+        .with_context(|| format!("{var} isn't valid UTF-8"))?;
+    taken.push((var, secret.clone()));
+    Ok(Some(secret))
+}
+
+/// Removes the variables secrets can be given in from the environment, before running scripts
+pub fn hide_from_scripts() -> crate::Result<()> {
+    for var in SECRET_VARS {
+        from_env(var)?;
+    }
+    Ok(())
+}
+
+/// How secrets are asked for
+enum Asker {
+    Tty,
+    Pinentry(String),
+}
+
+impl Asker {
+    fn get() -> Self {
+        match std::env::var(PINENTRY_VAR) {
+            Ok(method) if method == "tty" => Self::Tty,
+            Ok(program) if !program.is_empty() => Self::Pinentry(program),
+            _ if File::open("/dev/tty").is_ok() => Self::Tty,
+            _ => Self::Pinentry("pinentry".into()),
+        }
+    }
+}
+
+/// Asks for a secret described by `desc`, on the terminal or with pinentry
+pub fn ask(desc: &str) -> crate::Result<String> {
+    let secret = match Asker::get() {
+        Asker::Tty => ask_tty(&format!("{desc}: "))?,
+        Asker::Pinentry(program) => ask_pinentry(&program, desc)?,
+    };
+    if secret.is_empty() {
+        bail!("No passphrase was given");
+    }
+    Ok(secret)
+}
+
+/// Asks for a new secret twice, and returns it if both times match
+pub fn ask_new(desc: &str) -> crate::Result<String> {
+    let secret = ask(desc)?;
+    if ask("Repeat the passphrase")? != secret {
+        bail!("The passphrases don't match");
+    }
+    Ok(secret)
+}
+
+/// Asks on the terminal, without echoing what's typed
+fn ask_tty(prompt: &str) -> crate::Result<String> {
+    let mut tty = File::options().read(true).write(true).open("/dev/tty")?;
+    write!(tty, "{prompt}")?;
+    tty.flush()?;
+    let fd = tty.as_raw_fd();
+    let mut termios = std::mem::MaybeUninit::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let original = unsafe { termios.assume_init() };
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+    silent.c_lflag |= libc::ECHONL;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Asks with a pinentry program, speaking the Assuan protocol to it
+fn ask_pinentry(program: &str, desc: &str) -> crate::Result<String> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| {
+            format!(
+                "Failed to run {program} to ask for the passphrase. \
+                 Set {PINENTRY_VAR} to ask another way"
+            )
+        })?;
+    let mut input = child.stdin.take().context("Failed to write to pinentry")?;
+    let mut output = BufReader::new(child.stdout.take().context("Failed to read pinentry")?);
+    let greeting = assuan_response(&mut output);
+    let mut request = move |command: &str| -> crate::Result<String> {
+        writeln!(input, "{command}")?;
+        assuan_response(&mut output)
+    };
+    let result = greeting.and_then(|_| get_pin(&mut request, desc));
+    let _ = request("BYE");
+    // Closes the input of pinentry
+    drop(request);
+    let _ = child.wait();
+    result.with_context(|| format!("{program} failed"))
+}
+
+/// Asks for the secret, with `request` sending a command to pinentry and returning the response
+fn get_pin(
+    request: &mut impl FnMut(&str) -> crate::Result<String>,
+    desc: &str,
+) -> crate::Result<String> {
+    // Only the terminal based ones need these, and the others may not know them
+    if let Some(tty) = tty_name() {
+        let _ = request(&format!("OPTION ttyname={tty}"));
+        if let Ok(term) = std::env::var("TERM") {
+            let _ = request(&format!("OPTION ttytype={term}"));
+        }
+    }
+    request("SETTITLE OtKeep")?;
+    request(&format!("SETDESC {}", assuan_escape(desc)))?;
+    request("SETPROMPT Passphrase:")?;
+    request("GETPIN")
+}
+
+/// Reads the response to an Assuan command, and returns the data that came with it
+fn assuan_response(output: &mut impl BufRead) -> crate::Result<String> {
+    let mut data = String::new();
+    loop {
+        let mut line = String::new();
+        if output.read_line(&mut line)? == 0 {
+            bail!("It exited without answering");
+        }
+        let line = line.trim_end_matches('\n');
+        if line == "OK" || line.starts_with("OK ") {
+            return Ok(data);
+        }
+        if let Some(chunk) = line.strip_prefix("D ") {
+            data.push_str(&assuan_unescape(chunk)?);
+        } else if let Some(error) = line.strip_prefix("ERR ") {
+            // The error code, then the message
+            bail!(
+                "{}",
+                error.split_once(' ').map_or(error, |(_, message)| message)
+            );
+        }
+        // Status lines and comments don't matter here
+    }
+}
+
+/// Percent-escapes what can't be sent as is in a line of the Assuan protocol
+fn assuan_escape(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+fn assuan_unescape(text: &str) -> crate::Result<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' && tail.len() >= 2 {
+            let hex = std::str::from_utf8(&tail[..2]).ok();
+            if let Some(decoded) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                bytes.push(decoded);
+                rest = &tail[2..];
+                continue;
+            }
+        }
+        bytes.push(byte);
+        rest = tail;
+    }
+    String::from_utf8(bytes)
+        .ok()
+        .context("The passphrase isn't valid UTF-8")
+}
+
+/// Returns the path of the terminal OtKeep runs in, if any
+fn tty_name() -> Option<String> {
+    (0..=2).find_map(|fd| {
+        let name = unsafe { libc::ttyname(fd) };
+        if name.is_null() {
+            return None;
+        }
+        let name = unsafe { CStr::from_ptr(name) };
+        name.to_str().ok().map(str::to_owned)
+    })
+}
+
+/// Makes a gpg command, which gets the passphrase of the key from `OTKEEP_GPG_PASSPHRASE` if
+/// it's set and `needs_key` is true. Otherwise gpg-agent asks for it, on the terminal OtKeep runs
+/// in if it has a terminal based pinentry.
+pub(crate) fn gpg_command(needs_key: bool) -> crate::Result<Command> {
+    /// File descriptor gpg reads the passphrase from
+    const PASSPHRASE_FD: i32 = 3;
+    let mut cmd = Command::new("gpg");
+    let passphrase = match needs_key {
+        true => from_env(GPG_PASSPHRASE_VAR)?,
+        false => None,
+    };
+    let Some(passphrase) = passphrase else {
+        if std::env::var_os("GPG_TTY").is_none() {
+            if let Some(tty) = tty_name() {
+                cmd.env("GPG_TTY", tty);
+            }
+        }
+        return Ok(cmd);
+    };
+    cmd.args(["--pinentry-mode", "loopback", "--passphrase-fd"])
+        .arg(PASSPHRASE_FD.to_string());
+    let (reader, mut writer) = std::io::pipe()?;
+    // Small enough to fit in the pipe without anyone reading it yet
+    writeln!(writer, "{passphrase}")?;
+    drop(writer);
+    unsafe {
+        cmd.pre_exec(move || {
+            let fd = reader.as_raw_fd();
+            let result = if fd == PASSPHRASE_FD {
+                // Already in place, but it would be closed when gpg starts
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, PASSPHRASE_FD)
+            };
+            match result {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        });
+    }
+    Ok(cmd)
+}
+
+/// An entry in the keyring, which is used through `secret-tool` from libsecret
+pub struct KeyringEntry {
+    /// Shown in keyring managers
+    pub label: &'static str,
+    /// What the entry is found by
+    pub attributes: Vec<(&'static str, OsString)>,
+}
+
+impl KeyringEntry {
+    /// Looks up the secret. Without `secret-tool`, nothing is found.
+    pub fn lookup(&self) -> crate::Result<Option<String>> {
+        let output = match self.secret_tool("lookup").stderr(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if !output.status.success() || output.stdout.is_empty() {
+            return Ok(None);
+        }
+        let secret = String::from_utf8(output.stdout)
+            .ok()
+            .context("The stored passphrase isn't valid UTF-8")?;
+        Ok(Some(secret))
+    }
+
+    pub fn store(&self, secret: &str) -> crate::Result<()> {
+        ensure_keyring_available()?;
+        let mut child = self
+            .secret_tool("store")
+            .arg("--label")
+            .arg(self.label)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .context("Failed to write to secret-tool")?
+            .write_all(secret.as_bytes())?;
+        if !child.wait()?.success() {
+            bail!("secret-tool failed to store the passphrase");
+        }
+        Ok(())
+    }
+
+    /// Removes the entry, if it's there
+    pub fn clear(&self) -> crate::Result<()> {
+        match self.secret_tool("clear").stderr(Stdio::null()).status() {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn secret_tool(&self, action: &str) -> Command {
+        let mut cmd = Command::new("secret-tool");
+        cmd.arg(action);
+        for (key, value) in &self.attributes {
+            cmd.arg(key).arg(value);
+        }
+        cmd
+    }
+}
+
+/// Returns an error unless `secret-tool` is installed, which the keyring is used through
+pub fn ensure_keyring_available() -> crate::Result<()> {
+    let status = Command::new("secret-tool")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            bail!("Storing the passphrase in the keyring needs secret-tool, from libsecret")
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,121 @@
+//! Wrapper executables that forward to `orun`, so scripts can be invoked by bare name
+
+use {
+    crate::database::Database,
+    std::{
+        collections::HashSet,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Writes a wrapper executable for each script name into `dir`.
+///
+/// `exe` is the path of the OtKeep multicall binary the wrappers invoke. The wrappers run the
+/// scripts of the tree with `uuid`, so they work outside of the tree too.
+/// Names that can't be used as file names are skipped, and so are files that aren't shims of this
+/// tree.
+/// Returns the paths of the written shims.
+pub fn write_shims<'a>(
+    dir: &Path,
+    exe: &Path,
+    tree_id: i64,
+    uuid: &str,
+    names: impl IntoIterator<Item = &'a str>,
+) -> crate::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for name in names {
+        if !is_valid_shim_name(name) {
+            continue;
+        }
+        let path = dir.join(name);
+        if path.symlink_metadata().is_ok() && !is_shim_of(&path, tree_id) {
+            eprintln!(
+                "{} exists, and isn't a shim of this tree. Skipping",
+                path.display()
+            );
+            continue;
+        }
+        std::fs::write(&path, shim_body(exe, tree_id, uuid, name))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Removes the shims generated for `tree_id` from `dir`, except the ones named in `keep`.
+///
+/// Files not generated by OtKeep for this tree are left alone.
+pub fn remove_shims(dir: &Path, tree_id: i64, keep: &HashSet<&str>) -> crate::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_str().is_some_and(|name| keep.contains(name)) {
+            continue;
+        }
+        if is_shim_of(&entry.path(), tree_id) {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file at `path` is a shim generated for `tree_id`
+fn is_shim_of(path: &Path, tree_id: i64) -> bool {
+    std::fs::read_to_string(path)
+        .is_ok_and(|content| content.lines().nth(1) == Some(marker_line(tree_id).as_str()))
+}
+
+/// Regenerates the shims in every shim directory registered for `tree_id`
+pub fn refresh_shims(db: &Database, tree_id: i64) -> crate::Result<()> {
+    let dirs = db.shim_dirs_for_tree(tree_id)?;
+    if dirs.is_empty() {
+        return Ok(());
+    }
+    let exe = std::env::current_exe()?;
+    let uuid = db.tree_uuid(tree_id)?;
+    let scripts = db.scripts_for_tree(tree_id)?;
+    let names: HashSet<&str> = scripts.iter().map(|s| s.name.as_str()).collect();
+    for dir in dirs {
+        if !dir.exists() {
+            eprintln!("Shim directory {} is missing, skipping", dir.display());
+            continue;
+        }
+        remove_shims(&dir, tree_id, &names)?;
+        write_shims(&dir, &exe, tree_id, &uuid, names.iter().copied())?;
+    }
+    Ok(())
+}
+
+/// Removes the shims of `tree_id` from all of its shim directories
+pub fn remove_all_shims(db: &Database, tree_id: i64) -> crate::Result<()> {
+    for dir in db.shim_dirs_for_tree(tree_id)? {
+        if dir.exists() {
+            remove_shims(&dir, tree_id, &HashSet::new())?;
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_shim_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains('/')
+}
+
+fn marker_line(tree_id: i64) -> String {
+    format!("# Generated by OtKeep for tree {tree_id}. Do not edit.")
+}
+
+fn shim_body(exe: &Path, tree_id: i64, uuid: &str, name: &str) -> String {
+    format!(
+        "#!/bin/sh\n{}\nexec {} orun --tree {} {} \"$@\"\n",
+        marker_line(tree_id),
+        shell_quote(&exe.to_string_lossy()),
+        shell_quote(uuid),
+        shell_quote(name)
+    )
+}
+
+/// Quotes `s` for safe use as a single word in POSIX shell
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
@@ -0,0 +1,202 @@
+//! Signing scripts with GPG, and verifying the signatures before running them
+
+use {
+    crate::{
+        error::{bail, Context},
+        fs_util::ensure_dir_exists,
+        secrets::gpg_command,
+    },
+    std::{
+        io::Write,
+        path::Path,
+        process::{Command, Stdio},
+        str::FromStr,
+    },
+};
+
+const POLICY_FILENAME: &str = "signature-policy";
+const ALLOWED_SIGNERS_FILENAME: &str = "allowed-signers";
+
+/// What happens when a script without a valid signature is run.
+///
+/// Kept per device in the config directory, like the anchors, so a shared database can't
+/// change it.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Signatures aren't checked
+    #[default]
+    Ignore,
+    /// A warning is printed, but the script still runs
+    Warn,
+    /// The script doesn't run
+    Refuse,
+}
+
+impl Policy {
+    pub const NAMES: [&str; 3] = ["ignore", "warn", "refuse"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ignore => Self::NAMES[0],
+            Self::Warn => Self::NAMES[1],
+            Self::Refuse => Self::NAMES[2],
+        }
+    }
+
+    pub(crate) fn load(config_dir: &Path) -> crate::Result<Self> {
+        let path = config_dir.join(POLICY_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        std::fs::read_to_string(path)?.trim().parse()
+    }
+
+    pub(crate) fn save(self, config_dir: &Path) -> crate::Result<()> {
+        ensure_dir_exists(config_dir)?;
+        std::fs::write(
+            config_dir.join(POLICY_FILENAME),
+            format!("{}\n", self.name()),
+        )?;
+        Ok(())
+    }
+}
+
+impl FromStr for Policy {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "warn" => Ok(Self::Warn),
+            "refuse" => Ok(Self::Refuse),
+            _ => bail!(
+                "Unknown signature policy '{s}', expected one of: {}",
+                Self::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+/// Fingerprints of the keys whose signatures are valid on this device, even if gpg doesn't fully
+/// trust them.
+///
+/// Kept in the `allowed-signers` file next to the policy, one fingerprint per line. Empty lines and
+/// lines starting with `#` are ignored.
+#[derive(Clone, Default)]
+pub struct AllowedSigners {
+    fingerprints: Vec<String>,
+}
+
+impl AllowedSigners {
+    pub(crate) fn load(config_dir: &Path) -> crate::Result<Self> {
+        let path = config_dir.join(ALLOWED_SIGNERS_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let fingerprints = std::fs::read_to_string(path)?
+            .lines()
+            .map(normalize_fingerprint)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        Ok(Self { fingerprints })
+    }
+
+    fn contains(&self, fingerprint: &str) -> bool {
+        self.fingerprints
+            .contains(&normalize_fingerprint(fingerprint))
+    }
+}
+
+/// Fingerprints are often written in groups of four, and in lower case
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Makes a detached signature of `body`, with the default key of gpg or `key`
+pub fn sign(body: &[u8], key: Option<&str>) -> crate::Result<Vec<u8>> {
+    let mut cmd = gpg_command(true)?;
+    cmd.args(["--detach-sign", "--output", "-"]);
+    if let Some(key) = key {
+        cmd.args(["--local-user", key]);
+    }
+    let output = run_gpg(cmd, body)?;
+    if !output.status.success() {
+        bail!(
+            "gpg failed to sign: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Checks that `signature` is a valid signature of `body`, and returns who made it.
+///
+/// The key that made it has to be fully trusted by gpg, or be one of the `allowed` signers.
+pub fn verify(body: &[u8], signature: &[u8], allowed: &AllowedSigners) -> crate::Result<String> {
+    let dir = temp_dir::TempDir::new()?;
+    let sig_path = dir.child("signature");
+    std::fs::write(&sig_path, signature)?;
+    let mut cmd = gpg_command(false)?;
+    cmd.args(["--batch", "--status-fd", "1", "--verify"])
+        .arg(&sig_path)
+        .arg("-");
+    let output = run_gpg(cmd, body)?;
+    let status = String::from_utf8_lossy(&output.stdout);
+    let mut signer = None;
+    // The fingerprint of the signing key, then the one of its primary key
+    let mut fingerprints: Vec<&str> = Vec::new();
+    let mut trusted = false;
+    for line in status.lines() {
+        let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+            continue;
+        };
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match keyword {
+            // The key id, then the user id
+            "GOODSIG" => signer = Some(rest.split_once(' ').map_or(rest, |(_, user)| user)),
+            "VALIDSIG" => {
+                let fields: Vec<&str> = rest.split(' ').collect();
+                fingerprints.extend(fields.first().copied());
+                fingerprints.extend(fields.get(9).copied());
+            }
+            "TRUST_FULLY" | "TRUST_ULTIMATE" => trusted = true,
+            _ => {}
+        }
+    }
+    let (signer, fingerprint) = match (signer, fingerprints.first()) {
+        (Some(signer), Some(fingerprint)) if output.status.success() => (signer, fingerprint),
+        _ => bail!(
+            "Bad signature: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    };
+    if !trusted && !fingerprints.iter().any(|fpr| allowed.contains(fpr)) {
+        bail!(
+            "The signature by {signer} is good, but gpg doesn't trust the key {fingerprint}. \
+             Trust it fully in gpg, or add its fingerprint to the {ALLOWED_SIGNERS_FILENAME} file \
+             in the config directory"
+        );
+    }
+    Ok(signer.to_owned())
+}
+
+/// Runs `cmd` with `input` as its standard input, and collects its output
+pub(crate) fn run_gpg(mut cmd: Command, input: &[u8]) -> crate::Result<std::process::Output> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run gpg. Is it installed?")?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    // Written from another thread, so gpg can't block on a full output pipe while we write
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    Ok(output)
+}